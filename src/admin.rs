@@ -0,0 +1,56 @@
+//! Admin socket: a Unix socket accepting text commands, one per line.
+//! Every command is answered with a line: `ok`, the requested data or `error: <reason>`
+
+use crate::error::Error;
+use async_std::io::{prelude::BufReadExt, BufReader, WriteExt};
+use async_std::os::unix::net::UnixListener;
+use futures::StreamExt;
+use log::{info, warn};
+use std::fs;
+use std::os::unix::fs::FileTypeExt;
+use std::path::Path;
+
+/// Serves connections one at a time, passing every command to `on_command`
+pub async fn serve<F>(path: &Path, mut on_command: F) -> Result<(), Error>
+where
+    F: FnMut(&str) -> Result<String, Error>,
+{
+    // A socket left by a previous run prevents binding
+    if let Ok(meta) = fs::symlink_metadata(path) {
+        if meta.file_type().is_socket() {
+            fs::remove_file(path)?;
+        }
+    }
+    let listener = UnixListener::bind(path)
+        .await
+        .map_err(|e| Error::new(format!("Cannot bind to {}: {}", path.display(), e)))?;
+    info!("Admin socket: {}", path.display());
+
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let mut lines = BufReader::new(&stream).lines();
+        let mut writer = &stream;
+        while let Some(line) = lines.next().await {
+            let line = match line {
+                Ok(line) => line,
+                Err(e) => {
+                    warn!("Admin connection error: {}", e);
+                    break;
+                }
+            };
+            let command = line.trim();
+            if command.is_empty() {
+                continue;
+            }
+
+            let answer = match on_command(command) {
+                Ok(answer) => answer,
+                Err(e) => format!("error: {}", e),
+            };
+            if let Err(e) = writer.write_all(format!("{}\n", answer).as_bytes()).await {
+                warn!("Admin connection error: {}", e);
+                break;
+            }
+        }
+    }
+}
@@ -0,0 +1,178 @@
+//! Alerts of `--alert-if`: a command or a webhook run when window statistics degrade
+//!
+//! The thresholds are checked every statistics interval against the statistics of the
+//! window. On a breach the command of `--alert-cmd` is run with `sh -c` and the violations
+//! in the environment, and the JSON of the alert is POSTed to `--alert-webhook`. Both run
+//! in the background, and at most one alert is raised per `--alert-interval`: breaches in
+//! between are counted and reported with the next alert.
+
+use crate::error::Error;
+use crate::export::json_string;
+use async_std::io::prelude::*;
+use async_std::net::TcpStream;
+use async_std::task;
+use log::{info, warn};
+use std::fmt;
+use std::process::Command;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// Time to deliver a webhook
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// An `http://HOST[:PORT][/PATH]` URL, HTTPS isn't supported
+#[derive(Debug, Clone, PartialEq)]
+pub struct Webhook {
+    /// With the port
+    host: String,
+    path: String,
+}
+
+/// Alerts raised by one `Delays`, rate limited
+#[derive(Debug, Default)]
+pub struct Alerter {
+    last: Option<Instant>,
+    /// Breaches not alerted since the last alert
+    suppressed: u64,
+}
+
+impl Alerter {
+    /// Raises an alert about `violations` unless one was raised less than `interval` ago
+    pub fn alert(
+        &mut self,
+        violations: &[String],
+        cmd: Option<&str>,
+        webhook: Option<&Webhook>,
+        interval: Duration,
+    ) {
+        let now = Instant::now();
+        if matches!(self.last, Some(last) if now.duration_since(last) < interval) {
+            self.suppressed += 1;
+            return;
+        }
+        self.last = Some(now);
+        let suppressed = std::mem::take(&mut self.suppressed);
+
+        let text = violations.join("; ");
+        warn!("Alert: {}", text);
+        if let Some(cmd) = cmd {
+            run_command(cmd, &text, suppressed);
+        }
+        if let Some(webhook) = webhook {
+            let body = json_body(&text, violations, suppressed);
+            let webhook = webhook.clone();
+            task::spawn(async move {
+                let post = webhook.post("application/json", None, &body);
+                match async_std::future::timeout(WEBHOOK_TIMEOUT, post).await {
+                    Ok(Ok(())) => info!("The alert is delivered to {}", webhook),
+                    Ok(Err(e)) => warn!("Cannot deliver the alert to {}: {}", webhook, e),
+                    Err(_) => warn!("Cannot deliver the alert to {}: timed out", webhook),
+                }
+            });
+        }
+    }
+}
+
+/// Runs `cmd` in the background with `ALERT_VIOLATIONS` and `ALERT_SUPPRESSED` set
+fn run_command(cmd: &str, violations: &str, suppressed: u64) {
+    let child = Command::new("sh")
+        .arg("-c")
+        .arg(cmd)
+        .env("ALERT_VIOLATIONS", violations)
+        .env("ALERT_SUPPRESSED", suppressed.to_string())
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(e) => {
+            warn!("Cannot run the alert command: {}", e);
+            return;
+        }
+    };
+    let cmd = cmd.to_owned();
+    task::spawn_blocking(move || match child.wait() {
+        Ok(status) if status.success() => {}
+        Ok(status) => warn!("The alert command {:?} failed: {}", cmd, status),
+        Err(e) => warn!("Cannot wait for the alert command {:?}: {}", cmd, e),
+    });
+}
+
+/// `{"text": "...", "violations": ["..."], "suppressed": N}`, `text` is for chat webhooks
+fn json_body(text: &str, violations: &[String], suppressed: u64) -> String {
+    let violations: Vec<String> = violations.iter().map(|v| json_string(v)).collect();
+    format!(
+        "{{\"text\":{},\"violations\":[{}],\"suppressed\":{}}}",
+        json_string(&format!("Alert: {}", text)),
+        violations.join(","),
+        suppressed
+    )
+}
+
+impl Webhook {
+    /// POSTs `body` of `content_type`, with the `Authorization` header if it is set.
+    /// Fails unless the response is a success
+    pub async fn post(
+        &self,
+        content_type: &str,
+        authorization: Option<&str>,
+        body: &str,
+    ) -> Result<(), Error> {
+        let mut stream = TcpStream::connect(&self.host).await?;
+        let authorization = authorization
+            .map(|auth| format!("Authorization: {}\r\n", auth))
+            .unwrap_or_default();
+        let request = format!(
+            "POST {} HTTP/1.1\r\nHost: {}\r\nContent-Type: {}\r\n{}\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.path,
+            self.host,
+            content_type,
+            authorization,
+            body.len(),
+            body
+        );
+        stream.write_all(request.as_bytes()).await?;
+
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).await?;
+        let response = String::from_utf8_lossy(&response);
+        let status = response.lines().next().unwrap_or_default();
+        match status.split(' ').nth(1) {
+            Some(code) if code.starts_with('2') => Ok(()),
+            _ => Err(Error::new(format!("Unexpected response: {}", status))),
+        }
+    }
+}
+
+impl FromStr for Webhook {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let rest = s
+            .strip_prefix("http://")
+            .ok_or_else(|| Error::new(format!("Expected an http:// URL, got: {}", s)))?;
+        let (host, path) = match rest.find('/') {
+            Some(pos) => (&rest[..pos], &rest[pos..]),
+            None => (rest, "/"),
+        };
+        if host.is_empty() {
+            return Err(Error::new(format!("No host in the URL: {}", s)));
+        }
+        // The colons of an IPv6 address are in brackets
+        let has_port = host.rsplit(']').next().unwrap_or(host).contains(':');
+        let host = if has_port {
+            host.to_owned()
+        } else {
+            format!("{}:80", host)
+        };
+        Ok(Self {
+            host,
+            path: path.to_owned(),
+        })
+    }
+}
+
+impl fmt::Display for Webhook {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "http://{}{}", self.host, self.path)
+    }
+}
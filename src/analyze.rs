@@ -0,0 +1,179 @@
+//! `analyze` mode: statistics of a file of `--samples-out` recorded by a server
+//!
+//! Samples are replayed at the times they were recorded, so percentiles, jitter, the
+//! intervals of `--csv` and the like, and the heatmap come out as in the run itself.
+//! The loss is the loss of replies: packets which were sent but never answered at the
+//! end of the run are not in the file. Runs of consecutive lost replies are counted
+//! by session and summed up.
+
+use crate::config::AnalyzeOpts;
+use crate::error::Error;
+use crate::export;
+use crate::loss_runs::LossRuns;
+use crate::reordering::Reordering;
+use crate::samples;
+use crate::statistic::{self, SeqStats, SeqTracker};
+use log::warn;
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::net::SocketAddr;
+use std::time::Duration;
+
+/// A recorded session of a client
+struct Session {
+    rtt: statistic::Delays,
+    tracker: SeqTracker,
+    seq: SeqStats,
+    runs: LossRuns,
+    reordering: Reordering,
+}
+
+/// A line of the samples file
+struct Line {
+    time: Duration,
+    addr: SocketAddr,
+    session: u32,
+    seq: u32,
+    rtt: Duration,
+}
+
+pub async fn run(opts: AnalyzeOpts) -> Result<(), Error> {
+    let path = &opts.samples;
+    let err = |e: &dyn std::fmt::Display| Error::new(format!("{}: {}", path.display(), e));
+    let mut lines = BufReader::new(File::open(path).map_err(|e| err(&e))?).lines();
+    let header = lines.next().transpose().map_err(|e| err(&e))?;
+    if header.as_deref().map(str::trim_end) != Some(samples::HEADER) {
+        return Err(err(&"not a file of --samples-out"));
+    }
+
+    let mut statistics = statistic::Delays::new(opts.stats.clone(), Some("RTT".to_owned()));
+    // Replayed intervals would flood the live display, rows and records are shown
+    statistics.set_live(opts.stats.rows);
+    if let Some(path) = &opts.stats.heatmap {
+        statistics.enable_heatmap(path.clone());
+    }
+    let mut total = SeqStats::default();
+    let mut sessions = BTreeMap::new();
+    for (i, line) in lines.enumerate() {
+        let line = line.map_err(|e| err(&e))?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        // The header is line 1
+        let line = parse_line(&line).map_err(|e| err(&format!("line {}: {}", i + 2, e)))?;
+        let session = sessions.entry(line.session).or_insert_with(|| {
+            let label = format!("RTT {} {:08x}", line.addr, line.session);
+            let mut rtt = statistic::Delays::new(opts.stats.clone(), Some(label));
+            rtt.set_live(false);
+            Session {
+                rtt,
+                tracker: Default::default(),
+                seq: Default::default(),
+                runs: Default::default(),
+                reordering: Default::default(),
+            }
+        });
+        let change = session.tracker.on_seq(line.seq);
+        session.runs.on_seq(line.seq);
+        session.seq.add(change);
+        session.rtt.set_seq_stats(session.seq);
+        total.add(change);
+        statistics.set_seq_stats(total);
+        if change.duplicates > 0 {
+            continue;
+        }
+        session.reordering.on_seq(line.seq, line.time);
+        if let Some(median) = session.rtt.spike_median(line.rtt) {
+            on_spike(&opts, &line, median);
+        }
+        session.rtt.replay_event(line.time, line.rtt);
+        statistics.replay_event(line.time, line.rtt);
+    }
+
+    if !opts.stats.quiet {
+        println!(
+            "==== Summary of {} ({} clients) ====",
+            path.display(),
+            sessions.len()
+        );
+    }
+    let jsonl = opts.stats.jsonl.as_deref();
+    // A single client would repeat the statistics of all replies
+    if sessions.len() > 1 {
+        for session in sessions.values_mut() {
+            session.rtt.print_summary();
+            let label = session.rtt.label().unwrap_or_default();
+            session
+                .runs
+                .finished()
+                .print(label, opts.stats.quiet, jsonl);
+            session.reordering.print(&opts.stats, label);
+        }
+    }
+    statistics.print_summary();
+    let mut runs = LossRuns::default();
+    let mut reordering = Reordering::default();
+    for session in sessions.values() {
+        runs.merge(&session.runs.finished());
+        reordering.merge(&session.reordering);
+    }
+    runs.print("RTT", opts.stats.quiet, jsonl);
+    reordering.print(&opts.stats, "RTT");
+
+    let mut violations = statistics.check_thresholds();
+    for session in sessions.values() {
+        violations.extend(session.rtt.check_thresholds());
+    }
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::failed(violations))
+    }
+}
+
+/// Logs a spike with the time it was recorded at, and writes its event to `--jsonl`
+fn on_spike(opts: &AnalyzeOpts, line: &Line, median: Duration) {
+    let fields = statistic::spike_fields(line.seq, line.rtt, median);
+    warn!(
+        "RTT spike of {} session {:08x} at {:.3}: {}",
+        line.addr,
+        line.session,
+        line.time.as_secs_f64(),
+        fields
+    );
+    if let Some(path) = &opts.stats.jsonl {
+        let fields = format!(
+            "client={} session=\"{:08x}\" {}",
+            line.addr, line.session, fields
+        );
+        export::event_at(path, line.time, "spike", &fields);
+    }
+}
+
+/// A line of `samples::HEADER`
+fn parse_line(line: &str) -> Result<Line, String> {
+    let fields: Vec<&str> = line.trim_end().split(',').collect();
+    if fields.len() != 5 {
+        return Err(format!("{} fields instead of 5", fields.len()));
+    }
+    let number = |i: usize| {
+        fields[i]
+            .parse::<f64>()
+            .ok()
+            .filter(|x| x.is_finite() && *x >= 0.)
+            .ok_or_else(|| format!("bad number {:?}", fields[i]))
+    };
+    Ok(Line {
+        time: Duration::from_secs_f64(number(0)?),
+        addr: fields[1]
+            .parse()
+            .map_err(|_| format!("bad address {:?}", fields[1]))?,
+        session: u32::from_str_radix(fields[2], 16)
+            .map_err(|_| format!("bad session {:?}", fields[2]))?,
+        seq: fields[3]
+            .parse()
+            .map_err(|_| format!("bad sequence number {:?}", fields[3]))?,
+        rtt: Duration::from_secs_f64(number(4)? / 1000.),
+    })
+}
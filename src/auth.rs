@@ -0,0 +1,121 @@
+//! Optional packet authentication with pre-shared keys
+//!
+//! With keys configured, every packet ends with a trailer: the one byte ID of the key and
+//! an HMAC-SHA256 of the rest of the packet, truncated to `TAG_LEN` bytes. Packets without
+//! a valid trailer are dropped, so a server exposed to the Internet ignores forged joins
+//! and replies. Keys can be rotated: the server accepts every configured key and answers
+//! each client with the key of its join.
+
+use crate::error::Error;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::borrow::Cow;
+use std::str::FromStr;
+
+pub const TAG_LEN: usize = 8;
+/// Key ID and tag
+pub const TRAILER_LEN: usize = 1 + TAG_LEN;
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// A key and its ID, `ID:SECRET` on the command line
+#[derive(Debug, Clone)]
+pub struct AuthKey {
+    pub id: u8,
+    secret: Vec<u8>,
+}
+
+/// The configured keys, none if authentication is off
+#[derive(Debug, Clone, Default)]
+pub struct Auth {
+    keys: Vec<AuthKey>,
+}
+
+impl Auth {
+    pub fn new(keys: Vec<AuthKey>) -> Self {
+        Self { keys }
+    }
+
+    /// Bytes added to every packet
+    pub fn trailer_len(&self) -> usize {
+        if self.keys.is_empty() {
+            0
+        } else {
+            TRAILER_LEN
+        }
+    }
+
+    /// The key clients sign with: the first one
+    pub fn default_key_id(&self) -> u8 {
+        self.keys.first().map_or(0, |key| key.id)
+    }
+
+    /// Appends the trailer made with the key `key_id`, or with the first key if there is
+    /// no such key. Does nothing if authentication is off
+    pub fn sign(&self, key_id: u8, pkt: &mut Vec<u8>) {
+        let key = match self.key(key_id).or_else(|| self.keys.first()) {
+            Some(key) => key,
+            None => return,
+        };
+        let tag = key.mac(pkt).finalize().into_bytes();
+        pkt.push(key.id);
+        pkt.extend_from_slice(&tag[..TAG_LEN]);
+    }
+
+    /// `pkt` with the trailer, copied only if authentication is on
+    pub fn signed<'a>(&self, key_id: u8, pkt: &'a [u8]) -> Cow<'a, [u8]> {
+        if self.keys.is_empty() {
+            return Cow::Borrowed(pkt);
+        }
+        let mut signed = Vec::with_capacity(pkt.len() + TRAILER_LEN);
+        signed.extend_from_slice(pkt);
+        self.sign(key_id, &mut signed);
+        Cow::Owned(signed)
+    }
+
+    /// Checks the trailer of `pkt`, returns the length of the packet without it and
+    /// the key ID. `None` if the trailer is missing or wrong.
+    /// If authentication is off every packet is valid
+    pub fn verify(&self, pkt: &[u8]) -> Option<(usize, u8)> {
+        if self.keys.is_empty() {
+            return Some((pkt.len(), 0));
+        }
+        let len = pkt.len().checked_sub(TRAILER_LEN)?;
+        let key_id = pkt[len];
+        let key = self.key(key_id)?;
+        // The comparison takes constant time
+        key.mac(&pkt[..len])
+            .verify_truncated_left(&pkt[len + 1..])
+            .ok()?;
+        Some((len, key_id))
+    }
+
+    fn key(&self, id: u8) -> Option<&AuthKey> {
+        self.keys.iter().find(|key| key.id == id)
+    }
+}
+
+impl AuthKey {
+    fn mac(&self, data: &[u8]) -> HmacSha256 {
+        let mut mac = HmacSha256::new_from_slice(&self.secret).expect("HMAC takes any key");
+        mac.update(data);
+        mac
+    }
+}
+
+impl FromStr for AuthKey {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let err = || Error::new(format!("Expected ID:SECRET with ID 0-255, got: {}", s));
+        let (id, secret) = s.split_once(':').ok_or_else(err)?;
+        let id = id.parse().map_err(|_| err())?;
+        if secret.is_empty() {
+            return Err(Error::new("The authentication secret is empty"));
+        }
+        Ok(Self {
+            id,
+            secret: secret.as_bytes().to_vec(),
+        })
+    }
+}
@@ -0,0 +1,198 @@
+//! Baselines of `--save-baseline` and `--baseline`, for before/after comparisons
+//!
+//! A baseline file has a `key=value` line per statistics of a run, e.g. `RTT`, with the
+//! values of `METRICS` over the whole run. A later run compares its statistics to those
+//! with the same label, and fails like `--fail-if` if a metric is worse than the baseline
+//! by more than its `--regression-if` tolerance.
+
+use crate::error::Error;
+use crate::export;
+use crate::threshold::Metric;
+use std::collections::BTreeMap;
+use std::fmt::{self, Write as _};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Metrics saved in baselines
+pub const METRICS: [&str; 12] = [
+    "p50",
+    "p90",
+    "p95",
+    "p99",
+    "p99.9",
+    "avg",
+    "max",
+    "jitter",
+    "loss",
+    "reordered",
+    "mos",
+    "apdex",
+];
+
+/// A baseline loaded from its file
+#[derive(Debug, Clone)]
+pub struct Baseline {
+    path: PathBuf,
+    /// Values of metrics by label of the statistics and name of the metric
+    by_label: BTreeMap<String, BTreeMap<String, f64>>,
+}
+
+/// A `--regression-if` tolerance: how much worse than the baseline a metric can get
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tolerance {
+    metric: Metric,
+    /// The metric gets worse when it grows, the MOS gets worse when it drops
+    increase: bool,
+    value: f64,
+    /// `value` is in percents of the baseline value
+    relative: bool,
+    /// As given, for messages
+    text: String,
+}
+
+impl Baseline {
+    /// Reads the baseline file at `path`
+    pub fn load(path: &str) -> Result<Self, Error> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| Error::new(format!("Cannot read {}: {}", path, e)))?;
+        let mut by_label = BTreeMap::new();
+        for line in content.lines() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut label = String::new();
+            let mut metrics = BTreeMap::new();
+            for (key, value) in export::parse_record(line) {
+                if key == "label" {
+                    label = value;
+                } else if let Ok(value) = value.parse::<f64>() {
+                    metrics.insert(key.to_owned(), value);
+                }
+            }
+            by_label.insert(label, metrics);
+        }
+        Ok(Self {
+            path: PathBuf::from(path),
+            by_label,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Values of the metrics of the statistics labeled `label`, `None` if the baseline
+    /// has no such statistics
+    pub fn metrics(&self, label: &str) -> Option<&BTreeMap<String, f64>> {
+        self.by_label.get(label)
+    }
+}
+
+/// The baseline line of the statistics labeled `label`. `lookup` returns the values
+/// of metrics, `None` if they are unknown
+pub fn record(label: &str, lookup: impl Fn(Metric) -> Option<f64>) -> String {
+    let mut rec = format!("label={:?}", label);
+    for name in METRICS {
+        let metric: Metric = name.parse().expect("Known metric");
+        if let Some(value) = lookup(metric) {
+            write!(rec, " {}={:.3}", name, value).unwrap();
+        }
+    }
+    rec
+}
+
+impl Tolerance {
+    pub fn metric(&self) -> Metric {
+        self.metric
+    }
+
+    /// Checks the value of the metric against the baseline. Returns the regression
+    /// if the value is worse by more than the tolerance
+    pub fn check(&self, value: Option<f64>, baseline: Option<f64>) -> Option<String> {
+        let (value, baseline) = match (value, baseline) {
+            (Some(value), Some(baseline)) => (value, baseline),
+            (None, _) => return Some(format!("{}: {} is unknown", self, self.metric)),
+            (_, None) => return Some(format!("{}: {} is not in the baseline", self, self.metric)),
+        };
+        let worse_by = if self.increase {
+            value - baseline
+        } else {
+            baseline - value
+        };
+        let allowed = if self.relative {
+            baseline.abs() * self.value / 100.
+        } else {
+            self.value
+        };
+        if worse_by > allowed {
+            Some(format!(
+                "{}: {} is {:.2}{}, the baseline {:.2}{}",
+                self,
+                self.metric,
+                value,
+                self.metric.unit(),
+                baseline,
+                self.metric.unit()
+            ))
+        } else {
+            None
+        }
+    }
+}
+
+impl FromStr for Tolerance {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let pos = s.find(['+', '-']).ok_or_else(|| {
+            Error::new(format!(
+                "Expected METRIC+TOLERANCE or METRIC-TOLERANCE, got: {}",
+                s
+            ))
+        })?;
+        let metric: Metric = s[..pos].trim().parse()?;
+        let increase = s[pos..].starts_with('+');
+        let value = s[pos + 1..].trim();
+        let (num, mult, relative) = match metric.unit() {
+            "ms" => {
+                if let Some(n) = value.strip_suffix('%') {
+                    (n, 1., true)
+                } else if let Some(n) = value.strip_suffix("ms") {
+                    (n, 1., false)
+                } else if let Some(n) = value.strip_suffix("us") {
+                    (n, 0.001, false)
+                } else if let Some(n) = value.strip_suffix('s') {
+                    (n, 1000., false)
+                } else {
+                    (value, 1., false)
+                }
+            }
+            // Percentage points
+            "%" => (value.strip_suffix('%').unwrap_or(value), 1., false),
+            _ => (value, 1., false),
+        };
+        let value = num
+            .trim()
+            .parse::<f64>()
+            .ok()
+            .filter(|v| v.is_finite() && *v >= 0.)
+            .ok_or_else(|| Error::new(format!("Invalid tolerance: {}", s)))?;
+
+        Ok(Self {
+            metric,
+            increase,
+            value: value * mult,
+            relative,
+            text: s.to_owned(),
+        })
+    }
+}
+
+impl fmt::Display for Tolerance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
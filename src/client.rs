@@ -0,0 +1,821 @@
+//! `client` mode: registers on a server and replies to its data packets
+//!
+//! Clocks of the client and the server are not synchronized, so the client can't measure
+//! the one-way delay itself. Instead it measures how much later than the fastest packet
+//! seen so far each data packet arrives: the delay variation of the server to client path.
+//!
+//! Several clients can be simulated by one process to load-test a server.
+//! Each of them uses its own socket, their samples go to the same statistics.
+//! Every client can also run several parallel streams, each with its own socket: they
+//! are separate flows for the network and have separate statistics as well.
+
+use crate::auth::Auth;
+use crate::clock::ClockSync;
+use crate::config::{ClientOpts, StatsConfig};
+use crate::error::Error;
+use crate::interarrival::Interarrival;
+use crate::metrics;
+use crate::net::{enable_recv_tos, recv_msg, resolve, set_tos, source_addr, Ecn, Received};
+use crate::protocol::{
+    self, AckBody, DataHeader, Direction, JoinBody, PingBody, Report, ReportBody, SyncBody,
+    COOKIE_LEN,
+};
+use crate::rtcp::{self, ReportBlock, ReportPacket};
+use crate::rtp;
+use crate::statistic::{self, EcnCounts, InterarrivalJitter, SeqStats, SeqTracker};
+use crate::stop::run_until_stopped;
+use crate::websocket;
+use async_std::{future::timeout, net::UdpSocket, task::sleep};
+use futures::{future::try_join_all, try_join};
+use log::{debug, info, warn};
+use std::cell::RefCell;
+use std::cmp;
+use std::convert::{TryFrom, TryInto};
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+/// How often clients in the download direction report to the server
+const REPORT_INTERVAL: Duration = Duration::from_secs(1);
+/// Data packets in the upload direction, unless given: the server defaults
+const UPLOAD_INTERVAL: Duration = Duration::from_millis(20);
+const UPLOAD_PACKET_SIZE: usize = 256;
+/// Clients join again after this time without packets from the server, e.g. after
+/// a server restart or a NAT timeout. The server sends clock syncs every second
+const RECONNECT_SILENCE: Duration = Duration::from_secs(3);
+/// Further joins are sent after exponentially growing waits, from the first to the max
+const FIRST_REJOIN_WAIT: Duration = Duration::from_secs(1);
+const MAX_REJOIN_WAIT: Duration = Duration::from_secs(30);
+
+pub async fn run(opts: ClientOpts) -> Result<(), Error> {
+    let server = resolve(&opts.server).await?;
+
+    if let Some(interval) = opts.ping {
+        let client = Client::new(transport(server, &opts).await?, &opts, 0, 0).await?;
+        let mut delays = statistic::Delays::new(opts.stats.clone(), Some("RTT".to_owned()));
+        if let Some(path) = &opts.stats.heatmap {
+            delays.enable_heatmap(path.clone());
+        }
+        let stats = RefCell::new(delays);
+        let run = async {
+            try_join!(
+                client.ping(interval, &stats),
+                statistic::tick_every(|| stats.borrow_mut().tick()),
+                metrics::serve(opts.stats.metrics.as_deref()),
+                websocket::serve(opts.stats.websocket.as_deref())
+            )
+            .map(|_| ())
+        };
+        run_until_stopped(run, opts.duration).await?;
+        stats.borrow_mut().print_summary();
+        return check_thresholds(stats.borrow().check_thresholds());
+    }
+
+    let streams = opts.streams;
+    let mut clients = Vec::with_capacity(usize::from(opts.clients) * usize::from(streams));
+    for i in 0..u32::from(opts.clients) * u32::from(streams) {
+        // Streams are numbered from 1 if there are several
+        let stream = match streams {
+            1 => 0,
+            _ => (i % u32::from(streams)) as u8 + 1,
+        };
+        // Every client has its own QUIC connection, as it has its own socket
+        let transport = transport(server, &opts).await?;
+        clients.push(Client::new(transport, &opts, i, stream).await?);
+    }
+    info!(
+        "Joining {} from {} client(s), {} stream(s) each",
+        server, opts.clients, streams
+    );
+    for client in &clients {
+        client.join().await?;
+    }
+
+    let mut statistics = Statistics::new(&opts.stats, "Delay variation", streams);
+    if let Some(path) = &opts.stats.heatmap {
+        statistics.delays.enable_heatmap(path.clone());
+    }
+    let statistics = RefCell::new(statistics);
+    let start = Instant::now();
+    let loops = try_join_all(clients.iter_mut().map(|c| c.receive_loop(&statistics)));
+    let run = async {
+        try_join!(
+            loops,
+            statistic::tick_every(|| statistics.borrow_mut().tick()),
+            metrics::serve(opts.stats.metrics.as_deref()),
+            websocket::serve(opts.stats.websocket.as_deref())
+        )
+        .map(|_| ())
+    };
+    let res = run_until_stopped(run, opts.duration).await;
+
+    for client in &clients {
+        client.stop().await?;
+    }
+    res?;
+
+    statistics.borrow_mut().print_summary(start.elapsed());
+    let violations = statistics.borrow().delays.check_thresholds();
+    check_thresholds(violations)
+}
+
+/// Fails the run if any `--fail-if` threshold is violated
+fn check_thresholds(violations: Vec<String>) -> Result<(), Error> {
+    if violations.is_empty() {
+        Ok(())
+    } else {
+        Err(Error::failed(violations))
+    }
+}
+
+/// The address to send to: the server itself, or a local relay to it over QUIC or DTLS
+async fn transport(server: SocketAddr, opts: &ClientOpts) -> Result<SocketAddr, Error> {
+    if opts.quic {
+        #[cfg(feature = "quic")]
+        return crate::quic::connect(server).await;
+        #[cfg(not(feature = "quic"))]
+        return Err(Error::new("QUIC needs a build with the `quic` feature"));
+    }
+    if let Some(_psk) = &opts.dtls {
+        #[cfg(feature = "dtls")]
+        return crate::dtls::connect(server, _psk).await;
+        #[cfg(not(feature = "dtls"))]
+        return Err(Error::new("DTLS needs a build with the `dtls` feature"));
+    }
+    Ok(server)
+}
+
+/// Statistics of all simulated clients
+struct Statistics {
+    delays: statistic::Delays,
+    seq: SeqStats,
+    ecn: EcnCounts,
+    /// Of all clients and streams, the statistics by stream have none
+    interarrival: Interarrival,
+    cfg: StatsConfig,
+    /// The same by stream with several streams per client, the stream ID is the index + 1
+    streams: Vec<Statistics>,
+}
+
+impl Statistics {
+    fn new(cfg: &StatsConfig, label: &str, streams: u8) -> Self {
+        Self {
+            delays: statistic::Delays::new(cfg.clone(), Some(label.to_owned())),
+            seq: Default::default(),
+            ecn: Default::default(),
+            interarrival: Default::default(),
+            cfg: cfg.clone(),
+            streams: match streams {
+                1 => Vec::new(),
+                _ => (1..=streams)
+                    .map(|stream| Self::new(cfg, &format!("{} stream {}", label, stream), 1))
+                    .collect(),
+            },
+        }
+    }
+
+    /// Adds a data packet of `stream` with its delay variation, the time since the previous
+    /// packet if it is counted, the change of sequence statistics and its ECN codepoint
+    fn add(
+        &mut self,
+        stream: u8,
+        variation: Duration,
+        gap: Option<Duration>,
+        seq: SeqStats,
+        ecn: Ecn,
+    ) {
+        if let Some(gap) = gap {
+            self.interarrival.add(gap);
+        }
+        self.seq.add(seq);
+        self.delays.set_seq_stats(self.seq);
+        if seq.duplicates == 0 {
+            self.ecn.add(ecn);
+            self.delays.set_ecn(self.ecn);
+            self.delays.new_event(variation);
+        }
+        if let Some(stats) = usize::from(stream)
+            .checked_sub(1)
+            .and_then(|idx| self.streams.get_mut(idx))
+        {
+            stats.add(0, variation, None, seq, ecn);
+        }
+    }
+
+    /// Ends the intervals without samples, see `Delays::tick`
+    fn tick(&mut self) {
+        self.delays.tick();
+        for stats in &mut self.streams {
+            stats.tick();
+        }
+    }
+
+    /// Prints the summary of the run which took `elapsed`
+    fn print_summary(&mut self, elapsed: Duration) {
+        if !self.cfg.quiet {
+            println!("==== Summary after {:.1}s ====", elapsed.as_secs_f64());
+        }
+        self.print_stats_summary();
+    }
+
+    fn print_stats_summary(&mut self) {
+        self.delays.print_summary();
+        self.interarrival.print_summary(&self.cfg, "Interarrival");
+        for stats in &mut self.streams {
+            stats.print_stats_summary();
+        }
+    }
+}
+
+struct Client {
+    socket: UdpSocket,
+    server: SocketAddr,
+    start: Instant,
+    /// Assigned by the server, `None` until the server accepts the client
+    session: Option<u32>,
+    /// Interval in microseconds and packet size requested in joins, 0 for the server defaults
+    params: (u32, u16),
+    seqs: SeqTracker,
+    /// Statistics of this client reported to the server
+    seq: SeqStats,
+    /// ECN codepoints of data packets, reported to the server
+    ecn: EcnCounts,
+    jitter: InterarrivalJitter,
+    /// Sequence number and arrival of the latest data packet, see `Interarrival`
+    last_data: Option<(u32, Instant)>,
+    /// The smallest difference between the local receive time and the server send time,
+    /// compensated for the clock drift since the first data packet
+    min_transit_ms: Option<f64>,
+    /// Drift of the server clock, estimated from transit times of data packets, and
+    /// the local time of the first data packet
+    clock: ClockSync,
+    first_data_us: Option<i64>,
+    /// Expected and received packets at the previous RTCP receiver report
+    rtcp_prior: (u32, u32),
+    direction: Direction,
+    /// Interval and size of data packets in the upload direction
+    upload: (Duration, usize),
+    /// Number of the last data packet sent in the upload direction
+    upload_seq: u32,
+    /// When the next data packet or report is due, see `next_tick`
+    next_tick: Instant,
+    auth: Auth,
+    /// When a packet from the server was received
+    last_received: Instant,
+    /// When the next join is sent unless the server is heard from, see `rejoin`
+    rejoin_at: Instant,
+    rejoin_wait: Duration,
+    /// The server went silent after accepting the client
+    stalled: bool,
+    /// The address the server sees the client at, see `AckBody`
+    reflexive: Option<SocketAddr>,
+    /// ID of the stream among the parallel streams of the client, see `JoinBody`
+    stream: u8,
+}
+
+impl Client {
+    /// `idx` is the index of the socket among all streams of all simulated clients,
+    /// it selects the port if `--bind` is given
+    async fn new(
+        server: SocketAddr,
+        opts: &ClientOpts,
+        idx: u32,
+        stream: u8,
+    ) -> Result<Self, Error> {
+        let bind = match &opts.bind {
+            Some(bind) => {
+                let mut addr = resolve(bind).await?;
+                if addr.port() != 0 {
+                    let port = u16::try_from(idx)
+                        .ok()
+                        .and_then(|idx| addr.port().checked_add(idx))
+                        .ok_or_else(|| {
+                            Error::new(format!("No port for stream {} starting from {}", idx, bind))
+                        })?;
+                    addr.set_port(port);
+                }
+                addr
+            }
+            None if server.is_ipv4() => "0.0.0.0:0".parse()?,
+            None => "[::]:0".parse()?,
+        };
+        let socket = UdpSocket::bind(bind)
+            .await
+            .map_err(|e| Error::new(format!("Cannot bind to {}: {}", bind, e)))?;
+        set_tos(&socket, opts.dscp, opts.ecn)?;
+        enable_recv_tos(&socket)?;
+
+        Ok(Self {
+            socket,
+            server,
+            start: Instant::now(),
+            session: None,
+            params: (
+                opts.interval
+                    .map_or(0, |i| i.as_micros().try_into().unwrap_or(u32::MAX)),
+                opts.packet_size.map_or(0, |s| s as u16),
+            ),
+            seqs: Default::default(),
+            seq: Default::default(),
+            ecn: Default::default(),
+            jitter: Default::default(),
+            last_data: None,
+            min_transit_ms: None,
+            clock: Default::default(),
+            first_data_us: None,
+            rtcp_prior: (0, 0),
+            direction: opts.direction,
+            upload: (
+                opts.interval.unwrap_or(UPLOAD_INTERVAL),
+                opts.packet_size.unwrap_or(UPLOAD_PACKET_SIZE),
+            ),
+            upload_seq: 0,
+            next_tick: Instant::now(),
+            auth: Auth::new(opts.auth_key.iter().cloned().collect()),
+            last_received: Instant::now(),
+            rejoin_at: Instant::now() + RECONNECT_SILENCE,
+            rejoin_wait: FIRST_REJOIN_WAIT,
+            stalled: false,
+            reflexive: None,
+            stream,
+        })
+    }
+
+    /// Sends a packet to the server, with the authentication trailer if there is a key
+    async fn send(&self, pkt: &[u8]) -> Result<(), Error> {
+        let pkt = self.auth.signed(self.auth.default_key_id(), pkt);
+        self.socket.send_to(&pkt, self.server).await?;
+        Ok(())
+    }
+
+    async fn join(&self) -> Result<(), Error> {
+        debug!("Joining {} from {}", self.server, self.socket.local_addr()?);
+        self.send_join([0; COOKIE_LEN]).await
+    }
+
+    async fn send_join(&self, cookie: [u8; COOKIE_LEN]) -> Result<(), Error> {
+        let join = JoinBody {
+            cookie,
+            session: self.session.unwrap_or(0),
+            interval_us: self.params.0,
+            packet_size: self.params.1,
+            direction: self.direction,
+            stream: self.stream,
+        };
+        let mut pkt = Vec::with_capacity(protocol::PREFIX_LEN + protocol::JOIN_BODY_LEN);
+        join.write(&mut pkt);
+        self.send(&pkt).await?;
+        Ok(())
+    }
+
+    /// Pings the server at `interval` without joining, measures the round trip time
+    async fn ping(
+        &self,
+        interval: Duration,
+        stats: &RefCell<statistic::Delays>,
+    ) -> Result<(), Error> {
+        info!("Pinging {} every {:?}", self.server, interval);
+        try_join!(self.send_pings(interval), self.receive_pings(stats)).map(|_| ())
+    }
+
+    async fn send_pings(&self, interval: Duration) -> Result<(), Error> {
+        let mut pkt = Vec::with_capacity(protocol::PREFIX_LEN + protocol::PING_BODY_LEN);
+        for seq in 1.. {
+            pkt.clear();
+            let ping = PingBody {
+                seq,
+                time_us: self.start.elapsed().as_micros() as u64,
+            };
+            ping.write(&mut pkt);
+            self.send(&pkt).await?;
+            sleep(interval).await;
+        }
+        Ok(())
+    }
+
+    async fn receive_pings(&self, stats: &RefCell<statistic::Delays>) -> Result<(), Error> {
+        let mut seqs = SeqTracker::default();
+        let mut seq = SeqStats::default();
+        let mut buf = vec![0; 1500];
+        loop {
+            let (len, addr) = self.socket.recv_from(&mut buf).await?;
+            let now_us = self.start.elapsed().as_micros() as u64;
+            let pkt = match self.auth.verify(&buf[..len]) {
+                Some((len, _)) => &buf[..len],
+                None => {
+                    warn!("Wrong authentication trailer from {}, len: {}", addr, len);
+                    continue;
+                }
+            };
+            if addr != self.server || protocol::parse_type(pkt).ok() != Some(protocol::PING) {
+                warn!("Unexpected packet from {}, len: {}", addr, len);
+                continue;
+            }
+            let ping = match PingBody::parse(protocol::body(pkt)) {
+                Ok(ping) => ping,
+                Err(e) => {
+                    warn!("{}", e);
+                    continue;
+                }
+            };
+
+            let change = seqs.on_seq(ping.seq);
+            seq.add(change);
+            let mut stats = stats.borrow_mut();
+            stats.set_seq_stats(seq);
+            if change.duplicates == 0 {
+                let rtt = now_us.saturating_sub(ping.time_us);
+                stats.new_event(Duration::from_micros(rtt));
+            }
+        }
+    }
+
+    async fn stop(&self) -> Result<(), Error> {
+        if self.first_data_us.is_some() {
+            info!(
+                "Clock drift of {}: {:.1}ppm, taken out of the delay variation",
+                self.server,
+                self.clock.drift_ppm()
+            );
+        }
+        let session = self.session.map(u32::to_be_bytes).unwrap_or_default();
+        let body = if self.session.is_some() {
+            &session[..]
+        } else {
+            &[]
+        };
+        let pkt = protocol::control_pkt(protocol::STOP, body);
+        self.send(&pkt).await?;
+        Ok(())
+    }
+
+    async fn receive_loop(&mut self, stats: &RefCell<Statistics>) -> Result<(), Error> {
+        const BUF_LEN: usize = 65535;
+        let mut buf = vec![0; BUF_LEN];
+        loop {
+            let now = Instant::now();
+            if self.rejoin_at <= now {
+                self.rejoin().await?;
+                continue;
+            }
+            let deadline = match self.next_tick() {
+                Some(tick) if tick <= now => {
+                    self.on_tick().await?;
+                    continue;
+                }
+                Some(tick) => cmp::min(tick, self.rejoin_at),
+                None => self.rejoin_at,
+            };
+            let received = timeout(deadline - now, recv_msg(&self.socket, &mut buf)).await;
+            let Received { len, addr, tos, .. } = match received {
+                Ok(res) => res?,
+                Err(_) => continue,
+            };
+            if addr != self.server {
+                warn!("Packet from unexpected address: {}", addr);
+                continue;
+            }
+            let len = match self.auth.verify(&buf[..len]) {
+                Some((len, _)) => len,
+                None => {
+                    warn!("Wrong authentication trailer from {}, len: {}", addr, len);
+                    continue;
+                }
+            };
+            self.on_server_heard(stats);
+            let ecn = tos.map_or(Ecn::NotEct, Ecn::from_tos);
+
+            let pkt = &mut buf[..len];
+            if rtcp::is_rtcp(pkt) {
+                self.on_rtcp_pkt(pkt).await?;
+                continue;
+            }
+            // The server may frame data packets as RTP, replies keep the RTP header
+            let start = rtp::payload_offset(pkt);
+            let pkt_type = match protocol::parse_type(&pkt[start..]) {
+                Ok(pkt_type) => pkt_type,
+                Err(e) => {
+                    warn!("{}, len: {}", e, len);
+                    continue;
+                }
+            };
+
+            match pkt_type {
+                protocol::DATA => match self.on_data_pkt(pkt, start, ecn).await {
+                    Ok((variation, gap, seq)) => {
+                        stats
+                            .borrow_mut()
+                            .add(self.stream, variation, gap, seq, ecn)
+                    }
+                    Err(e) => warn!("Error handling packet: {}", e),
+                },
+                protocol::SYNC => self.on_sync_pkt(pkt).await?,
+                protocol::PROBE => {
+                    self.send(pkt).await?;
+                }
+                protocol::CHALLENGE => match protocol::body(pkt).try_into() {
+                    Ok(cookie) => self.send_join(cookie).await?,
+                    Err(_) => warn!("Wrong challenge packet len: {}", len),
+                },
+                protocol::ACK => match AckBody::parse(protocol::body(pkt)) {
+                    Ok(ack) => self.on_ack(ack)?,
+                    Err(e) => warn!("{}", e),
+                },
+                protocol::BYE => {
+                    info!("{} is shutting down", self.server);
+                    return Ok(());
+                }
+                protocol::REJECT => {
+                    return Err(Error::new(format!(
+                        "Server rejected the client: {}",
+                        String::from_utf8_lossy(protocol::body(pkt))
+                    )));
+                }
+                x => warn!("Unexpected packet type: {}. len: {}", x, len),
+            }
+        }
+    }
+
+    /// Joins again when the server is silent for `RECONNECT_SILENCE`, then after waits
+    /// doubling up to `MAX_REJOIN_WAIT`. The server resumes the session if it still knows it
+    async fn rejoin(&mut self) -> Result<(), Error> {
+        let silence = self.last_received.elapsed();
+        if self.session.is_some() && !self.stalled {
+            self.stalled = true;
+            warn!(
+                "No packets from {} for {:.1}s, joining again",
+                self.server,
+                silence.as_secs_f64()
+            );
+        } else {
+            debug!(
+                "No answer from {} for {:.1}s, joining again",
+                self.server,
+                silence.as_secs_f64()
+            );
+        }
+        self.rejoin_at = Instant::now() + self.rejoin_wait;
+        self.rejoin_wait = cmp::min(self.rejoin_wait * 2, MAX_REJOIN_WAIT);
+        self.join().await
+    }
+
+    /// Called on every packet of the server, marks the end of a stall in the statistics
+    fn on_server_heard(&mut self, stats: &RefCell<Statistics>) {
+        let now = Instant::now();
+        if self.stalled {
+            self.stalled = false;
+            let gap = now - self.last_received;
+            info!(
+                "{} is back after {:.1}s without packets",
+                self.server,
+                gap.as_secs_f64()
+            );
+            stats.borrow_mut().delays.add_gap(gap);
+        }
+        self.last_received = now;
+        self.rejoin_at = now + RECONNECT_SILENCE;
+        self.rejoin_wait = FIRST_REJOIN_WAIT;
+    }
+
+    /// When the next data packet is due in the upload direction, or the next report
+    /// in the download one. `None` in both directions or until the server accepts the client
+    fn next_tick(&self) -> Option<Instant> {
+        match self.direction {
+            Direction::Both => None,
+            _ => self.session.map(|_| self.next_tick),
+        }
+    }
+
+    async fn on_tick(&mut self) -> Result<(), Error> {
+        let session = match self.session {
+            Some(session) => session,
+            None => return Ok(()),
+        };
+
+        let mut pkt = Vec::new();
+        let interval = match self.direction {
+            Direction::Both => return Ok(()),
+            Direction::Download => {
+                let report = ReportBody {
+                    session,
+                    report: self.report(),
+                };
+                report.write(&mut pkt);
+                REPORT_INTERVAL
+            }
+            Direction::Upload => {
+                self.upload_seq += 1;
+                let header = DataHeader {
+                    session,
+                    seq: self.upload_seq,
+                    time_us: self.start.elapsed().as_micros() as u64,
+                    reply_us: 0,
+                    flags: 0,
+                    redundancy: 0,
+                    stream: self.stream,
+                    report: Default::default(),
+                };
+                header.write(protocol::DATA, &mut pkt);
+                let trailer_len = self.auth.trailer_len();
+                let len = self
+                    .upload
+                    .1
+                    .saturating_sub(protocol::CRC_LEN + trailer_len);
+                pkt.resize(len.max(pkt.len()), 0);
+                protocol::append_crc(&mut pkt, 0);
+                self.upload.0
+            }
+        };
+        // A late packet doesn't shift the schedule, but missed packets are not sent
+        self.next_tick = cmp::max(self.next_tick + interval, Instant::now());
+        self.send(&pkt).await?;
+        Ok(())
+    }
+
+    fn on_ack(&mut self, ack: AckBody) -> Result<(), Error> {
+        self.on_session(ack.session);
+        let addr = match ack.addr {
+            Some(addr) if self.reflexive != Some(addr) => addr,
+            _ => return Ok(()),
+        };
+        let local = source_addr(&self.socket, self.server)?;
+        match self.reflexive.replace(addr) {
+            Some(prev) => warn!(
+                "The NAT mapping of {} changed from {} to {}",
+                local, prev, addr
+            ),
+            None if addr == local => {
+                info!("{} sees the client at its own address: no NAT", self.server)
+            }
+            None => info!(
+                "{} sees the client at {}, the NAT mapping of {}",
+                self.server, addr, local
+            ),
+        }
+        Ok(())
+    }
+
+    fn on_session(&mut self, session: u32) {
+        if self.session != Some(session) {
+            debug!("Accepted by {}, session: {:08x}", self.server, session);
+            if self.session.is_some() {
+                // The server forgot the client, e.g. it restarted: a new stream starts
+                info!("{} started a new session: {:08x}", self.server, session);
+                self.seqs = Default::default();
+                self.min_transit_ms = None;
+                self.clock = Default::default();
+                self.first_data_us = None;
+                self.last_data = None;
+                self.jitter.restart();
+            }
+            self.session = Some(session);
+        }
+    }
+
+    /// Fills in the receive and send times and sends the packet back
+    async fn on_sync_pkt(&mut self, pkt: &[u8]) -> Result<(), Error> {
+        let t2 = self.start.elapsed().as_micros() as u64;
+        let mut sync = match SyncBody::parse(protocol::body(pkt)) {
+            Ok(sync) => sync,
+            Err(e) => {
+                warn!("{}", e);
+                return Ok(());
+            }
+        };
+        sync.t2 = t2;
+        sync.t3 = self.start.elapsed().as_micros() as u64;
+
+        let mut reply = Vec::with_capacity(protocol::PREFIX_LEN + protocol::SYNC_BODY_LEN);
+        sync.write(&mut reply);
+        self.send(&reply).await?;
+        Ok(())
+    }
+
+    /// Answers RTCP sender reports of the server with receiver reports on the downlink
+    async fn on_rtcp_pkt(&mut self, pkt: &[u8]) -> Result<(), Error> {
+        let received = Instant::now();
+        let (ssrc, sender_info) = match ReportPacket::parse(pkt) {
+            Ok(Some(ReportPacket {
+                ssrc,
+                sender_info: Some(info),
+                ..
+            })) => (ssrc, info),
+            Ok(_) => return Ok(()),
+            Err(e) => {
+                warn!("{}", e);
+                return Ok(());
+            }
+        };
+
+        let report = self.report();
+        let expected = report.expected.saturating_sub(self.rtcp_prior.0);
+        let lost = expected.saturating_sub(report.received.saturating_sub(self.rtcp_prior.1));
+        self.rtcp_prior = (report.expected, report.received);
+        let fraction_lost = match expected {
+            0 => 0,
+            _ => (u64::from(lost) * 256 / u64::from(expected)).min(255) as u8,
+        };
+
+        let rr = ReportPacket {
+            ssrc,
+            sender_info: None,
+            blocks: vec![ReportBlock {
+                ssrc,
+                fraction_lost,
+                cumulative_lost: report.expected.saturating_sub(report.received),
+                highest_seq: self.seqs.max_seq(),
+                jitter: (self.jitter.jitter_ms() * rtp::CLOCK_RATE as f64 / 1000.) as u32,
+                lsr: sender_info.ntp_time.compact(),
+                dlsr: rtcp::duration_to_compact(received.elapsed()),
+            }],
+        };
+        let mut buf = Vec::new();
+        let cname = format!("udp-jitter-test@{}", self.socket.local_addr()?);
+        rr.write(&cname, &mut buf);
+        self.send(&buf).await?;
+        Ok(())
+    }
+
+    fn report(&self) -> Report {
+        Report {
+            expected: self.seq.expected as u32,
+            received: self.seq.received as u32,
+            late: self.seq.reordered as u32,
+            jitter_us: (self.jitter.jitter_ms() * 1000.) as u32,
+            recovered: self.seq.recovered as u32,
+            ect: self.ecn.ect as u32,
+            ce: self.ecn.ce as u32,
+        }
+    }
+
+    /// Replies to the packet, returns its delay variation and the change of sequence statistics
+    /// `pkt` is the whole received packet, the data packet starts at `start` of it.
+    /// `ecn` is the ECN codepoint it arrived with
+    async fn on_data_pkt(
+        &mut self,
+        pkt: &mut [u8],
+        start: usize,
+        ecn: Ecn,
+    ) -> Result<(Duration, Option<Duration>, SeqStats), Error> {
+        let arrival = Instant::now();
+        let data = &mut pkt[start..];
+        let header = DataHeader::parse(data)?;
+        // The accept packet can be lost, but data packets carry the session as well
+        self.on_session(header.session);
+        let now_us = self.start.elapsed().as_micros() as i64;
+
+        let mut change = self.seqs.on_seq(header.seq);
+        if change.duplicates == 0 {
+            if protocol::is_crc_valid(data) {
+                change.recovered = self.seqs.on_redundancy(header.seq, header.redundancy);
+            } else {
+                warn!("Corrupted packet from {}, seq: {}", self.server, header.seq);
+                change.corrupted = 1;
+                DataHeader::set_flags(data, header.flags | protocol::FLAG_CORRUPTED);
+            }
+        }
+
+        let transit_ms = (now_us - header.time_us as i64) as f64 / 1000.;
+        let mut gap = None;
+        if change.duplicates == 0 {
+            self.jitter.on_transit(transit_ms);
+            self.ecn.add(ecn);
+            gap = self.on_data_arrival(header.seq, header.flags, arrival);
+            self.clock.on_one_way(header.time_us as i64, now_us);
+        }
+        self.seq.add(change);
+
+        // In the download direction the server learns about the stream from reports
+        if self.direction == Direction::Both {
+            protocol::set_type(data, protocol::REPLY);
+            DataHeader::set_reply_time(data, now_us as u64);
+            DataHeader::set_report(data, &self.report());
+            self.send(pkt).await?;
+        }
+
+        // Transit times drift along with the clocks, a slow ramp the path has nothing to do with
+        let first_us = *self.first_data_us.get_or_insert(now_us);
+        let transit_ms = transit_ms + self.clock.drift_us(first_us, now_us) / 1000.;
+        let min_transit_ms = self
+            .min_transit_ms
+            .map_or(transit_ms, |m| m.min(transit_ms));
+        self.min_transit_ms = Some(min_transit_ms);
+
+        let variation = Duration::from_secs_f64((transit_ms - min_transit_ms) / 1000.);
+        Ok((variation, gap, change))
+    }
+
+    /// Returns the time since the previous data packet if it is counted, see `Interarrival`
+    fn on_data_arrival(&mut self, seq: u32, flags: u8, arrival: Instant) -> Option<Duration> {
+        let prev = self.last_data;
+        // Reordered packets are not the latest ones
+        if prev.is_some_and(|(last, _)| seq.wrapping_sub(last) > u32::MAX / 2) {
+            return None;
+        }
+        self.last_data = Some((seq, arrival));
+        let (last, at) = prev?;
+        if seq != last.wrapping_add(1) || flags & protocol::FLAG_TRAIN != 0 {
+            return None;
+        }
+        Some(arrival.duration_since(at))
+    }
+}
@@ -0,0 +1,469 @@
+//! Clients registered on a server
+
+use crate::export;
+use crate::protocol::Direction;
+use crate::schedule::{IntervalPattern, SpurtPosition, VoiceActivity};
+use crate::store;
+use crate::websocket;
+use log::info;
+use std::cell::{Cell, RefCell};
+use std::cmp;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+/// How test packets are sent to a client: requested in its join or the server defaults
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct StreamParams {
+    pub interval: Duration,
+    /// Intervals follow the interval pattern of the server instead of `interval`
+    pub patterned: bool,
+    pub packet_size: usize,
+    /// Sizes follow the size model of the server instead of `packet_size`
+    pub variable_size: bool,
+    /// Clients in the upload direction are not sent data packets
+    pub direction: Direction,
+    /// Key of authentication trailers, the one the client joined with
+    pub key_id: u8,
+    /// One of several parallel streams of a client, see `JoinBody`
+    pub stream: u8,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Client {
+    /// Assigned by the server on join, identifies the client even if its address changes
+    pub session: u32,
+    pub addr: SocketAddr,
+    pub params: StreamParams,
+    /// Number of the last data packet sent to the client
+    pub seq: u32,
+    /// Position of the last data packet in the voice activity cycle
+    pub position: SpurtPosition,
+    /// When the last data packet was due on the schedule
+    pub scheduled: Instant,
+    next_send: Instant,
+    talking: bool,
+    /// When the current talk spurt or silence ends
+    state_end: Instant,
+    /// When a packet from the client was received
+    last_seen: Instant,
+    /// ICMP unreachable errors about packets sent to the client since then
+    unreachable: u32,
+    /// Bytes of data packets sent to the client
+    sent_bytes: u64,
+}
+
+pub struct Clients {
+    clients: RefCell<Vec<Client>>,
+    max_clients: Option<usize>,
+    pattern: Option<IntervalPattern>,
+    voice_activity: Option<VoiceActivity>,
+    /// JSON lines file of `--jsonl` joins and leaves are written to
+    events: Option<PathBuf>,
+    /// Bytes of data packets sent to all clients, including those which left
+    sent_bytes: Cell<u64>,
+}
+
+/// Where a packet of a session comes from, see `Clients::source`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Source {
+    /// The address of the client with the session
+    Client,
+    /// Another address: a moved client or a spoofed packet
+    OtherAddr,
+    /// No client has the session
+    Unknown,
+}
+
+pub struct ClientsIterator<'a> {
+    clients: &'a RefCell<Vec<Client>>,
+    idx: usize,
+}
+
+impl Clients {
+    pub fn new(
+        max_clients: Option<usize>,
+        pattern: Option<IntervalPattern>,
+        voice_activity: Option<VoiceActivity>,
+        events: Option<PathBuf>,
+    ) -> Self {
+        Self {
+            clients: RefCell::new(vec![]),
+            max_clients,
+            pattern,
+            voice_activity,
+            events,
+            sent_bytes: Cell::new(0),
+        }
+    }
+
+    /// Writes an event of the client to the `--jsonl` file and `--websocket` subscribers
+    pub fn event(&self, event: &str, addr: &SocketAddr, session: u32, fields: &str) {
+        match event {
+            "join" => store::client_joined(addr, session),
+            "leave" => store::client_left(session, fields.trim_start_matches("reason=")),
+            _ => {}
+        }
+        let fields = format!("client={} session=\"{:08x}\" {}", addr, session, fields);
+        let rec = export::event_record(event, &fields);
+        if let Some(path) = &self.events {
+            export::append_json(path, &rec);
+        }
+        websocket::publish(&rec);
+    }
+
+    /// Registers a client and returns its session.
+    /// A client resuming a known `session` from another address keeps it.
+    /// Returns `None` if the client is rejected because of the clients limit.
+    pub fn add_new_client(
+        &self,
+        addr: SocketAddr,
+        session: Option<u32>,
+        params: StreamParams,
+    ) -> Option<u32> {
+        if let Some(session) = session {
+            if self.move_client(session, addr) {
+                self.set_params(session, params);
+                return Some(session);
+            }
+        }
+
+        let mut clients = self.clients.borrow_mut();
+        if let Some(client) = clients.iter_mut().find(|c| c.addr == addr) {
+            info!("Connected is already in the list: {}", addr);
+            client.params = params;
+            client.last_seen = Instant::now();
+            client.unreachable = 0;
+            return Some(client.session);
+        }
+        if self.max_clients.is_some_and(|max| clients.len() >= max) {
+            return None;
+        }
+
+        let session = loop {
+            let session = rand::random();
+            if session != 0 && clients.iter().all(|c| c.session != session) {
+                break session;
+            }
+        };
+        info!(
+            "New client connected: {}, session: {:08x}, interval: {:?}, packet size: {}, direction: {:?}",
+            addr, session, params.interval, params.packet_size, params.direction
+        );
+        self.event(
+            "join",
+            &addr,
+            session,
+            &format!(
+                "interval_ms={:.3} packet_size={}",
+                params.interval.as_secs_f64() * 1000.,
+                params.packet_size
+            ),
+        );
+        clients.push(Client {
+            session,
+            addr,
+            params,
+            seq: 0,
+            position: Default::default(),
+            scheduled: Instant::now(),
+            next_send: Instant::now(),
+            // The first packet starts a talk spurt
+            talking: false,
+            state_end: Instant::now(),
+            last_seen: Instant::now(),
+            unreachable: 0,
+            sent_bytes: 0,
+        });
+
+        Some(session)
+    }
+
+    /// Removes the client at `addr` by its session, or by its address only if the session
+    /// is not known. Returns the session of the removed client
+    pub fn remove_client(&self, addr: &SocketAddr, session: Option<u32>) -> Option<u32> {
+        let mut clients = self.clients.borrow_mut();
+        let idx = clients
+            .iter()
+            .position(|c| c.addr == *addr && session.is_none_or(|session| c.session == session))?;
+        let client = clients.remove(idx);
+        info!("Client disconnected: {}", addr);
+        self.event("leave", addr, client.session, "reason=goodbye");
+        Some(client.session)
+    }
+
+    /// Where a packet of `session` from `addr` comes from, marks its client as alive if
+    /// it is the client's address
+    pub fn source(&self, session: u32, addr: SocketAddr) -> Source {
+        let mut clients = self.clients.borrow_mut();
+        match clients.iter_mut().find(|c| c.session == session) {
+            Some(client) if client.addr == addr => {
+                client.last_seen = Instant::now();
+                client.unreachable = 0;
+                Source::Client
+            }
+            Some(_) => Source::OtherAddr,
+            None => Source::Unknown,
+        }
+    }
+
+    /// Moves the client with `session` to `addr`, e.g. after a NAT rebinding, and marks
+    /// it as alive. Only for joins which answered a cookie challenge at `addr`, sessions
+    /// are in clear. Returns `false` if there is no such client
+    fn move_client(&self, session: u32, addr: SocketAddr) -> bool {
+        let mut clients = self.clients.borrow_mut();
+        match clients.iter_mut().find(|c| c.session == session) {
+            Some(client) => {
+                if client.addr != addr {
+                    info!(
+                        "Client {:08x} moved from {} to {}",
+                        session, client.addr, addr
+                    );
+                    client.addr = addr;
+                }
+                client.last_seen = Instant::now();
+                client.unreachable = 0;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes clients nothing was received from for `timeout`
+    pub fn evict_idle(&self, timeout: Duration) {
+        self.clients.borrow_mut().retain(|c| {
+            let idle = c.last_seen.elapsed();
+            if idle < timeout {
+                return true;
+            }
+            info!(
+                "Client {} evicted, session: {:08x}, silent for {:?}",
+                c.addr, c.session, idle
+            );
+            self.event("leave", &c.addr, c.session, "reason=idle");
+            false
+        });
+    }
+
+    /// Counts an ICMP unreachable error about a packet sent to `addr`. Clients with `limit`
+    /// errors and no packets received since the first one are removed
+    pub fn on_unreachable(&self, addr: &SocketAddr, limit: u32) {
+        self.clients.borrow_mut().retain_mut(|c| {
+            if c.addr != *addr {
+                return true;
+            }
+            c.unreachable += 1;
+            if c.unreachable < limit {
+                return true;
+            }
+            info!(
+                "Client {} removed, session: {:08x}, unreachable",
+                c.addr, c.session
+            );
+            self.event("leave", &c.addr, c.session, "reason=unreachable");
+            false
+        });
+    }
+
+    pub fn contains(&self, session: u32) -> bool {
+        self.clients.borrow().iter().any(|c| c.session == session)
+    }
+
+    pub fn params(&self, session: u32) -> Option<StreamParams> {
+        let clients = self.clients.borrow();
+        clients
+            .iter()
+            .find(|c| c.session == session)
+            .map(|c| c.params)
+    }
+
+    /// Changes the interval and the packet size of the client with `session`, the next
+    /// packet is sent with them right away. Returns `false` if there is no such client
+    pub fn set_rate(
+        &self,
+        session: u32,
+        interval: Option<Duration>,
+        packet_size: Option<usize>,
+    ) -> bool {
+        let mut clients = self.clients.borrow_mut();
+        let client = match clients.iter_mut().find(|c| c.session == session) {
+            Some(client) => client,
+            None => return false,
+        };
+        if let Some(interval) = interval {
+            client.params.interval = interval;
+            client.params.patterned = false;
+        }
+        if let Some(packet_size) = packet_size {
+            client.params.packet_size = packet_size;
+            client.params.variable_size = false;
+        }
+        client.next_send = Instant::now();
+        info!(
+            "Client {} changed, session: {:08x}, interval: {:?}, packet size: {}",
+            client.addr, client.session, client.params.interval, client.params.packet_size
+        );
+        true
+    }
+
+    fn set_params(&self, session: u32, params: StreamParams) {
+        let mut clients = self.clients.borrow_mut();
+        if let Some(client) = clients.iter_mut().find(|c| c.session == session) {
+            client.params = params;
+        }
+    }
+
+    /// Puts into `due` the clients whose next packet is due at `now`, with `seq` of the packet.
+    /// Returns when the next packet is due, `None` without clients
+    pub fn take_due(&self, now: Instant, due: &mut Vec<Client>) -> Option<Instant> {
+        due.clear();
+        let mut clients = self.clients.borrow_mut();
+        for client in clients.iter_mut().filter(|c| c.receives_data()) {
+            if client.next_send <= now {
+                client.seq += 1;
+                client.scheduled = client.next_send;
+                let mut interval = match &self.pattern {
+                    Some(pattern) if client.params.patterned => pattern.interval(client.seq),
+                    _ => client.params.interval,
+                };
+                if let Some(activity) = &self.voice_activity {
+                    interval = client.next_activity(activity, now, interval);
+                }
+                // A late packet doesn't shift the schedule, but missed packets are not sent
+                client.next_send = cmp::max(client.next_send + interval, now);
+                if self.voice_activity.is_some() && !client.talking {
+                    // A spurt starts as soon as the silence ends
+                    client.next_send = cmp::min(client.next_send, cmp::max(client.state_end, now));
+                }
+                due.push(*client);
+            }
+        }
+        clients
+            .iter()
+            .filter(|c| c.receives_data())
+            .map(|c| c.next_send)
+            .min()
+    }
+
+    /// Puts into `due` `len` packets for every client, sent back-to-back as a train
+    pub fn take_train(&self, len: u32, due: &mut Vec<Client>) {
+        due.clear();
+        for client in self.clients.borrow_mut().iter_mut() {
+            if !client.receives_data() {
+                continue;
+            }
+            for _ in 0..len {
+                client.seq += 1;
+                due.push(*client);
+            }
+        }
+    }
+
+    /// Counts the packets `bufs` sent to the clients of `due`, one to each
+    pub fn on_sent(&self, due: &[Client], bufs: &[Vec<u8>]) {
+        let mut clients = self.clients.borrow_mut();
+        // `due` is in the order of the clients, only those which changed while the packets
+        // were sent are searched for
+        let mut idx = 0;
+        for (sent, buf) in due.iter().zip(bufs) {
+            let bytes = buf.len() as u64;
+            self.sent_bytes.set(self.sent_bytes.get() + bytes);
+            let is_sent = |c: &Client| c.session == sent.session;
+            let found = match clients[idx..].iter().position(is_sent) {
+                Some(pos) => Some(idx + pos),
+                None => clients.iter().position(is_sent),
+            };
+            if let Some(found) = found {
+                idx = found;
+                clients[idx].sent_bytes += bytes;
+            }
+        }
+    }
+
+    /// Bytes of data packets sent to the client with `session`, or to all clients
+    /// without it
+    pub fn sent_bytes(&self, session: Option<u32>) -> u64 {
+        match session {
+            Some(session) => self
+                .clients
+                .borrow()
+                .iter()
+                .find(|c| c.session == session)
+                .map_or(0, |c| c.sent_bytes),
+            None => self.sent_bytes.get(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.clients.borrow().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.clients.borrow().is_empty()
+    }
+
+    pub fn iter(&self) -> ClientsIterator<'_> {
+        ClientsIterator {
+            clients: &self.clients,
+            idx: 0,
+        }
+    }
+}
+
+impl Client {
+    fn receives_data(&self) -> bool {
+        self.params.direction != Direction::Upload
+    }
+
+    /// Moves through the voice activity cycle on sending a packet at `now`,
+    /// sets its position and returns the interval to the next packet
+    fn next_activity(
+        &mut self,
+        activity: &VoiceActivity,
+        now: Instant,
+        talk_interval: Duration,
+    ) -> Duration {
+        let started = now >= self.state_end;
+        if started {
+            self.talking = !self.talking;
+            self.state_end = now + activity.state_duration(self.talking);
+        }
+
+        self.position = match (self.talking, started) {
+            (true, true) => SpurtPosition::Start,
+            (true, false) => SpurtPosition::Talk,
+            (false, _) => SpurtPosition::ComfortNoise,
+        };
+        if self.talking {
+            talk_interval
+        } else {
+            activity.comfort_noise_interval
+        }
+    }
+}
+
+impl<'a> IntoIterator for &'a Clients {
+    type Item = <ClientsIterator<'a> as Iterator>::Item;
+    type IntoIter = ClientsIterator<'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a> Iterator for ClientsIterator<'a> {
+    type Item = Client;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let item = self.clients.borrow().get(self.idx).copied();
+        self.idx += 1;
+        item
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        let len = self.clients.borrow().len().saturating_sub(self.idx);
+        (len, Some(len))
+    }
+}
+
+impl<'a> ExactSizeIterator for ClientsIterator<'a> {}
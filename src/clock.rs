@@ -0,0 +1,131 @@
+//! Estimation of the offset and drift between the clocks of a server and a client
+//!
+//! The server exchanges timestamps with clients both ways, which gives the offset. A client
+//! only sees packets of the server one way: the transit times have the offset and the path
+//! delay in them, which the drift can still be estimated from, as queueing only adds.
+
+use std::collections::VecDeque;
+
+/// Exchanges are grouped into periods and only the one with the smallest round trip time
+/// is kept from each period: it is the least distorted by queueing
+const PERIOD_US: i64 = 10_000_000;
+/// How many periods the drift is estimated over
+const MAX_PERIODS: usize = 30;
+
+/// Offset of the peer clock from the local one, changing linearly with the drift
+#[derive(Debug, Default)]
+pub struct ClockSync {
+    /// The best samples of finished periods
+    periods: VecDeque<Sample>,
+    current: Option<Sample>,
+    period_start_us: i64,
+    fit: Fit,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Sample {
+    /// Local time of the exchange middle
+    local_us: f64,
+    offset_us: f64,
+    rtt_us: i64,
+}
+
+/// Least squares line of offsets over local time
+#[derive(Debug, Default, Clone, Copy)]
+struct Fit {
+    mean_local_us: f64,
+    mean_offset_us: f64,
+    slope: f64,
+}
+
+impl ClockSync {
+    /// Registers an exchange, times in µs: `t1` - local send, `t2` - peer receive,
+    /// `t3` - peer send, `t4` - local receive
+    pub fn on_exchange(&mut self, t1: i64, t2: i64, t3: i64, t4: i64) {
+        let sample = Sample {
+            local_us: (t1 + t4) as f64 / 2.,
+            offset_us: ((t2 - t1) + (t3 - t4)) as f64 / 2.,
+            rtt_us: (t4 - t1) - (t3 - t2),
+        };
+        self.add(sample, t4);
+    }
+
+    /// Registers a packet sent at `sent_us` of the peer clock and received at `received_us`.
+    /// Offsets of these are off by the path delay, only the drift is right
+    pub fn on_one_way(&mut self, sent_us: i64, received_us: i64) {
+        let sample = Sample {
+            local_us: received_us as f64,
+            offset_us: (sent_us - received_us) as f64,
+            // The fastest packet of a period is kept
+            rtt_us: received_us - sent_us,
+        };
+        self.add(sample, received_us);
+    }
+
+    fn add(&mut self, sample: Sample, t4: i64) {
+        match self.current {
+            Some(current) if t4 - self.period_start_us >= PERIOD_US => {
+                if self.periods.len() == MAX_PERIODS {
+                    self.periods.pop_front();
+                }
+                self.periods.push_back(current);
+                self.period_start_us = t4;
+            }
+            Some(current) if current.rtt_us < sample.rtt_us => return,
+            Some(_) => {}
+            None => self.period_start_us = t4,
+        }
+        self.current = Some(sample);
+        self.fit = self.calculate_fit();
+    }
+
+    /// Offset in ms at the time of the last kept exchange, `None` without exchanges
+    pub fn latest_offset_ms(&self) -> Option<f64> {
+        Some(self.offset_us(self.current?.local_us) / 1000.)
+    }
+
+    /// Offset of the peer clock at the given local time, in µs
+    fn offset_us(&self, local_us: f64) -> f64 {
+        let fit = &self.fit;
+        fit.mean_offset_us + fit.slope * (local_us - fit.mean_local_us)
+    }
+
+    /// How much faster the peer clock goes, in parts per million
+    pub fn drift_ppm(&self) -> f64 {
+        self.fit.slope * 1e6
+    }
+
+    /// How many µs further the peer clock went than the local one from `from_us` to
+    /// `to_us` of the local clock
+    pub fn drift_us(&self, from_us: i64, to_us: i64) -> f64 {
+        self.fit.slope * (to_us - from_us) as f64
+    }
+
+    /// Converts a time of the peer clock to the local one, in µs
+    pub fn to_local(&self, peer_us: i64) -> i64 {
+        let peer_us = peer_us as f64;
+        // The offset depends on the local time, which is approximated first
+        let approx_us = peer_us - self.offset_us(peer_us);
+        (peer_us - self.offset_us(approx_us)).round() as i64
+    }
+
+    fn calculate_fit(&self) -> Fit {
+        let samples = || self.periods.iter().chain(&self.current);
+        let n = samples().count() as f64;
+        let mean_local_us = samples().map(|s| s.local_us).sum::<f64>() / n;
+        let mean_offset_us = samples().map(|s| s.offset_us).sum::<f64>() / n;
+
+        let (mut cov, mut var) = (0., 0.);
+        for s in samples() {
+            let dx = s.local_us - mean_local_us;
+            cov += dx * (s.offset_us - mean_offset_us);
+            var += dx * dx;
+        }
+
+        Fit {
+            mean_local_us,
+            mean_offset_us,
+            slope: if var > 0. { cov / var } else { 0. },
+        }
+    }
+}
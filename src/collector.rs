@@ -0,0 +1,208 @@
+//! `collect` mode: a table of the statistics probes send with `--report-to`
+//!
+//! Every probe reports the window statistics of its live display, e.g. `RTT`, `Uplink`
+//! and `Downlink`, every statistics interval. The table has a section per site: a row
+//! per probe and statistics with the latest report, and with several probes, a row per
+//! statistics of the whole site. A site row adds up the samples and the loss of its
+//! probes, its percentiles and jitter are the worst ones. Probes without a site are
+//! grouped by IP address.
+
+use crate::config::CollectOpts;
+use crate::error::Error;
+use crate::export;
+use crate::stop::run_until_stopped;
+use async_std::future;
+use async_std::net::UdpSocket;
+use log::{debug, info};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+const MOVE_UP: &str = "\x1b[1A";
+const DEL_LINE: &str = "\x1b[K";
+
+/// The latest report of a probe
+struct Report {
+    received: Instant,
+    values: BTreeMap<String, f64>,
+}
+
+/// Statistics of a site: a row of the table
+#[derive(Default)]
+struct Row {
+    samples: f64,
+    /// Sum of the averages weighted by samples
+    weighted_avg_ms: f64,
+    p99_ms: f64,
+    jitter_ms: f64,
+    expected: f64,
+    received: f64,
+}
+
+/// Reports by site, then by label of the statistics and address of the probe
+type Sites = BTreeMap<String, BTreeMap<(String, SocketAddr), Report>>;
+
+pub async fn run(opts: CollectOpts) -> Result<(), Error> {
+    let socket = UdpSocket::bind(&opts.bind)
+        .await
+        .map_err(|e| Error::new(format!("Cannot bind to {}: {}", opts.bind, e)))?;
+    info!("Collecting reports of probes on {}", socket.local_addr()?);
+    run_until_stopped(collect(&socket, &opts), opts.duration).await
+}
+
+async fn collect(socket: &UdpSocket, opts: &CollectOpts) -> Result<(), Error> {
+    let mut buf = vec![0; 65535];
+    let mut sites = Sites::new();
+    let mut last_draw = Instant::now();
+    let mut drawn_lines = 0;
+    loop {
+        let wait = opts.refresh.saturating_sub(last_draw.elapsed());
+        if let Ok(received) = future::timeout(wait, socket.recv_from(&mut buf)).await {
+            let (len, addr) = received?;
+            match std::str::from_utf8(&buf[..len]) {
+                Ok(rec) => on_report(&mut sites, opts, addr, rec.trim_end()),
+                Err(_) => debug!("Report of {} is not text", addr),
+            }
+        }
+        if last_draw.elapsed() >= opts.refresh {
+            last_draw = Instant::now();
+            drop_stale(&mut sites, opts.stale_after);
+            for _ in 0..drawn_lines {
+                eprint!("{}{}", MOVE_UP, DEL_LINE);
+            }
+            let table = render(&sites);
+            eprint!("{}", table);
+            drawn_lines = table.lines().count();
+        }
+    }
+}
+
+fn on_report(sites: &mut Sites, opts: &CollectOpts, addr: SocketAddr, rec: &str) {
+    let fields = export::parse_record(rec);
+    if !fields
+        .iter()
+        .any(|(key, value)| *key == "type" && value == "report")
+    {
+        debug!("Not a report from {}: {}", addr, rec);
+        return;
+    }
+    if let Some(path) = &opts.jsonl {
+        export::append_json(path, &format!("{} probe={}", rec, addr));
+    }
+    let mut site = addr.ip().to_string();
+    let mut label = String::new();
+    let mut values = BTreeMap::new();
+    for (key, value) in fields {
+        match key {
+            "site" => site = value,
+            "label" => label = value,
+            _ => {
+                if let Ok(value) = value.parse::<f64>() {
+                    values.insert(key.to_owned(), value);
+                }
+            }
+        }
+    }
+    let probes = sites.entry(site.clone()).or_default();
+    if !probes.keys().any(|(_, probe)| *probe == addr) {
+        info!("Probe {} of site {} is reporting", addr, site);
+    }
+    let report = Report {
+        received: Instant::now(),
+        values,
+    };
+    probes.insert((label, addr), report);
+}
+
+fn drop_stale(sites: &mut Sites, stale_after: Duration) {
+    for (site, probes) in sites.iter_mut() {
+        probes.retain(|(label, addr), report| {
+            let fresh = report.received.elapsed() < stale_after;
+            if !fresh {
+                info!(
+                    "Probe {} of site {} stopped reporting {}",
+                    addr, site, label
+                );
+            }
+            fresh
+        });
+    }
+    sites.retain(|_, probes| !probes.is_empty());
+}
+
+/// The table of all sites, a line per row
+fn render(sites: &Sites) -> String {
+    let mut table = format!(
+        "{:<24} {:<16} {:>8} {:>8} {:>8} {:>7} {:>7} {:>6}\n",
+        "SITE/PROBE", "LABEL", "SAMPLES", "AVG_MS", "P99_MS", "JITT_MS", "LOSS_%", "AGE_S"
+    );
+    for (site, probes) in sites {
+        let addrs: Vec<_> = probes.keys().map(|(_, addr)| addr).collect();
+        let several = addrs.iter().any(|addr| *addr != addrs[0]);
+        writeln!(table, "{}", site).unwrap();
+        let mut totals: BTreeMap<&str, Row> = BTreeMap::new();
+        for ((label, addr), report) in probes {
+            let row = Row::of(report);
+            writeln!(
+                table,
+                "  {:<22} {}{:>6.1}",
+                addr.to_string(),
+                row.format(label),
+                report.received.elapsed().as_secs_f64()
+            )
+            .unwrap();
+            totals.entry(label).or_default().add(&row);
+        }
+        if several {
+            for (label, row) in &totals {
+                writeln!(table, "  {:<22} {}", "(all probes)", row.format(label)).unwrap();
+            }
+        }
+    }
+    table
+}
+
+impl Row {
+    fn of(report: &Report) -> Self {
+        let value = |key: &str| report.values.get(key).copied().unwrap_or_default();
+        Self {
+            samples: value("samples"),
+            weighted_avg_ms: value("avg_ms") * value("samples"),
+            p99_ms: value("p99_ms"),
+            jitter_ms: value("rfc3550_jitter_ms"),
+            expected: value("expected"),
+            received: value("received"),
+        }
+    }
+
+    fn add(&mut self, other: &Row) {
+        self.samples += other.samples;
+        self.weighted_avg_ms += other.weighted_avg_ms;
+        self.p99_ms = self.p99_ms.max(other.p99_ms);
+        self.jitter_ms = self.jitter_ms.max(other.jitter_ms);
+        self.expected += other.expected;
+        self.received += other.received;
+    }
+
+    /// The columns from the label to the loss
+    fn format(&self, label: &str) -> String {
+        let avg_ms = if self.samples > 0. {
+            self.weighted_avg_ms / self.samples
+        } else {
+            0.
+        };
+        let loss = if self.expected > 0. {
+            format!(
+                "{:.2}",
+                (self.expected - self.received) * 100. / self.expected
+            )
+        } else {
+            "-".to_owned()
+        };
+        format!(
+            "{:<16} {:>8} {:>8.2} {:>8.2} {:>7.2} {:>7} ",
+            label, self.samples, avg_ms, self.p99_ms, self.jitter_ms, loss
+        )
+    }
+}
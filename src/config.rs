@@ -0,0 +1,1038 @@
+//! Command line configuration
+
+use crate::alert::Webhook;
+use crate::auth::AuthKey;
+use crate::baseline::{Baseline, Tolerance};
+use crate::error::Error;
+use crate::histogram::PercentileMethod;
+use crate::influx::InfluxTarget;
+use crate::net::Ecn;
+use crate::p2::RunQuantiles;
+use crate::payload::Pattern;
+use crate::protocol::{Direction, Format, MAX_PKT_LEN, MIN_DATA_LEN};
+use crate::schedule::{IntervalPattern, SizeModel, VoiceActivity};
+use crate::statistic::Units;
+use crate::threshold::Threshold;
+use crate::twamp;
+use log::LevelFilter;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+use structopt::clap::ArgMatches;
+use structopt::StructOpt;
+
+#[derive(Debug, Clone, StructOpt)]
+#[structopt(about = "UDP jitter test")]
+pub struct Opts {
+    #[structopt(flatten)]
+    pub log: LogConfig,
+
+    #[structopt(subcommand)]
+    pub cmd: Command,
+}
+
+#[derive(Debug, Clone, StructOpt)]
+#[allow(clippy::large_enum_variant)]
+pub enum Command {
+    /// Streams test packets to registered clients and measures round trip time of replies
+    Serve(ServeOpts),
+    /// Registers on a server and replies to its test packets
+    Client(ClientOpts),
+    /// Reflects TWAMP-Light test packets (RFC 5357) back to their senders
+    Reflect(ReflectOpts),
+    /// Sends TWAMP-Light test packets to a reflector and measures round trip time
+    Twamp(TwampOpts),
+    /// Analyzes results of a previous run
+    Analyze(AnalyzeOpts),
+    /// Receives the statistics of probes sent with `--report-to` and shows them by site
+    Collect(CollectOpts),
+}
+
+#[derive(Debug, Clone, StructOpt)]
+pub struct ServeOpts {
+    /// Address to listen on, `host:port`. Can be given several times to serve on several ports
+    #[structopt(
+        long,
+        value_name = "ADDR",
+        default_value = "0.0.0.0:8044",
+        number_of_values = 1
+    )]
+    pub bind: Vec<String>,
+
+    /// Preset of interval, packet size, DSCP and statistics window: voip, music, game or video.
+    /// Explicitly given options override the preset
+    #[structopt(long, value_name = "PROFILE")]
+    pub profile: Option<Profile>,
+
+    /// Interval between test packets sent to each client
+    #[structopt(long, value_name = "DURATION", default_value = "20ms", parse(try_from_str = parse_interval))]
+    pub interval: Duration,
+
+    /// Varying intervals used instead of `--interval`: `20,20,40,60` repeats the intervals
+    /// in order, `random:20,40,60` picks them at random. Plain numbers are milliseconds.
+    /// Clients requesting their own interval are not affected
+    #[structopt(long, value_name = "PATTERN")]
+    pub interval_pattern: Option<IntervalPattern>,
+
+    /// Bursty voice instead of a constant stream: `<talk>,<silence>[,<comfort noise interval>]`,
+    /// e.g. `1s,1.5s,160ms`. Talk spurts and silences of random length with the given means
+    /// alternate, only comfort noise packets are sent during silence. RTT statistics are
+    /// split by the position of packets in spurts
+    #[structopt(long, value_name = "MODEL")]
+    pub voice_activity: Option<VoiceActivity>,
+
+    /// Size of test packets in bytes
+    #[structopt(long, value_name = "BYTES", default_value = "256", parse(try_from_str = parse_packet_size))]
+    pub packet_size: usize,
+
+    /// Varying packet sizes used instead of `--packet-size`, emulating VBR codecs:
+    /// `uniform:<min>-<max>`, `normal:<mean>,<std dev>` or `trace:<path>` with a size per line.
+    /// Clients requesting their own packet size are not affected
+    #[structopt(long, value_name = "MODEL")]
+    pub size_model: Option<SizeModel>,
+
+    /// Every packet also carries the frames of the given number of previous packets, like
+    /// Opus in-band FEC or RED. Losses the redundancy would have concealed are counted
+    #[structopt(long, value_name = "FRAMES", default_value = "0")]
+    pub redundancy: u8,
+
+    /// Sends trains of the given number of back-to-back packets to every client and estimates
+    /// the available bandwidth from the dispersion of their replies
+    #[structopt(long, value_name = "N", parse(try_from_str = parse_train_len))]
+    pub burst: Option<u32>,
+
+    /// Interval between packet trains of `--burst`
+    #[structopt(long, value_name = "DURATION", default_value = "5s", parse(try_from_str = parse_interval))]
+    pub burst_interval: Duration,
+
+    /// Pre-shared key authenticating every packet, `ID:SECRET` with ID 0-255. Packets
+    /// without a valid HMAC are dropped. Can be given several times to rotate keys
+    #[structopt(long, value_name = "ID:SECRET", number_of_values = 1)]
+    pub auth_key: Vec<AuthKey>,
+
+    /// Also accepts clients over QUIC on the given address, `host:port`: test packets are
+    /// carried in QUIC DATAGRAM frames. Needs the `quic` feature
+    #[structopt(long, value_name = "ADDR")]
+    pub quic: Option<String>,
+
+    /// Also accepts clients over DTLS on the given address, `host:port`: test packets are
+    /// encrypted with `--dtls-psk`, like WebRTC media. Needs the `dtls` feature
+    #[structopt(long, value_name = "ADDR", requires = "dtls-psk")]
+    pub dtls: Option<String>,
+
+    /// Pre-shared key of DTLS clients
+    #[structopt(long, value_name = "KEY")]
+    pub dtls_psk: Option<String>,
+
+    /// Periodically sends every client probe packets of increasing size with DF set and
+    /// reports the largest size delivered, e.g. to catch tunnels with a small MTU
+    #[structopt(long)]
+    pub mtu_sweep: bool,
+
+    /// DSCP value of sent packets, 46 is Expedited Forwarding used for voice
+    #[structopt(long, value_name = "DSCP", default_value = "46", parse(try_from_str = parse_dscp))]
+    pub dscp: u8,
+
+    /// ECN codepoint of sent packets: none, ect0 or ect1 (L4S). CE marks of received
+    /// ECN-capable packets are counted either way
+    #[structopt(long, value_name = "ECN", default_value = "none")]
+    pub ecn: Ecn,
+
+    /// File with `key = value` settings applied on top of the command line ones.
+    /// Is re-read on SIGHUP. Supported keys: stats-interval, percentiles, percentile-method,
+    /// window, window-time, ewma-alpha, jitter-buffers, dscp. Percentiles are not reloaded
+    /// with `--run-quantiles p2`
+    #[structopt(long, value_name = "FILE", parse(from_os_str))]
+    pub config: Option<PathBuf>,
+
+    /// Unix socket accepting commands, one per line. Supported commands:
+    /// `reset` - clears statistics to start a clean measurement;
+    /// `clients` - lists registered clients, separated by `;`: session, address, interval
+    /// and packet size;
+    /// `set <SESSION|ADDR> [interval=<DURATION>] [size=<BYTES>]` - changes the stream
+    /// of one client, e.g. `set 127.0.0.1:5000 interval=5ms size=1200`
+    #[structopt(long, value_name = "PATH", parse(from_os_str))]
+    pub admin_socket: Option<PathBuf>,
+
+    /// Maximum number of registered clients, further registrations are rejected
+    #[structopt(long, value_name = "N")]
+    pub max_clients: Option<usize>,
+
+    /// Joins, queries, pings and packets of unknown types accepted from one source IP per
+    /// second, further ones are dropped. Raise it for many `--clients` on one host, 0
+    /// disables the limit
+    #[structopt(long, value_name = "PER_SECOND", default_value = "100")]
+    pub source_rate: u32,
+
+    /// Clients silent for this time are removed, e.g. after they quit without saying goodbye
+    #[structopt(long, value_name = "DURATION", default_value = "10s", parse(try_from_str = parse_duration))]
+    pub idle_timeout: Duration,
+
+    /// Framing of test packets: native or rtp. RTP packets carry the native packet as
+    /// their payload, the packet size includes the RTP header. Clients answer RTCP sender
+    /// reports of the server with receiver reports
+    #[structopt(long, value_name = "FORMAT", default_value = "native")]
+    pub format: Format,
+
+    /// Content of test packets: zeros, incrementing, random or file:<path>
+    #[structopt(long, value_name = "PATTERN", default_value = "random")]
+    pub payload: Pattern,
+
+    /// Seed of the random packet payload. Endpoints using the same seed generate
+    /// identical payloads. A random seed is used if not set
+    #[structopt(long, value_name = "SEED")]
+    pub seed: Option<u64>,
+
+    /// Writes every reply to FILE as a CSV line: the Unix time, the client address,
+    /// the session, the sequence number and the RTT in ms, for analysis after the run
+    #[structopt(long, value_name = "FILE", parse(from_os_str))]
+    pub samples_out: Option<PathBuf>,
+
+    /// Validates the configuration, binds sockets, prints the effective settings and exits
+    #[structopt(long)]
+    pub check: bool,
+
+    /// Stops after the given time, e.g. `300s` or `5m`, and prints a summary.
+    /// Runs forever if not set
+    #[structopt(long, value_name = "DURATION", parse(try_from_str = parse_duration))]
+    pub duration: Option<Duration>,
+
+    #[structopt(flatten)]
+    pub stats: StatsConfig,
+}
+
+/// Presets mirroring common real-time workloads
+#[derive(Debug, Clone, Copy)]
+pub enum Profile {
+    /// G.711 voice call: 20ms frames
+    Voip,
+    /// High quality Opus music stream
+    Music,
+    /// Game state updates at ~60 Hz
+    Game,
+    /// Video conference, ~2 Mbit/s of MTU-sized packets
+    Video,
+}
+
+/// Thresholds of the Apdex score, see `--apdex`
+#[derive(Debug, Clone, Copy)]
+pub struct Apdex {
+    pub satisfied: Duration,
+    pub tolerating: Duration,
+}
+
+#[derive(Debug, Clone, StructOpt)]
+pub struct ClientOpts {
+    /// Server address, `host:port`
+    #[structopt(value_name = "SERVER")]
+    pub server: String,
+
+    /// Local address to send from, `host:port`. Any free port by default.
+    /// With several clients, they use consecutive ports starting from the given one
+    #[structopt(long, value_name = "ADDR")]
+    pub bind: Option<String>,
+
+    /// Number of simulated clients, each joins from its own port and replies independently
+    #[structopt(long, value_name = "N", default_value = "1", parse(try_from_str = parse_clients))]
+    pub clients: u16,
+
+    /// Number of parallel test streams of every client, each from its own port with its
+    /// own stream ID and statistics, e.g. to see flows hashed to different ECMP paths
+    #[structopt(long, value_name = "N", default_value = "1", parse(try_from_str = parse_streams))]
+    pub streams: u8,
+
+    /// DSCP value of sent replies
+    #[structopt(long, value_name = "DSCP", default_value = "46", parse(try_from_str = parse_dscp))]
+    pub dscp: u8,
+
+    /// ECN codepoint of sent packets: none, ect0 or ect1 (L4S)
+    #[structopt(long, value_name = "ECN", default_value = "none")]
+    pub ecn: Ecn,
+
+    /// Interval between test packets requested from the server, the server's one if not set
+    #[structopt(long, value_name = "DURATION", parse(try_from_str = parse_interval))]
+    pub interval: Option<Duration>,
+
+    /// Size of test packets requested from the server, the server's one if not set
+    #[structopt(long, value_name = "BYTES", parse(try_from_str = parse_packet_size))]
+    pub packet_size: Option<usize>,
+
+    /// Which way data packets go: both (the server sends them, the client replies),
+    /// download (the client only sends periodic reports) or upload (the client sends them
+    /// at `--interval` and the server measures)
+    #[structopt(long, value_name = "DIRECTION", default_value = "both")]
+    pub direction: Direction,
+
+    /// Pre-shared key authenticating every packet, `ID:SECRET` with ID 0-255, one of the
+    /// keys of the server
+    #[structopt(long, value_name = "ID:SECRET")]
+    pub auth_key: Option<AuthKey>,
+
+    /// Connects to the `--quic` address of the server and carries test packets in QUIC
+    /// DATAGRAM frames instead of plain UDP. Needs the `quic` feature
+    #[structopt(long)]
+    pub quic: bool,
+
+    /// Connects to the `--dtls` address of the server and encrypts test packets with DTLS
+    /// using the given pre-shared key. Needs the `dtls` feature
+    #[structopt(long, value_name = "KEY", conflicts_with = "quic")]
+    pub dtls: Option<String>,
+
+    /// Sends pings at the given interval instead of joining the stream of the server,
+    /// e.g. for RTT spot checks or to keep a NAT binding open. Servers answer up to their
+    /// `--source-rate` pings per second
+    #[structopt(long, value_name = "INTERVAL", parse(try_from_str = parse_interval))]
+    pub ping: Option<Duration>,
+
+    /// Stops after the given time and prints a summary. Runs until interrupted if not set
+    #[structopt(long, value_name = "DURATION", parse(try_from_str = parse_duration))]
+    pub duration: Option<Duration>,
+
+    #[structopt(flatten)]
+    pub stats: StatsConfig,
+}
+
+#[derive(Debug, Clone, StructOpt)]
+pub struct ReflectOpts {
+    /// Address to listen on, `host:port`
+    #[structopt(long, value_name = "ADDR", default_value = "0.0.0.0:862")]
+    pub bind: String,
+
+    /// DSCP value of reflected packets
+    #[structopt(long, value_name = "DSCP", default_value = "46", parse(try_from_str = parse_dscp))]
+    pub dscp: u8,
+
+    /// Stops after the given time. Runs until interrupted if not set
+    #[structopt(long, value_name = "DURATION", parse(try_from_str = parse_duration))]
+    pub duration: Option<Duration>,
+}
+
+#[derive(Debug, Clone, StructOpt)]
+pub struct TwampOpts {
+    /// Reflector address, `host:port`
+    #[structopt(value_name = "REFLECTOR")]
+    pub reflector: String,
+
+    /// Local address to send from, `host:port`. Any free port by default
+    #[structopt(long, value_name = "ADDR")]
+    pub bind: Option<String>,
+
+    /// Interval between test packets
+    #[structopt(long, value_name = "DURATION", default_value = "20ms", parse(try_from_str = parse_interval))]
+    pub interval: Duration,
+
+    /// Size of test packets in bytes, reflected packets are as long
+    #[structopt(long, value_name = "BYTES", default_value = "128", parse(try_from_str = parse_twamp_packet_size))]
+    pub packet_size: usize,
+
+    /// DSCP value of sent packets
+    #[structopt(long, value_name = "DSCP", default_value = "46", parse(try_from_str = parse_dscp))]
+    pub dscp: u8,
+
+    /// Stops after the given time and prints a summary. Runs until interrupted if not set
+    #[structopt(long, value_name = "DURATION", parse(try_from_str = parse_duration))]
+    pub duration: Option<Duration>,
+
+    #[structopt(flatten)]
+    pub stats: StatsConfig,
+}
+
+#[derive(Debug, Clone, StructOpt)]
+pub struct AnalyzeOpts {
+    /// File of raw samples written by `serve --samples-out`
+    #[structopt(value_name = "SAMPLES", parse(from_os_str))]
+    pub samples: PathBuf,
+
+    #[structopt(flatten)]
+    pub stats: StatsConfig,
+}
+
+#[derive(Debug, Clone, StructOpt)]
+pub struct CollectOpts {
+    /// Address to receive reports on, `host:port`
+    #[structopt(long, value_name = "ADDR", default_value = "0.0.0.0:8045")]
+    pub bind: String,
+
+    /// How often the table of probes is redrawn
+    #[structopt(
+        long,
+        value_name = "DURATION",
+        default_value = "2s",
+        parse(try_from_str = parse_duration)
+    )]
+    pub refresh: Duration,
+
+    /// Probes without reports for this long are dropped from the table
+    #[structopt(
+        long,
+        value_name = "DURATION",
+        default_value = "30s",
+        parse(try_from_str = parse_duration)
+    )]
+    pub stale_after: Duration,
+
+    /// Appends every received report to FILE as JSON lines, with the address of its probe
+    #[structopt(long, value_name = "FILE", parse(from_os_str))]
+    pub jsonl: Option<PathBuf>,
+
+    /// Stops after the given time. Runs until interrupted if not set
+    #[structopt(long, value_name = "DURATION", parse(try_from_str = parse_duration))]
+    pub duration: Option<Duration>,
+}
+
+impl Command {
+    /// Statistics settings of commands which collect statistics
+    pub fn stats(&self) -> Option<&StatsConfig> {
+        match self {
+            Command::Serve(opts) => Some(&opts.stats),
+            Command::Client(opts) => Some(&opts.stats),
+            Command::Twamp(opts) => Some(&opts.stats),
+            Command::Analyze(opts) => Some(&opts.stats),
+            Command::Reflect(_) | Command::Collect(_) => None,
+        }
+    }
+}
+
+// Logging settings
+#[derive(Debug, Clone, StructOpt)]
+pub struct LogConfig {
+    /// Increases logging verbosity, can be repeated: `-v` for debug, `-vv` for trace
+    #[structopt(short, global = true, parse(from_occurrences))]
+    pub verbose: u8,
+
+    /// Decreases logging verbosity, can be repeated: `-q` for warnings, `-qq` for errors only
+    #[structopt(short = "q", global = true, parse(from_occurrences))]
+    pub less_verbose: u8,
+
+    /// Sets the log level explicitly: off, error, warn, info, debug or trace.
+    /// Overrides `-v` and `-q`
+    #[structopt(long, global = true, value_name = "LEVEL")]
+    pub log_level: Option<LevelFilter>,
+}
+
+// Settings of the `statistic` module.
+// Not a doc comment: structopt would use it as the program description.
+#[derive(Debug, Clone, StructOpt)]
+pub struct StatsConfig {
+    /// How often statistics are displayed, e.g. `2s`, `500ms` or `0.25` (seconds)
+    #[structopt(
+        long = "stats-interval",
+        value_name = "DURATION",
+        default_value = "2s",
+        parse(try_from_str = parse_duration)
+    )]
+    pub display_interval: Duration,
+
+    /// Comma separated list of percentiles to display, e.g. `50,90,99,99.9`
+    #[structopt(
+        long,
+        value_name = "LIST",
+        use_delimiter = true,
+        require_delimiter = true,
+        default_value = "80,90,95,98,98.5,99,99.5,99.8,99.9",
+        parse(try_from_str = parse_percentile)
+    )]
+    pub percentiles: Vec<f64>,
+
+    /// How percentiles are picked from the samples: nearest-rank (one of the samples)
+    /// or linear (interpolated between the two closest samples)
+    #[structopt(long, value_name = "METHOD", default_value = "nearest-rank")]
+    pub percentile_method: PercentileMethod,
+
+    /// Unit of delays on display: auto (ms or µs, whichever fits), ms, us or ns.
+    /// Delays are measured in µs, digits below are not shown. Tables and exports are in ms
+    #[structopt(long, value_name = "UNIT", default_value = "auto")]
+    pub units: Units,
+
+    /// Digits after the decimal point of delays on display and in tables
+    #[structopt(long, value_name = "DIGITS", default_value = "2", parse(try_from_str = parse_precision))]
+    pub precision: usize,
+
+    /// How percentiles of the whole run are calculated: histogram, from a histogram of
+    /// all samples with buckets of at most 1/128 of their values, or p2, with the P²
+    /// streaming estimator per percentile, which is not rounded and keeps 5 values
+    #[structopt(long, value_name = "METHOD", default_value = "histogram")]
+    pub run_quantiles: RunQuantiles,
+
+    /// Number of the latest samples statistics are calculated over
+    #[structopt(long, value_name = "SAMPLES", default_value = "150", parse(try_from_str = parse_window))]
+    pub window: usize,
+
+    /// Calculates statistics over the samples of the last DURATION instead, e.g. `30s`,
+    /// whatever the packet rate. `--window` is ignored then
+    #[structopt(long, value_name = "DURATION", parse(try_from_str = parse_window_time))]
+    pub window_time: Option<Duration>,
+
+    /// Weight of a new sample in the smoothed delay and jitter, in the (0, 1] range.
+    /// Smaller values give a steadier trend
+    #[structopt(long, value_name = "ALPHA", default_value = "0.05", parse(try_from_str = parse_ewma_alpha))]
+    pub ewma_alpha: f64,
+
+    /// Comma separated depths in milliseconds of simulated playout buffers, e.g. `20,40,60`.
+    /// Packets later than the fastest one by more than the depth count as too late to play
+    #[structopt(
+        long,
+        value_name = "LIST",
+        use_delimiter = true,
+        require_delimiter = true,
+        default_value = "20,40,60",
+        parse(try_from_str = parse_jitter_buffer)
+    )]
+    pub jitter_buffers: Vec<Duration>,
+
+    /// Apdex score of the delays: T or T,F in milliseconds, e.g. `50,200`. Samples within
+    /// T satisfy, within F, 4T by default, are tolerated, slower and lost ones frustrate.
+    /// The score, from 0 to 1, is (satisfied + tolerating / 2) / packets
+    #[structopt(long, value_name = "T[,F]")]
+    pub apdex: Option<Apdex>,
+
+    /// Logs a spike event, with the client, the sequence number and the time, whenever
+    /// an RTT is longer than FACTOR times the median of the window of its client.
+    /// Events of a server have the load of the host since shortly before: CPU, network
+    /// softirqs and drops. Events are written to `--jsonl` too
+    #[structopt(long, value_name = "FACTOR", parse(try_from_str = parse_spike_factor))]
+    pub spike_factor: Option<f64>,
+
+    /// Comma separated bucket bounds in milliseconds, e.g. `1,2,5,10,20,50`. Adds
+    /// a histogram of the window to the live display, with a bar per bucket
+    #[structopt(
+        long,
+        value_name = "LIST",
+        use_delimiter = true,
+        require_delimiter = true,
+        parse(try_from_str = parse_bucket)
+    )]
+    pub histogram: Vec<Duration>,
+
+    /// Adds a line of the window samples drawn with Unicode blocks to the live display,
+    /// the taller the slower, so spikes between refreshes are visible
+    #[structopt(long)]
+    pub sparkline: bool,
+
+    /// Appends a CSV row per statistics interval and statistics, e.g. per client, to FILE:
+    /// the window and the loss of the whole run. The columns are the same whatever
+    /// the other options, see the header
+    #[structopt(long, value_name = "FILE", parse(from_os_str))]
+    pub csv: Option<PathBuf>,
+
+    /// Appends the statistics of every interval and the summary to FILE as JSON lines,
+    /// with the keys of `--quiet` records, and events: joins and leaves of clients
+    /// and bursts of lost packets
+    #[structopt(long, value_name = "FILE", parse(from_os_str))]
+    pub jsonl: Option<PathBuf>,
+
+    /// Stores the run, its clients and the statistics of every interval in the SQLite
+    /// database FILE, created if needed, to keep a history of runs. Needs the `sqlite`
+    /// feature
+    #[structopt(long, value_name = "FILE", parse(from_os_str))]
+    pub sqlite: Option<PathBuf>,
+
+    /// Sends the window statistics of every interval to a `collect` instance at ADDR,
+    /// `host:port`, a UDP datagram per statistics of the live display
+    #[structopt(long, value_name = "ADDR")]
+    pub report_to: Option<String>,
+
+    /// Site of the probe in reports of `--report-to`, e.g. the branch office it is in.
+    /// The collector groups probes without a site by their IP address
+    #[structopt(long, value_name = "NAME")]
+    pub site: Option<String>,
+
+    /// Serves Prometheus metrics on http://ADDR/metrics, ADDR is `host:port`: the window
+    /// statistics of the live display, updated every interval, and all samples as
+    /// a histogram. A server adds its clients and how late its send loop is
+    #[structopt(long, value_name = "ADDR")]
+    pub metrics: Option<String>,
+
+    /// Streams the window statistics of every interval and events of clients, e.g.
+    /// spikes, to WebSocket subscribers of ws://ADDR/, ADDR is `host:port`. Messages are
+    /// the records of `--jsonl`. With the `dashboard` feature, http://ADDR/ is a page
+    /// charting them live
+    #[structopt(long, value_name = "ADDR")]
+    pub websocket: Option<String>,
+
+    /// Pushes the window statistics of every interval to a StatsD agent at ADDR,
+    /// `host:port`, as gauges with DogStatsD tags. Unlike `--report-to`, every client
+    /// of a server is pushed too, tagged with its address and session
+    #[structopt(long, value_name = "ADDR")]
+    pub statsd: Option<String>,
+
+    /// Prefix of the names of `--statsd` metrics
+    #[structopt(long, value_name = "PREFIX", default_value = "udp_jitter_test")]
+    pub statsd_prefix: String,
+
+    /// Tag of `--statsd` metrics, `key:value` or a bare value, e.g. `site:branch-1`.
+    /// Can be given several times
+    #[structopt(long, value_name = "TAG", number_of_values = 1)]
+    pub statsd_tag: Vec<String>,
+
+    /// Writes the window statistics of every interval in InfluxDB line protocol to
+    /// TARGET: an `http://` write URL, e.g.
+    /// `http://localhost:8086/api/v2/write?org=ORG&bucket=BUCKET`, or a file the points
+    /// are appended to. Points are tagged with the label, the run ID and, for a client
+    /// of a server, its address and session
+    #[structopt(long, value_name = "TARGET")]
+    pub influx: Option<InfluxTarget>,
+
+    /// API token of `--influx`, sent as `Authorization: Token TOKEN`
+    #[structopt(long, value_name = "TOKEN")]
+    pub influx_token: Option<String>,
+
+    /// Measurement of `--influx` points
+    #[structopt(long, value_name = "NAME", default_value = "udp_jitter_test")]
+    pub influx_measurement: String,
+
+    /// Run ID tag of `--influx` points, random by default
+    #[structopt(long, value_name = "ID")]
+    pub run_id: Option<String>,
+
+    /// Exports the window statistics of every interval to an OpenTelemetry collector,
+    /// POSTed to its OTLP/HTTP metrics URL, e.g. `http://localhost:4318/v1/metrics`.
+    /// Needs the `otlp` feature
+    #[structopt(long, value_name = "URL")]
+    pub otlp: Option<Webhook>,
+
+    /// Appends the histogram of every statistics interval to FILE, an HdrHistogram
+    /// interval log tagged with the labels of the statistics
+    #[structopt(long, value_name = "FILE", parse(from_os_str))]
+    pub hlog: Option<PathBuf>,
+
+    /// Writes a heatmap of the delays over the whole run to FILE at the end: a line per
+    /// `--heatmap-interval` and a column per delay range. A PNG image if FILE ends with
+    /// `.png`, with the `png` feature
+    #[structopt(long, value_name = "FILE", parse(from_os_str))]
+    pub heatmap: Option<PathBuf>,
+
+    /// Time slot of a line of the heatmap
+    #[structopt(
+        long,
+        value_name = "DURATION",
+        default_value = "1s",
+        parse(try_from_str = parse_heatmap_interval)
+    )]
+    pub heatmap_interval: Duration,
+
+    /// Replaces the live display with machine-readable `key=value` records on stdout
+    #[structopt(long)]
+    pub quiet: bool,
+
+    /// Replaces the live display with a row per statistics interval on stdout: the loss,
+    /// the average, the 99th percentile and the jitter of the interval. Handy for a log file
+    #[structopt(long, conflicts_with = "quiet")]
+    pub rows: bool,
+
+    /// Comma separated thresholds checked at the end of the run, e.g. `p99>40ms,loss>1%`.
+    /// The exit code is 2 if any holds. Metrics: pNN, avg, max and jitter in ms, loss and
+    /// reordered in %, mos, apdex. Operators: >, >=, <, <=
+    #[structopt(
+        long,
+        value_name = "LIST",
+        use_delimiter = true,
+        require_delimiter = true
+    )]
+    pub fail_if: Vec<Threshold>,
+
+    /// Comma separated thresholds checked every statistics interval against the window,
+    /// e.g. `p99>40ms,loss>1%`, like `--fail-if`. A breach runs `--alert-cmd` and
+    /// `--alert-webhook`. A server checks the statistics of all clients, not of each
+    #[structopt(
+        long,
+        value_name = "LIST",
+        use_delimiter = true,
+        require_delimiter = true
+    )]
+    pub alert_if: Vec<Threshold>,
+
+    /// Shell command run on a breach of `--alert-if`, with the violations
+    /// in `ALERT_VIOLATIONS` and the number of breaches not alerted in `ALERT_SUPPRESSED`
+    #[structopt(long, value_name = "COMMAND")]
+    pub alert_cmd: Option<String>,
+
+    /// `http://` URL the JSON of the alert is POSTed to on a breach of `--alert-if`
+    #[structopt(long, value_name = "URL")]
+    pub alert_webhook: Option<Webhook>,
+
+    /// Minimum time between two alerts, breaches in between are only counted
+    #[structopt(
+        long,
+        value_name = "DURATION",
+        default_value = "5m",
+        parse(try_from_str = parse_duration)
+    )]
+    pub alert_interval: Duration,
+
+    /// Saves the statistics of the run to FILE as a baseline for `--baseline`
+    #[structopt(long, value_name = "FILE", parse(from_os_str))]
+    pub save_baseline: Option<PathBuf>,
+
+    /// Compares the statistics of the run to a baseline saved with `--save-baseline`
+    #[structopt(long, value_name = "FILE", parse(try_from_str = Baseline::load))]
+    pub baseline: Option<Baseline>,
+
+    /// Comma separated tolerances of `--baseline`, e.g. `p99+20%,avg+2ms,loss+0.5,mos-0.3`:
+    /// how much a metric can grow, or drop with `-`, over its baseline value. Metrics
+    /// in ms take a tolerance in ms or in % of the baseline value, loss and reordered in
+    /// percentage points. The exit code is 2 if any is exceeded, as with `--fail-if`
+    #[structopt(
+        long,
+        value_name = "LIST",
+        use_delimiter = true,
+        require_delimiter = true
+    )]
+    pub regression_if: Vec<Tolerance>,
+}
+
+impl Opts {
+    /// Parses the command line, `--profile` presets are applied to options which are not
+    /// given explicitly
+    pub fn from_args_with_profile() -> Self {
+        let matches = Self::clap().get_matches();
+        let mut opts = Self::from_clap(&matches);
+
+        if let Command::Serve(serve) = &mut opts.cmd {
+            if let Some(serve_matches) = matches.subcommand_matches("serve") {
+                serve.apply_profile(serve_matches);
+            }
+        }
+
+        opts
+    }
+}
+
+impl ServeOpts {
+    fn apply_profile(&mut self, matches: &ArgMatches) {
+        let profile = some_or_ret!(self.profile);
+        let (interval_ms, packet_size, dscp, window) = match profile {
+            Profile::Voip => (20, 172, 46, 250),
+            Profile::Music => (20, 400, 26, 250),
+            Profile::Game => (16, 100, 32, 300),
+            Profile::Video => (5, 1200, 34, 1000),
+        };
+
+        let is_default = |name| matches.occurrences_of(name) == 0;
+        if is_default("interval") {
+            self.interval = Duration::from_millis(interval_ms);
+        }
+        if is_default("packet-size") {
+            self.packet_size = packet_size;
+        }
+        if is_default("dscp") {
+            self.dscp = dscp;
+        }
+        if is_default("window") {
+            self.stats.window = window;
+        }
+    }
+
+    /// Applies settings from the `--config` file, if any, on top of the current ones
+    pub fn apply_config_file(&mut self) -> Result<(), Error> {
+        let path = some_or_ret!(self.config.clone(), Ok(()));
+        let content = fs::read_to_string(&path)
+            .map_err(|e| Error::new(format!("Cannot read {}: {}", path.display(), e)))?;
+
+        for (n, line) in content.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or_default().trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let err_ctx = |e: Error| Error::new(format!("{}:{}: {}", path.display(), n + 1, e));
+            let mut key_value = line.splitn(2, '=');
+            let key = key_value.next().unwrap_or_default().trim();
+            let value = key_value
+                .next()
+                .ok_or_else(|| err_ctx(Error::new("Expected `key = value`")))?
+                .trim();
+            self.apply_setting(key, value).map_err(err_ctx)?;
+        }
+
+        Ok(())
+    }
+
+    fn apply_setting(&mut self, key: &str, value: &str) -> Result<(), Error> {
+        match key {
+            "stats-interval" => self.stats.display_interval = parse_duration(value)?,
+            "percentiles" => {
+                self.stats.percentiles = value
+                    .split(',')
+                    .map(parse_percentile)
+                    .collect::<Result<_, _>>()?
+            }
+            "window" => self.stats.window = parse_window(value)?,
+            "jitter-buffers" => {
+                self.stats.jitter_buffers = value
+                    .split(',')
+                    .map(parse_jitter_buffer)
+                    .collect::<Result<_, _>>()?
+            }
+            "percentile-method" => self.stats.percentile_method = value.parse()?,
+            "ewma-alpha" => self.stats.ewma_alpha = parse_ewma_alpha(value)?,
+            "window-time" => self.stats.window_time = Some(parse_window_time(value)?),
+            "dscp" => self.dscp = parse_dscp(value)?,
+            _ => return Err(Error::new(format!("Unknown setting: {}", key))),
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Profile {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "voip" => Ok(Profile::Voip),
+            "music" => Ok(Profile::Music),
+            "game" => Ok(Profile::Game),
+            "video" => Ok(Profile::Video),
+            _ => Err(Error::new(format!(
+                "Unknown profile: {}. Expected voip, music, game or video",
+                s
+            ))),
+        }
+    }
+}
+
+impl FromStr for Apdex {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |ms: &str| match parse_positive_ms(ms) {
+            Some(d) => Ok(d),
+            None => Err(Error::new(format!(
+                "Apdex threshold must be a positive number of milliseconds: {}",
+                ms
+            ))),
+        };
+        let (satisfied, tolerating) = match s.split_once(',') {
+            Some((t, f)) => (parse(t)?, parse(f)?),
+            None => (parse(s)?, parse(s)?.saturating_mul(4)),
+        };
+        if tolerating < satisfied {
+            return Err(Error::new(format!(
+                "Apdex tolerating threshold is below the satisfied one: {}",
+                s
+            )));
+        }
+        Ok(Self {
+            satisfied,
+            tolerating,
+        })
+    }
+}
+
+impl LogConfig {
+    pub fn level(&self) -> LevelFilter {
+        if let Some(level) = self.log_level {
+            return level;
+        }
+
+        const LEVELS: [LevelFilter; 6] = [
+            LevelFilter::Off,
+            LevelFilter::Error,
+            LevelFilter::Warn,
+            LevelFilter::Info,
+            LevelFilter::Debug,
+            LevelFilter::Trace,
+        ];
+        let idx = 3 + self.verbose as isize - self.less_verbose as isize;
+        LEVELS[idx.clamp(0, LEVELS.len() as isize - 1) as usize]
+    }
+}
+
+/// Parses a duration given as a number with an optional `ms`, `s`, `m` or `h` suffix.
+/// A number without a suffix is treated as seconds. Fractional values are allowed.
+pub fn parse_duration(s: &str) -> Result<Duration, Error> {
+    let s = s.trim();
+    let (num, mult) = if let Some(n) = s.strip_suffix("ms") {
+        (n, 0.001)
+    } else if let Some(n) = s.strip_suffix('s') {
+        (n, 1.)
+    } else if let Some(n) = s.strip_suffix('m') {
+        (n, 60.)
+    } else if let Some(n) = s.strip_suffix('h') {
+        (n, 3600.)
+    } else {
+        (s, 1.)
+    };
+
+    let secs = num
+        .trim()
+        .parse::<f64>()
+        .map_err(|_| Error::new(format!("Invalid duration: {}", s)))?
+        * mult;
+    // Negative, non-finite and too long durations
+    Duration::try_from_secs_f64(secs).map_err(|_| Error::new(format!("Invalid duration: {}", s)))
+}
+
+/// Parses a percentile given in percents, e.g. `99.9`, and returns it as a fraction: `0.999`
+pub fn parse_percentile(s: &str) -> Result<f64, Error> {
+    let p = s
+        .trim()
+        .parse::<f64>()
+        .map_err(|_| Error::new(format!("Invalid percentile: {}", s)))?;
+    if !(p > 0. && p <= 100.) {
+        return Err(Error::new(format!(
+            "Percentile must be in the (0, 100] range: {}",
+            s
+        )));
+    }
+
+    Ok(p / 100.)
+}
+
+fn parse_window(s: &str) -> Result<usize, Error> {
+    match s.trim().parse::<usize>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(Error::new(format!(
+            "Window must be a positive number of samples: {}",
+            s
+        ))),
+    }
+}
+
+fn parse_window_time(s: &str) -> Result<Duration, Error> {
+    let window = parse_duration(s)?;
+    if window.is_zero() {
+        return Err(Error::new(format!("Window must be longer than 0: {}", s)));
+    }
+    Ok(window)
+}
+
+fn parse_heatmap_interval(s: &str) -> Result<Duration, Error> {
+    let interval = parse_duration(s)?;
+    if interval.is_zero() {
+        return Err(Error::new(format!(
+            "Heatmap interval must be longer than 0: {}",
+            s
+        )));
+    }
+    Ok(interval)
+}
+
+/// A positive number of milliseconds, `None` if it isn't one or is too long
+fn parse_positive_ms(s: &str) -> Option<Duration> {
+    let ms = s.trim().parse::<f64>().ok().filter(|ms| *ms > 0.)?;
+    Duration::try_from_secs_f64(ms / 1000.).ok()
+}
+
+fn parse_jitter_buffer(s: &str) -> Result<Duration, Error> {
+    match parse_positive_ms(s) {
+        Some(d) => Ok(d),
+        None => Err(Error::new(format!(
+            "Jitter buffer depth must be a positive number of milliseconds: {}",
+            s
+        ))),
+    }
+}
+
+fn parse_bucket(s: &str) -> Result<Duration, Error> {
+    match parse_positive_ms(s) {
+        Some(d) => Ok(d),
+        None => Err(Error::new(format!(
+            "Histogram bucket bound must be a positive number of milliseconds: {}",
+            s
+        ))),
+    }
+}
+
+fn parse_precision(s: &str) -> Result<usize, Error> {
+    match s.trim().parse::<usize>() {
+        Ok(digits) if digits <= 6 => Ok(digits),
+        _ => Err(Error::new(format!(
+            "Precision must be 0 to 6 digits: {}",
+            s
+        ))),
+    }
+}
+
+fn parse_ewma_alpha(s: &str) -> Result<f64, Error> {
+    match s.trim().parse::<f64>() {
+        Ok(alpha) if alpha > 0. && alpha <= 1. => Ok(alpha),
+        _ => Err(Error::new(format!(
+            "EWMA alpha must be in the (0, 1] range: {}",
+            s
+        ))),
+    }
+}
+
+fn parse_spike_factor(s: &str) -> Result<f64, Error> {
+    match s.trim().parse::<f64>() {
+        Ok(factor) if factor > 1. && factor.is_finite() => Ok(factor),
+        _ => Err(Error::new(format!(
+            "Spike factor must be bigger than 1: {}",
+            s
+        ))),
+    }
+}
+
+fn parse_dscp(s: &str) -> Result<u8, Error> {
+    match s.trim().parse::<u8>() {
+        Ok(n) if n < 64 => Ok(n),
+        _ => Err(Error::new(format!(
+            "DSCP must be in the [0, 63] range: {}",
+            s
+        ))),
+    }
+}
+
+fn parse_interval(s: &str) -> Result<Duration, Error> {
+    let interval = parse_duration(s)?;
+    if interval.as_micros() == 0 {
+        return Err(Error::new(format!("Interval is too small: {}", s)));
+    }
+    Ok(interval)
+}
+
+fn parse_train_len(s: &str) -> Result<u32, Error> {
+    match s.trim().parse::<u32>() {
+        Ok(n) if (2..=1000).contains(&n) => Ok(n),
+        _ => Err(Error::new(format!(
+            "Train length must be in the [2, 1000] range: {}",
+            s
+        ))),
+    }
+}
+
+fn parse_packet_size(s: &str) -> Result<usize, Error> {
+    match s.trim().parse::<usize>() {
+        Ok(n) if (MIN_DATA_LEN..=MAX_PKT_LEN).contains(&n) => Ok(n),
+        _ => Err(Error::new(format!(
+            "Packet size must be in the [{}, {}] range: {}",
+            MIN_DATA_LEN, MAX_PKT_LEN, s
+        ))),
+    }
+}
+
+fn parse_twamp_packet_size(s: &str) -> Result<usize, Error> {
+    match s.trim().parse::<usize>() {
+        Ok(n) if (twamp::REFLECTOR_LEN..=MAX_PKT_LEN).contains(&n) => Ok(n),
+        _ => Err(Error::new(format!(
+            "Packet size must be in the [{}, {}] range: {}",
+            twamp::REFLECTOR_LEN,
+            MAX_PKT_LEN,
+            s
+        ))),
+    }
+}
+
+fn parse_streams(s: &str) -> Result<u8, Error> {
+    match s.trim().parse::<u8>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(Error::new(format!(
+            "Number of streams must be in the [1, {}] range: {}",
+            u8::MAX,
+            s
+        ))),
+    }
+}
+
+fn parse_clients(s: &str) -> Result<u16, Error> {
+    match s.trim().parse::<u16>() {
+        Ok(n) if n > 0 => Ok(n),
+        _ => Err(Error::new(format!(
+            "Number of clients must be in the [1, {}] range: {}",
+            u16::MAX,
+            s
+        ))),
+    }
+}
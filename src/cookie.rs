@@ -0,0 +1,76 @@
+//! Stateless registration cookies
+//!
+//! A join packet without a valid cookie is answered with a challenge carrying a cookie,
+//! bound to the address of the sender. Only a client which receives the challenge on
+//! that address can echo the cookie back, so a spoofed join can't make the server stream
+//! traffic to somebody else. The server keeps no state until the cookie is echoed.
+
+use crate::protocol::COOKIE_LEN;
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::Sha256;
+use std::convert::TryInto;
+use std::net::SocketAddr;
+use std::time::{Duration, Instant};
+
+const COOKIE_LIFETIME: Duration = Duration::from_secs(30);
+const TIME_LEN: usize = 4;
+
+pub type Cookie = [u8; COOKIE_LEN];
+
+/// Issues and verifies cookies: creation time followed by a truncated
+/// HMAC of the time and the client address
+pub struct Cookies {
+    secret: [u8; 32],
+    start: Instant,
+}
+
+impl Cookies {
+    pub fn new() -> Self {
+        let mut secret = [0; 32];
+        rand::thread_rng().fill_bytes(&mut secret);
+        Self {
+            secret,
+            start: Instant::now(),
+        }
+    }
+
+    pub fn make(&self, addr: &SocketAddr) -> Cookie {
+        let time = self.start.elapsed().as_secs() as u32;
+        self.make_at(addr, time)
+    }
+
+    pub fn verify(&self, addr: &SocketAddr, cookie: &Cookie) -> bool {
+        let time = u32::from_be_bytes(cookie[..TIME_LEN].try_into().unwrap());
+        let age = Duration::from_secs(u64::from(
+            (self.start.elapsed().as_secs() as u32).wrapping_sub(time),
+        ));
+        if age > COOKIE_LIFETIME {
+            return false;
+        }
+
+        // The comparison must take constant time: not to leak how many bytes match
+        let expected = self.make_at(addr, time);
+        expected
+            .iter()
+            .zip(cookie.iter())
+            .fold(0, |acc, (a, b)| acc | (a ^ b))
+            == 0
+    }
+
+    fn make_at(&self, addr: &SocketAddr, time: u32) -> Cookie {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret).unwrap();
+        mac.update(&time.to_be_bytes());
+        match addr {
+            SocketAddr::V4(a) => mac.update(&a.ip().octets()),
+            SocketAddr::V6(a) => mac.update(&a.ip().octets()),
+        }
+        mac.update(&addr.port().to_be_bytes());
+        let tag = mac.finalize().into_bytes();
+
+        let mut cookie = [0; COOKIE_LEN];
+        cookie[..TIME_LEN].copy_from_slice(&time.to_be_bytes());
+        cookie[TIME_LEN..].copy_from_slice(&tag[..COOKIE_LEN - TIME_LEN]);
+        cookie
+    }
+}
@@ -0,0 +1,330 @@
+//! DTLS-encrypted test traffic, built with the `dtls` feature
+//!
+//! Test packets are sent as DTLS 1.2 application data, keyed with a pre-shared key, so
+//! the probe traffic looks like encrypted media to DPI boxes. Like with QUIC, both sides
+//! relay the packets to and from the usual UDP sockets over loopback.
+//!
+//! OpenSSL works on in-memory datagrams: `Session` moves them between its buffers and
+//! the socket, so one server socket serves every client.
+
+use crate::error::Error;
+use crate::net::{any_addr, loopback_addr};
+use async_std::channel::{self, Receiver, Sender};
+use async_std::future::timeout;
+use async_std::net::UdpSocket;
+use async_std::task;
+use futures::future::{select, Either};
+use futures::pin_mut;
+use log::{debug, info, warn};
+use openssl::error::ErrorStack;
+use openssl::ssl::{ErrorCode, Ssl, SslContext, SslMethod, SslOptions, SslStream, SslVersion};
+use std::collections::{HashMap, VecDeque};
+use std::io::{self, Read, Write};
+use std::mem;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+const IDENTITY: &[u8] = b"udp-jitter-test";
+/// The longest key OpenSSL accepts
+const MAX_PSK_LEN: usize = 256;
+const CIPHERS: &str = "PSK-AES128-GCM-SHA256:PSK-AES256-GCM-SHA384:PSK-CHACHA20-POLY1305";
+/// Records carry whole test packets, the path MTU is up to the network
+const MTU: u32 = 16384;
+/// How often the handshake is checked for lost flights, OpenSSL resends them by its timer
+const HANDSHAKE_TICK: Duration = Duration::from_millis(100);
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Sessions are closed after this time without packets of the peer
+const IDLE_TIMEOUT: Duration = Duration::from_secs(30);
+const BUF_LEN: usize = 65535;
+
+/// Accepts DTLS clients on `bind` and relays their packets to the UDP `server`
+pub async fn serve(bind: SocketAddr, server: SocketAddr, psk: &str) -> Result<(), Error> {
+    let ctx = context(psk, false)?;
+    let socket = Arc::new(UdpSocket::bind(bind).await?);
+    info!("Accepting DTLS clients on {}", bind);
+
+    let mut peers: HashMap<SocketAddr, Sender<Vec<u8>>> = HashMap::new();
+    let mut buf = vec![0; BUF_LEN];
+    loop {
+        let (len, addr) = socket.recv_from(&mut buf).await?;
+        let datagram = buf[..len].to_vec();
+        let datagram = match peers.get(&addr) {
+            Some(tx) => match tx.try_send(datagram) {
+                Ok(()) => continue,
+                // The session is closed, a new one is started
+                Err(e) => e.into_inner(),
+            },
+            None => datagram,
+        };
+
+        peers.retain(|_, tx| !tx.is_closed());
+        let (tx, rx) = channel::unbounded();
+        tx.try_send(datagram).expect("A new channel is open");
+        peers.insert(addr, tx);
+
+        let mut ssl = Ssl::new(&ctx).map_err(tls_error)?;
+        ssl.set_accept_state();
+        let relay = UdpSocket::bind(loopback_addr(server.ip())).await?;
+        info!(
+            "DTLS client {} is relayed from {}",
+            addr,
+            relay.local_addr()?
+        );
+        let session = Session::new(ssl, socket.clone(), addr, relay, Some(server))?;
+        task::spawn(async move {
+            if let Err(e) = session.run(rx).await {
+                info!("DTLS session of {} is closed: {}", addr, e);
+            }
+        });
+    }
+}
+
+/// Connects to the DTLS `server` and returns the local address relaying packets to it
+pub async fn connect(server: SocketAddr, psk: &str) -> Result<SocketAddr, Error> {
+    let ctx = context(psk, true)?;
+    let socket = Arc::new(UdpSocket::bind(any_addr(server.ip())).await?);
+    let relay = UdpSocket::bind(loopback_addr(server.ip())).await?;
+    let addr = relay.local_addr()?;
+
+    let (tx, rx) = channel::unbounded();
+    let receiver = socket.clone();
+    task::spawn(async move {
+        if let Err(e) = receive_from(&receiver, server, tx).await {
+            warn!("Cannot receive from {}: {}", server, e);
+        }
+    });
+
+    let mut ssl = Ssl::new(&ctx).map_err(tls_error)?;
+    ssl.set_connect_state();
+    // The client's address is known from its first packet
+    let mut session = Session::new(ssl, socket, server, relay, None)?;
+    session.handshake(&rx).await?;
+    info!("Connected to {} over DTLS, relaying from {}", server, addr);
+
+    task::spawn(async move {
+        if let Err(e) = session.run(rx).await {
+            warn!("DTLS session with {} is closed: {}", server, e);
+        }
+    });
+    Ok(addr)
+}
+
+/// Passes datagrams of `server` to `tx` until the session is closed
+async fn receive_from(
+    socket: &UdpSocket,
+    server: SocketAddr,
+    tx: Sender<Vec<u8>>,
+) -> Result<(), Error> {
+    let mut buf = vec![0; BUF_LEN];
+    loop {
+        let (len, addr) = socket.recv_from(&mut buf).await?;
+        if addr != server {
+            debug!("Unexpected datagram from {}", addr);
+            continue;
+        }
+        if tx.send(buf[..len].to_vec()).await.is_err() {
+            return Ok(());
+        }
+    }
+}
+
+/// Datagrams between OpenSSL and the socket
+#[derive(Default)]
+struct Datagrams {
+    incoming: VecDeque<Vec<u8>>,
+    outgoing: Vec<Vec<u8>>,
+}
+
+impl Read for Datagrams {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let datagram = self.incoming.pop_front().ok_or(io::ErrorKind::WouldBlock)?;
+        let len = datagram.len().min(buf.len());
+        buf[..len].copy_from_slice(&datagram[..len]);
+        Ok(len)
+    }
+}
+
+impl Write for Datagrams {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.outgoing.push(buf.to_vec());
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A DTLS session with `peer`, relaying its packets to `relay_peer` and back.
+/// `relay_peer` becomes the sender of the latest packet received by `relay`
+struct Session {
+    stream: SslStream<Datagrams>,
+    socket: Arc<UdpSocket>,
+    peer: SocketAddr,
+    relay: UdpSocket,
+    relay_peer: Option<SocketAddr>,
+}
+
+/// What a session waits for
+enum Event {
+    Datagram(Vec<u8>),
+    Packet(usize, SocketAddr),
+    Closed,
+}
+
+impl Session {
+    fn new(
+        mut ssl: Ssl,
+        socket: Arc<UdpSocket>,
+        peer: SocketAddr,
+        relay: UdpSocket,
+        relay_peer: Option<SocketAddr>,
+    ) -> Result<Self, Error> {
+        ssl.set_mtu(MTU).map_err(tls_error)?;
+        let stream = SslStream::new(ssl, Datagrams::default()).map_err(tls_error)?;
+        Ok(Self {
+            stream,
+            socket,
+            peer,
+            relay,
+            relay_peer,
+        })
+    }
+
+    async fn handshake(&mut self, rx: &Receiver<Vec<u8>>) -> Result<(), Error> {
+        let handshake = async {
+            loop {
+                let res = self.stream.do_handshake();
+                self.flush().await?;
+                match res {
+                    Ok(()) => return Ok(()),
+                    Err(e) if e.code() == ErrorCode::WANT_READ => (),
+                    Err(e) => {
+                        return Err(Error::new(format!(
+                            "DTLS handshake with {} failed: {}",
+                            self.peer, e
+                        )))
+                    }
+                }
+
+                match timeout(HANDSHAKE_TICK, rx.recv()).await {
+                    Ok(Ok(datagram)) => self.stream.get_mut().incoming.push_back(datagram),
+                    Ok(Err(_)) => return Err(Error::new("The socket is closed")),
+                    Err(_) => (),
+                }
+            }
+        };
+        timeout(HANDSHAKE_TIMEOUT, handshake).await.map_err(|_| {
+            Error::new(format!(
+                "DTLS handshake with {} timed out, a wrong key looks the same",
+                self.peer
+            ))
+        })?
+    }
+
+    async fn run(mut self, rx: Receiver<Vec<u8>>) -> Result<(), Error> {
+        if !self.stream.ssl().is_init_finished() {
+            self.handshake(&rx).await?;
+        }
+
+        let mut buf = vec![0; BUF_LEN];
+        loop {
+            let event = {
+                let datagram = rx.recv();
+                let packet = self.relay.recv_from(&mut buf);
+                pin_mut!(datagram, packet);
+                match timeout(IDLE_TIMEOUT, select(datagram, packet)).await {
+                    Err(_) => return Err(Error::new("Idle timeout")),
+                    Ok(Either::Left((Ok(datagram), _))) => Event::Datagram(datagram),
+                    Ok(Either::Left((Err(_), _))) => Event::Closed,
+                    Ok(Either::Right((res, _))) => {
+                        let (len, addr) = res?;
+                        Event::Packet(len, addr)
+                    }
+                }
+            };
+
+            match event {
+                Event::Datagram(datagram) => {
+                    self.stream.get_mut().incoming.push_back(datagram);
+                    if !self.on_datagram(&mut buf).await? {
+                        return Ok(());
+                    }
+                }
+                Event::Packet(len, addr) => {
+                    self.relay_peer = Some(addr);
+                    if let Err(e) = self.stream.ssl_write(&buf[..len]) {
+                        debug!("Cannot encrypt a packet of {} bytes: {}", len, e);
+                    }
+                }
+                Event::Closed => return Ok(()),
+            }
+            self.flush().await?;
+        }
+    }
+
+    /// Relays the packets decrypted from received datagrams, returns false if the peer
+    /// closed the session
+    async fn on_datagram(&mut self, buf: &mut [u8]) -> Result<bool, Error> {
+        loop {
+            match self.stream.ssl_read(buf) {
+                Ok(len) => match self.relay_peer {
+                    Some(relay_peer) => {
+                        self.relay.send_to(&buf[..len], relay_peer).await?;
+                    }
+                    None => debug!("Packet from {} before any packet", self.peer),
+                },
+                Err(e) if e.code() == ErrorCode::WANT_READ => return Ok(true),
+                Err(e) if e.code() == ErrorCode::ZERO_RETURN => return Ok(false),
+                Err(e) => return Err(Error::new(format!("DTLS: {}", e))),
+            }
+        }
+    }
+
+    /// Sends datagrams written by OpenSSL
+    async fn flush(&mut self) -> Result<(), Error> {
+        let outgoing = mem::take(&mut self.stream.get_mut().outgoing);
+        for datagram in &outgoing {
+            self.socket.send_to(datagram, self.peer).await?;
+        }
+        Ok(())
+    }
+}
+
+fn context(psk: &str, client: bool) -> Result<SslContext, Error> {
+    let key = psk.as_bytes().to_vec();
+    if key.is_empty() || key.len() > MAX_PSK_LEN {
+        return Err(Error::new(format!(
+            "The DTLS key must be 1 to {} bytes long",
+            MAX_PSK_LEN
+        )));
+    }
+
+    let mut builder = SslContext::builder(SslMethod::dtls()).map_err(tls_error)?;
+    builder
+        .set_min_proto_version(Some(SslVersion::DTLS1_2))
+        .map_err(tls_error)?;
+    builder.set_cipher_list(CIPHERS).map_err(tls_error)?;
+    // The MTU is set explicitly, there is no socket under OpenSSL to query
+    builder.set_options(SslOptions::NO_QUERY_MTU);
+    if client {
+        builder.set_psk_client_callback(move |_, _hint, identity, out| {
+            // The identity is NUL-terminated
+            identity[..IDENTITY.len()].copy_from_slice(IDENTITY);
+            identity[IDENTITY.len()] = 0;
+            out[..key.len()].copy_from_slice(&key);
+            Ok(key.len())
+        });
+    } else {
+        builder.set_psk_server_callback(move |_, _identity, out| {
+            out[..key.len()].copy_from_slice(&key);
+            Ok(key.len())
+        });
+    }
+    Ok(builder.build())
+}
+
+fn tls_error(e: ErrorStack) -> Error {
+    Error::new(format!("TLS: {}", e))
+}
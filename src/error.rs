@@ -1,5 +1,4 @@
 use crate::merge_futures::WrongLayoutError;
-use rand;
 use std::borrow::Cow;
 use std::fmt;
 use std::io;
@@ -17,6 +16,22 @@ impl Error {
             repr: Box::new(ErrorRepr::Str(s.into())),
         }
     }
+
+    /// The run worked, but its results violate `--fail-if` thresholds
+    pub fn failed(violations: Vec<String>) -> Self {
+        Self {
+            repr: Box::new(ErrorRepr::Failed(violations)),
+        }
+    }
+
+    /// Exit code of a run ending with the error: CI can tell bad results from failures
+    /// to test
+    pub fn exit_code(&self) -> i32 {
+        match *self.repr {
+            ErrorRepr::Failed(_) => 2,
+            _ => 1,
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -27,6 +42,7 @@ pub enum ErrorRepr {
     SystemTime(SystemTimeError),
     Rand(rand::Error),
     WrongLayoutError(WrongLayoutError),
+    Failed(Vec<String>),
 }
 
 impl std::error::Error for Error {}
@@ -39,6 +55,9 @@ impl fmt::Display for Error {
             ErrorRepr::SystemTime(e) => fmt::Display::fmt(e, f),
             ErrorRepr::Rand(e) => fmt::Display::fmt(e, f),
             ErrorRepr::WrongLayoutError(e) => fmt::Display::fmt(e, f),
+            ErrorRepr::Failed(violations) => {
+                write!(f, "Thresholds are violated: {}", violations.join("; "))
+            }
         }
     }
 }
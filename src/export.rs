@@ -0,0 +1,292 @@
+//! Files statistics are exported to: `--csv`, `--jsonl` and `--hlog`
+//!
+//! All statistics of a run append to the same file, so files are kept open here by path.
+//! A file is created on the first line, and a line is written out at once: the rows of
+//! a long run can be followed with `tail -f`. A file which can't be written is skipped
+//! with a warning, the test goes on.
+//!
+//! JSON lines are the `key=value` records of `--quiet` as objects, and events, e.g. joins
+//! of clients: `{"type":"event","time":...,"event":"join",...}`.
+
+use log::warn;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{LineWriter, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Percentiles of `IntervalRow`, the same whatever `--percentiles`
+pub const ROW_PERCENTILES: [f64; 5] = [0.5, 0.9, 0.95, 0.99, 0.999];
+/// Keys of `ROW_PERCENTILES` in records
+const ROW_PERCENTILE_KEYS: [&str; ROW_PERCENTILES.len()] =
+    ["p50_ms", "p90_ms", "p95_ms", "p99_ms", "p99.9_ms"];
+pub const CSV_HEADER: &str = "time_s,label,samples,avg_ms,min_ms,max_ms,stddev_ms,\
+    rfc3550_jitter_ms,p50_ms,p90_ms,p95_ms,p99_ms,p99.9_ms,expected,received,\
+    loss_pct,window_loss_pct,reordered_pct";
+
+/// Window statistics at the end of an interval, a row of `--csv` and `--sqlite`
+#[derive(Default)]
+pub struct IntervalRow {
+    /// Since the Unix epoch
+    pub time: f64,
+    pub label: String,
+    pub samples: usize,
+    pub avg_ms: f64,
+    pub min_ms: f64,
+    pub max_ms: f64,
+    pub stddev_ms: f64,
+    /// RFC 3550 jitter
+    pub jitter_ms: f64,
+    /// At `ROW_PERCENTILES`
+    pub percentiles_ms: [f64; ROW_PERCENTILES.len()],
+    /// `None` if the loss is unknown
+    pub loss: Option<RowLoss>,
+}
+
+/// Loss of the whole run and of the window
+pub struct RowLoss {
+    pub expected: u64,
+    pub received: u64,
+    pub loss_pct: f64,
+    pub window_loss_pct: Option<f64>,
+    pub reordered_pct: f64,
+}
+
+/// `None` if the file failed
+static FILES: Mutex<BTreeMap<PathBuf, Option<LineWriter<File>>>> = Mutex::new(BTreeMap::new());
+
+/// Appends `line` to the file at `path`, a new file starts with `header`
+pub fn append(path: &Path, header: Option<&str>, line: &str) {
+    let mut files = FILES.lock().unwrap_or_else(|e| e.into_inner());
+    let file = files.entry(path.to_owned()).or_insert_with(|| {
+        let file = File::create(path).and_then(|file| {
+            let mut file = LineWriter::new(file);
+            if let Some(header) = header {
+                writeln!(file, "{}", header)?;
+            }
+            Ok(file)
+        });
+        match file {
+            Ok(file) => Some(file),
+            Err(e) => {
+                warn!("Cannot create {}: {}", path.display(), e);
+                None
+            }
+        }
+    });
+    if let Some(out) = file {
+        if let Err(e) = writeln!(out, "{}", line) {
+            warn!("Cannot write {}: {}", path.display(), e);
+            *file = None;
+        }
+    }
+}
+
+/// Appends the `key=value` record as a JSON object to the file at `path`
+pub fn append_json(path: &Path, rec: &str) {
+    append(path, None, &record_to_json(rec));
+}
+
+/// The record of an event happening now with `fields`, `key=value` pairs
+pub fn event_record(event: &str, fields: &str) -> String {
+    let time = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default();
+    event_record_at(time, event, fields)
+}
+
+/// Appends an event which happened at `time`, since the Unix epoch, to the JSON lines
+/// at `path`
+pub fn event_at(path: &Path, time: Duration, event: &str, fields: &str) {
+    append_json(path, &event_record_at(time, event, fields));
+}
+
+fn event_record_at(time: Duration, event: &str, fields: &str) -> String {
+    let rec = format!(
+        "type=event time={:.3} event={} {}",
+        time.as_secs_f64(),
+        event,
+        fields
+    );
+    rec.trim_end().to_owned()
+}
+
+/// `key=value` pairs separated by spaces as a JSON object. Values quoted like `{:?}`
+/// are strings, other values are numbers if they parse as ones
+pub fn record_to_json(rec: &str) -> String {
+    let mut json = String::from("{");
+    for (key, value, quoted) in record_fields(rec) {
+        if json.len() > 1 {
+            json.push(',');
+        }
+        json.push_str(&json_string(key));
+        json.push(':');
+        if !quoted && is_json_number(&value) {
+            json.push_str(&value);
+        } else {
+            json.push_str(&json_string(&value));
+        }
+    }
+    json.push('}');
+    json
+}
+
+/// The `key=value` pairs of a record, values unquoted
+pub fn parse_record(rec: &str) -> Vec<(&str, String)> {
+    record_fields(rec)
+        .into_iter()
+        .map(|(key, value, _)| (key, value))
+        .collect()
+}
+
+/// Keys, values and whether the value was quoted
+fn record_fields(rec: &str) -> Vec<(&str, String, bool)> {
+    let mut fields = Vec::new();
+    let mut rest = rec.trim_start();
+    while let Some(eq) = rest.find('=') {
+        let key = &rest[..eq];
+        rest = &rest[eq + 1..];
+        if let Some(quoted) = rest.strip_prefix('"') {
+            let (value, len) = unquote(quoted);
+            rest = &quoted[len..];
+            fields.push((key, value, true));
+        } else {
+            let end = rest.find(' ').unwrap_or(rest.len());
+            fields.push((key, rest[..end].to_owned(), false));
+            rest = &rest[end..];
+        }
+        rest = rest.trim_start();
+    }
+    fields
+}
+
+fn is_json_number(s: &str) -> bool {
+    let digits = s.strip_prefix('-').unwrap_or(s);
+    digits.starts_with(|c: char| c.is_ascii_digit())
+        && digits.ends_with(|c: char| c.is_ascii_digit())
+        && !(digits.len() > 1 && digits.starts_with('0') && !digits[1..].starts_with('.'))
+        && s.parse::<f64>().is_ok_and(f64::is_finite)
+}
+
+/// The string quoted with `{:?}` which `s` starts with, after the opening quote,
+/// and the length of `s` up to the closing quote included
+fn unquote(s: &str) -> (String, usize) {
+    let mut value = String::new();
+    let mut chars = s.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return (value, i + 1),
+            '\\' => match chars.next() {
+                Some((_, 'n')) => value.push('\n'),
+                Some((_, 't')) => value.push('\t'),
+                Some((_, c)) => value.push(c),
+                None => break,
+            },
+            c => value.push(c),
+        }
+    }
+    (value, s.len())
+}
+
+/// `s` as a JSON string, quoted and escaped
+pub fn json_string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+impl IntervalRow {
+    /// A row with the columns of `CSV_HEADER`
+    pub fn to_csv(&self) -> String {
+        let mut row = format!(
+            "{:.3},{},{}",
+            self.time,
+            csv_field(&self.label),
+            self.samples
+        );
+        // The delays of an interval without samples are unknown, not zero
+        if self.samples > 0 {
+            write!(
+                row,
+                ",{:.3},{:.3},{:.3},{:.3},{:.3}",
+                self.avg_ms, self.min_ms, self.max_ms, self.stddev_ms, self.jitter_ms
+            )
+            .unwrap();
+            for ms in &self.percentiles_ms {
+                write!(row, ",{:.3}", ms).unwrap();
+            }
+        } else {
+            row.push_str(&",".repeat(5 + ROW_PERCENTILES.len()));
+        }
+        match &self.loss {
+            Some(loss) => {
+                write!(
+                    row,
+                    ",{},{},{:.3},",
+                    loss.expected, loss.received, loss.loss_pct
+                )
+                .unwrap();
+                if let Some(window_loss) = loss.window_loss_pct {
+                    write!(row, "{:.3}", window_loss).unwrap();
+                }
+                write!(row, ",{:.3}", loss.reordered_pct).unwrap();
+            }
+            None => row.push_str(",,,,,"),
+        }
+        row
+    }
+}
+
+impl IntervalRow {
+    /// A `key=value` record of the given type with the columns of `CSV_HEADER`
+    pub fn to_record(&self, rec_type: &str) -> String {
+        let mut rec = format!(
+            "type={} time={:.3} label={:?} samples={}",
+            rec_type, self.time, self.label, self.samples
+        );
+        // Exported as metrics, the delays of an interval without samples would be zeros
+        if self.samples > 0 {
+            write!(
+                rec,
+                " avg_ms={:.3} min_ms={:.3} max_ms={:.3} stddev_ms={:.3} rfc3550_jitter_ms={:.3}",
+                self.avg_ms, self.min_ms, self.max_ms, self.stddev_ms, self.jitter_ms
+            )
+            .unwrap();
+            for (key, ms) in ROW_PERCENTILE_KEYS.iter().zip(&self.percentiles_ms) {
+                write!(rec, " {}={:.3}", key, ms).unwrap();
+            }
+        }
+        if let Some(loss) = &self.loss {
+            write!(
+                rec,
+                " expected={} received={} loss_pct={:.3} reordered_pct={:.3}",
+                loss.expected, loss.received, loss.loss_pct, loss.reordered_pct
+            )
+            .unwrap();
+            if let Some(window_loss) = loss.window_loss_pct {
+                write!(rec, " window_loss_pct={:.3}", window_loss).unwrap();
+            }
+        }
+        rec
+    }
+}
+
+/// `s` quoted if it has a comma or a quote
+fn csv_field(s: &str) -> String {
+    if s.contains(',') || s.contains('"') {
+        format!("\"{}\"", s.replace('"', "\"\""))
+    } else {
+        s.to_owned()
+    }
+}
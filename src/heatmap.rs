@@ -0,0 +1,172 @@
+//! Heatmap of delays over the whole run, see `--heatmap`
+//!
+//! Samples are counted by the time slot they arrived in, `--heatmap-interval` long, and
+//! by a delay bin. Bins are half an octave wide from `FIRST_BOUND`, so the heatmap covers
+//! sub-millisecond LAN delays and seconds of a stalled Wi-Fi alike. A periodic spike
+//! shows as a column of dark slots every period.
+//!
+//! The text heatmap has a line per time slot and a character per bin, darker with more
+//! samples. With the `png` feature a `.png` file gets an image instead: time goes right,
+//! delay goes up.
+
+use crate::error::Error;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// Upper bound of the first bin
+const FIRST_BOUND: Duration = Duration::from_micros(100);
+/// The last bin counts everything above `FIRST_BOUND * 2^((BINS - 2) / 2)`, about 400ms
+const BINS: usize = 26;
+/// From no samples to the most samples of a cell
+const SHADES: &[u8] = b" .:-=+*#%@";
+
+pub struct Heatmap {
+    path: PathBuf,
+    interval: Duration,
+    /// Counts of the bins by time slot
+    slots: Vec<[u64; BINS]>,
+}
+
+impl Heatmap {
+    pub fn new(path: PathBuf, interval: Duration) -> Self {
+        Self {
+            path,
+            interval,
+            slots: Vec::new(),
+        }
+    }
+
+    /// Counts a sample of `dur` which arrived `at` after the start
+    pub fn add(&mut self, at: Duration, dur: Duration) {
+        let slot = (at.as_secs_f64() / self.interval.as_secs_f64()) as usize;
+        if slot >= self.slots.len() {
+            self.slots.resize(slot + 1, [0; BINS]);
+        }
+        self.slots[slot][bin(dur)] += 1;
+    }
+
+    /// Writes the heatmap to its file, a PNG image if the name ends with `.png`
+    pub fn write(&self) -> Result<(), Error> {
+        let is_png = self
+            .path
+            .extension()
+            .is_some_and(|ext| ext.eq_ignore_ascii_case("png"));
+        if is_png {
+            return self.write_png();
+        }
+        fs::write(&self.path, self.to_text())
+            .map_err(|e| Error::new(format!("Cannot write {}: {}", self.path.display(), e)))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn to_text(&self) -> String {
+        let most = self.most();
+        let mut text = format!("{:>10} |", "TIME_S");
+        for i in (0..BINS).step_by(4) {
+            write!(text, "{:<4}", format_ms(lower_bound(i))).unwrap();
+        }
+        text.push_str("| SAMPLES  (columns start at ms)\n");
+
+        for (slot, counts) in self.slots.iter().enumerate() {
+            let start = self.interval.as_secs_f64() * slot as f64;
+            write!(text, "{:>10.1} |", start).unwrap();
+            for count in counts {
+                text.push(char::from(SHADES[shade(*count, most, SHADES.len())]));
+            }
+            writeln!(text, "{:>2}| {}", "", counts.iter().sum::<u64>()).unwrap();
+        }
+        text
+    }
+
+    /// The count of the fullest cell, at least 1
+    fn most(&self) -> u64 {
+        self.slots
+            .iter()
+            .flat_map(|counts| counts.iter().copied())
+            .max()
+            .unwrap_or_default()
+            .max(1)
+    }
+
+    #[cfg(feature = "png")]
+    fn write_png(&self) -> Result<(), Error> {
+        /// Pixels of a cell
+        const CELL_WIDTH: usize = 2;
+        const CELL_HEIGHT: usize = 8;
+
+        let width = self.slots.len().max(1) * CELL_WIDTH;
+        let height = BINS * CELL_HEIGHT;
+        let most = self.most();
+        let mut pixels = vec![255u8; width * height];
+        for (slot, counts) in self.slots.iter().enumerate() {
+            for (bin, count) in counts.iter().enumerate() {
+                // White without samples, black for the fullest cell
+                let level = 255 - shade(*count, most, 256) as u8;
+                let top = (BINS - 1 - bin) * CELL_HEIGHT;
+                for y in top..top + CELL_HEIGHT {
+                    let row = y * width + slot * CELL_WIDTH;
+                    pixels[row..row + CELL_WIDTH]
+                        .iter_mut()
+                        .for_each(|p| *p = level);
+                }
+            }
+        }
+
+        let err = |e: &dyn std::fmt::Display| {
+            Error::new(format!("Cannot write {}: {}", self.path.display(), e))
+        };
+        let file = fs::File::create(&self.path).map_err(|e| err(&e))?;
+        let mut encoder =
+            png::Encoder::new(std::io::BufWriter::new(file), width as u32, height as u32);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header().map_err(|e| err(&e))?;
+        writer.write_image_data(&pixels).map_err(|e| err(&e))
+    }
+
+    #[cfg(not(feature = "png"))]
+    fn write_png(&self) -> Result<(), Error> {
+        Err(Error::new(
+            "PNG heatmaps need a build with the `png` feature",
+        ))
+    }
+}
+
+fn bin(dur: Duration) -> usize {
+    if dur < FIRST_BOUND {
+        return 0;
+    }
+    let halves = 2. * (dur.as_secs_f64() / FIRST_BOUND.as_secs_f64()).log2();
+    (halves as usize + 1).min(BINS - 1)
+}
+
+fn lower_bound(bin: usize) -> Duration {
+    match bin {
+        0 => Duration::ZERO,
+        _ => FIRST_BOUND.mul_f64(2f64.powf((bin - 1) as f64 / 2.)),
+    }
+}
+
+fn format_ms(d: Duration) -> String {
+    let ms = d.as_secs_f64() * 1000.;
+    if ms < 1. && ms > 0. {
+        format!("{:.1}", ms)
+    } else {
+        format!("{:.0}", ms)
+    }
+}
+
+/// Shade of `count` out of `levels`, on a log scale so sparse outliers stay visible.
+/// Any samples give at least the first shade after the empty one
+fn shade(count: u64, most: u64, levels: usize) -> usize {
+    if count == 0 {
+        return 0;
+    }
+    let fraction = (count as f64).ln_1p() / (most as f64).ln_1p();
+    ((fraction * (levels - 1) as f64).round() as usize).clamp(1, levels - 1)
+}
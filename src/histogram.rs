@@ -0,0 +1,282 @@
+//! Log-linear histogram of durations, in the style of HDR histograms
+//!
+//! Durations are counted in microsecond buckets up to `2^SUB_BITS` µs. Above that, every
+//! power of two range is split into `2^(SUB_BITS - 1)` buckets of equal width, so a bucket
+//! is at most 1/128 of its values wide. Adding and removing a sample is O(1) and
+//! a quantile is found by walking the buckets, whatever the number of samples.
+
+use crate::error::Error;
+use std::convert::TryFrom;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Bits of precision: values are exact below `2^SUB_BITS` µs, relative error is below
+/// `2^(1 - SUB_BITS)` above
+const SUB_BITS: u32 = 8;
+const SUB_COUNT: u64 = 1 << SUB_BITS;
+const HALF_COUNT: u64 = SUB_COUNT / 2;
+
+/// How a quantile is picked from the sorted samples
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PercentileMethod {
+    /// The smallest sample with at least the fraction `q` of samples not above it:
+    /// always one of the samples
+    NearestRank,
+    /// Interpolated between the two closest samples, the samples are at the quantiles
+    /// `i / (len - 1)`
+    Linear,
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct Histogram {
+    counts: Vec<u64>,
+    total: u64,
+}
+
+impl Histogram {
+    pub fn add(&mut self, d: Duration) {
+        let idx = bucket(micros(d));
+        if idx >= self.counts.len() {
+            self.counts.resize(idx + 1, 0);
+        }
+        self.counts[idx] += 1;
+        self.total += 1;
+    }
+
+    /// Removes a sample added before
+    pub fn remove(&mut self, d: Duration) {
+        if let Some(count) = self.counts.get_mut(bucket(micros(d))) {
+            if *count > 0 {
+                *count -= 1;
+                self.total -= 1;
+            }
+        }
+    }
+
+    /// Adds the samples of another histogram
+    pub fn merge(&mut self, other: &Self) {
+        if other.counts.len() > self.counts.len() {
+            self.counts.resize(other.counts.len(), 0);
+        }
+        for (count, other) in self.counts.iter_mut().zip(&other.counts) {
+            *count += other;
+        }
+        self.total += other.total;
+    }
+
+    pub fn clear(&mut self) {
+        self.counts.clear();
+        self.total = 0;
+    }
+
+    /// The quantile `q` in the [0, 1] range of the samples. Samples are rounded up to
+    /// the end of their buckets. `None` if there are no samples
+    pub fn quantile(&self, q: f64, method: PercentileMethod) -> Option<Duration> {
+        if self.total == 0 {
+            return None;
+        }
+        let q = q.clamp(0., 1.);
+        let micros = match method {
+            PercentileMethod::NearestRank => {
+                let rank = ((self.total as f64 * q).ceil() as u64).clamp(1, self.total);
+                self.value_at(rank - 1)?
+            }
+            PercentileMethod::Linear => {
+                let pos = (self.total - 1) as f64 * q;
+                let lower = pos.floor() as u64;
+                let (low, high) = (self.value_at(lower)?, self.value_at(pos.ceil() as u64)?);
+                low + ((high - low) as f64 * (pos - lower as f64)).round() as u64
+            }
+        };
+        Some(Duration::from_micros(micros))
+    }
+
+    /// The sample at `index` of the sorted samples
+    fn value_at(&self, index: u64) -> Option<u64> {
+        let mut seen = 0;
+        for (idx, count) in self.counts.iter().enumerate() {
+            seen += count;
+            if seen > index {
+                return Some(highest_value(idx));
+            }
+        }
+        None
+    }
+
+    /// Sample counts by bucket, in the bucket layout of an HdrHistogram of microseconds
+    /// with 2 significant digits
+    pub fn counts(&self) -> &[u64] {
+        &self.counts
+    }
+
+    /// The biggest value of the bucket at `idx`, in microseconds
+    pub fn bucket_end(idx: usize) -> u64 {
+        highest_value(idx)
+    }
+
+    /// The smallest sample, rounded down to the start of its bucket
+    pub fn min(&self) -> Option<Duration> {
+        let idx = self.counts.iter().position(|count| *count > 0)?;
+        Some(Duration::from_micros(lowest_value(idx)))
+    }
+}
+
+fn micros(d: Duration) -> u64 {
+    u64::try_from(d.as_micros()).unwrap_or(u64::MAX)
+}
+
+fn bucket(value: u64) -> usize {
+    if value < SUB_COUNT {
+        return value as usize;
+    }
+    let shift = 64 - value.leading_zeros() - SUB_BITS;
+    let top = value >> shift;
+    (SUB_COUNT + u64::from(shift - 1) * HALF_COUNT + top - HALF_COUNT) as usize
+}
+
+fn lowest_value(idx: usize) -> u64 {
+    let idx = idx as u64;
+    if idx < SUB_COUNT {
+        return idx;
+    }
+    let shift = (idx - SUB_COUNT) / HALF_COUNT + 1;
+    let top = (idx - SUB_COUNT) % HALF_COUNT + HALF_COUNT;
+    top << shift
+}
+
+fn highest_value(idx: usize) -> u64 {
+    let idx = idx as u64;
+    if idx < SUB_COUNT {
+        return idx;
+    }
+    let shift = (idx - SUB_COUNT) / HALF_COUNT + 1;
+    lowest_value(idx as usize).saturating_add((1 << shift) - 1)
+}
+
+impl FromStr for PercentileMethod {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "nearest-rank" => Ok(PercentileMethod::NearestRank),
+            "linear" => Ok(PercentileMethod::Linear),
+            _ => Err(Error::new(format!(
+                "Unknown percentile method: {}, expected nearest-rank or linear",
+                s
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn histogram(micros: &[u64]) -> Histogram {
+        let mut h = Histogram::default();
+        for m in micros {
+            h.add(Duration::from_micros(*m));
+        }
+        h
+    }
+
+    fn quantile(h: &Histogram, q: f64, method: PercentileMethod) -> u64 {
+        h.quantile(q, method).unwrap().as_micros() as u64
+    }
+
+    #[test]
+    fn empty() {
+        let h = Histogram::default();
+        assert_eq!(h.quantile(0.5, PercentileMethod::NearestRank), None);
+        assert_eq!(h.quantile(0.5, PercentileMethod::Linear), None);
+        assert_eq!(h.min(), None);
+    }
+
+    #[test]
+    fn single_sample() {
+        let h = histogram(&[7]);
+        for q in [0., 0.5, 0.99, 1.] {
+            assert_eq!(quantile(&h, q, PercentileMethod::NearestRank), 7);
+            assert_eq!(quantile(&h, q, PercentileMethod::Linear), 7);
+        }
+    }
+
+    #[test]
+    fn nearest_rank() {
+        let h = histogram(&[40, 10, 30, 20]);
+        let q = |q| quantile(&h, q, PercentileMethod::NearestRank);
+        assert_eq!(q(0.), 10);
+        assert_eq!(q(0.25), 10);
+        assert_eq!(q(0.26), 20);
+        assert_eq!(q(0.5), 20);
+        assert_eq!(q(0.75), 30);
+        // The tail of a small window is its biggest sample, not one before it
+        assert_eq!(q(0.9), 40);
+        assert_eq!(q(0.99), 40);
+        assert_eq!(q(1.), 40);
+    }
+
+    #[test]
+    fn nearest_rank_of_two() {
+        let h = histogram(&[1, 2]);
+        assert_eq!(quantile(&h, 0.5, PercentileMethod::NearestRank), 1);
+        assert_eq!(quantile(&h, 0.51, PercentileMethod::NearestRank), 2);
+    }
+
+    #[test]
+    fn linear() {
+        let h = histogram(&[10, 20, 30, 40, 50]);
+        let q = |q| quantile(&h, q, PercentileMethod::Linear);
+        assert_eq!(q(0.), 10);
+        assert_eq!(q(0.1), 14);
+        assert_eq!(q(0.5), 30);
+        assert_eq!(q(0.9), 46);
+        assert_eq!(q(1.), 50);
+    }
+
+    #[test]
+    fn duplicates() {
+        let h = histogram(&[5, 5, 5, 100]);
+        assert_eq!(quantile(&h, 0.75, PercentileMethod::NearestRank), 5);
+        assert_eq!(quantile(&h, 0.8, PercentileMethod::NearestRank), 100);
+        assert_eq!(quantile(&h, 0.5, PercentileMethod::Linear), 5);
+    }
+
+    #[test]
+    fn remove() {
+        let mut h = histogram(&[1, 2, 3]);
+        h.remove(Duration::from_micros(3));
+        assert_eq!(quantile(&h, 1., PercentileMethod::NearestRank), 2);
+        // Samples which were never added are ignored
+        h.remove(Duration::from_micros(200));
+        assert_eq!(quantile(&h, 1., PercentileMethod::NearestRank), 2);
+        h.clear();
+        assert_eq!(h.quantile(1., PercentileMethod::NearestRank), None);
+    }
+
+    #[test]
+    fn relative_error() {
+        for micros in [255, 256, 1_000, 12_345, 987_654, 60_000_000] {
+            let h = histogram(&[micros]);
+            let value = quantile(&h, 0.5, PercentileMethod::NearestRank);
+            let min = h.min().unwrap().as_micros() as u64;
+            assert!(
+                min <= micros && micros <= value,
+                "{}: {}..{}",
+                micros,
+                min,
+                value
+            );
+            assert!((value - min) as f64 <= micros as f64 / 128., "{}", micros);
+        }
+    }
+
+    #[test]
+    fn buckets_are_contiguous() {
+        for idx in 1..5000 {
+            assert_eq!(lowest_value(idx), highest_value(idx - 1) + 1, "{}", idx);
+            assert_eq!(bucket(lowest_value(idx)), idx);
+            assert_eq!(bucket(highest_value(idx)), idx);
+        }
+    }
+}
@@ -0,0 +1,119 @@
+//! HdrHistogram interval logs of `--hlog`, for HistogramLogAnalyzer, hdr-plot and the like
+//!
+//! A line per statistics interval and statistics, tagged with the label, holds
+//! the histogram of the interval in the compressed V2 encoding of HdrHistogram, base64.
+//! `Histogram` has the bucket layout of an HdrHistogram of microseconds with 2 significant
+//! digits, so its counts are encoded as they are. Maximums are in milliseconds.
+
+use crate::histogram::Histogram;
+use base64::Engine;
+use std::time::Duration;
+
+const ENCODING_COOKIE: u32 = 0x1c84_9303;
+const COMPRESSED_ENCODING_COOKIE: u32 = 0x1c84_9304;
+/// Settings of the equivalent HdrHistogram
+const SIGNIFICANT_DIGITS: u32 = 2;
+const LOWEST_VALUE: u64 = 1;
+/// An hour in microseconds, more if a histogram has bigger values
+const HIGHEST_VALUE: u64 = 3_600_000_000;
+
+/// The header of a new log starting at `start`, since the Unix epoch. Timestamps of lines
+/// are seconds since the Unix epoch too
+pub fn header(start: Duration) -> String {
+    format!(
+        "#[Histogram log format version 1.3]\n\
+         #[StartTime: {:.3} (seconds since epoch)]\n\
+         #[BaseTime: 0.000 (seconds since epoch)]\n\
+         \"StartTimestamp\",\"Interval_Length\",\"Interval_Max\",\"Interval_Compressed_Histogram\"",
+        start.as_secs_f64()
+    )
+}
+
+/// A line of the interval from `start` to `end`, `start` is since the Unix epoch
+pub fn line(
+    tag: &str,
+    start: Duration,
+    end: Duration,
+    max: Duration,
+    histogram: &Histogram,
+) -> String {
+    // Tags end at a comma and can't have spaces
+    let tag: String = tag
+        .chars()
+        .map(|c| {
+            if c == ',' || c.is_whitespace() {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+    format!(
+        "Tag={},{:.3},{:.3},{:.3},{}",
+        tag,
+        start.as_secs_f64(),
+        end.saturating_sub(start).as_secs_f64(),
+        max.as_secs_f64() * 1000.,
+        base64::engine::general_purpose::STANDARD.encode(compressed(histogram.counts()))
+    )
+}
+
+fn compressed(counts: &[u64]) -> Vec<u8> {
+    let encoded = encoded(counts);
+    let deflated = miniz_oxide::deflate::compress_to_vec_zlib(&encoded, 6);
+    let mut out = Vec::with_capacity(8 + deflated.len());
+    out.extend_from_slice(&COMPRESSED_ENCODING_COOKIE.to_be_bytes());
+    out.extend_from_slice(&(deflated.len() as u32).to_be_bytes());
+    out.extend_from_slice(&deflated);
+    out
+}
+
+/// The V2 encoding: a header and the counts as ZigZag LEB128 numbers, a run of empty
+/// buckets is a negative number
+fn encoded(counts: &[u64]) -> Vec<u8> {
+    let len = counts
+        .iter()
+        .rposition(|count| *count > 0)
+        .map_or(0, |i| i + 1);
+    let mut payload = Vec::new();
+    let mut zeros = 0i64;
+    for count in &counts[..len] {
+        if *count == 0 {
+            zeros += 1;
+            continue;
+        }
+        if zeros > 0 {
+            put_zigzag(&mut payload, -zeros);
+            zeros = 0;
+        }
+        put_zigzag(&mut payload, *count as i64);
+    }
+
+    let highest = HIGHEST_VALUE.max(Histogram::bucket_end(len.saturating_sub(1)));
+    let mut out = Vec::with_capacity(40 + payload.len());
+    out.extend_from_slice(&ENCODING_COOKIE.to_be_bytes());
+    out.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+    // Normalizing index offset
+    out.extend_from_slice(&0u32.to_be_bytes());
+    out.extend_from_slice(&SIGNIFICANT_DIGITS.to_be_bytes());
+    out.extend_from_slice(&LOWEST_VALUE.to_be_bytes());
+    out.extend_from_slice(&highest.to_be_bytes());
+    // Integer to double value conversion ratio
+    out.extend_from_slice(&1f64.to_be_bytes());
+    out.extend_from_slice(&payload);
+    out
+}
+
+/// ZigZag LEB128 of HdrHistogram: up to 9 bytes, the last one has 8 bits
+fn put_zigzag(out: &mut Vec<u8>, value: i64) {
+    let mut value = ((value << 1) ^ (value >> 63)) as u64;
+    for _ in 0..8 {
+        if value < 0x80 {
+            out.push(value as u8);
+            return;
+        }
+        out.push((value & 0x7f) as u8 | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
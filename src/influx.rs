@@ -0,0 +1,114 @@
+//! Points of `--influx`: the window statistics of every interval in InfluxDB line
+//! protocol, e.g.
+//! `udp_jitter_test,label=RTT,run=5f3a9c1e samples=150,avg_ms=12.345,... 1700000000000000000`
+//!
+//! The fields are the keys of `--quiet` records, the tags the label of the statistics,
+//! the run ID and, for a client of a server, its address and session. Points are POSTed
+//! to a write URL in the background, one request per statistics and interval, or
+//! appended to a file. A point which can't be written is lost, the test goes on.
+
+use crate::alert::Webhook;
+use crate::config::StatsConfig;
+use crate::error::Error;
+use crate::export::{self, IntervalRow};
+use async_std::task;
+use log::warn;
+use std::fmt::Write as _;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+/// Time to write a point
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Where points are written
+#[derive(Debug, Clone, PartialEq)]
+pub enum InfluxTarget {
+    /// The write endpoint, `/write` of InfluxDB 1 or `/api/v2/write` with the database
+    /// or the bucket in the query
+    Http(Webhook),
+    File(PathBuf),
+}
+
+/// Writes the statistics of `row` to `cfg.influx`, tagged with `tags` besides the label
+/// and the run ID
+pub fn send(cfg: &StatsConfig, tags: &[(&str, String)], row: &IntervalRow) {
+    let target = some_or_ret!(&cfg.influx);
+    let point = point(cfg, tags, row);
+    match target {
+        InfluxTarget::File(path) => export::append(path, None, &point),
+        InfluxTarget::Http(url) => {
+            let url = url.clone();
+            let authorization = cfg.influx_token.as_ref().map(|t| format!("Token {}", t));
+            task::spawn(async move {
+                let write = url.post("text/plain", authorization.as_deref(), &point);
+                match async_std::future::timeout(WRITE_TIMEOUT, write).await {
+                    Ok(Ok(())) => {}
+                    Ok(Err(e)) => warn!("Cannot write to {}: {}", url, e),
+                    Err(_) => warn!("Cannot write to {}: timed out", url),
+                }
+            });
+        }
+    }
+}
+
+/// The line of `row`, timestamped in nanoseconds
+fn point(cfg: &StatsConfig, tags: &[(&str, String)], row: &IntervalRow) -> String {
+    let run_id = cfg.run_id.as_deref().unwrap_or_else(|| random_run_id());
+    let mut point = format!(
+        "{},label={},run={}",
+        escape(&cfg.influx_measurement, ", "),
+        escape(&row.label, ",= "),
+        escape(run_id, ",= ")
+    );
+    for (key, value) in tags {
+        write!(point, ",{}={}", key, escape(value, ",= ")).unwrap();
+    }
+    let rec = row.to_record("influx");
+    let mut sep = ' ';
+    for (key, value) in export::parse_record(&rec) {
+        if key == "type" || key == "time" || key == "label" {
+            continue;
+        }
+        write!(point, "{}{}={}", sep, escape(key, ",= "), value).unwrap();
+        sep = ',';
+    }
+    write!(point, " {:.0}", row.time * 1e9).unwrap();
+    point
+}
+
+/// The run ID of points without `--run-id`, the same for the whole run
+fn random_run_id() -> &'static str {
+    static RUN_ID: OnceLock<String> = OnceLock::new();
+    RUN_ID.get_or_init(|| format!("{:08x}", rand::random::<u32>()))
+}
+
+/// `s` with the characters of `special` escaped with a backslash
+fn escape(s: &str, special: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        if special.contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+impl FromStr for InfluxTarget {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.starts_with("http://") {
+            Ok(InfluxTarget::Http(s.parse()?))
+        } else if s.contains("://") {
+            Err(Error::new(format!(
+                "Expected an http:// URL or a file, got: {}",
+                s
+            )))
+        } else {
+            Ok(InfluxTarget::File(s.into()))
+        }
+    }
+}
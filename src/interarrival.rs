@@ -0,0 +1,94 @@
+//! Interarrival times of data packets of the server, measured by clients
+//!
+//! The server sends data packets on a schedule, so times between arrivals are its pacing
+//! plus the variation the path to the client adds: queues which build up and drain on the
+//! way stretch some gaps and squeeze the following ones. Unlike the RTT, nothing of the
+//! return path is in them. Only gaps between consecutive sequence numbers are counted, a
+//! lost packet would double a gap, and gaps of back-to-back packet trains are left out.
+
+use crate::config::StatsConfig;
+use crate::export;
+use crate::histogram::Histogram;
+use crate::statistic::{self, as_millis_f64};
+use std::cmp;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+#[derive(Debug, Default)]
+pub struct Interarrival {
+    count: u64,
+    sum: Duration,
+    /// Of gaps in ms, for the standard deviation
+    sum_squares: f64,
+    min: Option<Duration>,
+    max: Duration,
+    histogram: Histogram,
+}
+
+impl Interarrival {
+    pub fn add(&mut self, gap: Duration) {
+        self.count += 1;
+        self.sum += gap;
+        self.sum_squares += as_millis_f64(gap).powi(2);
+        self.min = Some(self.min.map_or(gap, |min| cmp::min(min, gap)));
+        self.max = cmp::max(self.max, gap);
+        self.histogram.add(gap);
+    }
+
+    /// Prints the distribution of gaps, or its record with `cfg.quiet`, and writes
+    /// the record to `cfg.jsonl`. Nothing without gaps
+    pub fn print_summary(&self, cfg: &StatsConfig, label: &str) {
+        if self.count == 0 {
+            return;
+        }
+        // Buckets are rounded up, the maximum is exact
+        let quantile = |q| {
+            let d = self.histogram.quantile(q, cfg.percentile_method);
+            as_millis_f64(cmp::min(d.unwrap_or_default(), self.max))
+        };
+        let avg_ms = as_millis_f64(self.sum) / self.count as f64;
+        let stddev_ms = (self.sum_squares / self.count as f64 - avg_ms.powi(2))
+            .max(0.)
+            .sqrt();
+        let gaps = [
+            as_millis_f64(self.min.unwrap_or_default()),
+            quantile(0.01),
+            quantile(0.5),
+            quantile(0.99),
+            quantile(0.999),
+            as_millis_f64(self.max),
+        ];
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let rec = format!(
+            "type=interarrival time={:.3} label={:?} count={} avg_ms={:.3} stddev_ms={:.3} \
+             min_ms={:.3} p1_ms={:.3} p50_ms={:.3} p99_ms={:.3} p99.9_ms={:.3} max_ms={:.3}",
+            time.as_secs_f64(),
+            label,
+            self.count,
+            avg_ms,
+            stddev_ms,
+            gaps[0],
+            gaps[1],
+            gaps[2],
+            gaps[3],
+            gaps[4],
+            gaps[5]
+        );
+        if let Some(path) = &cfg.jsonl {
+            export::append_json(path, &rec);
+        }
+        if cfg.quiet {
+            println!("{}", rec);
+            return;
+        }
+        println!(
+            "{}: {} gaps of {} avg, stddev {}, {} min/p1/p50/p99/p99.9/max",
+            label,
+            self.count,
+            statistic::format_ms(cfg, avg_ms),
+            statistic::format_ms(cfg, stddev_ms),
+            statistic::format_ms_list(cfg, &gaps)
+        );
+    }
+}
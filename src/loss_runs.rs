@@ -0,0 +1,211 @@
+//! Runs of consecutive lost packets and the Gilbert model fitted to them
+//!
+//! The same loss percentage sounds very different whether packets are lost one at a
+//! time, which concealment hides, or in bursts, which cut out syllables. Lengths of loss
+//! runs are counted once packets are too far behind the highest received one to still
+//! arrive, so reordered packets don't split runs.
+//!
+//! The model is the two-state Gilbert one, the Gilbert-Elliott model with no loss in the
+//! Good state and no delivery in the Bad one: `p` is the probability to go from Good to
+//! Bad, `r` from Bad back to Good. The burst ratio of ITU-T G.113, `1 / (p + r)`, is 1
+//! for random loss and grows with the burstiness.
+
+use crate::export;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::path::Path;
+
+/// Packets behind the highest received one which may still arrive
+const WINDOW: u32 = 128;
+
+#[derive(Debug, Default, Clone)]
+pub struct LossRuns {
+    /// The highest received sequence number, and bit `i` set if `max - i` was received
+    window: Option<(u32, u128)>,
+    /// The first sequence number not counted yet
+    next: u32,
+    /// Length of the run being counted
+    run: u64,
+    received: u64,
+    lost: u64,
+    /// Number of runs by length
+    by_len: BTreeMap<u64, u64>,
+}
+
+/// Parameters of the Gilbert model, see the module documentation
+#[derive(Debug, Clone, Copy)]
+pub struct Gilbert {
+    pub p: f64,
+    pub r: f64,
+}
+
+impl LossRuns {
+    /// Registers a received packet, duplicates included
+    pub fn on_seq(&mut self, seq: u32) {
+        let (max, seen) = match self.window {
+            Some(window) => window,
+            None => {
+                self.window = Some((seq, 1));
+                self.next = seq;
+                return;
+            }
+        };
+        if seq <= max {
+            // Too late packets were already counted as lost
+            if seq >= self.next {
+                self.window = Some((max, seen | 1 << (max - seq)));
+            }
+            return;
+        }
+        let keep_from = seq.saturating_sub(WINDOW - 1);
+        while self.next < keep_from && self.next <= max {
+            let received = (seen >> (max - self.next)) & 1 == 1;
+            self.count(received, 1);
+            self.next += 1;
+        }
+        if self.next < keep_from {
+            self.count(false, u64::from(keep_from - self.next));
+            self.next = keep_from;
+        }
+        self.window = Some((seq, seen.checked_shl(seq - max).unwrap_or(0) | 1));
+    }
+
+    fn count(&mut self, received: bool, packets: u64) {
+        if received {
+            self.end_run();
+            self.received += packets;
+        } else {
+            self.run += packets;
+            self.lost += packets;
+        }
+    }
+
+    fn end_run(&mut self) {
+        if self.run > 0 {
+            *self.by_len.entry(self.run).or_default() += 1;
+            self.run = 0;
+        }
+    }
+
+    /// The runs with the packets still in the window counted, as at the end of a test
+    pub fn finished(&self) -> Self {
+        let mut runs = self.clone();
+        if let Some((max, seen)) = self.window {
+            while runs.next <= max {
+                runs.count((seen >> (max - runs.next)) & 1 == 1, 1);
+                runs.next += 1;
+            }
+        }
+        runs.end_run();
+        runs
+    }
+
+    /// Adds the counted runs of another stream
+    pub fn merge(&mut self, other: &Self) {
+        self.received += other.received;
+        self.lost += other.lost;
+        for (len, count) in &other.by_len {
+            *self.by_len.entry(*len).or_default() += count;
+        }
+    }
+
+    pub fn runs(&self) -> u64 {
+        self.by_len.values().sum()
+    }
+
+    pub fn longest(&self) -> u64 {
+        self.by_len.keys().next_back().copied().unwrap_or_default()
+    }
+
+    /// Average length of runs, `None` without runs
+    pub fn avg_len(&self) -> Option<f64> {
+        match self.runs() {
+            0 => None,
+            runs => Some(self.lost as f64 / runs as f64),
+        }
+    }
+
+    /// `None` without losses or without received packets
+    pub fn gilbert(&self) -> Option<Gilbert> {
+        let runs = self.runs() as f64;
+        if runs == 0. || self.received == 0 {
+            return None;
+        }
+        Some(Gilbert {
+            p: runs / self.received as f64,
+            r: runs / self.lost as f64,
+        })
+    }
+
+    /// `len:count` of every length, comma separated
+    pub fn lengths(&self) -> String {
+        let mut lengths = String::new();
+        for (len, count) in &self.by_len {
+            let sep = if lengths.is_empty() { "" } else { "," };
+            write!(lengths, "{}{}:{}", sep, len, count).unwrap();
+        }
+        lengths
+    }
+
+    /// The `type=loss_runs` record of the runs of the statistics labeled `label`
+    pub fn record(&self, label: &str) -> String {
+        let mut rec = format!(
+            "type=loss_runs label={:?} runs={} longest={} lengths={}",
+            label,
+            self.runs(),
+            self.longest(),
+            self.lengths()
+        );
+        if let Some(g) = self.gilbert() {
+            write!(
+                rec,
+                " gilbert_p={:.6} gilbert_r={:.6} burst_ratio={:.3}",
+                g.p,
+                g.r,
+                g.burst_ratio()
+            )
+            .unwrap();
+        }
+        rec
+    }
+
+    /// Prints the runs, or their record with `quiet`, and writes the record to `jsonl`
+    pub fn print(&self, label: &str, quiet: bool, jsonl: Option<&Path>) {
+        let rec = self.record(label);
+        if let Some(path) = jsonl {
+            export::append_json(path, &rec);
+        }
+        if quiet {
+            println!("{}", rec);
+            return;
+        }
+        match self.avg_len() {
+            None => println!("{} loss runs: none", label),
+            Some(avg_len) => {
+                println!(
+                    "{} loss runs: {}, {:.2} packets long on average, the longest {}",
+                    label,
+                    self.runs(),
+                    avg_len,
+                    self.longest()
+                );
+                println!("Loss runs by length: {}", self.lengths().replace(',', ", "));
+            }
+        }
+        if let Some(g) = self.gilbert() {
+            println!(
+                "Gilbert model: p={:.3}% r={:.2}%, burst ratio {:.2}",
+                g.p * 100.,
+                g.r * 100.,
+                g.burst_ratio()
+            );
+        }
+    }
+}
+
+impl Gilbert {
+    /// See the module documentation
+    pub fn burst_ratio(&self) -> f64 {
+        1. / (self.p + self.r)
+    }
+}
@@ -20,7 +20,7 @@ type RawVoidPtr = Option<NonNull<u8>>;
 /// different future executions.
 /// If you want to use it to await on multiple futures,
 /// you should get `FuturesMerger` by calling `borrow`.
-#[derive(Debug)]
+#[derive(Debug, Default)]
 pub struct FuturesMergerMemoryOwner {
     data: RawVoidPtr,
     capacity: usize,
@@ -70,7 +70,7 @@ pub struct WrongLayoutError {
 impl FuturesMergerMemoryOwner {
     pub fn borrow<F: Future<Output = Result<(), E>>, E: StdError>(
         &mut self,
-    ) -> Result<FuturesMerger<F, E>, WrongLayoutError> {
+    ) -> Result<FuturesMerger<'_, F, E>, WrongLayoutError> {
         if let Some(layout) = &self.layout {
             let new_layout = get_layout::<F>();
             if *layout != new_layout {
@@ -91,18 +91,6 @@ impl FuturesMergerMemoryOwner {
     }
 }
 
-impl Default for FuturesMergerMemoryOwner {
-    fn default() -> Self {
-        Self {
-            data: None,
-            capacity: 0,
-            to_poll: vec![],
-            layout: None,
-            drop_fn: None,
-        }
-    }
-}
-
 impl Drop for FuturesMergerMemoryOwner {
     fn drop(&mut self) {
         if let Some(drop_fn) = self.drop_fn.take() {
@@ -123,7 +111,7 @@ impl<'a, F: Future<Output = Result<(), E>>, E: StdError> FuturesMerger<'a, F, E>
         self.futures.reserve(additional);
     }
 
-    pub fn run(&mut self) -> FuturesMergerAwait<F, E> {
+    pub fn run(&mut self) -> FuturesMergerAwait<'_, F, E> {
         FuturesMergerAwait {
             futures: &mut self.futures,
             to_poll: &mut self.top.to_poll,
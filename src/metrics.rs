@@ -0,0 +1,285 @@
+//! Prometheus metrics of `--metrics`: an HTTP endpoint serving `/metrics` in the text
+//! exposition format
+//!
+//! The statistics of the live display are published every interval: the window
+//! percentiles, average, maximum and jitter as gauges, the loss of the whole run and of
+//! the window, and all samples as a histogram. A server adds the number of its clients
+//! and how late its send loop is. Delays are in seconds, as Prometheus has it.
+
+use crate::error::Error;
+use crate::export::{IntervalRow, ROW_PERCENTILES};
+use crate::histogram::Histogram;
+use async_std::future;
+use async_std::io::{ReadExt, WriteExt};
+use async_std::net::{TcpListener, TcpStream};
+use futures::StreamExt;
+use log::{debug, info};
+use std::collections::BTreeMap;
+use std::fmt::{self, Write as _};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Upper bounds of the histogram buckets in seconds, `+Inf` is added
+const BUCKETS: [f64; 12] = [
+    0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1., 2.5,
+];
+/// Time a scraper has to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// `None` until the endpoint is served, updates are dropped then
+static REGISTRY: Mutex<Option<Registry>> = Mutex::new(None);
+
+#[derive(Default)]
+struct Registry {
+    /// By label of the statistics
+    delays: BTreeMap<String, DelayMetrics>,
+    /// Number of clients by address of the server
+    clients: BTreeMap<String, usize>,
+    /// Average and maximum lateness in seconds by address of the server and what is late,
+    /// see `statistic::Lateness`
+    lateness: BTreeMap<(String, &'static str), (f64, f64)>,
+}
+
+#[derive(Default)]
+struct DelayMetrics {
+    /// The latest interval
+    row: IntervalRow,
+    /// Samples by bucket of `BUCKETS`, the last one is `+Inf`
+    buckets: [u64; BUCKETS.len() + 1],
+    count: u64,
+    sum: Duration,
+}
+
+/// Serves `/metrics` on `bind` until the run ends, right away without it
+pub async fn serve(bind: Option<&str>) -> Result<(), Error> {
+    let bind = some_or_ret!(bind, Ok(()));
+    let listener = TcpListener::bind(bind)
+        .await
+        .map_err(|e| Error::new(format!("Cannot bind to {}: {}", bind, e)))?;
+    info!(
+        "Prometheus metrics: http://{}/metrics",
+        listener.local_addr()?
+    );
+    *lock() = Some(Registry::default());
+
+    let mut incoming = listener.incoming();
+    while let Some(stream) = incoming.next().await {
+        let stream = stream?;
+        // A scraper which doesn't send its request in time is dropped
+        if let Ok(Err(e)) = future::timeout(REQUEST_TIMEOUT, answer(stream)).await {
+            debug!("Metrics connection error: {}", e);
+        }
+    }
+    Ok(())
+}
+
+async fn answer(mut stream: TcpStream) -> std::io::Result<()> {
+    let mut request = Vec::new();
+    let mut buf = [0; 1024];
+    while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < 16 * 1024 {
+        let len = stream.read(&mut buf).await?;
+        if len == 0 {
+            break;
+        }
+        request.extend_from_slice(&buf[..len]);
+    }
+    let request = String::from_utf8_lossy(&request);
+    let mut words = request.split_whitespace();
+    let (status, body) = match (words.next(), words.next()) {
+        (Some("GET"), Some(path)) if path.split('?').next() == Some("/metrics") => {
+            ("200 OK", render())
+        }
+        (Some("GET"), _) => ("404 Not Found", "Not found, see /metrics\n".to_owned()),
+        _ => (
+            "405 Method Not Allowed",
+            "Only GET is supported\n".to_owned(),
+        ),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: text/plain; version=0.0.4\r\nContent-Length: {}\r\n\
+         Connection: close\r\n\r\n{}",
+        status,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await
+}
+
+fn lock() -> std::sync::MutexGuard<'static, Option<Registry>> {
+    REGISTRY.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Publishes the interval `row` of the statistics with the samples of the interval
+pub fn update_delays(row: IntervalRow, samples: &Histogram, sum: Duration) {
+    let mut registry = lock();
+    let registry = some_or_ret!(registry.as_mut());
+    let metrics = registry.delays.entry(row.label.clone()).or_default();
+    for (idx, count) in samples.counts().iter().enumerate() {
+        let secs = Histogram::bucket_end(idx) as f64 / 1e6;
+        metrics.buckets[BUCKETS.partition_point(|bound| *bound < secs)] += count;
+        metrics.count += count;
+    }
+    metrics.sum += sum;
+    metrics.row = row;
+}
+
+/// Publishes the number of clients of the server at `server`
+pub fn update_clients(server: &str, clients: usize) {
+    if let Some(registry) = lock().as_mut() {
+        registry.clients.insert(server.to_owned(), clients);
+    }
+}
+
+/// Publishes how late events `what` of the server at `server` are, see
+/// `statistic::Lateness`
+pub fn update_lateness(server: &str, what: &'static str, avg: Duration, max: Duration) {
+    if let Some(registry) = lock().as_mut() {
+        let lateness = (avg.as_secs_f64(), max.as_secs_f64());
+        registry
+            .lateness
+            .insert((server.to_owned(), what), lateness);
+    }
+}
+
+/// The exposition of all metrics
+fn render() -> String {
+    let registry = lock();
+    let registry = some_or_ret!(registry.as_ref(), String::new());
+    let mut out = String::new();
+    let delays = &registry.delays;
+
+    header(&mut out, "delay_seconds", "histogram", "All samples");
+    for (label, m) in delays {
+        let mut cumulative = 0;
+        for (i, count) in m.buckets.iter().enumerate() {
+            cumulative += count;
+            let le = BUCKETS.get(i).map_or("+Inf".to_owned(), f64::to_string);
+            let labels = format!("label={:?},le={:?}", label, le);
+            sample(&mut out, "delay_seconds_bucket", &labels, cumulative);
+        }
+        let labels = format!("label={:?}", label);
+        sample(&mut out, "delay_seconds_sum", &labels, m.sum.as_secs_f64());
+        sample(&mut out, "delay_seconds_count", &labels, m.count);
+    }
+
+    header(
+        &mut out,
+        "window_delay_seconds",
+        "gauge",
+        "Percentiles of the window",
+    );
+    for (label, m) in delays {
+        for (p, ms) in ROW_PERCENTILES.iter().zip(&m.row.percentiles_ms) {
+            let labels = format!("label={:?},quantile=\"{}\"", label, p);
+            sample(&mut out, "window_delay_seconds", &labels, ms / 1000.);
+        }
+    }
+    let gauge = |out: &mut String, name, help, value: fn(&IntervalRow) -> Option<f64>| {
+        by_label(out, delays, name, "gauge", help, value)
+    };
+    gauge(
+        &mut out,
+        "window_delay_avg_seconds",
+        "Average of the window",
+        |r| Some(r.avg_ms / 1000.),
+    );
+    gauge(
+        &mut out,
+        "window_delay_min_seconds",
+        "Minimum of the window",
+        |r| Some(r.min_ms / 1000.),
+    );
+    gauge(
+        &mut out,
+        "window_delay_max_seconds",
+        "Maximum of the window",
+        |r| Some(r.max_ms / 1000.),
+    );
+    gauge(
+        &mut out,
+        "window_delay_stddev_seconds",
+        "Standard deviation of the window",
+        |r| Some(r.stddev_ms / 1000.),
+    );
+    gauge(
+        &mut out,
+        "jitter_seconds",
+        "RFC 3550 interarrival jitter",
+        |r| Some(r.jitter_ms / 1000.),
+    );
+    gauge(&mut out, "loss_ratio", "Loss of the whole run", |r| {
+        Some(r.loss.as_ref()?.loss_pct / 100.)
+    });
+    gauge(&mut out, "window_loss_ratio", "Loss of the window", |r| {
+        Some(r.loss.as_ref()?.window_loss_pct? / 100.)
+    });
+    let counter = |out: &mut String, name, help, value: fn(&IntervalRow) -> Option<f64>| {
+        by_label(out, delays, name, "counter", help, value)
+    };
+    counter(
+        &mut out,
+        "packets_expected_total",
+        "Packets expected",
+        |r| Some(r.loss.as_ref()?.expected as f64),
+    );
+    counter(
+        &mut out,
+        "packets_received_total",
+        "Packets received",
+        |r| Some(r.loss.as_ref()?.received as f64),
+    );
+
+    if !registry.clients.is_empty() {
+        header(&mut out, "clients", "gauge", "Clients of the server");
+        for (server, clients) in &registry.clients {
+            sample(
+                &mut out,
+                "clients",
+                &format!("server={:?}", server),
+                clients,
+            );
+        }
+    }
+    if !registry.lateness.is_empty() {
+        let help = "How late the send loop wakes up and sends packets";
+        header(&mut out, "lateness_seconds", "gauge", help);
+        for ((server, what), (avg, max)) in &registry.lateness {
+            for (stat, value) in [("avg", avg), ("max", max)] {
+                let labels = format!("server={:?},what={:?},stat={:?}", server, what, stat);
+                sample(&mut out, "lateness_seconds", &labels, value);
+            }
+        }
+    }
+    out
+}
+
+/// A metric of every statistics which has it
+fn by_label(
+    out: &mut String,
+    delays: &BTreeMap<String, DelayMetrics>,
+    name: &str,
+    metric_type: &str,
+    help: &str,
+    value: fn(&IntervalRow) -> Option<f64>,
+) {
+    let values: Vec<_> = delays
+        .iter()
+        .filter_map(|(label, m)| Some((label, value(&m.row)?)))
+        .collect();
+    if values.is_empty() {
+        return;
+    }
+    header(out, name, metric_type, help);
+    for (label, value) in values {
+        sample(out, name, &format!("label={:?}", label), value);
+    }
+}
+
+fn header(out: &mut String, name: &str, metric_type: &str, help: &str) {
+    writeln!(out, "# HELP udp_jitter_test_{} {}", name, help).unwrap();
+    writeln!(out, "# TYPE udp_jitter_test_{} {}", name, metric_type).unwrap();
+}
+
+fn sample(out: &mut String, name: &str, labels: &str, value: impl fmt::Display) {
+    writeln!(out, "udp_jitter_test_{}{{{}}} {}", name, labels, value).unwrap();
+}
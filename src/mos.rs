@@ -0,0 +1,55 @@
+//! Call quality estimate with the E-model of ITU-T G.107
+//!
+//! The simplified model of G.107 for a G.711 call with packet loss concealment: the
+//! rating R starts at 93.2 and is lowered by the mouth-to-ear delay and by the loss.
+//! The delay is the one-way network delay plus the packetization and a jitter buffer of
+//! twice the jitter. R is mapped to the mean opinion score, 1 (bad) to 4.5 (best).
+
+use std::fmt;
+use std::time::Duration;
+
+/// Basic signal-to-noise ratio with the default G.107 parameters
+const R0: f64 = 93.2;
+/// Equipment impairment and packet-loss robustness of G.711 with PLC, G.113 Appendix I
+const IE: f64 = 0.;
+const BPL: f64 = 25.1;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Quality {
+    pub r_factor: f64,
+    pub mos: f64,
+}
+
+/// Estimates the quality of calls with packets sent every `interval`
+pub fn estimate(
+    one_way_delay_ms: f64,
+    jitter_ms: f64,
+    loss_percent: f64,
+    interval: Duration,
+) -> Quality {
+    let delay_ms = one_way_delay_ms + 2. * jitter_ms + interval.as_secs_f64() * 1000.;
+    let id = 0.024 * delay_ms + 0.11 * (delay_ms - 177.3).max(0.);
+    // Losses are taken as random: BurstR is 1
+    let ie_eff = IE + (95. - IE) * loss_percent / (loss_percent + BPL);
+    let r_factor = (R0 - id - ie_eff).clamp(0., 100.);
+    Quality {
+        r_factor,
+        mos: mos(r_factor),
+    }
+}
+
+fn mos(r: f64) -> f64 {
+    if r <= 0. {
+        1.
+    } else if r >= 100. {
+        4.5
+    } else {
+        1. + 0.035 * r + r * (r - 60.) * (100. - r) * 7e-6
+    }
+}
+
+impl fmt::Display for Quality {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "R-factor {:.1}, MOS {:.2}", self.r_factor, self.mos)
+    }
+}
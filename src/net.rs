@@ -0,0 +1,432 @@
+//! Socket helpers
+
+use crate::error::Error;
+use async_std::net::{ToSocketAddrs, UdpSocket};
+#[cfg(any(feature = "quic", feature = "dtls"))]
+use std::net::IpAddr;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr, SocketAddrV4, SocketAddrV6};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::str::FromStr;
+use std::{fmt, io, mem};
+
+/// ECN codepoint: the two low bits of the TOS byte, RFC 3168
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Ecn {
+    NotEct = 0,
+    /// ECT(1), L4S traffic
+    Ect1 = 1,
+    /// ECT(0), classic ECN-capable traffic
+    Ect0 = 2,
+    /// Congestion experienced, set by routers on ECN-capable packets instead of dropping
+    Ce = 3,
+}
+
+impl Ecn {
+    pub fn from_tos(tos: u8) -> Self {
+        match tos & 3 {
+            0 => Ecn::NotEct,
+            1 => Ecn::Ect1,
+            2 => Ecn::Ect0,
+            _ => Ecn::Ce,
+        }
+    }
+}
+
+impl FromStr for Ecn {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(Ecn::NotEct),
+            "ect0" => Ok(Ecn::Ect0),
+            "ect1" => Ok(Ecn::Ect1),
+            _ => Err(Error::new(format!(
+                "Unknown ECN codepoint: {}. Expected none, ect0 or ect1",
+                s
+            ))),
+        }
+    }
+}
+
+pub fn set_dscp(s: &UdpSocket, dscp: u8) -> Result<(), Error> {
+    set_tos(s, dscp, Ecn::NotEct)
+}
+
+/// Sets DSCP and the ECN codepoint of sent packets
+pub fn set_tos(s: &UdpSocket, dscp: u8, ecn: Ecn) -> Result<(), Error> {
+    let tos = libc::c_int::from(dscp) << 2 | ecn as libc::c_int;
+    if s.local_addr()?.is_ipv6() {
+        set_int_opt(s, libc::IPPROTO_IPV6, libc::IPV6_TCLASS, tos)?;
+    }
+    // Also for IPv4 peers of a dual-stack socket
+    set_int_opt(s, libc::IPPROTO_IP, libc::IP_TOS, tos)
+}
+
+/// Sets TTL, or hop limit for IPv6, of sent packets
+pub fn set_ttl(s: &UdpSocket, ttl: u8) -> Result<(), Error> {
+    let (level, name) = if s.local_addr()?.is_ipv4() {
+        (libc::IPPROTO_IP, libc::IP_TTL)
+    } else {
+        (libc::IPPROTO_IPV6, libc::IPV6_UNICAST_HOPS)
+    };
+    set_int_opt(s, level, name, ttl.into())
+}
+
+/// With `probe` sent packets have DF set and are never fragmented, even if they are bigger
+/// than the known path MTU. Otherwise the kernel default: DF is set, but packets bigger
+/// than the path MTU are fragmented locally
+pub fn set_mtu_probe(s: &UdpSocket, probe: bool) -> Result<(), Error> {
+    let (level, name, value) = match (s.local_addr()?.is_ipv4(), probe) {
+        (true, true) => (
+            libc::IPPROTO_IP,
+            libc::IP_MTU_DISCOVER,
+            libc::IP_PMTUDISC_PROBE,
+        ),
+        (true, false) => (
+            libc::IPPROTO_IP,
+            libc::IP_MTU_DISCOVER,
+            libc::IP_PMTUDISC_WANT,
+        ),
+        (false, true) => (
+            libc::IPPROTO_IPV6,
+            libc::IPV6_MTU_DISCOVER,
+            libc::IPV6_PMTUDISC_PROBE,
+        ),
+        (false, false) => (
+            libc::IPPROTO_IPV6,
+            libc::IPV6_MTU_DISCOVER,
+            libc::IPV6_PMTUDISC_WANT,
+        ),
+    };
+    set_int_opt(s, level, name, value)
+}
+
+pub fn get_tos(s: &UdpSocket) -> Result<libc::c_int, Error> {
+    get_int_opt(s.as_raw_fd(), libc::IPPROTO_IP, libc::IP_TOS)
+}
+
+/// The path MTU to `addr` known to the kernel: the MTU of the outgoing interface, lowered
+/// by ICMP "fragmentation needed" or "packet too big" messages from routers on the way
+pub fn path_mtu(addr: SocketAddr) -> Result<usize, Error> {
+    let (local, level, name): (SocketAddr, _, _) = if addr.is_ipv4() {
+        (
+            (Ipv4Addr::UNSPECIFIED, 0).into(),
+            libc::IPPROTO_IP,
+            libc::IP_MTU,
+        )
+    } else {
+        (
+            (Ipv6Addr::UNSPECIFIED, 0).into(),
+            libc::IPPROTO_IPV6,
+            libc::IPV6_MTU,
+        )
+    };
+    // The MTU can be read only from a connected socket
+    let s = std::net::UdpSocket::bind(local)?;
+    s.connect(addr)?;
+    Ok(get_int_opt(s.as_raw_fd(), level, name)? as usize)
+}
+
+/// The local address packets to `addr` are sent from, with the port of `s`.
+/// `s` itself can be bound to any address
+pub fn source_addr(s: &UdpSocket, addr: SocketAddr) -> Result<SocketAddr, Error> {
+    let mut local = s.local_addr()?;
+    if local.ip().is_unspecified() {
+        // The kernel picks the source address of a connected socket by the routes
+        let probe = std::net::UdpSocket::bind(SocketAddr::new(local.ip(), 0))?;
+        probe.connect(addr)?;
+        local.set_ip(probe.local_addr()?.ip());
+    }
+    Ok(local)
+}
+
+/// A datagram received by `recv_msg`
+#[derive(Debug, Clone, Copy)]
+pub struct Received {
+    pub len: usize,
+    pub addr: SocketAddr,
+    /// TTL or hop limit of the packet if `enable_recv_ttl` was called
+    pub ttl: Option<u8>,
+    /// TOS or traffic class of the packet if `enable_recv_tos` was called
+    pub tos: Option<u8>,
+}
+
+/// Makes `recv_msg` report TTL of received packets
+pub fn enable_recv_ttl(s: &UdpSocket) -> Result<(), Error> {
+    let (level, name) = if s.local_addr()?.is_ipv4() {
+        (libc::IPPROTO_IP, libc::IP_RECVTTL)
+    } else {
+        (libc::IPPROTO_IPV6, libc::IPV6_RECVHOPLIMIT)
+    };
+    set_int_opt(s, level, name, 1)
+}
+
+/// Makes `recv_msg` report TOS of received packets, e.g. for their ECN bits
+pub fn enable_recv_tos(s: &UdpSocket) -> Result<(), Error> {
+    if s.local_addr()?.is_ipv6() {
+        set_int_opt(s, libc::IPPROTO_IPV6, libc::IPV6_RECVTCLASS, 1)?;
+    }
+    set_int_opt(s, libc::IPPROTO_IP, libc::IP_RECVTOS, 1)
+}
+
+/// Receives a datagram along with its IP header fields. async-std has no `recvmsg`,
+/// so readiness is awaited with `peek_from` and the datagram is read without blocking
+pub async fn recv_msg(s: &UdpSocket, buf: &mut [u8]) -> io::Result<Received> {
+    loop {
+        s.peek_from(&mut [0; 1]).await?;
+        match recv_msg_nonblocking(s, buf) {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+            res => return res,
+        }
+    }
+}
+
+fn recv_msg_nonblocking(s: &UdpSocket, buf: &mut [u8]) -> io::Result<Received> {
+    let mut addr: libc::sockaddr_storage = unsafe { mem::zeroed() };
+    // u64 keeps control messages aligned
+    let mut control = [0u64; 16];
+    let mut iov = libc::iovec {
+        iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+        iov_len: buf.len(),
+    };
+    let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+    msg.msg_name = &mut addr as *mut _ as *mut libc::c_void;
+    msg.msg_namelen = mem::size_of_val(&addr) as libc::socklen_t;
+    msg.msg_iov = &mut iov;
+    msg.msg_iovlen = 1;
+    msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+    msg.msg_controllen = mem::size_of_val(&control);
+
+    let len = unsafe { libc::recvmsg(s.as_raw_fd(), &mut msg, libc::MSG_DONTWAIT) };
+    if len < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let (mut ttl, mut tos) = (None, None);
+    unsafe {
+        let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+        while !cmsg.is_null() {
+            let c = &*cmsg;
+            let is_ttl = (c.cmsg_level == libc::IPPROTO_IP && c.cmsg_type == libc::IP_TTL)
+                || (c.cmsg_level == libc::IPPROTO_IPV6 && c.cmsg_type == libc::IPV6_HOPLIMIT);
+            if is_ttl {
+                let value = (libc::CMSG_DATA(cmsg) as *const libc::c_int).read_unaligned();
+                ttl = Some(value as u8);
+            }
+            // IPv4 TOS is a single byte, unlike the other values
+            if c.cmsg_level == libc::IPPROTO_IP && c.cmsg_type == libc::IP_TOS {
+                tos = Some(libc::CMSG_DATA(cmsg).read());
+            }
+            if c.cmsg_level == libc::IPPROTO_IPV6 && c.cmsg_type == libc::IPV6_TCLASS {
+                let value = (libc::CMSG_DATA(cmsg) as *const libc::c_int).read_unaligned();
+                tos = Some(value as u8);
+            }
+            cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+        }
+    }
+
+    Ok(Received {
+        len: len as usize,
+        addr: to_socket_addr(&addr)?,
+        ttl,
+        tos,
+    })
+}
+
+/// Makes the kernel queue ICMP errors about sent packets, they are read by `recv_errors`.
+/// The latest error also fails the next send or receive on the socket once
+pub fn enable_recv_err(s: &UdpSocket) -> Result<(), Error> {
+    if s.local_addr()?.is_ipv6() {
+        set_int_opt(s, libc::IPPROTO_IPV6, libc::IPV6_RECVERR, 1)?;
+    }
+    // Also for IPv4 peers of a dual-stack socket
+    set_int_opt(s, libc::IPPROTO_IP, libc::IP_RECVERR, 1)
+}
+
+/// An ICMP error about a packet sent to `addr`
+#[derive(Debug, Clone, Copy)]
+pub struct IcmpError {
+    pub addr: SocketAddr,
+    pub errno: i32,
+}
+
+impl IcmpError {
+    /// Port, host or network unreachable: nobody receives packets sent to `addr`.
+    /// Errors like "fragmentation needed" are about single packets
+    pub fn is_unreachable(&self) -> bool {
+        is_unreachable_errno(self.errno)
+    }
+}
+
+impl fmt::Display for IcmpError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}: {}",
+            self.addr,
+            io::Error::from_raw_os_error(self.errno)
+        )
+    }
+}
+
+/// Whether `e` is an ICMP unreachable error reported by a send or a receive, see
+/// `enable_recv_err`. It is about some earlier packet, not the failed call
+pub fn is_unreachable_error(e: &io::Error) -> bool {
+    // async-std wraps OS errors in its own, only the kind is kept
+    matches!(
+        e.kind(),
+        io::ErrorKind::ConnectionRefused
+            | io::ErrorKind::HostUnreachable
+            | io::ErrorKind::NetworkUnreachable
+    )
+}
+
+fn is_unreachable_errno(errno: i32) -> bool {
+    matches!(
+        errno,
+        libc::ECONNREFUSED | libc::EHOSTUNREACH | libc::ENETUNREACH
+    )
+}
+
+/// Reads the ICMP errors queued since the last call without blocking, see `enable_recv_err`
+pub fn recv_errors(s: &UdpSocket) -> Result<Vec<IcmpError>, Error> {
+    let mut errors = Vec::new();
+    loop {
+        let mut addr: libc::sockaddr_storage = unsafe { mem::zeroed() };
+        let mut control = [0u64; 16];
+        // The queued packet itself is not needed
+        let mut buf = [0u8; 1];
+        let mut iov = libc::iovec {
+            iov_base: buf.as_mut_ptr() as *mut libc::c_void,
+            iov_len: buf.len(),
+        };
+        let mut msg: libc::msghdr = unsafe { mem::zeroed() };
+        msg.msg_name = &mut addr as *mut _ as *mut libc::c_void;
+        msg.msg_namelen = mem::size_of_val(&addr) as libc::socklen_t;
+        msg.msg_iov = &mut iov;
+        msg.msg_iovlen = 1;
+        msg.msg_control = control.as_mut_ptr() as *mut libc::c_void;
+        msg.msg_controllen = mem::size_of_val(&control);
+
+        let flags = libc::MSG_ERRQUEUE | libc::MSG_DONTWAIT;
+        if unsafe { libc::recvmsg(s.as_raw_fd(), &mut msg, flags) } < 0 {
+            let e = io::Error::last_os_error();
+            if e.kind() == io::ErrorKind::WouldBlock {
+                return Ok(errors);
+            }
+            return Err(e.into());
+        }
+
+        unsafe {
+            let mut cmsg = libc::CMSG_FIRSTHDR(&msg);
+            while !cmsg.is_null() {
+                let c = &*cmsg;
+                let is_err = (c.cmsg_level == libc::IPPROTO_IP && c.cmsg_type == libc::IP_RECVERR)
+                    || (c.cmsg_level == libc::IPPROTO_IPV6 && c.cmsg_type == libc::IPV6_RECVERR);
+                if is_err {
+                    let err =
+                        (libc::CMSG_DATA(cmsg) as *const libc::sock_extended_err).read_unaligned();
+                    let from_icmp = err.ee_origin == libc::SO_EE_ORIGIN_ICMP
+                        || err.ee_origin == libc::SO_EE_ORIGIN_ICMP6;
+                    if from_icmp {
+                        errors.push(IcmpError {
+                            addr: to_socket_addr(&addr)?,
+                            errno: err.ee_errno as i32,
+                        });
+                    }
+                }
+                cmsg = libc::CMSG_NXTHDR(&msg, cmsg);
+            }
+        }
+    }
+}
+
+fn to_socket_addr(addr: &libc::sockaddr_storage) -> io::Result<SocketAddr> {
+    match libc::c_int::from(addr.ss_family) {
+        libc::AF_INET => {
+            let a = unsafe { &*(addr as *const _ as *const libc::sockaddr_in) };
+            let ip = Ipv4Addr::from(u32::from_be(a.sin_addr.s_addr));
+            Ok(SocketAddrV4::new(ip, u16::from_be(a.sin_port)).into())
+        }
+        libc::AF_INET6 => {
+            let a = unsafe { &*(addr as *const _ as *const libc::sockaddr_in6) };
+            let ip = Ipv6Addr::from(a.sin6_addr.s6_addr);
+            let port = u16::from_be(a.sin6_port);
+            Ok(SocketAddrV6::new(ip, port, a.sin6_flowinfo, a.sin6_scope_id).into())
+        }
+        family => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Unsupported address family: {}", family),
+        )),
+    }
+}
+
+fn get_int_opt(fd: RawFd, level: libc::c_int, name: libc::c_int) -> Result<libc::c_int, Error> {
+    let mut value: libc::c_int = 0;
+    let mut len = mem::size_of_val(&value) as libc::socklen_t;
+    let res = unsafe {
+        libc::getsockopt(
+            fd,
+            level,
+            name,
+            &mut value as *mut _ as *mut libc::c_void,
+            &mut len,
+        )
+    };
+
+    if res == 0 {
+        Ok(value)
+    } else {
+        Err(io::Error::last_os_error().into())
+    }
+}
+
+fn set_int_opt(
+    s: &UdpSocket,
+    level: libc::c_int,
+    name: libc::c_int,
+    value: libc::c_int,
+) -> Result<(), Error> {
+    let res = unsafe {
+        libc::setsockopt(
+            s.as_raw_fd(),
+            level,
+            name,
+            &value as *const _ as *const libc::c_void,
+            mem::size_of_val(&value) as u32,
+        )
+    };
+
+    if res == 0 {
+        Ok(())
+    } else {
+        Err(io::Error::last_os_error().into())
+    }
+}
+
+/// Any local address of the family of `ip`, with any port
+#[cfg(any(feature = "quic", feature = "dtls"))]
+pub fn any_addr(ip: IpAddr) -> SocketAddr {
+    match ip {
+        IpAddr::V4(_) => (Ipv4Addr::UNSPECIFIED, 0).into(),
+        IpAddr::V6(_) => (Ipv6Addr::UNSPECIFIED, 0).into(),
+    }
+}
+
+/// The loopback address of the family of `ip`, with any port
+#[cfg(any(feature = "quic", feature = "dtls"))]
+pub fn loopback_addr(ip: IpAddr) -> SocketAddr {
+    match ip {
+        IpAddr::V4(_) => (Ipv4Addr::LOCALHOST, 0).into(),
+        IpAddr::V6(_) => (Ipv6Addr::LOCALHOST, 0).into(),
+    }
+}
+
+/// Resolves `host:port` to the first found address
+pub async fn resolve(addr: &str) -> Result<SocketAddr, Error> {
+    addr.to_socket_addrs()
+        .await
+        .map_err(|e| Error::new(format!("Cannot resolve {}: {}", addr, e)))?
+        .next()
+        .ok_or_else(|| Error::new(format!("Cannot resolve {}", addr)))
+}
@@ -0,0 +1,125 @@
+//! OpenTelemetry metrics of `--otlp`, needs the `otlp` feature
+//!
+//! The window statistics of every interval are POSTed to a collector with OTLP/HTTP in
+//! the JSON encoding, a gauge per key of `--quiet` records, e.g. `udp_jitter_test.p99_ms`.
+//! Data points have the label of the statistics and, for a client of a server, its
+//! address and session as attributes. The resource is `udp-jitter-test`, or
+//! `OTEL_SERVICE_NAME`, with the attributes of `OTEL_RESOURCE_ATTRIBUTES`. Like reports,
+//! metrics which can't be delivered are lost, the test goes on.
+
+use crate::config::StatsConfig;
+use crate::error::Error;
+use crate::export::IntervalRow;
+
+#[cfg(feature = "otlp")]
+mod exporter {
+    use super::*;
+    use crate::export::{self, json_string};
+    use async_std::task;
+    use log::warn;
+    use std::env;
+    use std::fmt::Write as _;
+    use std::iter;
+    use std::time::Duration;
+
+    /// Time to deliver the metrics of an interval
+    const EXPORT_TIMEOUT: Duration = Duration::from_secs(10);
+
+    pub fn send(cfg: &StatsConfig, tags: &[(&str, String)], row: &IntervalRow) {
+        let url = some_or_ret!(&cfg.otlp).clone();
+        let body = request(tags, row);
+        task::spawn(async move {
+            let export = url.post("application/json", None, &body);
+            match async_std::future::timeout(EXPORT_TIMEOUT, export).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => warn!("Cannot export metrics to {}: {}", url, e),
+                Err(_) => warn!("Cannot export metrics to {}: timed out", url),
+            }
+        });
+    }
+
+    /// The `ExportMetricsServiceRequest` of `row`
+    fn request(tags: &[(&str, String)], row: &IntervalRow) -> String {
+        let mut attributes = vec![("label", row.label.clone())];
+        attributes.extend(tags.iter().map(|(key, value)| (*key, value.clone())));
+        let attributes = json_attributes(attributes.iter().map(|(k, v)| (*k, v.as_str())));
+        let time = format!("{:.0}", row.time * 1e9);
+
+        let mut metrics = String::new();
+        let rec = row.to_record("otlp");
+        for (key, value) in export::parse_record(&rec) {
+            if key == "type" || key == "time" || key == "label" {
+                continue;
+            }
+            let value = some_or_cont!(value.parse::<f64>().ok());
+            let unit = if key.ends_with("_ms") {
+                "ms"
+            } else if key.ends_with("_pct") {
+                "%"
+            } else {
+                "1"
+            };
+            let sep = if metrics.is_empty() { "" } else { "," };
+            write!(
+                metrics,
+                "{}{{\"name\":{},\"unit\":\"{}\",\"gauge\":{{\"dataPoints\":[{{\
+                 \"attributes\":{},\"timeUnixNano\":\"{}\",\"asDouble\":{}}}]}}}}",
+                sep,
+                json_string(&format!("udp_jitter_test.{}", key)),
+                unit,
+                attributes,
+                time,
+                value
+            )
+            .unwrap();
+        }
+
+        let service = env::var("OTEL_SERVICE_NAME").unwrap_or_else(|_| "udp-jitter-test".into());
+        let resource_env = env::var("OTEL_RESOURCE_ATTRIBUTES").unwrap_or_default();
+        let resource = resource_env
+            .split(',')
+            .filter_map(|attribute| attribute.split_once('='))
+            .map(|(key, value)| (key.trim(), value.trim()))
+            .filter(|(key, _)| *key != "service.name");
+        let resource =
+            json_attributes(iter::once(("service.name", service.as_str())).chain(resource));
+        format!(
+            "{{\"resourceMetrics\":[{{\"resource\":{{\"attributes\":{}}},\"scopeMetrics\":[{{\
+             \"scope\":{{\"name\":\"udp-jitter-test\",\"version\":\"{}\"}},\"metrics\":[{}]}}]}}]}}",
+            resource,
+            env!("CARGO_PKG_VERSION"),
+            metrics
+        )
+    }
+
+    /// `KeyValue`s of string values as a JSON array
+    fn json_attributes<'a>(attributes: impl Iterator<Item = (&'a str, &'a str)>) -> String {
+        let attributes: Vec<_> = attributes
+            .map(|(key, value)| {
+                format!(
+                    "{{\"key\":{},\"value\":{{\"stringValue\":{}}}}}",
+                    json_string(key),
+                    json_string(value)
+                )
+            })
+            .collect();
+        format!("[{}]", attributes.join(","))
+    }
+}
+
+/// Fails if `--otlp` is set but the build can't export
+pub fn check(cfg: &StatsConfig) -> Result<(), Error> {
+    if cfg!(feature = "otlp") || cfg.otlp.is_none() {
+        return Ok(());
+    }
+    Err(Error::new(
+        "OpenTelemetry export needs a build with the `otlp` feature",
+    ))
+}
+
+/// Exports the statistics of `row`, with `tags` besides the label, to the collector
+/// of `cfg.otlp`. Does nothing without it
+pub fn send(_cfg: &StatsConfig, _tags: &[(&str, String)], _row: &IntervalRow) {
+    #[cfg(feature = "otlp")]
+    exporter::send(_cfg, _tags, _row);
+}
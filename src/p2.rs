@@ -0,0 +1,177 @@
+//! P² streaming quantile estimation (Jain and Chlamtac, 1985)
+//!
+//! A quantile is tracked with 5 markers: the minimum, the maximum, the quantile and two
+//! markers halfway to it. Markers are moved with a piecewise-parabolic interpolation as
+//! samples come, so memory and time per sample are constant and values aren't rounded
+//! to buckets, whatever the length of the run. The estimate converges as samples come,
+//! extreme quantiles of short runs are rough.
+
+use crate::error::Error;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// How the percentiles of the whole run are calculated
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RunQuantiles {
+    /// From the log-linear histogram of all samples, see `Histogram`
+    Histogram,
+    /// With a P² estimator per percentile
+    P2,
+}
+
+#[derive(Debug, Clone)]
+pub struct P2 {
+    q: f64,
+    count: usize,
+    /// Marker heights in microseconds, the first samples until there are 5
+    heights: [f64; 5],
+    /// Actual and desired marker positions, and increments of desired positions
+    positions: [f64; 5],
+    desired: [f64; 5],
+    increments: [f64; 5],
+}
+
+impl P2 {
+    /// An estimator of the quantile `q` in the [0, 1] range
+    pub fn new(q: f64) -> Self {
+        let q = q.clamp(0., 1.);
+        Self {
+            q,
+            count: 0,
+            heights: [0.; 5],
+            positions: [1., 2., 3., 4., 5.],
+            desired: [1., 1. + 2. * q, 1. + 4. * q, 3. + 2. * q, 5.],
+            increments: [0., q / 2., q, (1. + q) / 2., 1.],
+        }
+    }
+
+    pub fn quantile_of(&self) -> f64 {
+        self.q
+    }
+
+    pub fn add(&mut self, d: Duration) {
+        let x = d.as_secs_f64() * 1e6;
+        if self.count < 5 {
+            self.heights[self.count] = x;
+            self.count += 1;
+            if self.count == 5 {
+                self.heights.sort_unstable_by(f64::total_cmp);
+            }
+            return;
+        }
+        self.count += 1;
+
+        let h = &mut self.heights;
+        let k = if x < h[0] {
+            h[0] = x;
+            0
+        } else if x >= h[4] {
+            h[4] = x;
+            3
+        } else {
+            (0..4).find(|&i| x < h[i + 1]).unwrap_or(3)
+        };
+        for n in &mut self.positions[k + 1..] {
+            *n += 1.;
+        }
+        for (desired, inc) in self.desired.iter_mut().zip(&self.increments) {
+            *desired += inc;
+        }
+
+        for i in 1..4 {
+            let d = self.desired[i] - self.positions[i];
+            let n = &self.positions;
+            if (d >= 1. && n[i + 1] - n[i] > 1.) || (d <= -1. && n[i - 1] - n[i] < -1.) {
+                let d = d.signum();
+                let height = self.parabolic(i, d);
+                self.heights[i] = if self.heights[i - 1] < height && height < self.heights[i + 1] {
+                    height
+                } else {
+                    self.linear(i, d)
+                };
+                self.positions[i] += d;
+            }
+        }
+    }
+
+    fn parabolic(&self, i: usize, d: f64) -> f64 {
+        let (n, h) = (&self.positions, &self.heights);
+        h[i] + d / (n[i + 1] - n[i - 1])
+            * ((n[i] - n[i - 1] + d) * (h[i + 1] - h[i]) / (n[i + 1] - n[i])
+                + (n[i + 1] - n[i] - d) * (h[i] - h[i - 1]) / (n[i] - n[i - 1]))
+    }
+
+    fn linear(&self, i: usize, d: f64) -> f64 {
+        let (n, h) = (&self.positions, &self.heights);
+        let j = if d > 0. { i + 1 } else { i - 1 };
+        h[i] + d * (h[j] - h[i]) / (n[j] - n[i])
+    }
+
+    /// The estimate, the nearest-rank quantile while there are less than 5 samples.
+    /// `None` without samples
+    pub fn value(&self) -> Option<Duration> {
+        let micros = match self.count {
+            0 => return None,
+            count if count < 5 => {
+                let mut first = self.heights[..count].to_vec();
+                first.sort_unstable_by(f64::total_cmp);
+                let rank = (count as f64 * self.q).ceil() as usize;
+                first[rank.clamp(1, count) - 1]
+            }
+            _ => self.heights[2],
+        };
+        Some(Duration::from_secs_f64(micros.max(0.) / 1e6))
+    }
+}
+
+impl FromStr for RunQuantiles {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "histogram" => Ok(RunQuantiles::Histogram),
+            "p2" => Ok(RunQuantiles::P2),
+            _ => Err(Error::new(format!(
+                "Unknown quantile estimation: {}, expected histogram or p2",
+                s
+            ))),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn micros(d: Option<Duration>) -> f64 {
+        d.unwrap().as_secs_f64() * 1e6
+    }
+
+    #[test]
+    fn empty() {
+        assert_eq!(P2::new(0.5).value(), None);
+    }
+
+    #[test]
+    fn few_samples() {
+        let mut p2 = P2::new(0.5);
+        for m in [30, 10, 20] {
+            p2.add(Duration::from_micros(m));
+        }
+        assert_eq!(micros(p2.value()), 20.);
+    }
+
+    #[test]
+    fn uniform() {
+        let mut median = P2::new(0.5);
+        let mut p99 = P2::new(0.99);
+        // A permutation of 0..10000, so the samples are spread over the run
+        for i in 0..10_000u64 {
+            let d = Duration::from_micros(i * 7919 % 10_000);
+            median.add(d);
+            p99.add(d);
+        }
+        assert!((micros(median.value()) - 5_000.).abs() < 100.);
+        assert!((micros(p99.value()) - 9_900.).abs() < 100.);
+    }
+}
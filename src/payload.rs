@@ -0,0 +1,114 @@
+//! Payload of test packets
+
+use crate::error::Error;
+use rand::{self, rngs::SmallRng, RngCore, SeedableRng};
+use std::cmp;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+const RANDOM_DATA_LEN: usize = 2000;
+
+/// What test packets are filled with
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    Zeros,
+    /// Bytes 0, 1, ..., 255, 0, 1, ...
+    Incrementing,
+    Random,
+    /// Content of the file, repeated if packets are longer than the file
+    File(PathBuf),
+}
+
+/// Endlessly repeated payload data, generated once from a `Pattern`
+pub struct PayloadData {
+    data: Vec<u8>,
+}
+
+/// Fills packets with `PayloadData`, each packet continues where the previous one stopped
+pub struct PayloadProvider<'a> {
+    data: &'a [u8],
+    idx: usize,
+}
+
+impl FromStr for Pattern {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "zeros" => Ok(Pattern::Zeros),
+            "incrementing" => Ok(Pattern::Incrementing),
+            "random" => Ok(Pattern::Random),
+            _ => match s.strip_prefix("file:") {
+                Some(path) if !path.is_empty() => Ok(Pattern::File(path.into())),
+                _ => Err(Error::new(format!(
+                    "Unknown payload: {}. Expected zeros, incrementing, random or file:<path>",
+                    s
+                ))),
+            },
+        }
+    }
+}
+
+impl PayloadData {
+    /// `seed` is used by `Pattern::Random`, if not set the data is different every run
+    pub fn new(pattern: &Pattern, seed: Option<u64>) -> Result<Self, Error> {
+        let data = match pattern {
+            Pattern::Zeros => vec![0; u8::MAX as usize + 1],
+            Pattern::Incrementing => (0..=u8::MAX).collect(),
+            Pattern::Random => {
+                let mut rng = match seed {
+                    Some(seed) => SmallRng::seed_from_u64(seed),
+                    None => SmallRng::from_rng(rand::thread_rng())?,
+                };
+
+                let mut res = vec![0; RANDOM_DATA_LEN];
+                rng.fill_bytes(&mut res);
+                res
+            }
+            Pattern::File(path) => {
+                let data = fs::read(path).map_err(|e| {
+                    Error::new(format!("Cannot read payload {}: {}", path.display(), e))
+                })?;
+                if data.is_empty() {
+                    return Err(Error::new(format!(
+                        "Payload file is empty: {}",
+                        path.display()
+                    )));
+                }
+                data
+            }
+        };
+
+        Ok(Self { data })
+    }
+
+    pub fn provider(&self) -> PayloadProvider<'_> {
+        PayloadProvider {
+            data: &self.data,
+            idx: 0,
+        }
+    }
+}
+
+impl<'a> PayloadProvider<'a> {
+    /// Appends payload to `buf` until it is `len` bytes long
+    pub fn fill(&mut self, buf: &mut Vec<u8>, len: usize) {
+        let mut to_fill = len.saturating_sub(buf.len());
+        let mut left_data_size = self.data.len() - self.idx;
+
+        while to_fill > 0 {
+            let to_copy = cmp::min(to_fill, left_data_size);
+            buf.extend_from_slice(&self.data[self.idx..self.idx + to_copy]);
+
+            self.idx += to_copy;
+            to_fill -= to_copy;
+            left_data_size -= to_copy;
+
+            if self.idx >= self.data.len() {
+                self.idx = 0;
+                left_data_size = self.data.len();
+            }
+        }
+    }
+}
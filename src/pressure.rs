@@ -0,0 +1,179 @@
+//! Load of the probe host attached to spike events, to tell a slow network from
+//! an overloaded host
+//!
+//! Counters are read from `/proc`: CPU time from `/proc/stat`, network softirqs from
+//! `/proc/softirqs`, backlog drops and squeezes of the network stack from
+//! `/proc/net/softnet_stat` and drops of the interfaces, except loopback, from
+//! `/proc/net/dev`. Events get the changes since a reference sample which is kept at most
+//! `REFERENCE_AGE` old. Counters which can't be read, e.g. outside Linux, are left out.
+
+use std::fmt::Write as _;
+use std::fs;
+use std::time::{Duration, Instant};
+
+/// Age after which the reference sample is taken again
+const REFERENCE_AGE: Duration = Duration::from_secs(1);
+
+#[derive(Default)]
+pub struct Pressure {
+    reference: Option<(Instant, Counters)>,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+struct Counters {
+    /// Jiffies of all CPUs: busy and all
+    cpu: Option<(u64, u64)>,
+    net_rx_softirqs: Option<u64>,
+    net_tx_softirqs: Option<u64>,
+    /// Packets dropped from full backlogs and times the budget ran out
+    softnet_dropped: Option<u64>,
+    softnet_squeezed: Option<u64>,
+    nic_rx_dropped: Option<u64>,
+    nic_tx_dropped: Option<u64>,
+}
+
+impl Pressure {
+    /// Keeps the reference sample fresh, call often, e.g. on every reply
+    pub fn tick(&mut self) {
+        let fresh = self
+            .reference
+            .as_ref()
+            .is_some_and(|(time, _)| time.elapsed() < REFERENCE_AGE);
+        if !fresh {
+            self.reference = Some((Instant::now(), Counters::read()));
+        }
+    }
+
+    /// The `key=value` fields of the changes since the reference sample, which is replaced
+    /// with the current one. Empty if nothing can be read
+    pub fn fields(&mut self) -> String {
+        let now = Counters::read();
+        let mut fields = String::new();
+        if let Some((time, prev)) = self.reference.replace((Instant::now(), now)) {
+            write!(fields, "pressure_s={:.3}", time.elapsed().as_secs_f64()).unwrap();
+            if let (Some((busy, total)), Some((prev_busy, prev_total))) = (now.cpu, prev.cpu) {
+                let total = total.saturating_sub(prev_total);
+                if total > 0 {
+                    let busy = busy.saturating_sub(prev_busy) as f64 / total as f64;
+                    write!(fields, " cpu_busy_pct={:.1}", busy * 100.).unwrap();
+                }
+            }
+            let deltas = [
+                ("net_rx_softirqs", now.net_rx_softirqs, prev.net_rx_softirqs),
+                ("net_tx_softirqs", now.net_tx_softirqs, prev.net_tx_softirqs),
+                ("softnet_dropped", now.softnet_dropped, prev.softnet_dropped),
+                (
+                    "softnet_squeezed",
+                    now.softnet_squeezed,
+                    prev.softnet_squeezed,
+                ),
+                ("nic_rx_dropped", now.nic_rx_dropped, prev.nic_rx_dropped),
+                ("nic_tx_dropped", now.nic_tx_dropped, prev.nic_tx_dropped),
+            ];
+            for (name, now, prev) in deltas {
+                if let (Some(now), Some(prev)) = (now, prev) {
+                    write!(fields, " {}={}", name, now.saturating_sub(prev)).unwrap();
+                }
+            }
+        }
+        fields
+    }
+}
+
+impl Counters {
+    fn read() -> Self {
+        let (net_rx_softirqs, net_tx_softirqs) = read_softirqs();
+        let (softnet_dropped, softnet_squeezed) = read_softnet();
+        let (nic_rx_dropped, nic_tx_dropped) = read_nic_drops();
+        Self {
+            cpu: read_cpu(),
+            net_rx_softirqs,
+            net_tx_softirqs,
+            softnet_dropped,
+            softnet_squeezed,
+            nic_rx_dropped,
+            nic_tx_dropped,
+        }
+    }
+}
+
+/// Busy and all jiffies of the `cpu` line: user nice system idle iowait irq softirq steal
+fn read_cpu() -> Option<(u64, u64)> {
+    let stat = fs::read_to_string("/proc/stat").ok()?;
+    let line = stat.lines().find(|line| line.starts_with("cpu "))?;
+    let jiffies: Vec<u64> = line
+        .split_whitespace()
+        .skip(1)
+        .take(8)
+        .filter_map(|n| n.parse().ok())
+        .collect();
+    if jiffies.len() < 5 {
+        return None;
+    }
+    let total: u64 = jiffies.iter().sum();
+    let idle = jiffies[3] + jiffies[4];
+    Some((total - idle, total))
+}
+
+/// `NET_RX` and `NET_TX` of all CPUs
+fn read_softirqs() -> (Option<u64>, Option<u64>) {
+    let softirqs = match fs::read_to_string("/proc/softirqs") {
+        Ok(softirqs) => softirqs,
+        Err(_) => return (None, None),
+    };
+    let sum = |name: &str| {
+        let line = softirqs
+            .lines()
+            .find(|line| line.trim_start().starts_with(name))?;
+        Some(
+            line.split_whitespace()
+                .skip(1)
+                .filter_map(|n| n.parse::<u64>().ok())
+                .sum(),
+        )
+    };
+    (sum("NET_RX:"), sum("NET_TX:"))
+}
+
+/// Dropped and squeezed of all CPUs, the second and third hex columns
+fn read_softnet() -> (Option<u64>, Option<u64>) {
+    let softnet = match fs::read_to_string("/proc/net/softnet_stat") {
+        Ok(softnet) => softnet,
+        Err(_) => return (None, None),
+    };
+    let (mut dropped, mut squeezed) = (0, 0);
+    for line in softnet.lines() {
+        let mut columns = line
+            .split_whitespace()
+            .map(|n| u64::from_str_radix(n, 16).unwrap_or_default());
+        dropped += columns.nth(1).unwrap_or_default();
+        squeezed += columns.next().unwrap_or_default();
+    }
+    (Some(dropped), Some(squeezed))
+}
+
+/// Receive and transmit drops of all interfaces but loopback
+fn read_nic_drops() -> (Option<u64>, Option<u64>) {
+    let dev = match fs::read_to_string("/proc/net/dev") {
+        Ok(dev) => dev,
+        Err(_) => return (None, None),
+    };
+    let (mut rx, mut tx) = (0, 0);
+    // After two header lines: `name: rx_bytes rx_packets errs drop ... tx_bytes ...`
+    for line in dev.lines().skip(2) {
+        let (name, counters) = match line.split_once(':') {
+            Some(split) => split,
+            None => continue,
+        };
+        if name.trim() == "lo" {
+            continue;
+        }
+        let counters: Vec<u64> = counters
+            .split_whitespace()
+            .map(|n| n.parse().unwrap_or_default())
+            .collect();
+        rx += counters.get(3).copied().unwrap_or_default();
+        tx += counters.get(11).copied().unwrap_or_default();
+    }
+    (Some(rx), Some(tx))
+}
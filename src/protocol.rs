@@ -0,0 +1,562 @@
+//! Wire format of packets exchanged by servers and clients
+//!
+//! Every packet starts with a prefix: `MAGIC`, `VERSION` and a one byte type:
+//! * `l` - a client joins, the server starts sending it data packets: `JoinBody`.
+//!   Without a valid cookie the server replies with a challenge instead;
+//! * `c` - a challenge from the server: the cookie the client must echo in a new join;
+//! * `a` - the server accepts a client: `AckBody`, the session assigned to the client
+//!   and the address the server sees it at;
+//! * `s` - a client stops, the server forgets it. Carries the session if it is known;
+//! * `d` - a data packet from the server: `DataHeader`, the payload and its CRC32.
+//!   With redundancy the payload starts with the frames of the previous packets.
+//!   Clients in the upload direction send data packets to the server instead;
+//! * `r` - a reply from the client: the data packet sent back with the type replaced,
+//!   the reply time and the downlink `Report` filled in;
+//! * `o` - a downlink report of a client in the download direction, which doesn't reply
+//!   to data packets: `ReportBody`;
+//! * `e` - the server rejects a client, followed by a text reason;
+//! * `b` - the server shuts down, followed by the session of the client;
+//! * `q` - a statistics query, answered with a `q` packet with the current statistics
+//!   as `key=value` lines. The answer is cut to the query length after a whole line,
+//!   so queries must be padded, e.g. to 1472 bytes. A query too short for a line is
+//!   answered with `error="pad the query"`. Queries of a source are rate limited;
+//! * `t` - clock synchronization: `SyncBody`. The server sends it with its send time,
+//!   the client fills in its receive and send times and sends it back;
+//! * `m` - a path MTU probe from the server: the session padded to the probe size, sent
+//!   with DF set. The client sends it back as it is;
+//! * `p` - a ping: `PingBody`, reflected by the server as it is. Needs no join, e.g. for
+//!   RTT spot checks or to keep a NAT binding open;
+//!
+//! Multi-byte numbers are big-endian. In the RTP format data packets and replies are
+//! prefixed with an RTP header, see `rtp`. With authentication keys every packet ends
+//! with an authentication trailer, see `auth`.
+
+use crate::error::Error;
+use crate::rtp;
+use std::convert::TryInto;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+
+pub const MAGIC: [u8; 3] = *b"UJT";
+pub const VERSION: u8 = 10;
+/// Magic, version and packet type
+pub const PREFIX_LEN: usize = MAGIC.len() + 2;
+
+pub const JOIN: u8 = b'l';
+pub const CHALLENGE: u8 = b'c';
+pub const ACK: u8 = b'a';
+pub const STOP: u8 = b's';
+pub const DATA: u8 = b'd';
+pub const REPLY: u8 = b'r';
+pub const REJECT: u8 = b'e';
+pub const SYNC: u8 = b't';
+pub const BYE: u8 = b'b';
+pub const QUERY: u8 = b'q';
+pub const PING: u8 = b'p';
+pub const REPORT: u8 = b'o';
+pub const PROBE: u8 = b'm';
+
+pub const COOKIE_LEN: usize = 20;
+/// Cookie, session, interval, packet size, direction and stream. Joins are never shorter
+/// than challenges, so the server can't be used to amplify spoofed traffic
+pub const JOIN_BODY_LEN: usize = COOKIE_LEN + 4 + 4 + 2 + 1 + 1;
+
+/// Prefix, session, packet counter, send time, reply time, flags, redundancy, stream
+/// and report
+pub const DATA_HEADER_LEN: usize = PREFIX_LEN + 27 + REPORT_LEN;
+const REPORT_LEN: usize = 28;
+pub const CRC_LEN: usize = 4;
+/// Data packets without payload
+pub const MIN_DATA_LEN: usize = DATA_HEADER_LEN + CRC_LEN;
+/// The biggest UDP payload over IPv4
+pub const MAX_PKT_LEN: usize = 65507;
+
+/// Set in replies to data packets with a wrong CRC
+pub const FLAG_CORRUPTED: u8 = 1;
+/// Set by the server on the first packet of a talk spurt
+pub const FLAG_SPURT_START: u8 = 2;
+/// Set by the server on packets sent during silence
+pub const FLAG_COMFORT_NOISE: u8 = 4;
+/// Set by the server on packets of back-to-back trains
+pub const FLAG_TRAIN: u8 = 8;
+
+/// How data packets are framed on the wire
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Native,
+    /// Native packets as the payload of RTP packets
+    Rtp,
+}
+
+/// Header of data packets, replies carry it back with the reply time filled in
+#[derive(Debug, Clone, Copy)]
+pub struct DataHeader {
+    pub session: u32,
+    /// Number of the send tick, gaps in the echoed replies are lost packets
+    pub seq: u32,
+    /// Microseconds since the server start
+    pub time_us: u64,
+    /// Microseconds of the client clock when it replied, 0 in data packets
+    pub reply_us: u64,
+    /// `FLAG_*` bits, set by the server in data packets and added by the client in replies
+    pub flags: u8,
+    /// Number of frames of the previous packets carried before the frame of this one
+    pub redundancy: u8,
+    /// The stream of the client the packet belongs to, see `JoinBody`
+    pub stream: u8,
+    pub report: Report,
+}
+
+/// Downlink statistics of a client since it joined, filled in by the client in replies.
+/// Zeros in data packets
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Report {
+    pub expected: u32,
+    pub received: u32,
+    /// Received after a packet with a bigger sequence number, too late for playout
+    pub late: u32,
+    /// Interarrival jitter in microseconds, RFC 3550
+    pub jitter_us: u32,
+    /// Lost frames which arrived in the redundancy of later packets
+    pub recovered: u32,
+    /// Received packets with the ECN codepoint ECT(0) or ECT(1), and with CE
+    pub ect: u32,
+    pub ce: u32,
+}
+
+/// Body of `JOIN` packets
+#[derive(Debug, Clone, Copy)]
+pub struct JoinBody {
+    /// All zeros if the client has no cookie yet
+    pub cookie: [u8; COOKIE_LEN],
+    /// A session to resume from a new address, 0 for a new client
+    pub session: u32,
+    /// Requested interval between data packets, 0 for the server default
+    pub interval_us: u32,
+    /// Requested size of data packets, 0 for the server default
+    pub packet_size: u16,
+    pub direction: Direction,
+    /// Clients with several parallel streams number them from 1, each joins on its own.
+    /// 0 for the only stream
+    pub stream: u8,
+}
+
+/// Which way data packets go
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Direction {
+    /// The server sends data packets, the client replies to each of them
+    Both,
+    /// The server sends data packets, the client only sends periodic reports
+    Download,
+    /// The client sends data packets, the server measures them
+    Upload,
+}
+
+/// Session and report
+pub const REPORT_BODY_LEN: usize = 4 + REPORT_LEN;
+
+/// Body of `REPORT` packets
+#[derive(Debug, Clone, Copy)]
+pub struct ReportBody {
+    pub session: u32,
+    pub report: Report,
+}
+
+/// Session and the three timestamps of the exchange
+pub const SYNC_BODY_LEN: usize = 4 + 3 * 8;
+
+/// Body of `SYNC` packets, times are microseconds of the clock of each side
+#[derive(Debug, Clone, Copy)]
+pub struct SyncBody {
+    pub session: u32,
+    /// The server sent the packet
+    pub t1: u64,
+    /// The client received it
+    pub t2: u64,
+    /// The client sent it back
+    pub t3: u64,
+}
+
+impl Format {
+    /// Bytes added to native packets
+    pub fn overhead(self) -> usize {
+        match self {
+            Format::Native => 0,
+            Format::Rtp => rtp::HEADER_LEN,
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "native" => Ok(Format::Native),
+            "rtp" => Ok(Format::Rtp),
+            _ => Err(Error::new(format!(
+                "Unknown format: {}. Expected native or rtp",
+                s
+            ))),
+        }
+    }
+}
+
+/// Body of `ACK` packets. `addr` is the address of the client seen by the server, like
+/// the mapped address of STUN: behind a NAT it is the public side of the mapping.
+/// Servers of older builds don't send it
+#[derive(Debug, Clone, Copy)]
+pub struct AckBody {
+    pub session: u32,
+    pub addr: Option<SocketAddr>,
+}
+
+/// Sequence number and send time
+pub const PING_BODY_LEN: usize = 4 + 8;
+
+/// Body of `PING` packets, only the sender interprets it
+#[derive(Debug, Clone, Copy)]
+pub struct PingBody {
+    pub seq: u32,
+    /// Microseconds of the sender clock
+    pub time_us: u64,
+}
+
+/// Why a packet is not a valid packet of this protocol
+#[derive(Debug)]
+pub enum PrefixError {
+    /// Probably not our packet at all: a scanner or a misdirected packet
+    Magic,
+    /// A peer speaking another version of the protocol
+    Version(u8),
+}
+
+pub fn write_prefix(pkt_type: u8, buf: &mut Vec<u8>) {
+    buf.extend_from_slice(&MAGIC);
+    buf.push(VERSION);
+    buf.push(pkt_type);
+}
+
+/// Validates the prefix and returns the packet type
+pub fn parse_type(pkt: &[u8]) -> Result<u8, PrefixError> {
+    if pkt.len() < PREFIX_LEN || pkt[..MAGIC.len()] != MAGIC {
+        return Err(PrefixError::Magic);
+    }
+
+    let version = pkt[MAGIC.len()];
+    if version != VERSION {
+        return Err(PrefixError::Version(version));
+    }
+
+    Ok(pkt[PREFIX_LEN - 1])
+}
+
+/// Changes the type of a packet with a valid prefix
+pub fn set_type(pkt: &mut [u8], pkt_type: u8) {
+    pkt[PREFIX_LEN - 1] = pkt_type;
+}
+
+/// Packet data after the prefix
+pub fn body(pkt: &[u8]) -> &[u8] {
+    &pkt[PREFIX_LEN..]
+}
+
+/// Parses a session carried in the body of `ACK` and `STOP` packets
+pub fn parse_session(body: &[u8]) -> Option<u32> {
+    Some(u32::from_be_bytes(body.get(..4)?.try_into().unwrap()))
+}
+
+/// Appends the CRC32 of the payload of a data packet starting at `start` of `pkt`
+pub fn append_crc(pkt: &mut Vec<u8>, start: usize) {
+    let crc = crc32fast::hash(&pkt[start + DATA_HEADER_LEN..]);
+    pkt.extend_from_slice(&crc.to_be_bytes());
+}
+
+/// Checks the CRC32 of the payload of a data packet or a reply
+pub fn is_crc_valid(pkt: &[u8]) -> bool {
+    if pkt.len() < MIN_DATA_LEN {
+        return false;
+    }
+    let (data, crc) = pkt.split_at(pkt.len() - CRC_LEN);
+    crc32fast::hash(&data[DATA_HEADER_LEN..]).to_be_bytes() == crc
+}
+
+/// Makes a packet without a header, e.g. `JOIN` or `REJECT`
+pub fn control_pkt(pkt_type: u8, body: &[u8]) -> Vec<u8> {
+    let mut pkt = Vec::with_capacity(PREFIX_LEN + body.len());
+    write_prefix(pkt_type, &mut pkt);
+    pkt.extend_from_slice(body);
+    pkt
+}
+
+impl DataHeader {
+    pub fn write(&self, pkt_type: u8, buf: &mut Vec<u8>) {
+        write_prefix(pkt_type, buf);
+        buf.extend_from_slice(&self.session.to_be_bytes());
+        buf.extend_from_slice(&self.seq.to_be_bytes());
+        buf.extend_from_slice(&self.time_us.to_be_bytes());
+        buf.extend_from_slice(&self.reply_us.to_be_bytes());
+        buf.push(self.flags);
+        buf.push(self.redundancy);
+        buf.push(self.stream);
+        self.report.write(buf);
+    }
+
+    /// Parses the header of a data or reply packet, the prefix is not checked
+    pub fn parse(pkt: &[u8]) -> Result<Self, Error> {
+        if pkt.len() < DATA_HEADER_LEN {
+            return Err(Error::new(format!(
+                "Received too short packet, len: {}",
+                pkt.len()
+            )));
+        }
+
+        let body = body(pkt);
+        Ok(Self {
+            session: u32::from_be_bytes(body[0..4].try_into().unwrap()),
+            seq: u32::from_be_bytes(body[4..8].try_into().unwrap()),
+            time_us: u64::from_be_bytes(body[8..16].try_into().unwrap()),
+            reply_us: u64::from_be_bytes(body[16..24].try_into().unwrap()),
+            flags: body[24],
+            redundancy: body[25],
+            stream: body[26],
+            report: Report::parse(&body[27..27 + REPORT_LEN]),
+        })
+    }
+
+    /// Changes the reply time of a written header
+    pub fn set_reply_time(pkt: &mut [u8], reply_us: u64) {
+        pkt[PREFIX_LEN + 16..PREFIX_LEN + 24].copy_from_slice(&reply_us.to_be_bytes());
+    }
+
+    /// Changes the flags of a written header
+    pub fn set_flags(pkt: &mut [u8], flags: u8) {
+        pkt[PREFIX_LEN + 24] = flags;
+    }
+
+    /// Changes the report of a written header
+    pub fn set_report(pkt: &mut [u8], report: &Report) {
+        let mut buf = Vec::with_capacity(REPORT_LEN);
+        report.write(&mut buf);
+        pkt[PREFIX_LEN + 27..DATA_HEADER_LEN].copy_from_slice(&buf);
+    }
+}
+
+impl Report {
+    fn write(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.expected.to_be_bytes());
+        buf.extend_from_slice(&self.received.to_be_bytes());
+        buf.extend_from_slice(&self.late.to_be_bytes());
+        buf.extend_from_slice(&self.jitter_us.to_be_bytes());
+        buf.extend_from_slice(&self.recovered.to_be_bytes());
+        buf.extend_from_slice(&self.ect.to_be_bytes());
+        buf.extend_from_slice(&self.ce.to_be_bytes());
+    }
+
+    fn parse(body: &[u8]) -> Self {
+        let u32_at = |pos: usize| u32::from_be_bytes(body[pos..pos + 4].try_into().unwrap());
+        Self {
+            expected: u32_at(0),
+            received: u32_at(4),
+            late: u32_at(8),
+            jitter_us: u32_at(12),
+            recovered: u32_at(16),
+            ect: u32_at(20),
+            ce: u32_at(24),
+        }
+    }
+}
+
+impl JoinBody {
+    /// Writes the whole `JOIN` packet
+    pub fn write(&self, buf: &mut Vec<u8>) {
+        write_prefix(JOIN, buf);
+        buf.extend_from_slice(&self.cookie);
+        buf.extend_from_slice(&self.session.to_be_bytes());
+        buf.extend_from_slice(&self.interval_us.to_be_bytes());
+        buf.extend_from_slice(&self.packet_size.to_be_bytes());
+        buf.push(self.direction as u8);
+        buf.push(self.stream);
+    }
+
+    pub fn parse(body: &[u8]) -> Result<Self, Error> {
+        if body.len() < JOIN_BODY_LEN {
+            return Err(Error::new(format!(
+                "Too short join packet, body len: {}",
+                body.len()
+            )));
+        }
+
+        let (cookie, body) = body.split_at(COOKIE_LEN);
+        Ok(Self {
+            cookie: cookie.try_into().unwrap(),
+            session: u32::from_be_bytes(body[0..4].try_into().unwrap()),
+            interval_us: u32::from_be_bytes(body[4..8].try_into().unwrap()),
+            packet_size: u16::from_be_bytes(body[8..10].try_into().unwrap()),
+            direction: match body[10] {
+                0 => Direction::Both,
+                1 => Direction::Download,
+                2 => Direction::Upload,
+                x => return Err(Error::new(format!("Unknown direction: {}", x))),
+            },
+            stream: body[11],
+        })
+    }
+}
+
+impl FromStr for Direction {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "both" => Ok(Direction::Both),
+            "download" => Ok(Direction::Download),
+            "upload" => Ok(Direction::Upload),
+            _ => Err(Error::new(format!(
+                "Unknown direction: {}. Expected both, download or upload",
+                s
+            ))),
+        }
+    }
+}
+
+impl ReportBody {
+    /// Writes the whole `REPORT` packet
+    pub fn write(&self, buf: &mut Vec<u8>) {
+        write_prefix(REPORT, buf);
+        buf.extend_from_slice(&self.session.to_be_bytes());
+        self.report.write(buf);
+    }
+
+    pub fn parse(body: &[u8]) -> Result<Self, Error> {
+        if body.len() < REPORT_BODY_LEN {
+            return Err(Error::new(format!(
+                "Too short report packet, body len: {}",
+                body.len()
+            )));
+        }
+
+        Ok(Self {
+            session: u32::from_be_bytes(body[..4].try_into().unwrap()),
+            report: Report::parse(&body[4..REPORT_BODY_LEN]),
+        })
+    }
+}
+
+impl SyncBody {
+    /// Writes the whole `SYNC` packet
+    pub fn write(&self, buf: &mut Vec<u8>) {
+        write_prefix(SYNC, buf);
+        buf.extend_from_slice(&self.session.to_be_bytes());
+        buf.extend_from_slice(&self.t1.to_be_bytes());
+        buf.extend_from_slice(&self.t2.to_be_bytes());
+        buf.extend_from_slice(&self.t3.to_be_bytes());
+    }
+
+    pub fn parse(body: &[u8]) -> Result<Self, Error> {
+        if body.len() < SYNC_BODY_LEN {
+            return Err(Error::new(format!(
+                "Too short sync packet, body len: {}",
+                body.len()
+            )));
+        }
+
+        let u64_at = |pos: usize| u64::from_be_bytes(body[pos..pos + 8].try_into().unwrap());
+        Ok(Self {
+            session: u32::from_be_bytes(body[..4].try_into().unwrap()),
+            t1: u64_at(4),
+            t2: u64_at(12),
+            t3: u64_at(20),
+        })
+    }
+}
+
+impl AckBody {
+    /// Writes the whole `ACK` packet: the session, then the address family (4 or 6),
+    /// the IP and the port
+    pub fn write(&self, buf: &mut Vec<u8>) {
+        write_prefix(ACK, buf);
+        buf.extend_from_slice(&self.session.to_be_bytes());
+        if let Some(addr) = self.addr {
+            match addr.ip() {
+                IpAddr::V4(ip) => {
+                    buf.push(4);
+                    buf.extend_from_slice(&ip.octets());
+                }
+                IpAddr::V6(ip) => {
+                    buf.push(6);
+                    buf.extend_from_slice(&ip.octets());
+                }
+            }
+            buf.extend_from_slice(&addr.port().to_be_bytes());
+        }
+    }
+
+    pub fn parse(body: &[u8]) -> Result<Self, Error> {
+        let session = parse_session(body).ok_or_else(|| {
+            Error::new(format!("Too short accept packet, body len: {}", body.len()))
+        })?;
+        let addr = match body.get(4) {
+            None => None,
+            Some(&family) => {
+                let (ip, rest): (IpAddr, _) = match family {
+                    4 if body.len() >= 4 + 1 + 4 + 2 => {
+                        let octets: [u8; 4] = body[5..9].try_into().unwrap();
+                        (Ipv4Addr::from(octets).into(), &body[9..])
+                    }
+                    6 if body.len() >= 4 + 1 + 16 + 2 => {
+                        let octets: [u8; 16] = body[5..21].try_into().unwrap();
+                        (Ipv6Addr::from(octets).into(), &body[21..])
+                    }
+                    _ => {
+                        return Err(Error::new(format!(
+                            "Wrong address in an accept packet, family: {}, body len: {}",
+                            family,
+                            body.len()
+                        )))
+                    }
+                };
+                let port = u16::from_be_bytes(rest[..2].try_into().unwrap());
+                Some(SocketAddr::new(ip, port))
+            }
+        };
+        Ok(Self { session, addr })
+    }
+}
+
+impl PingBody {
+    /// Writes the whole `PING` packet
+    pub fn write(&self, buf: &mut Vec<u8>) {
+        write_prefix(PING, buf);
+        buf.extend_from_slice(&self.seq.to_be_bytes());
+        buf.extend_from_slice(&self.time_us.to_be_bytes());
+    }
+
+    pub fn parse(body: &[u8]) -> Result<Self, Error> {
+        if body.len() < PING_BODY_LEN {
+            return Err(Error::new(format!(
+                "Too short ping packet, body len: {}",
+                body.len()
+            )));
+        }
+
+        Ok(Self {
+            seq: u32::from_be_bytes(body[..4].try_into().unwrap()),
+            time_us: u64::from_be_bytes(body[4..12].try_into().unwrap()),
+        })
+    }
+}
+
+impl fmt::Display for PrefixError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PrefixError::Magic => write!(f, "Not a protocol packet"),
+            PrefixError::Version(v) => write!(
+                f,
+                "Unsupported protocol version: {}, expected: {}",
+                v, VERSION
+            ),
+        }
+    }
+}
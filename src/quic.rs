@@ -0,0 +1,251 @@
+//! QUIC DATAGRAM transport, built with the `quic` feature
+//!
+//! Test packets are carried unchanged in DATAGRAM frames (RFC 9221) of a QUIC connection,
+//! so middleboxes see QUIC-encapsulated media instead of plain UDP. Both sides relay them
+//! to and from the usual UDP sockets over loopback: the server sees a QUIC client as a
+//! client at a local address, and the test logic is the same as with plain UDP.
+//!
+//! The server uses a self-signed certificate that clients don't verify: the connection
+//! only encapsulates the test traffic.
+
+use crate::error::Error;
+use crate::net::{any_addr, loopback_addr};
+use async_std::net::UdpSocket;
+use async_std::task;
+use bytes::Bytes;
+use futures::try_join;
+use log::{debug, info, warn};
+use quinn::crypto::rustls::{QuicClientConfig, QuicServerConfig};
+use quinn::rustls::{self, pki_types};
+use quinn::{ClientConfig, Connection, Endpoint, Incoming, SendDatagramError, ServerConfig};
+use std::convert::TryFrom;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const SERVER_NAME: &str = "udp-jitter-test";
+const ALPN: &[u8] = b"ujt";
+/// Keeps NAT bindings of idle connections, e.g. of clients in the upload direction
+const KEEP_ALIVE_INTERVAL: Duration = Duration::from_secs(5);
+
+/// Accepts QUIC clients on `bind` and relays their datagrams to the UDP `server`
+pub async fn serve(bind: SocketAddr, server: SocketAddr) -> Result<(), Error> {
+    let endpoint = Endpoint::server(server_config()?, bind)?;
+    info!("Accepting QUIC clients on {}", bind);
+
+    while let Some(incoming) = endpoint.accept().await {
+        task::spawn(async move {
+            let remote = incoming.remote_address();
+            if let Err(e) = relay_client(incoming, server).await {
+                info!("QUIC connection of {} is closed: {}", remote, e);
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Connects to the QUIC `server` and returns the local address relaying datagrams to it
+pub async fn connect(server: SocketAddr) -> Result<SocketAddr, Error> {
+    let mut endpoint = Endpoint::client(any_addr(server.ip()))?;
+    endpoint.set_default_client_config(client_config()?);
+    let conn = endpoint
+        .connect(server, SERVER_NAME)
+        .map_err(|e| Error::new(format!("Cannot connect to {} over QUIC: {}", server, e)))?
+        .await
+        .map_err(|e| Error::new(format!("Cannot connect to {} over QUIC: {}", server, e)))?;
+    if conn.max_datagram_size().is_none() {
+        return Err(Error::new(format!(
+            "{} doesn't support QUIC datagrams",
+            server
+        )));
+    }
+
+    let socket = UdpSocket::bind(loopback_addr(server.ip())).await?;
+    let addr = socket.local_addr()?;
+    info!("Connected to {} over QUIC, relaying from {}", server, addr);
+    task::spawn(async move {
+        // The endpoint is closed when dropped
+        let _endpoint = endpoint;
+        // The client's address is known from its first packet
+        if let Err(e) = relay(&conn, &socket, None).await {
+            warn!("QUIC connection to {} is closed: {}", server, e);
+        }
+    });
+    Ok(addr)
+}
+
+async fn relay_client(incoming: Incoming, server: SocketAddr) -> Result<(), Error> {
+    let conn = incoming.await.map_err(|e| Error::new(e.to_string()))?;
+    let socket = UdpSocket::bind(loopback_addr(server.ip())).await?;
+    info!(
+        "QUIC client {} is relayed from {}",
+        conn.remote_address(),
+        socket.local_addr()?
+    );
+    relay(&conn, &socket, Some(server)).await
+}
+
+/// Relays datagrams of `conn` to `peer` and back. `peer` becomes the sender of the latest
+/// packet received by `socket`
+async fn relay(
+    conn: &Connection,
+    socket: &UdpSocket,
+    peer: Option<SocketAddr>,
+) -> Result<(), Error> {
+    // Both halves run on the multi-threaded executor
+    let peer = Mutex::new(peer);
+    try_join!(
+        relay_to_udp(conn, socket, &peer),
+        relay_to_quic(conn, socket, &peer)
+    )?;
+    Ok(())
+}
+
+async fn relay_to_udp(
+    conn: &Connection,
+    socket: &UdpSocket,
+    peer: &Mutex<Option<SocketAddr>>,
+) -> Result<(), Error> {
+    loop {
+        let datagram = conn
+            .read_datagram()
+            .await
+            .map_err(|e| Error::new(e.to_string()))?;
+        let peer = *peer.lock().unwrap();
+        match peer {
+            Some(peer) => {
+                socket.send_to(&datagram, peer).await?;
+            }
+            None => debug!("Datagram from {} before any packet", conn.remote_address()),
+        }
+    }
+}
+
+async fn relay_to_quic(
+    conn: &Connection,
+    socket: &UdpSocket,
+    peer: &Mutex<Option<SocketAddr>>,
+) -> Result<(), Error> {
+    const BUF_LEN: usize = 65535;
+    let mut buf = vec![0; BUF_LEN];
+    let mut too_large_seen = false;
+    loop {
+        let (len, addr) = socket.recv_from(&mut buf).await?;
+        *peer.lock().unwrap() = Some(addr);
+
+        match conn.send_datagram(Bytes::copy_from_slice(&buf[..len])) {
+            Ok(()) => (),
+            // Datagrams are never fragmented, bigger packets are lost
+            Err(SendDatagramError::TooLarge) => {
+                if !too_large_seen {
+                    too_large_seen = true;
+                    warn!(
+                        "Packets of {} bytes don't fit QUIC datagrams to {}, max is {:?}",
+                        len,
+                        conn.remote_address(),
+                        conn.max_datagram_size()
+                    );
+                }
+            }
+            Err(e) => return Err(Error::new(e.to_string())),
+        }
+    }
+}
+
+fn server_config() -> Result<ServerConfig, Error> {
+    let cert = rcgen::generate_simple_self_signed(vec![SERVER_NAME.to_owned()])
+        .map_err(|e| Error::new(format!("Cannot generate a certificate: {}", e)))?;
+    let key = pki_types::PrivatePkcs8KeyDer::from(cert.key_pair.serialize_der());
+
+    let mut crypto = rustls::ServerConfig::builder_with_provider(provider())
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .map_err(tls_error)?
+        .with_no_client_auth()
+        .with_single_cert(vec![cert.cert.der().clone()], key.into())
+        .map_err(tls_error)?;
+    crypto.alpn_protocols = vec![ALPN.to_vec()];
+
+    let crypto = QuicServerConfig::try_from(crypto).map_err(|e| Error::new(e.to_string()))?;
+    let mut config = ServerConfig::with_crypto(Arc::new(crypto));
+    config.transport_config(transport_config());
+    Ok(config)
+}
+
+fn client_config() -> Result<ClientConfig, Error> {
+    let provider = provider();
+    let mut crypto = rustls::ClientConfig::builder_with_provider(provider.clone())
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .map_err(tls_error)?
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(NoCertificateVerification(provider)))
+        .with_no_client_auth();
+    crypto.alpn_protocols = vec![ALPN.to_vec()];
+
+    let crypto = QuicClientConfig::try_from(crypto).map_err(|e| Error::new(e.to_string()))?;
+    let mut config = ClientConfig::new(Arc::new(crypto));
+    config.transport_config(transport_config());
+    Ok(config)
+}
+
+fn transport_config() -> Arc<quinn::TransportConfig> {
+    let mut config = quinn::TransportConfig::default();
+    config.keep_alive_interval(Some(KEEP_ALIVE_INTERVAL));
+    Arc::new(config)
+}
+
+fn provider() -> Arc<rustls::crypto::CryptoProvider> {
+    Arc::new(rustls::crypto::ring::default_provider())
+}
+
+fn tls_error(e: rustls::Error) -> Error {
+    Error::new(format!("TLS: {}", e))
+}
+
+/// Accepts any certificate of the server, but still checks the handshake signatures
+#[derive(Debug)]
+struct NoCertificateVerification(Arc<rustls::crypto::CryptoProvider>);
+
+impl rustls::client::danger::ServerCertVerifier for NoCertificateVerification {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &pki_types::CertificateDer<'_>,
+        _intermediates: &[pki_types::CertificateDer<'_>],
+        _server_name: &pki_types::ServerName<'_>,
+        _ocsp: &[u8],
+        _now: pki_types::UnixTime,
+    ) -> Result<rustls::client::danger::ServerCertVerified, rustls::Error> {
+        Ok(rustls::client::danger::ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &pki_types::CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &pki_types::CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
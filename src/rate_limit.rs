@@ -0,0 +1,83 @@
+//! Per source IP rate limiting of packets which cost the server work before the sender
+//! is known: joins and packets of unknown types
+//!
+//! Every source IP has a token bucket refilled at `rate` tokens per second and holding
+//! up to a second worth of them, so a scanner or a flood from one host can't cause log
+//! spam or churn of the client list. Legitimate clients send a few joins per session.
+
+use log::{debug, warn};
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::time::Instant;
+
+/// Buckets kept at most, e.g. with spoofed sources. Sources over the limit aren't limited
+const MAX_SOURCES: usize = 65536;
+
+struct Bucket {
+    tokens: f64,
+    last: Instant,
+    /// Packets were dropped since the bucket was last refilled
+    limited: bool,
+}
+
+pub struct RateLimiter {
+    /// Tokens per second, no limit if 0
+    rate: f64,
+    buckets: HashMap<IpAddr, Bucket>,
+}
+
+impl RateLimiter {
+    pub fn new(rate: u32) -> Self {
+        Self {
+            rate: f64::from(rate),
+            buckets: HashMap::new(),
+        }
+    }
+
+    /// Takes a token of `ip`, returns false if the packet must be dropped
+    pub fn allow(&mut self, ip: IpAddr) -> bool {
+        if self.rate == 0.0 {
+            return true;
+        }
+        let now = Instant::now();
+        if self.buckets.len() >= MAX_SOURCES && !self.buckets.contains_key(&ip) {
+            self.prune(now);
+            if self.buckets.len() >= MAX_SOURCES {
+                return true;
+            }
+        }
+
+        let rate = self.rate;
+        let bucket = self.buckets.entry(ip).or_insert(Bucket {
+            tokens: rate,
+            last: now,
+            limited: false,
+        });
+        bucket.tokens =
+            (bucket.tokens + now.duration_since(bucket.last).as_secs_f64() * rate).min(rate);
+        bucket.last = now;
+
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            if bucket.limited && bucket.tokens >= rate - 1.0 {
+                bucket.limited = false;
+                debug!("{} is no longer rate limited", ip);
+            }
+            true
+        } else {
+            if !bucket.limited {
+                bucket.limited = true;
+                warn!("Rate limiting {}: over {} packets per second", ip, rate);
+            }
+            false
+        }
+    }
+
+    /// Forgets sources with full buckets, they are as good as new
+    fn prune(&mut self, now: Instant) {
+        let rate = self.rate;
+        self.buckets.retain(|_, bucket| {
+            bucket.tokens + now.duration_since(bucket.last).as_secs_f64() * rate < rate
+        });
+    }
+}
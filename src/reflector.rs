@@ -0,0 +1,74 @@
+//! `reflect` mode: a stateless TWAMP-Light session reflector
+//!
+//! Test packets shorter than reflected ones are dropped: like the server, the reflector
+//! never answers with more than it got, so it can't amplify spoofed traffic.
+
+use crate::config::ReflectOpts;
+use crate::error::Error;
+use crate::net::{enable_recv_ttl, recv_msg, set_dscp};
+use crate::stop::run_until_stopped;
+use crate::twamp::{self, NtpTime, ReflectorPacket, SenderPacket};
+use async_std::net::UdpSocket;
+use log::{debug, info, warn};
+use std::cell::Cell;
+
+pub async fn run(opts: ReflectOpts) -> Result<(), Error> {
+    let socket = UdpSocket::bind(&opts.bind)
+        .await
+        .map_err(|e| Error::new(format!("Cannot bind to {}: {}", opts.bind, e)))?;
+    set_dscp(&socket, opts.dscp)?;
+    enable_recv_ttl(&socket)?;
+    info!(
+        "Reflecting TWAMP-Light test packets on {}",
+        socket.local_addr()?
+    );
+
+    let reflected = Cell::new(0);
+    let res = run_until_stopped(reflect(&socket, &reflected), opts.duration).await;
+    info!("Reflected {} packets", reflected.get());
+    res
+}
+
+async fn reflect(socket: &UdpSocket, reflected: &Cell<u64>) -> Result<(), Error> {
+    const BUF_LEN: usize = 65535;
+    let mut buf = vec![0; BUF_LEN];
+    let mut answer = Vec::with_capacity(BUF_LEN);
+    loop {
+        let received = recv_msg(socket, &mut buf).await?;
+        let receive_time = NtpTime::now();
+        // Symmetric sizes of RFC 6038
+        if received.len < twamp::REFLECTOR_LEN {
+            debug!(
+                "Too short test packet from {}, len: {}",
+                received.addr, received.len
+            );
+            continue;
+        }
+        let sender = match SenderPacket::parse(&buf[..received.len]) {
+            Ok(sender) => sender,
+            Err(e) => {
+                debug!("{} from {}", e, received.addr);
+                continue;
+            }
+        };
+
+        // Stateless reflectors copy the sender sequence number, RFC 8972
+        let reflector = ReflectorPacket {
+            seq: sender.seq,
+            time: NtpTime::now(),
+            error_estimate: twamp::ERROR_ESTIMATE,
+            receive_time,
+            sender_seq: sender.seq,
+            sender_time: sender.time,
+            sender_error_estimate: sender.error_estimate,
+            sender_ttl: received.ttl.unwrap_or(0),
+        };
+        answer.clear();
+        // Reflected packets are as long as the sender ones
+        reflector.write(&mut answer, received.len);
+        match socket.send_to(&answer, received.addr).await {
+            Ok(_) => reflected.set(reflected.get() + 1),
+            Err(e) => warn!("Cannot reflect to {}: {}", received.addr, e),
+        }
+    }
+}
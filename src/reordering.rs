@@ -0,0 +1,147 @@
+//! Reordering extent and late-time offset of RFC 4737
+//!
+//! A packet is reordered if it arrives after one with a bigger sequence number. Its
+//! extent is the number of packets which arrived between the earliest of those and the
+//! packet itself, included: the number of packets a receive buffer has to hold to put it
+//! back in order. Its late-time offset is the time since that earliest packet arrived:
+//! how much longer a jitter buffer has to wait for it.
+//!
+//! The earliest bigger packet is looked for among the last `HISTORY` arrivals, extents
+//! and offsets of packets later than that are counted from the oldest of them.
+
+use crate::config::StatsConfig;
+use crate::export;
+use crate::histogram::Histogram;
+use crate::statistic;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::Write as _;
+use std::time::Duration;
+
+/// Arrivals remembered to find the earliest packet with a bigger sequence number
+const HISTORY: usize = 1024;
+
+#[derive(Debug, Default, Clone)]
+pub struct Reordering {
+    /// Arrival index, sequence number and time of the latest arrivals
+    history: VecDeque<(u64, u32, Duration)>,
+    arrivals: u64,
+    max_seq: Option<u32>,
+    /// Number of reordered packets by extent
+    extents: BTreeMap<u64, u64>,
+    late_times: Histogram,
+    max_late_time: Duration,
+}
+
+impl Reordering {
+    /// Registers a packet which arrived at `time`, since any start. Duplicates are not
+    /// packets of their own, leave them out
+    pub fn on_seq(&mut self, seq: u32, time: Duration) {
+        let index = self.arrivals;
+        self.arrivals += 1;
+        if self.max_seq.is_some_and(|max| seq < max) {
+            let earliest = self
+                .history
+                .iter()
+                .find(|(_, s, _)| *s > seq)
+                .or_else(|| self.history.front());
+            if let Some(&(j, _, arrival)) = earliest {
+                *self.extents.entry(index - j).or_default() += 1;
+                let late_time = time.saturating_sub(arrival);
+                self.late_times.add(late_time);
+                self.max_late_time = self.max_late_time.max(late_time);
+            }
+        }
+        self.max_seq = Some(self.max_seq.map_or(seq, |max| max.max(seq)));
+        if self.history.len() == HISTORY {
+            self.history.pop_front();
+        }
+        self.history.push_back((index, seq, time));
+    }
+
+    /// Adds the reordered packets of another stream
+    pub fn merge(&mut self, other: &Self) {
+        for (extent, count) in &other.extents {
+            *self.extents.entry(*extent).or_default() += count;
+        }
+        self.late_times.merge(&other.late_times);
+        self.max_late_time = self.max_late_time.max(other.max_late_time);
+    }
+
+    pub fn reordered(&self) -> u64 {
+        self.extents.values().sum()
+    }
+
+    pub fn max_extent(&self) -> u64 {
+        self.extents.keys().next_back().copied().unwrap_or_default()
+    }
+
+    /// `extent:count` of every extent, comma separated
+    fn extents(&self) -> String {
+        let mut extents = String::new();
+        for (extent, count) in &self.extents {
+            let sep = if extents.is_empty() { "" } else { "," };
+            write!(extents, "{}{}:{}", sep, extent, count).unwrap();
+        }
+        extents
+    }
+
+    /// Late-time offsets at the median, p99 and the maximum in ms, `None` without
+    /// reordered packets
+    fn late_times_ms(&self, cfg: &StatsConfig) -> Option<[f64; 3]> {
+        // Quantiles are rounded up to the ends of their buckets
+        let quantile = |q| {
+            let d = self.late_times.quantile(q, cfg.percentile_method)?;
+            Some(d.min(self.max_late_time).as_secs_f64() * 1000.)
+        };
+        Some([
+            quantile(0.5)?,
+            quantile(0.99)?,
+            self.max_late_time.as_secs_f64() * 1000.,
+        ])
+    }
+
+    /// The `type=reordering` record of the statistics labeled `label`
+    pub fn record(&self, cfg: &StatsConfig, label: &str) -> String {
+        let mut rec = format!(
+            "type=reordering label={:?} reordered={} max_extent={} extents={}",
+            label,
+            self.reordered(),
+            self.max_extent(),
+            self.extents()
+        );
+        if let Some([p50, p99, max]) = self.late_times_ms(cfg) {
+            write!(
+                rec,
+                " late_time_p50_ms={:.3} late_time_p99_ms={:.3} late_time_max_ms={:.3}",
+                p50, p99, max
+            )
+            .unwrap();
+        }
+        rec
+    }
+
+    /// Prints the reordering, or its record with `quiet`, and writes the record to `--jsonl`
+    pub fn print(&self, cfg: &StatsConfig, label: &str) {
+        let rec = self.record(cfg, label);
+        if let Some(path) = &cfg.jsonl {
+            export::append_json(path, &rec);
+        }
+        if cfg.quiet {
+            println!("{}", rec);
+            return;
+        }
+        match self.late_times_ms(cfg) {
+            None => println!("{} reordering (RFC 4737): none", label),
+            Some([p50, p99, max]) => {
+                println!(
+                    "{} reordering (RFC 4737): {} packets, extent up to {}, late-time offset {} p50/p99/max",
+                    label,
+                    self.reordered(),
+                    self.max_extent(),
+                    statistic::format_ms_list(cfg, &[p50, p99, max])
+                );
+                println!("Reordering extents: {}", self.extents().replace(',', ", "));
+            }
+        }
+    }
+}
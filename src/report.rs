@@ -0,0 +1,57 @@
+//! Reports of `--report-to`: the window statistics of every interval sent to a `collect`
+//! instance, a `type=report` record per UDP datagram, e.g.
+//! `type=report time=1700000000.000 label="RTT" samples=150 avg_ms=12.345 ... site="branch-1"`
+//!
+//! The keys are the columns of `--csv`. Reports are fire and forget: a collector which is
+//! down loses them, the test goes on.
+
+use crate::export::IntervalRow;
+use log::{debug, warn};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::net::{SocketAddr, ToSocketAddrs, UdpSocket};
+use std::sync::Mutex;
+
+/// Sockets by collector address, `None` if the address can't be resolved or bound for
+static SOCKETS: Mutex<BTreeMap<String, Option<(UdpSocket, SocketAddr)>>> =
+    Mutex::new(BTreeMap::new());
+
+/// Sends the statistics of `row` of a probe at `site` to the collector at `target`
+pub fn send(target: &str, site: Option<&str>, row: &IntervalRow) {
+    let mut rec = row.to_record("report");
+    if let Some(site) = site {
+        write!(rec, " site={:?}", site).unwrap();
+    }
+    let mut sockets = SOCKETS.lock().unwrap_or_else(|e| e.into_inner());
+    let socket = sockets
+        .entry(target.to_owned())
+        .or_insert_with(|| match connect(target) {
+            Ok(socket) => Some(socket),
+            Err(e) => {
+                warn!("Cannot report to {}: {}", target, e);
+                None
+            }
+        });
+    if let Some((socket, addr)) = socket {
+        if let Err(e) = socket.send_to(rec.as_bytes(), *addr) {
+            debug!("Cannot send a report to {}: {}", addr, e);
+        }
+    }
+}
+
+/// A non-blocking socket to send datagrams to `target`, and its address
+pub fn connect(target: &str) -> std::io::Result<(UdpSocket, SocketAddr)> {
+    let addr = target
+        .to_socket_addrs()?
+        .next()
+        .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "no address found"))?;
+    let bind: SocketAddr = if addr.is_ipv4() {
+        ([0, 0, 0, 0], 0).into()
+    } else {
+        ([0u16; 8], 0).into()
+    };
+    let socket = UdpSocket::bind(bind)?;
+    // Never blocks the test, a report which doesn't fit in the socket buffer is dropped
+    socket.set_nonblocking(true)?;
+    Ok((socket, addr))
+}
@@ -0,0 +1,205 @@
+//! RTCP sender and receiver reports (RFC 3550) of the RTP format, multiplexed with data
+//! packets on the same port (RFC 5761).
+//!
+//! The server sends a sender report to every client on each clock synchronization,
+//! a client answers with a receiver report on its downlink. LSR and DLSR of the report
+//! give the server the round trip time. Compound packets end with an SDES CNAME, as
+//! RFC 3550 requires. Multi-byte numbers are big-endian.
+
+use crate::error::Error;
+use crate::twamp::NtpTime;
+use std::convert::TryInto;
+use std::time::Duration;
+
+const RTCP_VERSION: u8 = 2;
+pub const SR: u8 = 200;
+pub const RR: u8 = 201;
+const SDES: u8 = 202;
+/// The last RTCP packet type
+const APP: u8 = 204;
+const CNAME: u8 = 1;
+
+/// Version, count, packet type and length
+const HEADER_LEN: usize = 4;
+const SENDER_INFO_LEN: usize = 20;
+const BLOCK_LEN: usize = 24;
+
+/// Sender part of a sender report
+#[derive(Debug, Clone, Copy)]
+pub struct SenderInfo {
+    pub ntp_time: NtpTime,
+    /// RTP timestamp of the same moment as `ntp_time`
+    pub rtp_timestamp: u32,
+    pub packets: u32,
+    /// Payload octets, without RTP headers
+    pub octets: u32,
+}
+
+/// Reception statistics of one RTP stream
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ReportBlock {
+    pub ssrc: u32,
+    /// Lost part of packets expected since the previous report, in 1/256 units
+    pub fraction_lost: u8,
+    /// 24 bits wide
+    pub cumulative_lost: u32,
+    /// Extended highest sequence number received
+    pub highest_seq: u32,
+    /// Interarrival jitter in RTP timestamp units
+    pub jitter: u32,
+    /// Middle 32 bits of the NTP time of the last sender report, 0 if there was none
+    pub lsr: u32,
+    /// Delay since the last sender report in 1/65536 seconds
+    pub dlsr: u32,
+}
+
+/// A sender report if `sender_info` is set, otherwise a receiver report
+#[derive(Debug, Clone)]
+pub struct ReportPacket {
+    pub ssrc: u32,
+    pub sender_info: Option<SenderInfo>,
+    pub blocks: Vec<ReportBlock>,
+}
+
+/// Whether a packet arriving on the RTP port is RTCP: its packet type takes the place
+/// of the marker bit and the payload type of RTP
+pub fn is_rtcp(pkt: &[u8]) -> bool {
+    pkt.len() >= HEADER_LEN && pkt[0] >> 6 == RTCP_VERSION && (SR..=APP).contains(&pkt[1])
+}
+
+/// Converts a compact NTP duration, e.g. DLSR, to a `Duration`
+pub fn compact_to_duration(units: u32) -> Duration {
+    Duration::from_micros((u64::from(units) * 1_000_000) >> 16)
+}
+
+/// Converts a `Duration` to compact NTP units, saturating
+pub fn duration_to_compact(d: Duration) -> u32 {
+    ((d.as_micros() << 16) / 1_000_000)
+        .try_into()
+        .unwrap_or(u32::MAX)
+}
+
+impl ReportPacket {
+    /// Writes a compound packet: the report followed by SDES with `cname`
+    pub fn write(&self, cname: &str, buf: &mut Vec<u8>) {
+        let (pkt_type, len) = match self.sender_info {
+            Some(_) => (SR, 4 + SENDER_INFO_LEN),
+            None => (RR, 4),
+        };
+        let len = len + BLOCK_LEN * self.blocks.len();
+        write_header(self.blocks.len() as u8, pkt_type, HEADER_LEN + len, buf);
+        buf.extend_from_slice(&self.ssrc.to_be_bytes());
+        if let Some(info) = &self.sender_info {
+            info.ntp_time.write(buf);
+            buf.extend_from_slice(&info.rtp_timestamp.to_be_bytes());
+            buf.extend_from_slice(&info.packets.to_be_bytes());
+            buf.extend_from_slice(&info.octets.to_be_bytes());
+        }
+        for block in &self.blocks {
+            block.write(buf);
+        }
+
+        // SSRC, the item and at least one zero byte ending the item list, padded to 32 bits
+        let cname = &cname.as_bytes()[..cname.len().min(u8::MAX as usize)];
+        let len = (4 + 2 + cname.len() + 1).div_ceil(4) * 4;
+        write_header(1, SDES, HEADER_LEN + len, buf);
+        let start = buf.len();
+        buf.extend_from_slice(&self.ssrc.to_be_bytes());
+        buf.push(CNAME);
+        buf.push(cname.len() as u8);
+        buf.extend_from_slice(cname);
+        buf.resize(start + len, 0);
+    }
+
+    /// Finds the first sender or receiver report of a compound packet
+    pub fn parse(mut pkt: &[u8]) -> Result<Option<Self>, Error> {
+        while pkt.len() >= HEADER_LEN {
+            if pkt[0] >> 6 != RTCP_VERSION {
+                return Err(Error::new(format!("Wrong RTCP version: {}", pkt[0] >> 6)));
+            }
+            let count = usize::from(pkt[0] & 0x1f);
+            let pkt_type = pkt[1];
+            let len = 4 * (usize::from(u16::from_be_bytes([pkt[2], pkt[3]])) + 1);
+            if len > pkt.len() {
+                return Err(Error::new(format!(
+                    "Truncated RTCP packet, len: {}, expected: {}",
+                    pkt.len(),
+                    len
+                )));
+            }
+            let (body, rest) = (&pkt[HEADER_LEN..len], &pkt[len..]);
+            pkt = rest;
+
+            let info_len = match pkt_type {
+                SR => SENDER_INFO_LEN,
+                RR => 0,
+                _ => continue,
+            };
+            if body.len() < 4 + info_len + count * BLOCK_LEN {
+                return Err(Error::new(format!(
+                    "Too short RTCP report, len: {}, blocks: {}",
+                    body.len(),
+                    count
+                )));
+            }
+
+            let sender_info = if pkt_type == SR {
+                Some(SenderInfo {
+                    ntp_time: NtpTime::parse(&body[4..12]),
+                    rtp_timestamp: read_u32(&body[12..]),
+                    packets: read_u32(&body[16..]),
+                    octets: read_u32(&body[20..]),
+                })
+            } else {
+                None
+            };
+            let blocks = body[4 + info_len..]
+                .chunks_exact(BLOCK_LEN)
+                .take(count)
+                .map(ReportBlock::parse)
+                .collect();
+            return Ok(Some(Self {
+                ssrc: read_u32(body),
+                sender_info,
+                blocks,
+            }));
+        }
+        Ok(None)
+    }
+}
+
+impl ReportBlock {
+    fn write(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.ssrc.to_be_bytes());
+        let lost = self.cumulative_lost.min(0x7f_ffff);
+        buf.extend_from_slice(&(u32::from(self.fraction_lost) << 24 | lost).to_be_bytes());
+        buf.extend_from_slice(&self.highest_seq.to_be_bytes());
+        buf.extend_from_slice(&self.jitter.to_be_bytes());
+        buf.extend_from_slice(&self.lsr.to_be_bytes());
+        buf.extend_from_slice(&self.dlsr.to_be_bytes());
+    }
+
+    fn parse(b: &[u8]) -> Self {
+        let lost = read_u32(&b[4..]);
+        Self {
+            ssrc: read_u32(b),
+            fraction_lost: (lost >> 24) as u8,
+            cumulative_lost: lost & 0xff_ffff,
+            highest_seq: read_u32(&b[8..]),
+            jitter: read_u32(&b[12..]),
+            lsr: read_u32(&b[16..]),
+            dlsr: read_u32(&b[20..]),
+        }
+    }
+}
+
+/// `len` is the length of the packet with the header in bytes, a multiple of 4
+fn write_header(count: u8, pkt_type: u8, len: usize, buf: &mut Vec<u8>) {
+    buf.push(RTCP_VERSION << 6 | count & 0x1f);
+    buf.push(pkt_type);
+    buf.extend_from_slice(&((len / 4 - 1) as u16).to_be_bytes());
+}
+
+fn read_u32(b: &[u8]) -> u32 {
+    u32::from_be_bytes(b[..4].try_into().unwrap())
+}
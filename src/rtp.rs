@@ -0,0 +1,71 @@
+//! RTP framing of data packets (RFC 3550). In the RTP format data packets and replies
+//! are RTP packets carrying the native packet as their payload, so captures can be
+//! decoded by RTP analyzers
+
+use crate::rtcp;
+use std::convert::TryInto;
+use std::time::Duration;
+
+const RTP_VERSION: u8 = 2;
+/// Fixed header without CSRCs and extensions, the only one sent
+pub const HEADER_LEN: usize = 12;
+/// The first dynamic payload type
+pub const PAYLOAD_TYPE: u8 = 96;
+/// Clock rate of timestamps, the one of Opus
+pub const CLOCK_RATE: u64 = 48_000;
+
+#[derive(Debug, Clone, Copy)]
+pub struct RtpHeader {
+    /// Set on the first packet of a stream
+    pub marker: bool,
+    pub payload_type: u8,
+    pub seq: u16,
+    pub timestamp: u32,
+    pub ssrc: u32,
+}
+
+impl RtpHeader {
+    pub fn write(&self, buf: &mut Vec<u8>) {
+        buf.push(RTP_VERSION << 6);
+        buf.push((self.marker as u8) << 7 | self.payload_type & 0x7f);
+        buf.extend_from_slice(&self.seq.to_be_bytes());
+        buf.extend_from_slice(&self.timestamp.to_be_bytes());
+        buf.extend_from_slice(&self.ssrc.to_be_bytes());
+    }
+
+    /// Parses the header of an RTP packet, returns it with the offset of the payload.
+    /// Native packets are never taken for RTP: the version bits of their magic are 1
+    pub fn parse(pkt: &[u8]) -> Option<(Self, usize)> {
+        if pkt.len() < HEADER_LEN || pkt[0] >> 6 != RTP_VERSION || rtcp::is_rtcp(pkt) {
+            return None;
+        }
+        let csrc_count = usize::from(pkt[0] & 0x0f);
+        let mut offset = HEADER_LEN + 4 * csrc_count;
+        if pkt[0] & 0x10 != 0 {
+            let ext = pkt.get(offset..offset + 4)?;
+            offset += 4 + 4 * usize::from(u16::from_be_bytes([ext[2], ext[3]]));
+        }
+        if offset > pkt.len() {
+            return None;
+        }
+
+        let header = Self {
+            marker: pkt[1] & 0x80 != 0,
+            payload_type: pkt[1] & 0x7f,
+            seq: u16::from_be_bytes(pkt[2..4].try_into().unwrap()),
+            timestamp: u32::from_be_bytes(pkt[4..8].try_into().unwrap()),
+            ssrc: u32::from_be_bytes(pkt[8..12].try_into().unwrap()),
+        };
+        Some((header, offset))
+    }
+}
+
+/// RTP timestamp of the time since the start of the stream
+pub fn timestamp(elapsed: Duration) -> u32 {
+    (elapsed.as_micros() as u64 * CLOCK_RATE / 1_000_000) as u32
+}
+
+/// Offset of the native packet: after the RTP header if there is one
+pub fn payload_offset(pkt: &[u8]) -> usize {
+    RtpHeader::parse(pkt).map_or(0, |(_, offset)| offset)
+}
@@ -0,0 +1,83 @@
+//! Raw samples of `--samples-out`: a CSV line per reply
+//!
+//! `time_s,client,session,seq,rtt_ms` with the Unix time of the reply. Lines are passed
+//! to a task writing them through a buffer, so the receive loop never waits for the disk.
+//! The buffer is flushed whenever no lines are pending, a crash loses little.
+
+use crate::error::Error;
+use async_std::channel::{self, Receiver, Sender};
+use async_std::fs::File;
+use async_std::io::{prelude::*, BufWriter};
+use async_std::task::{self, JoinHandle};
+use log::warn;
+use std::net::SocketAddr;
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+pub const HEADER: &str = "time_s,client,session,seq,rtt_ms";
+
+/// Adds samples to the file, can be cloned for every server
+#[derive(Clone)]
+pub struct SamplesOut {
+    tx: Sender<String>,
+}
+
+/// The task writing the file
+pub struct SamplesWriter {
+    task: JoinHandle<()>,
+}
+
+/// Creates the file and starts writing it
+pub async fn create(path: &Path) -> Result<(SamplesOut, SamplesWriter), Error> {
+    let file = File::create(path)
+        .await
+        .map_err(|e| Error::new(format!("Cannot create {}: {}", path.display(), e)))?;
+    let (tx, rx) = channel::unbounded();
+    let path = path.to_owned();
+    let task = task::spawn(async move {
+        // The test goes on without the file
+        if let Err(e) = write_lines(file, rx).await {
+            warn!("Cannot write samples to {}: {}", path.display(), e);
+        }
+    });
+    Ok((SamplesOut { tx }, SamplesWriter { task }))
+}
+
+async fn write_lines(file: File, rx: Receiver<String>) -> Result<(), Error> {
+    let mut out = BufWriter::new(file);
+    out.write_all(HEADER.as_bytes()).await?;
+    out.write_all(b"\n").await?;
+    while let Ok(line) = rx.recv().await {
+        out.write_all(line.as_bytes()).await?;
+        if rx.is_empty() {
+            out.flush().await?;
+        }
+    }
+    out.flush().await?;
+    Ok(())
+}
+
+impl SamplesOut {
+    pub fn add(&self, client: SocketAddr, session: u32, seq: u32, rtt: Duration) {
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let line = format!(
+            "{:.6},{},{:08x},{},{:.3}\n",
+            time.as_secs_f64(),
+            client,
+            session,
+            seq,
+            rtt.as_secs_f64() * 1000.
+        );
+        // Fails only if the writer failed, it has logged why
+        let _ = self.tx.try_send(line);
+    }
+}
+
+impl SamplesWriter {
+    /// Writes out the pending samples, once all `SamplesOut` are dropped
+    pub async fn finish(self) {
+        self.task.await
+    }
+}
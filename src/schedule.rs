@@ -0,0 +1,247 @@
+//! Schedules of test packets: intervals between them and their sizes
+
+use crate::config::parse_duration;
+use crate::error::Error;
+use std::fs;
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// Intervals between packets of a stream, mimicking codecs changing the frame duration
+#[derive(Debug, Clone, PartialEq)]
+pub enum IntervalPattern {
+    /// The intervals repeated in order
+    Cycle(Vec<Duration>),
+    /// Every interval picked at random from the list
+    Random(Vec<Duration>),
+}
+
+/// On/off voice activity model: talk spurts sending packets at the stream interval,
+/// silences sending only comfort noise packets. Durations of both are exponentially
+/// distributed, as in a two-state Markov chain
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct VoiceActivity {
+    pub mean_talk: Duration,
+    pub mean_silence: Duration,
+    pub comfort_noise_interval: Duration,
+}
+
+/// Where in the voice activity cycle a packet is sent
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub enum SpurtPosition {
+    /// The first packet of a talk spurt
+    Start,
+    /// Further packets of a talk spurt, or every packet without voice activity
+    #[default]
+    Talk,
+    /// A packet sent during silence
+    ComfortNoise,
+}
+
+impl VoiceActivity {
+    /// Random duration of the next talk spurt or silence
+    pub fn state_duration(&self, talking: bool) -> Duration {
+        let mean = if talking {
+            self.mean_talk
+        } else {
+            self.mean_silence
+        };
+        let u: f64 = rand::random();
+        mean.mul_f64(-(1. - u).ln())
+    }
+}
+
+/// `1s,1.5s,160ms`: mean talk spurt, mean silence and the comfort noise interval.
+/// The comfort noise interval is 160ms if not given
+impl FromStr for VoiceActivity {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let items = s
+            .split(',')
+            .map(parse_duration)
+            .collect::<Result<Vec<_>, _>>()?;
+        match items[..] {
+            [talk, silence] => Ok(Self {
+                mean_talk: talk,
+                mean_silence: silence,
+                comfort_noise_interval: Duration::from_millis(160),
+            }),
+            [talk, silence, cn] if cn.as_micros() > 0 => Ok(Self {
+                mean_talk: talk,
+                mean_silence: silence,
+                comfort_noise_interval: cn,
+            }),
+            _ => Err(Error::new(format!(
+                "Invalid voice activity: {}. Expected <talk>,<silence>[,<comfort noise interval>]",
+                s
+            ))),
+        }
+    }
+}
+
+/// How sizes of packets vary, emulating a VBR codec
+#[derive(Debug, Clone, PartialEq)]
+pub enum SizeModel {
+    /// Sizes uniformly distributed in the inclusive range
+    Uniform(usize, usize),
+    Normal {
+        mean: f64,
+        std_dev: f64,
+    },
+    /// A file with a size per line, e.g. recorded from a real codec, repeated in order
+    Trace(PathBuf),
+}
+
+/// Sizes of packets generated once from a `SizeModel`
+pub struct PacketSizes {
+    model: SizeModel,
+    trace: Vec<usize>,
+    /// Generated sizes are clamped into the range
+    min: usize,
+    max: usize,
+}
+
+impl SizeModel {
+    fn parse_size(s: &str) -> Result<usize, Error> {
+        s.trim()
+            .parse()
+            .map_err(|_| Error::new(format!("Invalid packet size: {}", s)))
+    }
+}
+
+/// `uniform:<min>-<max>`, `normal:<mean>,<std dev>` or `trace:<path>`
+impl FromStr for SizeModel {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || {
+            Error::new(format!(
+                "Unknown size model: {}. Expected uniform:<min>-<max>, normal:<mean>,<std dev> or trace:<path>",
+                s
+            ))
+        };
+        let (kind, args) = s.split_once(':').ok_or_else(invalid)?;
+        match kind {
+            "uniform" => {
+                let (min, max) = args.split_once('-').ok_or_else(invalid)?;
+                let (min, max) = (Self::parse_size(min)?, Self::parse_size(max)?);
+                if min > max {
+                    return Err(invalid());
+                }
+                Ok(SizeModel::Uniform(min, max))
+            }
+            "normal" => {
+                let (mean, std_dev) = args.split_once(',').ok_or_else(invalid)?;
+                let parse = |s: &str| match s.trim().parse::<f64>() {
+                    Ok(n) if n.is_finite() && n >= 0. => Ok(n),
+                    _ => Err(invalid()),
+                };
+                Ok(SizeModel::Normal {
+                    mean: parse(mean)?,
+                    std_dev: parse(std_dev)?,
+                })
+            }
+            "trace" if !args.is_empty() => Ok(SizeModel::Trace(args.into())),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl PacketSizes {
+    /// Sizes are kept in the `[min, max]` range
+    pub fn new(model: &SizeModel, min: usize, max: usize) -> Result<Self, Error> {
+        let trace = match model {
+            SizeModel::Trace(path) => {
+                let text = fs::read_to_string(path).map_err(|e| {
+                    Error::new(format!("Cannot read size trace {}: {}", path.display(), e))
+                })?;
+                let trace = text
+                    .lines()
+                    .map(str::trim)
+                    .filter(|l| !l.is_empty() && !l.starts_with('#'))
+                    .map(SizeModel::parse_size)
+                    .collect::<Result<Vec<_>, _>>()?;
+                if trace.is_empty() {
+                    return Err(Error::new(format!(
+                        "Size trace is empty: {}",
+                        path.display()
+                    )));
+                }
+                trace
+            }
+            _ => Vec::new(),
+        };
+
+        Ok(Self {
+            model: model.clone(),
+            trace,
+            min,
+            max,
+        })
+    }
+
+    /// Size of the packet with `seq`, counted from 1
+    pub fn size(&self, seq: u32) -> usize {
+        let size = match &self.model {
+            SizeModel::Uniform(min, max) => min + rand::random::<usize>() % (max - min + 1),
+            SizeModel::Normal { mean, std_dev } => {
+                // Box-Muller transform
+                let (u1, u2): (f64, f64) = (rand::random(), rand::random());
+                let z = (-2. * (1. - u1).ln()).sqrt() * (2. * std::f64::consts::PI * u2).cos();
+                (mean + std_dev * z).round().max(0.) as usize
+            }
+            SizeModel::Trace(_) => self.trace[seq.wrapping_sub(1) as usize % self.trace.len()],
+        };
+        size.clamp(self.min, self.max)
+    }
+}
+
+impl IntervalPattern {
+    /// Interval after the packet with `seq`, counted from 1
+    pub fn interval(&self, seq: u32) -> Duration {
+        match self {
+            IntervalPattern::Cycle(intervals) => {
+                intervals[seq.wrapping_sub(1) as usize % intervals.len()]
+            }
+            IntervalPattern::Random(intervals) => {
+                intervals[rand::random::<usize>() % intervals.len()]
+            }
+        }
+    }
+}
+
+/// `20,20,40,60` cycles through the intervals, `random:20,40` picks them at random.
+/// Plain numbers are milliseconds, durations with units are accepted as well
+impl FromStr for IntervalPattern {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (random, list) = match s.strip_prefix("random:") {
+            Some(list) => (true, list),
+            None => (false, s),
+        };
+
+        let intervals = list
+            .split(',')
+            .map(|item| {
+                let item = item.trim();
+                let interval = match item.parse::<f64>() {
+                    Ok(ms) if ms.is_finite() && ms > 0. => Duration::from_secs_f64(ms / 1000.),
+                    Ok(_) => Duration::default(),
+                    Err(_) => parse_duration(item)?,
+                };
+                if interval.as_micros() == 0 {
+                    return Err(Error::new(format!("Interval is too small: {}", item)));
+                }
+                Ok(interval)
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(if random {
+            IntervalPattern::Random(intervals)
+        } else {
+            IntervalPattern::Cycle(intervals)
+        })
+    }
+}
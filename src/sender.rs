@@ -0,0 +1,135 @@
+//! `twamp` mode: sends TWAMP-Light test packets to a reflector and measures round trip time
+
+use crate::config::TwampOpts;
+use crate::error::Error;
+use crate::net::{resolve, set_dscp, set_ttl};
+use crate::statistic::{self, Lateness, SeqStats, SeqTracker};
+use crate::stop::run_until_stopped;
+use crate::twamp::{self, NtpTime, ReflectorPacket, SenderPacket};
+use async_std::{net::UdpSocket, task::sleep};
+use futures::try_join;
+use log::{info, warn};
+use std::cell::RefCell;
+use std::net::SocketAddr;
+use std::time::Instant;
+
+struct Statistics {
+    delays: statistic::Delays,
+    seqs: SeqTracker,
+    seq: SeqStats,
+    wakeups: Lateness,
+}
+
+pub async fn run(opts: TwampOpts) -> Result<(), Error> {
+    let reflector = resolve(&opts.reflector).await?;
+    let bind: SocketAddr = match &opts.bind {
+        Some(bind) => resolve(bind).await?,
+        None if reflector.is_ipv4() => "0.0.0.0:0".parse()?,
+        None => "[::]:0".parse()?,
+    };
+    let socket = UdpSocket::bind(bind)
+        .await
+        .map_err(|e| Error::new(format!("Cannot bind to {}: {}", bind, e)))?;
+    set_dscp(&socket, opts.dscp)?;
+    set_ttl(&socket, twamp::SENDER_TTL)?;
+    info!("Sending TWAMP-Light test packets to {}", reflector);
+
+    let mut delays = statistic::Delays::new(opts.stats.clone(), Some("RTT".to_owned()));
+    if let Some(path) = &opts.stats.heatmap {
+        delays.enable_heatmap(path.clone());
+    }
+    let statistics = RefCell::new(Statistics {
+        delays,
+        seqs: Default::default(),
+        seq: Default::default(),
+        wakeups: Lateness::new("scheduling", "wake-ups"),
+    });
+    let run = async {
+        try_join!(
+            send_loop(&socket, reflector, &opts, &statistics),
+            receive_loop(&socket, reflector, &statistics),
+            statistic::tick_every(|| statistics.borrow_mut().delays.tick())
+        )
+        .map(|_| ())
+    };
+    run_until_stopped(run, opts.duration).await?;
+
+    let stats = &mut *statistics.borrow_mut();
+    stats.delays.print_summary();
+    let rtt_jitter_ms = stats.delays.jitter_ms();
+    stats
+        .wakeups
+        .print_summary(&opts.stats, "Send scheduling", Some(rtt_jitter_ms));
+    let violations = stats.delays.check_thresholds();
+    if !violations.is_empty() {
+        return Err(Error::failed(violations));
+    }
+    Ok(())
+}
+
+async fn send_loop(
+    socket: &UdpSocket,
+    reflector: SocketAddr,
+    opts: &TwampOpts,
+    statistics: &RefCell<Statistics>,
+) -> Result<(), Error> {
+    let mut pkt = Vec::with_capacity(opts.packet_size);
+    let mut wake_up = None;
+    for seq in 0.. {
+        let pkt_send_time = Instant::now();
+        if let Some(wake_up) = wake_up {
+            statistics.borrow_mut().wakeups.add(wake_up, pkt_send_time);
+        }
+
+        pkt.clear();
+        let sender = SenderPacket {
+            seq,
+            time: NtpTime::now(),
+            error_estimate: twamp::ERROR_ESTIMATE,
+        };
+        sender.write(&mut pkt, opts.packet_size);
+        socket.send_to(&pkt, reflector).await?;
+
+        wake_up = Some(pkt_send_time + opts.interval);
+        sleep(opts.interval.saturating_sub(pkt_send_time.elapsed())).await;
+    }
+    Ok(())
+}
+
+async fn receive_loop(
+    socket: &UdpSocket,
+    reflector: SocketAddr,
+    statistics: &RefCell<Statistics>,
+) -> Result<(), Error> {
+    const BUF_LEN: usize = 65535;
+    let mut buf = vec![0; BUF_LEN];
+    loop {
+        let (len, addr) = socket.recv_from(&mut buf).await?;
+        let now = NtpTime::now();
+        if addr != reflector {
+            warn!("Packet from unexpected address: {}", addr);
+            continue;
+        }
+        let reflected = match ReflectorPacket::parse(&buf[..len]) {
+            Ok(reflected) => reflected,
+            Err(e) => {
+                warn!("{}", e);
+                continue;
+            }
+        };
+
+        // Time spent in the reflector is not a part of the network round trip
+        let in_reflector = reflected.time.duration_since(reflected.receive_time);
+        let rtt = now
+            .duration_since(reflected.sender_time)
+            .saturating_sub(in_reflector);
+
+        let stats = &mut *statistics.borrow_mut();
+        let change = stats.seqs.on_seq(reflected.sender_seq);
+        stats.seq.add(change);
+        stats.delays.set_seq_stats(stats.seq);
+        if change.duplicates == 0 {
+            stats.delays.new_event(rtt);
+        }
+    }
+}
@@ -0,0 +1,2312 @@
+//! `serve` mode: streams test packets to registered clients and measures round trip time
+
+use crate::admin;
+use crate::auth::Auth;
+use crate::clients::{Client, Clients, Source, StreamParams};
+use crate::clock::ClockSync;
+use crate::config::{parse_duration, ServeOpts, StatsConfig};
+use crate::cookie::Cookies;
+use crate::error::Error;
+use crate::loss_runs::LossRuns;
+use crate::merge_futures::FuturesMergerMemoryOwner;
+use crate::metrics;
+use crate::mos::{self, Quality};
+use crate::net::{
+    enable_recv_err, enable_recv_tos, get_tos, is_unreachable_error, path_mtu, recv_errors,
+    recv_msg, set_mtu_probe, set_tos, Ecn, Received,
+};
+use crate::p2::RunQuantiles;
+use crate::payload::{PayloadData, PayloadProvider};
+use crate::pressure::Pressure;
+use crate::protocol::{
+    self, AckBody, DataHeader, Direction, Format, JoinBody, PrefixError, Report, ReportBody,
+    SyncBody,
+};
+use crate::rate_limit::RateLimiter;
+use crate::reordering::Reordering;
+use crate::rtcp::{self, ReportBlock, ReportPacket, SenderInfo};
+use crate::rtp::{self, RtpHeader};
+use crate::samples::{self, SamplesOut};
+use crate::schedule::{PacketSizes, SpurtPosition};
+use crate::statistic::{self, ByteCounts, EcnCounts, Lateness, SeqStats, SeqTracker};
+use crate::stop::run_until_stopped;
+use crate::twamp::NtpTime;
+use crate::websocket;
+use async_std::{net::UdpSocket, task::sleep};
+use futures::{future::try_join_all, try_join};
+use log::{debug, error, info, warn};
+use signal_hook::consts::SIGHUP;
+use std::cell::RefCell;
+use std::cmp;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::fmt::{self, Write as _};
+use std::mem;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// How often the clocks of clients are synchronized
+const SYNC_INTERVAL: Duration = Duration::from_secs(1);
+/// Range of intervals clients can request
+const MIN_CLIENT_INTERVAL: Duration = Duration::from_millis(1);
+const MAX_CLIENT_INTERVAL: Duration = Duration::from_secs(10);
+/// How often path MTU probes are sent with `--mtu-sweep`
+const MTU_SWEEP_INTERVAL: Duration = Duration::from_secs(10);
+/// Clients are removed after this many ICMP unreachable errors with no packets from them
+const UNREACHABLE_LIMIT: u32 = 5;
+/// Replies to packets sent this recently may still be on the way, they are not lost yet
+const IN_FLIGHT_TIME: Duration = Duration::from_secs(1);
+/// Consecutive lost replies reported as a loss burst event with `--jsonl`
+const LOSS_BURST_LEN: u64 = 3;
+/// UDP payload sizes of probes: the IPv4 minimum, the IPv6 minimum, common tunnels,
+/// PPPoE, Ethernet and jumbo frames. IPv4 and UDP headers take another 28 bytes
+const PROBE_SIZES: [usize; 9] = [548, 1232, 1372, 1392, 1432, 1464, 1472, 4068, 8972];
+/// Answer to a query too short for a statistics record
+const QUERY_TOO_SHORT: &str = "error=\"pad the query\"\n";
+
+pub async fn run(cli_opts: ServeOpts) -> Result<(), Error> {
+    let mut opts = cli_opts.clone();
+    opts.apply_config_file()?;
+
+    let mut servers = Vec::with_capacity(opts.bind.len());
+    for addr in &opts.bind {
+        servers.push(Server::new(addr, &opts).await?);
+    }
+
+    if opts.check {
+        return print_effective_settings(&opts, &servers);
+    }
+
+    let (samples_out, samples_writer) = match &opts.samples_out {
+        Some(path) => {
+            let (out, writer) = samples::create(path).await?;
+            (Some(out), Some(writer))
+        }
+        None => (None, None),
+    };
+    let (mut recvs, mut sends): (Vec<_>, Vec<_>) = servers
+        .iter()
+        .map(|server| server.split(samples_out.clone()))
+        .collect::<Result<Vec<_>, _>>()?
+        .into_iter()
+        .map(|(recv, send)| (RefCell::new(recv), send))
+        .unzip();
+    drop(samples_out);
+
+    let run =
+        try_join_all(recvs.iter().zip(&mut sends).map(|(recv, send)| async move {
+            try_join!(ServerRecv::listen(recv), send.send_loop())
+        }));
+    let run = async {
+        try_join!(
+            run,
+            reload_on_sighup(&cli_opts, &opts.stats, &servers, &recvs),
+            serve_admin(&opts, &servers, &recvs),
+            statistic::tick_every(|| recvs.iter().for_each(|recv| recv.borrow_mut().tick())),
+            metrics::serve(opts.stats.metrics.as_deref()),
+            websocket::serve(opts.stats.websocket.as_deref()),
+            serve_quic(&opts, &servers),
+            serve_dtls(&opts, &servers)
+        )
+        .map(|_| ())
+    };
+    let res = run_until_stopped(run, opts.duration).await;
+
+    for server in &servers {
+        server.say_goodbye().await;
+    }
+    if let Some(writer) = samples_writer {
+        for recv in &mut recvs {
+            recv.get_mut().samples_out = None;
+        }
+        writer.finish().await;
+    }
+    res?;
+
+    for ((recv, send), server) in recvs.iter_mut().zip(&mut sends).zip(&servers) {
+        let recv = recv.get_mut();
+        recv.print_summary();
+        let label = server.stats_label("Send scheduling").unwrap_or_default();
+        let rtt_jitter_ms = recv.statistics.jitter_ms();
+        send.wakeups
+            .print_summary(&opts.stats, &label, Some(rtt_jitter_ms));
+        let label = server.stats_label("Send time").unwrap_or_default();
+        send.send_times.print_summary(&opts.stats, &label, None);
+    }
+    let violations: Vec<String> = recvs
+        .iter_mut()
+        .flat_map(|recv| recv.get_mut().statistics.check_thresholds())
+        .collect();
+    if !violations.is_empty() {
+        return Err(Error::failed(violations));
+    }
+    Ok(())
+}
+
+struct Server {
+    socket: UdpSocket,
+    clients: Clients,
+    payload: PayloadData,
+    sizes: Option<PacketSizes>,
+    interval: Duration,
+    /// Clients with the default interval follow the interval pattern
+    patterned: bool,
+    /// RTT statistics are split by spurt position
+    voice_activity: bool,
+    redundancy: u8,
+    train_len: Option<u32>,
+    train_interval: Duration,
+    mtu_sweep: bool,
+    packet_size: usize,
+    format: Format,
+    auth: Auth,
+    idle_timeout: Duration,
+    /// Joins per second per source IP
+    source_rate: u32,
+    start: Instant,
+    stats_cfg: StatsConfig,
+    label: Option<String>,
+}
+
+struct ServerRecv<'a> {
+    socket: &'a UdpSocket,
+    clients: &'a Clients,
+    cookies: Cookies,
+    limiter: RateLimiter,
+    /// A client seen at another address by the packet being handled, which gets a cookie
+    /// challenge there: it moves once it joins with the cookie
+    challenge_to: Option<SocketAddr>,
+    /// Answers to the packet being handled, sent once it is
+    outgoing: Vec<(Vec<u8>, SocketAddr)>,
+    start: &'a Instant,
+    statistics: statistic::Delays,
+    uplink: statistic::Delays,
+    downlink: statistic::Delays,
+    /// With voice activity
+    spurts: Option<SpurtStats>,
+    train_len: Option<u32>,
+    /// Available bandwidth estimates of received packet trains, Mbit/s
+    train_rates: Vec<f64>,
+    sessions: HashMap<u32, SessionStats>,
+    seq: SeqStats,
+    /// Data packets of clients in the upload direction
+    upload_seq: SeqStats,
+    /// Sum of the latest reports of clients
+    reported: SeqStats,
+    /// Bytes of replies of all clients
+    acked_bytes: u64,
+    /// ECN codepoints of replies and data packets of clients
+    uplink_ecn: EcnCounts,
+    /// Sum of the ECN codepoints in reports of clients
+    reported_ecn: EcnCounts,
+    /// Sum of the jitter in the latest reports of clients, and how many clients reported
+    reported_jitter_us_sum: u64,
+    reporting_sessions: usize,
+    default_params: StreamParams,
+    format: Format,
+    auth: &'a Auth,
+    streams: StreamsStats,
+    clients_stats: ClientsStats,
+    /// `--samples-out`
+    samples_out: Option<SamplesOut>,
+    /// Load of the host for spike events, see `--spike-factor`
+    pressure: Pressure,
+}
+
+/// RTT and loss by client, the aggregate is in `ServerRecv::statistics`
+struct ClientsStats {
+    clients: BTreeMap<u32, ClientStats>,
+    /// Settings and the label prefix of new clients
+    cfg: StatsConfig,
+    label: Option<String>,
+    /// Of the aggregate, for the loss runs of all clients
+    rtt_label: Option<String>,
+    uplink_label: Option<String>,
+    downlink_label: Option<String>,
+    /// Interval of data packets for clients which are gone before their stats are made
+    default_interval: Duration,
+}
+
+struct ClientStats {
+    /// The latest address of the client
+    addr: SocketAddr,
+    /// Interval of data packets to the client, for the call quality estimate
+    interval: Duration,
+    rtt: statistic::Delays,
+    seq: SeqStats,
+    /// One-way delays of the client, like `ServerRecv::uplink` and `downlink`
+    uplink: statistic::Delays,
+    downlink: statistic::Delays,
+    upload_seq: SeqStats,
+    reported: SeqStats,
+    acked_bytes: u64,
+    /// Of replies and data packets of the client
+    loss_runs: LossRuns,
+    reordering: Reordering,
+}
+
+/// RTT and loss by stream ID of clients with several streams
+struct StreamsStats {
+    streams: BTreeMap<u8, (statistic::Delays, SeqStats)>,
+    /// Settings and the label prefix of new streams
+    cfg: StatsConfig,
+    label: Option<String>,
+}
+
+/// RTT by the position of packets in the voice activity cycle
+struct SpurtStats {
+    start: statistic::Delays,
+    talk: statistic::Delays,
+    comfort_noise: statistic::Delays,
+}
+
+/// What is tracked for every session on replies
+#[derive(Default)]
+struct SessionStats {
+    seqs: SeqTracker,
+    clock: ClockSync,
+    /// The latest downlink report of the client
+    report: Report,
+    /// Whether the client sent a report at all, upload-only ones don't
+    reported: bool,
+    /// ECN codepoints of packets received from the client
+    ecn: EcnCounts,
+    /// The latest receiver report of the client in the RTP format
+    rtcp: Option<RtcpStats>,
+    /// Replies of the packet train being received
+    train: Option<Train>,
+    /// The biggest path MTU probe sent back by the client
+    max_probe: Option<usize>,
+    /// The biggest reply of the client
+    max_reply: usize,
+    /// Replies since the previous clock synchronization exchange
+    replies_since_sync: u32,
+    /// Sync exchanges in a row without replies between them
+    syncs_without_replies: u32,
+    fragmentation: Option<Fragmentation>,
+}
+
+/// How packets bigger than the path MTU are treated
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Fragmentation {
+    /// Routers report the smaller MTU with ICMP, so the server fragments the packets
+    Fragmented { ip_len: usize, path_mtu: usize },
+    /// Big packets are dropped without ICMP, while small ones get through
+    BlackHoled { ip_len: usize },
+}
+
+impl fmt::Display for Fragmentation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Fragmentation::Fragmented { ip_len, path_mtu } => write!(
+                f,
+                "packets of {} bytes are fragmented, path MTU is {} bytes",
+                ip_len, path_mtu
+            ),
+            Fragmentation::BlackHoled { ip_len } => write!(
+                f,
+                "packets of {} bytes are black-holed: dropped without ICMP",
+                ip_len
+            ),
+        }
+    }
+}
+
+struct Train {
+    first_seq: u32,
+    received: u32,
+    /// Bytes of replies after the first received one
+    bytes: usize,
+    first_arrival: Instant,
+    last_arrival: Instant,
+}
+
+struct RtcpStats {
+    block: ReportBlock,
+    /// From LSR and DLSR, unknown if the client didn't get a sender report yet
+    rtt: Option<Duration>,
+}
+
+struct ServerSend<'a> {
+    socket: &'a UdpSocket,
+    clients: &'a Clients,
+    send_futures: FuturesMergerMemoryOwner,
+    interval: Duration,
+    idle_timeout: Duration,
+    last_sync: Instant,
+    train_len: Option<u32>,
+    train_interval: Duration,
+    last_train: Instant,
+    mtu_sweep: bool,
+    /// When path MTU probes were sent to each session
+    last_sweeps: HashMap<u32, Instant>,
+    sync_bufs: Vec<Vec<u8>>,
+    auth: &'a Auth,
+    /// CNAME of RTCP sender reports
+    cname: String,
+    pkt: PktToSend<'a>,
+    /// Lateness of `send_loop` waking up for due packets
+    wakeups: Lateness,
+    /// Lateness of data packets from their time on the schedule of their client
+    send_times: Lateness,
+}
+
+struct PktToSend<'a> {
+    start: &'a Instant,
+    /// Clients a packet is due to, `bufs` has a packet for each of them
+    due: Vec<Client>,
+    bufs: Vec<Vec<u8>>,
+    payload: PayloadProvider<'a>,
+    sizes: Option<&'a PacketSizes>,
+    /// Number of previous frames carried by every packet
+    redundancy: u8,
+    /// The latest frames sent to each session, the oldest first
+    frames: HashMap<u32, VecDeque<Vec<u8>>>,
+    format: Format,
+    auth: &'a Auth,
+}
+
+impl Server {
+    async fn new(addr: &str, opts: &ServeOpts) -> Result<Self, Error> {
+        let socket = UdpSocket::bind(addr)
+            .await
+            .map_err(|e| Error::new(format!("Cannot bind to {}: {}", addr, e)))?;
+        let addr = socket.local_addr()?;
+        set_tos(&socket, opts.dscp, opts.ecn)?;
+        enable_recv_err(&socket)?;
+        enable_recv_tos(&socket)?;
+
+        let auth = Auth::new(opts.auth_key.clone());
+        let min_packet_size = protocol::MIN_DATA_LEN + opts.format.overhead() + auth.trailer_len();
+        if opts.packet_size < min_packet_size {
+            return Err(Error::new(format!(
+                "Packet size {} is too small for the {:?} format, the minimum is {}",
+                opts.packet_size, opts.format, min_packet_size
+            )));
+        }
+
+        Ok(Self {
+            socket,
+            clients: Clients::new(
+                opts.max_clients,
+                opts.interval_pattern.clone(),
+                opts.voice_activity,
+                opts.stats.jsonl.clone(),
+            ),
+            payload: PayloadData::new(&opts.payload, opts.seed)?,
+            sizes: match &opts.size_model {
+                Some(model) => Some(PacketSizes::new(
+                    model,
+                    min_packet_size,
+                    protocol::MAX_PKT_LEN,
+                )?),
+                None => None,
+            },
+            interval: opts.interval,
+            patterned: opts.interval_pattern.is_some(),
+            voice_activity: opts.voice_activity.is_some(),
+            redundancy: opts.redundancy,
+            train_len: opts.burst,
+            train_interval: opts.burst_interval,
+            mtu_sweep: opts.mtu_sweep,
+            packet_size: opts.packet_size,
+            format: opts.format,
+            auth,
+            idle_timeout: opts.idle_timeout,
+            source_rate: opts.source_rate,
+            start: Instant::now(),
+            stats_cfg: opts.stats.clone(),
+            // Several servers print statistics, mark each with its address
+            label: if opts.bind.len() > 1 {
+                Some(addr.to_string())
+            } else {
+                None
+            },
+        })
+    }
+
+    /// Replies are written to `samples_out` if it is set
+    fn split(
+        &self,
+        samples_out: Option<SamplesOut>,
+    ) -> Result<(ServerRecv<'_>, ServerSend<'_>), Error> {
+        Ok((
+            ServerRecv {
+                socket: &self.socket,
+                clients: &self.clients,
+                cookies: Cookies::new(),
+                limiter: RateLimiter::new(self.source_rate),
+                challenge_to: None,
+                outgoing: Vec::new(),
+                start: &self.start,
+                statistics: self.rtt_statistics(),
+                uplink: statistic::Delays::new(self.stats_cfg.clone(), self.stats_label("Uplink")),
+                downlink: statistic::Delays::new(
+                    self.stats_cfg.clone(),
+                    self.stats_label("Downlink"),
+                ),
+                spurts: if self.voice_activity {
+                    Some(SpurtStats {
+                        start: statistic::Delays::new(
+                            self.stats_cfg.clone(),
+                            self.stats_label("RTT spurt start"),
+                        ),
+                        talk: statistic::Delays::new(
+                            self.stats_cfg.clone(),
+                            self.stats_label("RTT in spurt"),
+                        ),
+                        comfort_noise: statistic::Delays::new(
+                            self.stats_cfg.clone(),
+                            self.stats_label("RTT comfort noise"),
+                        ),
+                    })
+                } else {
+                    None
+                },
+                train_len: self.train_len,
+                train_rates: Vec::new(),
+                sessions: HashMap::new(),
+                seq: Default::default(),
+                upload_seq: Default::default(),
+                reported: Default::default(),
+                acked_bytes: 0,
+                uplink_ecn: Default::default(),
+                reported_ecn: Default::default(),
+                reported_jitter_us_sum: 0,
+                reporting_sessions: 0,
+                default_params: StreamParams {
+                    interval: self.interval,
+                    patterned: self.patterned,
+                    packet_size: self.packet_size,
+                    variable_size: self.sizes.is_some(),
+                    direction: Direction::Both,
+                    key_id: self.auth.default_key_id(),
+                    stream: 0,
+                },
+                format: self.format,
+                auth: &self.auth,
+                streams: StreamsStats {
+                    streams: BTreeMap::new(),
+                    cfg: self.stats_cfg.clone(),
+                    label: self.stats_label("RTT stream"),
+                },
+                clients_stats: ClientsStats {
+                    clients: BTreeMap::new(),
+                    cfg: self.stats_cfg.clone(),
+                    label: self.stats_label("RTT client"),
+                    rtt_label: self.stats_label("RTT"),
+                    uplink_label: self.stats_label("Uplink client"),
+                    downlink_label: self.stats_label("Downlink client"),
+                    default_interval: self.interval,
+                },
+                samples_out,
+                pressure: Default::default(),
+            },
+            ServerSend {
+                socket: &self.socket,
+                clients: &self.clients,
+                send_futures: Default::default(),
+                interval: self.interval,
+                idle_timeout: self.idle_timeout,
+                last_sync: Instant::now(),
+                train_len: self.train_len,
+                train_interval: self.train_interval,
+                last_train: Instant::now(),
+                mtu_sweep: self.mtu_sweep,
+                last_sweeps: HashMap::new(),
+                sync_bufs: Vec::new(),
+                auth: &self.auth,
+                cname: format!("udp-jitter-test@{}", self.socket.local_addr()?),
+                pkt: PktToSend {
+                    start: &self.start,
+                    due: Vec::new(),
+                    bufs: Vec::new(),
+                    payload: self.payload.provider(),
+                    sizes: self.sizes.as_ref(),
+                    redundancy: self.redundancy,
+                    frames: HashMap::new(),
+                    format: self.format,
+                    auth: &self.auth,
+                },
+                wakeups: Lateness::new("scheduling", "wake-ups"),
+                send_times: Lateness::new("send_time", "packets"),
+            },
+        ))
+    }
+
+    /// Tells every client the server is going away
+    async fn say_goodbye(&self) {
+        for client in &self.clients {
+            let mut bye = protocol::control_pkt(protocol::BYE, &client.session.to_be_bytes());
+            self.auth.sign(client.params.key_id, &mut bye);
+            if let Err(e) = send_to(&self.socket, &bye, client.addr).await {
+                warn!("Cannot say goodbye to {}: {}", client.addr, e);
+            }
+        }
+    }
+
+    /// Statistics of all replies, with the heatmap of `--heatmap`. Several servers write
+    /// their heatmaps to files named after their addresses
+    fn rtt_statistics(&self) -> statistic::Delays {
+        let mut statistics =
+            statistic::Delays::new(self.stats_cfg.clone(), self.stats_label("RTT"));
+        if let Some(path) = &self.stats_cfg.heatmap {
+            let path = match &self.label {
+                Some(label) => {
+                    let mut name = path.file_stem().unwrap_or_default().to_os_string();
+                    name.push(format!("-{}", label.replace(':', "_")));
+                    if let Some(ext) = path.extension() {
+                        name.push(".");
+                        name.push(ext);
+                    }
+                    path.with_file_name(name)
+                }
+                None => path.clone(),
+            };
+            statistics.enable_heatmap(path);
+        }
+        statistics
+    }
+
+    fn stats_label(&self, name: &str) -> Option<String> {
+        Some(match &self.label {
+            Some(label) => format!("{} {}", label, name),
+            None => name.to_owned(),
+        })
+    }
+}
+
+impl<'a> ServerRecv<'a> {
+    /// `this` is borrowed only to handle a packet, not while waiting for one: the admin
+    /// socket resets the statistics in between
+    async fn listen(this: &RefCell<ServerRecv<'a>>) -> Result<(), Error> {
+        const BUF_LEN: usize = 65535;
+        let mut buf = vec![0; BUF_LEN];
+        let (socket, clients) = {
+            let recv = this.borrow();
+            (recv.socket, recv.clients)
+        };
+        loop {
+            let Received { len, addr, tos, .. } = match recv_msg(socket, &mut buf).await {
+                Ok(received) => received,
+                Err(e) if is_unreachable_error(&e) => {
+                    on_icmp_errors(socket, clients);
+                    continue;
+                }
+                Err(e) => return Err(e.into()),
+            };
+
+            let outgoing = this.borrow_mut().on_received(addr, &buf[..len], tos);
+            for (pkt, to) in outgoing {
+                if let Err(e) = send_to(socket, &pkt, to).await {
+                    warn!("Error handling packet: {}", e);
+                }
+            }
+        }
+    }
+
+    /// Applies statistics settings of a reloaded configuration
+    fn set_config(&mut self, cfg: StatsConfig) {
+        self.uplink.set_config(cfg.clone());
+        self.downlink.set_config(cfg.clone());
+        if let Some(spurts) = &mut self.spurts {
+            for stats in spurts.all() {
+                stats.set_config(cfg.clone());
+            }
+        }
+        self.streams.set_config(cfg.clone());
+        self.clients_stats.set_config(cfg.clone());
+        self.statistics.set_config(cfg);
+    }
+
+    /// Handles a packet, returns the packets to send in answer
+    fn on_received(
+        &mut self,
+        addr: SocketAddr,
+        buf: &[u8],
+        tos: Option<u8>,
+    ) -> Vec<(Vec<u8>, SocketAddr)> {
+        let ecn = tos.map_or(Ecn::NotEct, Ecn::from_tos);
+        let r = self.on_new_pkt(addr, buf, ecn);
+        if let Err(e) = r {
+            warn!("Error handling packet: {}", e);
+        }
+
+        // Clients can be evicted by the sending side
+        if self.sessions.len() > self.clients.len() {
+            let clients = self.clients;
+            let (jitter_sum, reporting) = (
+                &mut self.reported_jitter_us_sum,
+                &mut self.reporting_sessions,
+            );
+            self.sessions.retain(|session, stats| {
+                let keep = clients.contains(*session);
+                if !keep {
+                    log_session(*session, stats);
+                    *jitter_sum -= u64::from(stats.report.jitter_us);
+                    *reporting -= usize::from(stats.reported);
+                }
+                keep
+            });
+        }
+        mem::take(&mut self.outgoing)
+    }
+
+    /// `ecn` is the ECN codepoint the packet arrived with
+    fn on_new_pkt(&mut self, addr: SocketAddr, buf: &[u8], ecn: Ecn) -> Result<(), Error> {
+        let request_len = buf.len();
+        let (len, key_id) = match self.auth.verify(buf) {
+            Some(verified) => verified,
+            None => {
+                debug!(
+                    "Wrong authentication trailer from {}, len: {}",
+                    addr,
+                    buf.len()
+                );
+                return Ok(());
+            }
+        };
+        let buf = &buf[..len];
+        if rtcp::is_rtcp(buf) {
+            self.on_rtcp_pkt(addr, buf)?;
+            return self.challenge_moved(request_len, key_id);
+        }
+        // Replies in the RTP format carry the native packet as the RTP payload
+        let buf = &buf[rtp::payload_offset(buf)..];
+        let pkt_type = match protocol::parse_type(buf) {
+            Ok(pkt_type) => pkt_type,
+            Err(e @ PrefixError::Magic) => {
+                debug!("{} from {}, len: {}", e, addr, buf.len());
+                return Ok(());
+            }
+            Err(e @ PrefixError::Version(_)) => {
+                if !self.limiter.allow(addr.ip()) {
+                    return Ok(());
+                }
+                warn!("{} from {}", e, addr);
+                // The source isn't verified: the reply is no longer than the packet not to
+                // amplify spoofed ones, the reason is cut to fit or the packet is dropped
+                let room = request_len.checked_sub(protocol::PREFIX_LEN + self.auth.trailer_len());
+                let room = some_or_ret!(room, Ok(()));
+                let reason = "unsupported protocol version";
+                return self.send_reject(&reason[..reason.len().min(room)], addr, key_id);
+            }
+        };
+
+        match pkt_type {
+            protocol::JOIN => {
+                if self.limiter.allow(addr.ip()) {
+                    self.on_join_pkt(addr, buf, key_id)?;
+                }
+            }
+            protocol::STOP => {
+                let session = protocol::parse_session(protocol::body(buf));
+                // Only the client itself can stop its session
+                if let Some(session) = self.clients.remove_client(&addr, session) {
+                    if let Some(stats) = self.sessions.remove(&session) {
+                        log_session(session, &stats);
+                        self.reported_jitter_us_sum -= u64::from(stats.report.jitter_us);
+                        self.reporting_sessions -= usize::from(stats.reported);
+                    }
+                }
+            }
+            protocol::REPLY => self.on_replay_pkt(addr, buf, ecn)?,
+            protocol::DATA => self.on_upload_pkt(addr, buf, ecn)?,
+            protocol::PROBE => self.on_probe_pkt(addr, buf),
+            protocol::REPORT => {
+                let body = ReportBody::parse(protocol::body(buf))?;
+                if self.of_client(body.session, addr, "Report") {
+                    self.on_report(addr, body.session, body.report);
+                }
+            }
+            protocol::SYNC => self.on_sync_pkt(addr, buf)?,
+            protocol::QUERY => {
+                if self.limiter.allow(addr.ip()) {
+                    self.on_query_pkt(addr, buf, key_id)?;
+                }
+            }
+            // Reflected as it is, never bigger than the request
+            protocol::PING => {
+                if self.limiter.allow(addr.ip()) {
+                    self.outgoing
+                        .push((self.auth.signed(key_id, buf).into_owned(), addr));
+                }
+            }
+            x => {
+                if self.limiter.allow(addr.ip()) {
+                    warn!("Unexpected packet type: {}. len: {}", x, buf.len());
+                }
+            }
+        }
+
+        self.challenge_moved(request_len, key_id)
+    }
+
+    /// Challenges the client seen at another address by a packet of `request_len` bytes.
+    /// Like the reject of a version, the challenge is no longer than the packet
+    fn challenge_moved(&mut self, request_len: usize, key_id: u8) -> Result<(), Error> {
+        let to = some_or_ret!(self.challenge_to.take(), Ok(()));
+        let challenge_len = protocol::PREFIX_LEN + protocol::COOKIE_LEN + self.auth.trailer_len();
+        if request_len >= challenge_len && self.limiter.allow(to.ip()) {
+            self.send_challenge(to, key_id)?;
+        }
+        Ok(())
+    }
+
+    /// `key_id` is the authentication key of the join, the client is answered with it
+    fn on_join_pkt(&mut self, addr: SocketAddr, buf: &[u8], key_id: u8) -> Result<(), Error> {
+        let join = JoinBody::parse(protocol::body(buf))?;
+        if !self.cookies.verify(&addr, &join.cookie) {
+            return self.send_challenge(addr, key_id);
+        }
+
+        let mut params = match self.requested_params(&join) {
+            Ok(params) => params,
+            Err(reason) => {
+                warn!("Rejecting {}: {}", addr, reason);
+                return self.send_reject(reason, addr, key_id);
+            }
+        };
+        params.key_id = key_id;
+
+        let resume = if join.session != 0 {
+            Some(join.session)
+        } else {
+            None
+        };
+        match self.clients.add_new_client(addr, resume, params) {
+            Some(session) => {
+                let mut ack = Vec::new();
+                AckBody {
+                    session,
+                    addr: Some(addr),
+                }
+                .write(&mut ack);
+                self.auth.sign(key_id, &mut ack);
+                self.outgoing.push((ack, addr));
+            }
+            None => {
+                warn!("Client limit is reached, rejecting: {}", addr);
+                self.send_reject("too many clients", addr, key_id)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// The client at `addr` answers with a join carrying the cookie and its session
+    fn send_challenge(&mut self, addr: SocketAddr, key_id: u8) -> Result<(), Error> {
+        debug!("Sending a cookie challenge to {}", addr);
+        let mut challenge = protocol::control_pkt(protocol::CHALLENGE, &self.cookies.make(&addr));
+        self.auth.sign(key_id, &mut challenge);
+        self.outgoing.push((challenge, addr));
+        Ok(())
+    }
+
+    fn send_reject(&mut self, reason: &str, addr: SocketAddr, key_id: u8) -> Result<(), Error> {
+        let mut pkt = protocol::control_pkt(protocol::REJECT, reason.as_bytes());
+        self.auth.sign(key_id, &mut pkt);
+        self.outgoing.push((pkt, addr));
+        Ok(())
+    }
+
+    fn requested_params(&self, join: &JoinBody) -> Result<StreamParams, &'static str> {
+        let mut params = self.default_params;
+        params.direction = join.direction;
+        params.stream = join.stream;
+        if join.interval_us != 0 {
+            params.interval = Duration::from_micros(join.interval_us.into());
+            params.patterned = false;
+            if !(MIN_CLIENT_INTERVAL..=MAX_CLIENT_INTERVAL).contains(&params.interval) {
+                return Err("unsupported interval");
+            }
+        }
+        if join.packet_size != 0 {
+            params.packet_size = join.packet_size.into();
+            params.variable_size = false;
+            let min_packet_size =
+                protocol::MIN_DATA_LEN + self.format.overhead() + self.auth.trailer_len();
+            if !(min_packet_size..=protocol::MAX_PKT_LEN).contains(&params.packet_size) {
+                return Err("unsupported packet size");
+            }
+        }
+        Ok(params)
+    }
+
+    /// Whether a packet of `session` from `addr`, `what` in logs, is of its client.
+    /// Sessions are in clear: only a join answering a cookie challenge moves a client, the
+    /// challenge is sent to the other address
+    fn of_client(&mut self, session: u32, addr: SocketAddr, what: &str) -> bool {
+        match self.clients.source(session, addr) {
+            Source::Client => true,
+            Source::OtherAddr => {
+                debug!(
+                    "{} of session {:08x} from {}, not the address of its client",
+                    what, session, addr
+                );
+                self.challenge_to = Some(addr);
+                false
+            }
+            Source::Unknown => {
+                debug!("{} of unknown session {:08x} from {}", what, session, addr);
+                false
+            }
+        }
+    }
+
+    fn on_replay_pkt(&mut self, addr: SocketAddr, buf: &[u8], ecn: Ecn) -> Result<(), Error> {
+        let header = DataHeader::parse(buf)?;
+        if !self.of_client(header.session, addr, "Reply") {
+            return Ok(());
+        }
+
+        let pkt_time = Duration::from_micros(header.time_us);
+        let now = self.start.elapsed();
+        let rtt = now
+            .checked_sub(pkt_time)
+            .ok_or_else(|| Error::new("Replay packet time is bigger than now"))?;
+
+        self.on_report(addr, header.session, header.report);
+
+        let session = self.sessions.entry(header.session).or_default();
+        session.max_reply = session.max_reply.max(buf.len() + self.auth.trailer_len());
+        session.replies_since_sync += 1;
+        let mut change = session.seqs.on_seq(header.seq);
+        if change.duplicates == 0 {
+            // The client echoes a corrupted packet as it is, so its flag is checked first
+            if header.flags & protocol::FLAG_CORRUPTED != 0 {
+                warn!(
+                    "Session {:08x} received a corrupted packet, seq: {}",
+                    header.session, header.seq
+                );
+                change.corrupted = 1;
+            } else if !protocol::is_crc_valid(buf) {
+                warn!(
+                    "Corrupted reply of session {:08x}, seq: {}",
+                    header.session, header.seq
+                );
+                change.corrupted = 1;
+            } else {
+                change.recovered = session.seqs.on_redundancy(header.seq, header.redundancy);
+            }
+        }
+        if let Some(len) = self.train_len {
+            if let Some(rate) = on_train_reply(&mut session.train, len, &header, buf.len()) {
+                debug!(
+                    "Packet train of session {:08x}: {:.2} Mbit/s",
+                    header.session, rate
+                );
+                self.train_rates.push(rate);
+            }
+        }
+        self.seq.add(change);
+        self.statistics.set_seq_stats(self.seq);
+        // Replies reordered into the gap are counted as received later
+        let lost = change.expected.saturating_sub(change.received);
+        if lost >= LOSS_BURST_LEN {
+            let first = u64::from(header.seq).saturating_sub(lost);
+            self.clients.event(
+                "loss_burst",
+                &addr,
+                header.session,
+                &format!("first_seq={} lost={}", first, lost),
+            );
+        }
+        if change.duplicates == 0 {
+            session.ecn.add(ecn);
+            self.uplink_ecn.add(ecn);
+            self.uplink.set_ecn(self.uplink_ecn);
+        }
+        if header.stream != 0 {
+            self.streams.on_reply(header.stream, change, rtt);
+        }
+        self.clients_stats
+            .on_arrival(self.clients, header.session, addr, header.seq, change, now);
+        let spike = self
+            .clients_stats
+            .on_reply(self.clients, header.session, addr, change, rtt);
+        if let Some(median) = spike {
+            let mut fields = statistic::spike_fields(header.seq, rtt, median);
+            let pressure = self.pressure.fields();
+            if !pressure.is_empty() {
+                fields.push(' ');
+                fields.push_str(&pressure);
+            }
+            warn!(
+                "RTT spike of {} session {:08x}: {}",
+                addr, header.session, fields
+            );
+            self.clients.event("spike", &addr, header.session, &fields);
+        } else if self.clients_stats.cfg.spike_factor.is_some() {
+            self.pressure.tick();
+        }
+        if let Some(out) = &self.samples_out {
+            out.add(addr, header.session, header.seq, rtt);
+        }
+        if change.duplicates > 0 {
+            return Ok(());
+        }
+        let reply_len = (buf.len() + self.auth.trailer_len()) as u64;
+        self.acked_bytes += reply_len;
+        self.statistics.set_bytes(ByteCounts {
+            sent: self.clients.sent_bytes(None),
+            acked: self.acked_bytes,
+        });
+        self.statistics.new_event(rtt);
+        if let Some(spurts) = &mut self.spurts {
+            spurts.by_flags(header.flags).new_event(rtt);
+        }
+
+        // The client replies right away, so its receive and send times are the same
+        let (sent_us, reply_us, now_us) = (
+            header.time_us as i64,
+            header.reply_us as i64,
+            now.as_micros() as i64,
+        );
+        session
+            .clock
+            .on_exchange(sent_us, reply_us, reply_us, now_us);
+        let reply_us = session.clock.to_local(reply_us);
+        let to_delay = |us: i64| Duration::from_micros(cmp::max(us, 0) as u64);
+        let (down, up) = (to_delay(reply_us - sent_us), to_delay(now_us - reply_us));
+        self.downlink.new_event(down);
+        self.uplink.new_event(up);
+        let client = self
+            .clients_stats
+            .client(self.clients, header.session, addr);
+        client.downlink.new_event(down);
+        client.uplink.new_event(up);
+        client.acked_bytes += reply_len;
+        client.rtt.set_bytes(ByteCounts {
+            sent: self.clients.sent_bytes(Some(header.session)),
+            acked: client.acked_bytes,
+        });
+
+        Ok(())
+    }
+
+    /// Registers a path MTU probe sent back by a client
+    fn on_probe_pkt(&mut self, addr: SocketAddr, buf: &[u8]) {
+        let session = match protocol::parse_session(protocol::body(buf)) {
+            Some(session) if self.of_client(session, addr, "Probe") => session,
+            _ => return,
+        };
+
+        let len = buf.len() + self.auth.trailer_len();
+        let stats = self.sessions.entry(session).or_default();
+        if stats.max_probe.is_none_or(|max| len > max) {
+            stats.max_probe = Some(len);
+            debug!(
+                "Path MTU of session {:08x}: {} byte packets are delivered",
+                session, len
+            );
+        }
+    }
+
+    /// Measures a data packet of a client in the upload direction
+    fn on_upload_pkt(&mut self, addr: SocketAddr, buf: &[u8], ecn: Ecn) -> Result<(), Error> {
+        let header = DataHeader::parse(buf)?;
+        if !self.of_client(header.session, addr, "Data") {
+            return Ok(());
+        }
+
+        let session = self.sessions.entry(header.session).or_default();
+        let mut change = session.seqs.on_seq(header.seq);
+        if change.duplicates == 0 && !protocol::is_crc_valid(buf) {
+            warn!(
+                "Corrupted data packet of session {:08x}, seq: {}",
+                header.session, header.seq
+            );
+            change.corrupted = 1;
+        }
+        self.upload_seq.add(change);
+        self.uplink.set_seq_stats(self.upload_seq);
+        let now = self.start.elapsed();
+        self.clients_stats
+            .on_arrival(self.clients, header.session, addr, header.seq, change, now);
+        let client = self
+            .clients_stats
+            .client(self.clients, header.session, addr);
+        client.upload_seq.add(change);
+        client.uplink.set_seq_stats(client.upload_seq);
+        if change.duplicates > 0 {
+            return Ok(());
+        }
+        session.ecn.add(ecn);
+        self.uplink_ecn.add(ecn);
+        self.uplink.set_ecn(self.uplink_ecn);
+
+        // Without replies the clock is synchronized by sync packets only
+        let now_us = self.start.elapsed().as_micros() as i64;
+        let sent_us = session.clock.to_local(header.time_us as i64);
+        let delay = Duration::from_micros(cmp::max(now_us - sent_us, 0) as u64);
+        self.uplink.new_event(delay);
+        client.uplink.new_event(delay);
+        Ok(())
+    }
+
+    fn on_sync_pkt(&mut self, addr: SocketAddr, buf: &[u8]) -> Result<(), Error> {
+        let sync = SyncBody::parse(protocol::body(buf))?;
+        if !self.of_client(sync.session, addr, "Sync") {
+            return Ok(());
+        }
+
+        let now_us = self.start.elapsed().as_micros() as i64;
+        let session = self.sessions.entry(sync.session).or_default();
+        session
+            .clock
+            .on_exchange(sync.t1 as i64, sync.t2 as i64, sync.t3 as i64, now_us);
+        self.check_fragmentation(sync.session, addr);
+        Ok(())
+    }
+
+    /// Checks if data packets of a session exceed the path MTU, on every sync exchange.
+    /// If routers report a smaller MTU, the kernel fragments bigger packets. If they don't,
+    /// big data packets are lost while small sync packets get through
+    fn check_fragmentation(&mut self, session: u32, addr: SocketAddr) {
+        let params = match self.clients.params(session) {
+            Some(params) => params,
+            None => return,
+        };
+        let stats = match self.sessions.get_mut(&session) {
+            Some(stats) => stats,
+            None => return,
+        };
+        let ip_header_len = if addr.is_ipv4() { 28 } else { 48 };
+        let ip_len = stats.max_reply.max(params.packet_size) + ip_header_len;
+
+        // Only clients in both directions reply to data packets
+        let replied = stats.replies_since_sync > 0;
+        if params.direction == Direction::Both && !replied {
+            stats.syncs_without_replies += 1;
+        } else {
+            stats.syncs_without_replies = 0;
+        }
+        stats.replies_since_sync = 0;
+
+        let fragmentation = match path_mtu(addr) {
+            Ok(mtu) if ip_len > mtu => Some(Fragmentation::Fragmented {
+                ip_len,
+                path_mtu: mtu,
+            }),
+            Ok(_) => None,
+            Err(e) => {
+                debug!("Cannot get path MTU to {}: {}", addr, e);
+                None
+            }
+        };
+        // Probes of every size were delivered if the biggest one was
+        let probe_lost = stats.max_probe.is_some_and(|max| {
+            max < PROBE_SIZES[PROBE_SIZES.len() - 1] && max + ip_header_len < ip_len
+        });
+        // Links must carry IP packets of 576 bytes, or 1280 bytes for IPv6, without
+        // fragmentation, so smaller packets are lost for other reasons
+        let min_mtu = if addr.is_ipv4() { 576 } else { 1280 };
+        let black_hole = ip_len > min_mtu && (stats.syncs_without_replies >= 2 || probe_lost);
+        let fragmentation = fragmentation.or(if black_hole {
+            Some(Fragmentation::BlackHoled { ip_len })
+        } else {
+            None
+        });
+
+        if let Some(new) = fragmentation {
+            if stats.fragmentation != fragmentation {
+                warn!("Session {:08x}: {}", session, new);
+            }
+        }
+        // Black holes are remembered, as replies stop after them
+        if fragmentation.is_some() || replied {
+            stats.fragmentation = fragmentation;
+        }
+    }
+
+    fn on_rtcp_pkt(&mut self, addr: SocketAddr, buf: &[u8]) -> Result<(), Error> {
+        let now = NtpTime::now().compact();
+        let report = match ReportPacket::parse(buf)? {
+            Some(report) => report,
+            None => return Ok(()),
+        };
+        // Clients report with their session as SSRC, both of their own and the received stream
+        if !self.of_client(report.ssrc, addr, "RTCP") {
+            return Ok(());
+        }
+
+        for &block in report.blocks.iter().filter(|b| b.ssrc == report.ssrc) {
+            let rtt = if block.lsr != 0 {
+                let units = now.wrapping_sub(block.lsr).wrapping_sub(block.dlsr);
+                Some(rtcp::compact_to_duration(units))
+            } else {
+                None
+            };
+            let rtcp = RtcpStats { block, rtt };
+            debug!(
+                "RTCP of session {:08x}: fraction lost {}/256, jitter {:.2}ms, RTT {:?}",
+                report.ssrc,
+                block.fraction_lost,
+                rtcp.jitter_ms(),
+                rtt
+            );
+            self.sessions.entry(report.ssrc).or_default().rtcp = Some(rtcp);
+        }
+        Ok(())
+    }
+
+    fn on_query_pkt(&mut self, addr: SocketAddr, buf: &[u8], key_id: u8) -> Result<(), Error> {
+        let mut records = String::new();
+        for stats in [&mut self.statistics, &mut self.uplink, &mut self.downlink] {
+            records.push_str(&stats.window_record("query"));
+            records.push('\n');
+        }
+        // Not an amplifier for spoofed queries: the answer is cut to the query length,
+        // after a whole record
+        let max_len = buf.len().saturating_sub(protocol::PREFIX_LEN);
+        let fitting = &records.as_bytes()[..records.len().min(max_len)];
+        let records = match fitting.iter().rposition(|&b| b == b'\n') {
+            Some(end) => &records[..=end],
+            None if QUERY_TOO_SHORT.len() <= max_len => QUERY_TOO_SHORT,
+            None => return Ok(()),
+        };
+        let mut answer = Vec::new();
+        protocol::write_prefix(protocol::QUERY, &mut answer);
+        answer.extend_from_slice(records.as_bytes());
+        self.auth.sign(key_id, &mut answer);
+
+        self.outgoing.push((answer, addr));
+        Ok(())
+    }
+
+    /// Adds what changed since the previous report of the session to the downlink statistics
+    fn on_report(&mut self, addr: SocketAddr, session: u32, report: Report) {
+        let stats = self.sessions.entry(session).or_default();
+        let prev = stats.report;
+        // Replies can be reordered, reports only grow
+        if report.received < prev.received {
+            return;
+        }
+        stats.report = report;
+        if !stats.reported {
+            stats.reported = true;
+            self.reporting_sessions += 1;
+        }
+
+        let delta = |new: u32, old: u32| u64::from(new.saturating_sub(old));
+        let change = SeqStats {
+            expected: delta(report.expected, prev.expected),
+            received: delta(report.received, prev.received),
+            reordered: delta(report.late, prev.late),
+            recovered: delta(report.recovered, prev.recovered),
+            ..Default::default()
+        };
+        self.reported.add(change);
+        let client = self.clients_stats.client(self.clients, session, addr);
+        client.reported.add(change);
+        client.downlink.set_seq_stats(client.reported);
+        let (ect, ce) = (delta(report.ect, prev.ect), delta(report.ce, prev.ce));
+        self.reported_ecn.ect += ect;
+        self.reported_ecn.ce += ce;
+        self.reported_ecn.not_ect += delta(report.received, prev.received).saturating_sub(ect + ce);
+        self.downlink.set_ecn(self.reported_ecn);
+        self.reported_jitter_us_sum =
+            self.reported_jitter_us_sum + u64::from(report.jitter_us) - u64::from(prev.jitter_us);
+
+        let avg_jitter_us = self.reported_jitter_us_sum as f64 / self.reporting_sessions as f64;
+        self.downlink.set_seq_stats(self.reported);
+        self.downlink.set_reported_jitter(avg_jitter_us / 1000.);
+    }
+
+    fn reset(&mut self) {
+        self.statistics.reset();
+        self.uplink.reset();
+        self.downlink.reset();
+        if let Some(spurts) = &mut self.spurts {
+            for stats in spurts.all() {
+                stats.reset();
+            }
+        }
+        self.streams.streams.clear();
+        self.clients_stats.clients.clear();
+        // Clocks and the latest reports are kept: they are the base for new reports
+        for stats in self.sessions.values_mut() {
+            stats.seqs = Default::default();
+        }
+        self.seq = Default::default();
+        self.upload_seq = Default::default();
+        self.reported = Default::default();
+        self.uplink_ecn = Default::default();
+        self.reported_ecn = Default::default();
+        for stats in self.sessions.values_mut() {
+            stats.ecn = Default::default();
+        }
+        info!("Statistics are reset");
+    }
+
+    /// Counts packets sent to connected clients after their latest reply as lost,
+    /// except for the ones which may still be on the way. Clients without replies are
+    /// skipped: what was sent to them since a statistics reset is unknown
+    fn count_unanswered(&mut self) {
+        for client in self.clients {
+            let seqs = match self.sessions.get(&client.session) {
+                Some(stats) if stats.seqs.max_seq() > 0 => &stats.seqs,
+                _ => continue,
+            };
+            if client.params.direction == Direction::Upload {
+                continue;
+            }
+            let in_flight =
+                (IN_FLIGHT_TIME.as_secs_f64() / client.params.interval.as_secs_f64()).ceil();
+            let unanswered = client
+                .seq
+                .saturating_sub(seqs.max_seq())
+                .saturating_sub(in_flight as u32);
+            if unanswered == 0 {
+                continue;
+            }
+            let change = SeqStats {
+                expected: u64::from(unanswered),
+                ..Default::default()
+            };
+            self.seq.add(change);
+            self.clients_stats
+                .on_unanswered(self.clients, client.session, client.addr, change);
+        }
+        self.statistics.set_seq_stats(self.seq);
+    }
+
+    /// Ends the intervals without samples of connected clients and of their aggregates,
+    /// see `Delays::tick`
+    fn tick(&mut self) {
+        if self.clients.is_empty() {
+            return;
+        }
+        self.statistics.tick();
+        self.uplink.tick();
+        self.downlink.tick();
+        self.clients_stats.tick(self.clients);
+    }
+
+    fn print_summary(&mut self) {
+        self.count_unanswered();
+        if !self.clients_stats.cfg.quiet {
+            let addr = self
+                .socket
+                .local_addr()
+                .map_or("?".to_owned(), |a| a.to_string());
+            println!(
+                "==== Summary of {} after {:.1}s ====",
+                addr,
+                self.start.elapsed().as_secs_f64()
+            );
+        }
+        for (session, stats) in &self.sessions {
+            log_session(*session, stats);
+        }
+        self.clients_stats.print_summary();
+        if let Some(quality) = estimate_quality(&self.statistics, self.default_params.interval) {
+            self.statistics.set_quality(quality);
+        }
+        self.statistics.print_summary();
+        self.clients_stats.print_loss_runs();
+        self.clients_stats.print_reordering();
+        self.uplink.print_summary();
+        self.downlink.print_summary();
+        if !self.train_rates.is_empty() {
+            let rates = &mut self.train_rates;
+            rates.sort_by(f64::total_cmp);
+            println!(
+                "Available bandwidth (packet trains): {:.2}/{:.2}/{:.2} Mbit/s min/median/max of {} trains",
+                rates[0],
+                rates[rates.len() / 2],
+                rates[rates.len() - 1],
+                rates.len()
+            );
+        }
+        if let Some(spurts) = &mut self.spurts {
+            for stats in spurts.all() {
+                stats.print_summary();
+            }
+        }
+        for (rtt, _) in self.streams.streams.values_mut() {
+            rtt.print_summary();
+        }
+    }
+}
+
+impl StreamsStats {
+    /// Tracks a reply of `stream`, `change` is the change of sequence statistics
+    fn on_reply(&mut self, stream: u8, change: SeqStats, rtt: Duration) {
+        let (cfg, label) = (&self.cfg, &self.label);
+        let (delays, seq) = self.streams.entry(stream).or_insert_with(|| {
+            let label = label.as_ref().map(|label| format!("{} {}", label, stream));
+            (
+                statistic::Delays::new(cfg.clone(), label),
+                Default::default(),
+            )
+        });
+        seq.add(change);
+        delays.set_seq_stats(*seq);
+        if change.duplicates == 0 {
+            delays.new_event(rtt);
+        }
+    }
+
+    fn set_config(&mut self, cfg: StatsConfig) {
+        for (delays, _) in self.streams.values_mut() {
+            delays.set_config(cfg.clone());
+        }
+        self.cfg = cfg;
+    }
+}
+
+impl ClientsStats {
+    /// Tracks a reply of `session` from `addr`, `change` is the change of sequence statistics.
+    /// Returns the median RTT of the client if the reply is a spike, see `Delays::spike_median`
+    fn on_reply(
+        &mut self,
+        clients: &Clients,
+        session: u32,
+        addr: SocketAddr,
+        change: SeqStats,
+        rtt: Duration,
+    ) -> Option<Duration> {
+        let client = self.client(clients, session, addr);
+        client.seq.add(change);
+        client.rtt.set_seq_stats(client.seq);
+        if change.duplicates > 0 {
+            return None;
+        }
+        let spike = client.rtt.spike_median(rtt);
+        client.rtt.new_event(rtt);
+        spike
+    }
+
+    /// Tracks loss runs and reordering of a reply or data packet `seq` which arrived at
+    /// `time` since the start, `change` is the change of sequence statistics
+    fn on_arrival(
+        &mut self,
+        clients: &Clients,
+        session: u32,
+        addr: SocketAddr,
+        seq: u32,
+        change: SeqStats,
+        time: Duration,
+    ) {
+        let client = self.client(clients, session, addr);
+        client.loss_runs.on_seq(seq);
+        if change.duplicates == 0 {
+            client.reordering.on_seq(seq, time);
+        }
+    }
+
+    /// Adds packets which were never answered, see `ServerRecv::count_unanswered`
+    fn on_unanswered(
+        &mut self,
+        clients: &Clients,
+        session: u32,
+        addr: SocketAddr,
+        change: SeqStats,
+    ) {
+        let client = self.client(clients, session, addr);
+        client.seq.add(change);
+        client.rtt.set_seq_stats(client.seq);
+    }
+
+    fn client(&mut self, clients: &Clients, session: u32, addr: SocketAddr) -> &mut ClientStats {
+        let (cfg, label, default_interval) = (&self.cfg, &self.label, self.default_interval);
+        let (uplink_label, downlink_label) = (&self.uplink_label, &self.downlink_label);
+        let client = self.clients.entry(session).or_insert_with(|| {
+            let label = label
+                .as_ref()
+                .map(|label| format!("{} {} {:08x}", label, addr, session));
+            let mut rtt = statistic::Delays::new(cfg.clone(), label);
+            // Clients are shown in a table, the live display is the aggregate
+            rtt.set_live(false);
+            let tag = |delays: &mut statistic::Delays| {
+                delays.add_tag("client", addr.to_string());
+                delays.add_tag("session", format!("{:08x}", session));
+            };
+            tag(&mut rtt);
+            let one_way = |label: &Option<String>| {
+                let label = label
+                    .as_ref()
+                    .map(|label| format!("{} {} {:08x}", label, addr, session));
+                let mut delays = statistic::Delays::new(cfg.clone(), label);
+                delays.set_live(false);
+                tag(&mut delays);
+                delays
+            };
+            ClientStats {
+                addr,
+                interval: clients
+                    .params(session)
+                    .map_or(default_interval, |params| params.interval),
+                rtt,
+                seq: Default::default(),
+                uplink: one_way(uplink_label),
+                downlink: one_way(downlink_label),
+                upload_seq: Default::default(),
+                reported: Default::default(),
+                acked_bytes: 0,
+                loss_runs: Default::default(),
+                reordering: Default::default(),
+            }
+        });
+        client.addr = addr;
+        client
+    }
+
+    fn set_config(&mut self, cfg: StatsConfig) {
+        for client in self.clients.values_mut() {
+            client.rtt.set_config(cfg.clone());
+            client.uplink.set_config(cfg.clone());
+            client.downlink.set_config(cfg.clone());
+        }
+        self.cfg = cfg;
+    }
+
+    /// Ticks the statistics of the connected clients, those of clients which left stay
+    fn tick(&mut self, connected: &Clients) {
+        for (session, client) in &mut self.clients {
+            if connected.contains(*session) {
+                client.rtt.tick();
+                client.uplink.tick();
+                client.downlink.tick();
+            }
+        }
+    }
+
+    /// Prints a table of the clients, or their summary records with `quiet`
+    fn print_summary(&mut self) {
+        if self.clients.is_empty() {
+            return;
+        }
+        for client in self.clients.values_mut() {
+            if let Some(quality) = estimate_quality(&client.rtt, client.interval) {
+                client.rtt.set_quality(quality);
+            }
+        }
+        if self.cfg.quiet {
+            for client in self.clients.values_mut() {
+                client.rtt.print_summary();
+                for delays in [&mut client.uplink, &mut client.downlink] {
+                    if has_samples(delays) {
+                        delays.print_summary();
+                    }
+                }
+            }
+            return;
+        }
+        let apdex = self.cfg.apdex.is_some();
+        println!("RTT by client:");
+        println!(
+            "SESSION  {:<22}{} R-FACT   MOS{} SENT_MBPS ACKD_MBPS",
+            "ADDRESS",
+            statistic::TABLE_HEADER,
+            if apdex { "  APDEX" } else { "" }
+        );
+        for (session, client) in &self.clients {
+            let mut quality = match estimate_quality(&client.rtt, client.interval) {
+                Some(q) => format!("{:>7.1} {:>5.2}", q.r_factor, q.mos),
+                None => format!("{:>7} {:>5}", "-", "-"),
+            };
+            if apdex {
+                match client.rtt.apdex() {
+                    Some(score) => write!(quality, " {:>6.2}", score).unwrap(),
+                    None => write!(quality, " {:>6}", "-").unwrap(),
+                }
+            }
+            match client.rtt.throughput_mbps() {
+                Some((sent, acked)) => write!(quality, " {:>9.3} {:>9.3}", sent, acked).unwrap(),
+                None => write!(quality, " {:>9} {:>9}", "-", "-").unwrap(),
+            }
+            println!(
+                "{:08x} {:<22}{}{}",
+                session,
+                client.addr.to_string(),
+                client.rtt.table_row(),
+                quality
+            );
+        }
+        self.print_direction("Uplink", |c| &c.uplink);
+        self.print_direction("Downlink", |c| &c.downlink);
+    }
+
+    /// Prints the loss runs of all clients, after a table of them with several clients or
+    /// their records with `quiet`
+    fn print_loss_runs(&self) {
+        let (quiet, jsonl) = (self.cfg.quiet, self.cfg.jsonl.as_deref());
+        let mut total = LossRuns::default();
+        if self.clients.len() > 1 && !quiet {
+            println!("Loss runs by client:");
+            println!(
+                "SESSION  {:<22}    RUNS  AVG_LEN  LONGEST GILB_P_% GILB_R_% BURST_R",
+                "ADDRESS"
+            );
+        }
+        for (session, client) in &self.clients {
+            let runs = client.loss_runs.finished();
+            total.merge(&runs);
+            if self.clients.len() < 2 {
+                continue;
+            }
+            if quiet {
+                if let Some(label) = client.rtt.label() {
+                    runs.print(label, quiet, jsonl);
+                }
+                continue;
+            }
+            let gilbert = match runs.gilbert() {
+                Some(g) => format!(
+                    "{:>8.3} {:>8.2} {:>7.2}",
+                    g.p * 100.,
+                    g.r * 100.,
+                    g.burst_ratio()
+                ),
+                None => format!("{:>8} {:>8} {:>7}", "-", "-", "-"),
+            };
+            println!(
+                "{:08x} {:<22}{:>8} {:>8} {:>8} {}",
+                session,
+                client.addr.to_string(),
+                runs.runs(),
+                runs.avg_len()
+                    .map_or_else(|| "-".to_owned(), |len| format!("{:.2}", len)),
+                runs.longest(),
+                gilbert
+            );
+        }
+        if !self.clients.is_empty() {
+            total.print(self.rtt_label.as_deref().unwrap_or("RTT"), quiet, jsonl);
+        }
+    }
+
+    /// Prints the reordering of all clients, after the one of every client with reordered
+    /// packets if there are several clients
+    fn print_reordering(&self) {
+        if self.clients.is_empty() {
+            return;
+        }
+        let mut total = Reordering::default();
+        for client in self.clients.values() {
+            total.merge(&client.reordering);
+            let shown = self.cfg.quiet || client.reordering.reordered() > 0;
+            if self.clients.len() > 1 && shown {
+                if let Some(label) = client.rtt.label() {
+                    client.reordering.print(&self.cfg, label);
+                }
+            }
+        }
+        total.print(&self.cfg, self.rtt_label.as_deref().unwrap_or("RTT"));
+    }
+
+    /// Prints a table of the one-way statistics of the clients, unless none has any
+    fn print_direction(&self, direction: &str, delays: fn(&ClientStats) -> &statistic::Delays) {
+        if !self.clients.values().any(|c| has_samples(delays(c))) {
+            return;
+        }
+        println!("{} by client:", direction);
+        println!("SESSION  {:<22}{}", "ADDRESS", statistic::TABLE_HEADER);
+        for (session, client) in &self.clients {
+            println!(
+                "{:08x} {:<22}{}",
+                session,
+                client.addr.to_string(),
+                delays(client).table_row()
+            );
+        }
+    }
+}
+
+/// Whether one-way statistics of a client have anything: clients of one direction
+/// or without synchronized clocks have none in the other
+fn has_samples(delays: &statistic::Delays) -> bool {
+    delays.avg_ms().is_some() || delays.seq_stats().expected > 0
+}
+
+/// Estimates the quality of calls over the path of the `rtt` statistics, taking half
+/// of the RTT as the one-way delay. `None` without samples
+fn estimate_quality(rtt: &statistic::Delays, interval: Duration) -> Option<Quality> {
+    let avg_ms = rtt.avg_ms()?;
+    Some(mos::estimate(
+        avg_ms / 2.,
+        rtt.jitter_ms(),
+        rtt.seq_stats().loss_percent(),
+        interval,
+    ))
+}
+
+/// Tracks replies of packet trains of `len` packets, returns the bandwidth estimate
+/// in Mbit/s when a train is over
+fn on_train_reply(
+    train: &mut Option<Train>,
+    len: u32,
+    header: &DataHeader,
+    pkt_len: usize,
+) -> Option<f64> {
+    let now = Instant::now();
+    let in_train = |t: &Train| header.seq.wrapping_sub(t.first_seq) < len;
+    let mut rate = None;
+    if header.flags & protocol::FLAG_TRAIN != 0 {
+        match train {
+            Some(t) if in_train(t) => {
+                t.received += 1;
+                t.bytes += pkt_len;
+                t.last_arrival = now;
+            }
+            _ => {
+                rate = train.take().and_then(|t| t.rate_mbps());
+                *train = Some(Train {
+                    first_seq: header.seq,
+                    received: 1,
+                    bytes: 0,
+                    first_arrival: now,
+                    last_arrival: now,
+                });
+            }
+        }
+    } else if train
+        .as_ref()
+        .is_some_and(|t| !in_train(t) && header.seq > t.first_seq)
+    {
+        // Packets of the steady stream can be sent in the middle of a train
+        rate = train.take().and_then(|t| t.rate_mbps());
+    }
+
+    if train.as_ref().is_some_and(|t| t.received == len) {
+        rate = train.take().and_then(|t| t.rate_mbps());
+    }
+    rate
+}
+
+impl Train {
+    /// Needs at least two received packets
+    fn rate_mbps(&self) -> Option<f64> {
+        let dispersion = self.last_arrival - self.first_arrival;
+        if self.received < 2 || dispersion.is_zero() {
+            return None;
+        }
+        Some(self.bytes as f64 * 8. / dispersion.as_secs_f64() / 1e6)
+    }
+}
+
+impl SpurtStats {
+    /// Statistics of the position marked by `FLAG_*` of a data packet
+    fn by_flags(&mut self, flags: u8) -> &mut statistic::Delays {
+        if flags & protocol::FLAG_SPURT_START != 0 {
+            &mut self.start
+        } else if flags & protocol::FLAG_COMFORT_NOISE != 0 {
+            &mut self.comfort_noise
+        } else {
+            &mut self.talk
+        }
+    }
+
+    fn all(&mut self) -> [&mut statistic::Delays; 3] {
+        [&mut self.start, &mut self.talk, &mut self.comfort_noise]
+    }
+}
+
+impl<'a> ServerSend<'a> {
+    async fn send_loop(&mut self) -> Result<(), Error> {
+        loop {
+            let next_send = self.send_due_packets().await?;
+            // The receiving side may not see the errors, they fail sends as well
+            on_icmp_errors(self.socket, self.clients);
+            if self.last_sync.elapsed() >= SYNC_INTERVAL {
+                self.last_sync = Instant::now();
+                self.clients.evict_idle(self.idle_timeout);
+                let clients = self.clients;
+                self.pkt
+                    .frames
+                    .retain(|session, _| clients.contains(*session));
+                self.last_sweeps
+                    .retain(|session, _| clients.contains(*session));
+                self.send_sync_to_all().await?;
+                let addr = self.socket.local_addr()?.to_string();
+                metrics::update_clients(&addr, self.clients.len());
+                self.wakeups.publish(&addr);
+                self.send_times.publish(&addr);
+                if self.pkt.format == Format::Rtp {
+                    self.send_sender_reports().await?;
+                }
+            }
+            if self.mtu_sweep {
+                self.send_mtu_probes().await?;
+            }
+            if let Some(len) = self.train_len {
+                if self.last_train.elapsed() >= self.train_interval {
+                    self.last_train = Instant::now();
+                    self.clients.take_train(len, &mut self.pkt.due);
+                    self.pkt.gen_next_pkts(protocol::FLAG_TRAIN);
+                    self.send_pkts().await?;
+                }
+            }
+
+            // Without clients new ones are checked for every default interval
+            let wake_up = next_send.unwrap_or_else(|| Instant::now() + self.interval);
+            sleep(wake_up.saturating_duration_since(Instant::now())).await;
+            if next_send.is_some() {
+                self.wakeups.add(wake_up, Instant::now());
+            }
+        }
+    }
+
+    /// Sends packets to clients they are due to, returns when the next packet is due
+    async fn send_due_packets(&mut self) -> Result<Option<Instant>, Error> {
+        let next_send = self.clients.take_due(Instant::now(), &mut self.pkt.due);
+        if self.pkt.due.is_empty() {
+            return Ok(next_send);
+        }
+
+        self.pkt.gen_next_pkts(0);
+        let sent = Instant::now();
+        self.send_pkts().await?;
+        for client in &self.pkt.due {
+            self.send_times.add(client.scheduled, sent);
+        }
+
+        Ok(next_send)
+    }
+
+    /// Sends the generated packets to their clients
+    async fn send_pkts(&mut self) -> Result<(), Error> {
+        let mut futs = self.send_futures.borrow()?;
+
+        futs.reserve(self.pkt.due.len());
+        let (socket, pkts) = (self.socket, &self.pkt.bufs);
+        futs.extend(
+            self.pkt
+                .due
+                .iter()
+                .zip(pkts)
+                .map(|(client, pkt)| send_to(socket, pkt, client.addr)),
+        );
+
+        futs.run().await?;
+        self.clients.on_sent(&self.pkt.due, pkts);
+
+        Ok(())
+    }
+
+    /// Starts a clock synchronization exchange with every client
+    async fn send_sync_to_all(&mut self) -> Result<(), Error> {
+        let t1 = self.pkt.start.elapsed().as_micros() as u64;
+        self.sync_bufs.resize_with(self.clients.len(), Vec::new);
+        for (buf, client) in self.sync_bufs.iter_mut().zip(self.clients) {
+            buf.clear();
+            let sync = SyncBody {
+                session: client.session,
+                t1,
+                t2: 0,
+                t3: 0,
+            };
+            sync.write(buf);
+            self.auth.sign(client.params.key_id, buf);
+        }
+
+        let mut futs = self.send_futures.borrow()?;
+        let (clients, socket, pkts) = (self.clients, self.socket, &self.sync_bufs);
+        futs.extend(
+            clients
+                .iter()
+                .zip(pkts)
+                .map(|(client, pkt)| send_to(socket, pkt, client.addr)),
+        );
+        futs.run().await?;
+
+        Ok(())
+    }
+
+    /// Sends probes of every size in `PROBE_SIZES` with DF set to new clients and to clients
+    /// swept more than `MTU_SWEEP_INTERVAL` ago
+    async fn send_mtu_probes(&mut self) -> Result<(), Error> {
+        let now = Instant::now();
+        let last_sweeps = &self.last_sweeps;
+        let due: Vec<Client> = self
+            .clients
+            .iter()
+            .filter(|client| {
+                last_sweeps
+                    .get(&client.session)
+                    .is_none_or(|last| now.duration_since(*last) >= MTU_SWEEP_INTERVAL)
+            })
+            .collect();
+        if due.is_empty() {
+            return Ok(());
+        }
+
+        set_mtu_probe(self.socket, true)?;
+        let mut pkt = Vec::with_capacity(PROBE_SIZES[PROBE_SIZES.len() - 1]);
+        for client in due {
+            self.last_sweeps.insert(client.session, now);
+            for &size in &PROBE_SIZES {
+                pkt.clear();
+                protocol::write_prefix(protocol::PROBE, &mut pkt);
+                pkt.extend_from_slice(&client.session.to_be_bytes());
+                pkt.resize(size - self.auth.trailer_len(), 0);
+                self.auth.sign(client.params.key_id, &mut pkt);
+                // Probes bigger than the MTU of the local interface are not sent at all
+                if let Err(e) = send_to(self.socket, &pkt, client.addr).await {
+                    debug!(
+                        "Probe of {} bytes to {} is not sent: {}",
+                        size, client.addr, e
+                    );
+                }
+            }
+        }
+        set_mtu_probe(self.socket, false)
+    }
+
+    /// Sends an RTCP sender report to every client, clients answer with receiver reports
+    async fn send_sender_reports(&mut self) -> Result<(), Error> {
+        let ntp_time = NtpTime::now();
+        let rtp_timestamp = rtp::timestamp(self.pkt.start.elapsed());
+        self.sync_bufs.resize_with(self.clients.len(), Vec::new);
+        for (buf, client) in self.sync_bufs.iter_mut().zip(self.clients) {
+            buf.clear();
+            let payload_len = client.params.packet_size - rtp::HEADER_LEN;
+            let report = ReportPacket {
+                ssrc: client.session,
+                sender_info: Some(SenderInfo {
+                    ntp_time,
+                    rtp_timestamp,
+                    packets: client.seq,
+                    octets: client.seq.wrapping_mul(payload_len as u32),
+                }),
+                blocks: Vec::new(),
+            };
+            report.write(&self.cname, buf);
+            self.auth.sign(client.params.key_id, buf);
+        }
+
+        let mut futs = self.send_futures.borrow()?;
+        let (clients, socket, pkts) = (self.clients, self.socket, &self.sync_bufs);
+        futs.extend(
+            clients
+                .iter()
+                .zip(pkts)
+                .map(|(client, pkt)| send_to(socket, pkt, client.addr)),
+        );
+        futs.run().await?;
+
+        Ok(())
+    }
+}
+
+impl RtcpStats {
+    fn jitter_ms(&self) -> f64 {
+        f64::from(self.block.jitter) * 1000. / rtp::CLOCK_RATE as f64
+    }
+}
+
+fn log_session(session: u32, stats: &SessionStats) {
+    let clock = &stats.clock;
+    if let Some(offset_ms) = clock.latest_offset_ms() {
+        info!(
+            "Clock of session {:08x}: offset {:.1}ms, drift {:.1}ppm",
+            session,
+            offset_ms,
+            clock.drift_ppm()
+        );
+    }
+    if let Some(max_probe) = stats.max_probe {
+        info!(
+            "Path MTU of session {:08x}: the largest delivered probe is {} of {} bytes",
+            session,
+            max_probe,
+            PROBE_SIZES[PROBE_SIZES.len() - 1]
+        );
+    }
+    let (up, report) = (&stats.ecn, &stats.report);
+    if up.is_used() || report.ect + report.ce > 0 {
+        let percent = |ce: u64, ect: u64| match ce + ect {
+            0 => 0.,
+            total => ce as f64 * 100. / total as f64,
+        };
+        info!(
+            "ECN of session {:08x}: CE-marked uplink {:.2}%, downlink {:.2}%",
+            session,
+            up.ce_percent(),
+            percent(report.ce.into(), report.ect.into())
+        );
+    }
+    if let Some(fragmentation) = &stats.fragmentation {
+        info!(
+            "Fragmentation of session {:08x}: {}",
+            session, fragmentation
+        );
+    }
+    if let Some(rtcp) = &stats.rtcp {
+        info!(
+            "RTCP of session {:08x}: fraction lost {}/256, lost {}, jitter {:.2}ms, RTT {}",
+            session,
+            rtcp.block.fraction_lost,
+            rtcp.block.cumulative_lost,
+            rtcp.jitter_ms(),
+            rtcp.rtt
+                .map_or_else(|| "unknown".to_owned(), |rtt| format!("{:?}", rtt))
+        );
+    }
+}
+
+async fn send_to<'a>(socket: &'a UdpSocket, pkt: &'a [u8], addr: SocketAddr) -> Result<(), Error> {
+    match socket.send_to(pkt, addr).await {
+        // An ICMP error about an earlier packet, this one is not sent yet
+        Err(e) if is_unreachable_error(&e) => socket.send_to(pkt, addr).await?,
+        res => res?,
+    };
+    Ok(())
+}
+
+/// Reads ICMP errors about sent packets and removes clients which are unreachable
+fn on_icmp_errors(socket: &UdpSocket, clients: &Clients) {
+    match recv_errors(socket) {
+        Ok(errors) => {
+            for error in errors.iter().filter(|e| e.is_unreachable()) {
+                debug!("ICMP error about a packet to {}", error);
+                clients.on_unreachable(&error.addr, UNREACHABLE_LIMIT);
+            }
+        }
+        Err(e) => warn!("Cannot read ICMP errors: {}", e),
+    }
+}
+
+impl<'a> PktToSend<'a> {
+    /// Generates a packet for every due client into `bufs`
+    /// `flags` are added to the flags of every packet
+    fn gen_next_pkts(&mut self, flags: u8) {
+        let elapsed = self.start.elapsed();
+        let time_us = elapsed.as_micros() as u64;
+        let rtp_timestamp = rtp::timestamp(elapsed);
+
+        self.bufs.resize_with(self.due.len(), Vec::new);
+        for (buf, client) in self.bufs.iter_mut().zip(&self.due) {
+            buf.clear();
+            if self.format == Format::Rtp {
+                let rtp_header = RtpHeader {
+                    marker: client.seq == 1,
+                    payload_type: rtp::PAYLOAD_TYPE,
+                    seq: client.seq as u16,
+                    timestamp: rtp_timestamp,
+                    ssrc: client.session,
+                };
+                rtp_header.write(buf);
+            }
+            let start = buf.len();
+
+            let packet_size = match self.sizes {
+                Some(sizes) if client.params.variable_size => sizes.size(client.seq),
+                _ => client.params.packet_size,
+            };
+            let trailer_len = self.auth.trailer_len();
+            let frame_len =
+                packet_size - start - protocol::DATA_HEADER_LEN - protocol::CRC_LEN - trailer_len;
+            let history = self.frames.entry(client.session).or_default();
+            // The oldest frames are left out if the packet would be too big
+            let mut len = packet_size + history.iter().map(Vec::len).sum::<usize>();
+            let skip = history
+                .iter()
+                .take_while(|frame| {
+                    let too_big = len > protocol::MAX_PKT_LEN - trailer_len;
+                    len -= frame.len();
+                    too_big
+                })
+                .count();
+
+            let header = DataHeader {
+                session: client.session,
+                seq: client.seq,
+                time_us,
+                reply_us: 0,
+                flags: flags
+                    | match client.position {
+                        SpurtPosition::Start => protocol::FLAG_SPURT_START,
+                        SpurtPosition::Talk => 0,
+                        SpurtPosition::ComfortNoise => protocol::FLAG_COMFORT_NOISE,
+                    },
+                redundancy: (history.len() - skip) as u8,
+                stream: client.params.stream,
+                report: Default::default(),
+            };
+            header.write(protocol::DATA, buf);
+
+            for frame in history.iter().skip(skip) {
+                buf.extend_from_slice(frame);
+            }
+            let frame_start = buf.len();
+            self.payload.fill(buf, frame_start + frame_len);
+            if self.redundancy > 0 {
+                let mut frame = if history.len() >= usize::from(self.redundancy) {
+                    history.pop_front().unwrap_or_default()
+                } else {
+                    Vec::new()
+                };
+                frame.clear();
+                frame.extend_from_slice(&buf[frame_start..]);
+                history.push_back(frame);
+            }
+            protocol::append_crc(buf, start);
+            self.auth.sign(client.params.key_id, buf);
+        }
+    }
+}
+
+fn print_effective_settings(opts: &ServeOpts, servers: &[Server]) -> Result<(), Error> {
+    println!("Configuration is valid");
+    for server in servers {
+        println!(
+            "Listening on: {}, IP TOS: {:#04x}",
+            server.socket.local_addr()?,
+            get_tos(&server.socket)?
+        );
+    }
+    match opts.duration {
+        Some(d) => println!("Duration: {:?}", d),
+        None => println!("Duration: unlimited"),
+    }
+    if let Some(profile) = opts.profile {
+        println!("Profile: {:?}", profile);
+    }
+    println!("Interval: {:?}", opts.interval);
+    if let Some(pattern) = &opts.interval_pattern {
+        println!("Interval pattern: {:?}", pattern);
+    }
+    if let Some(activity) = &opts.voice_activity {
+        println!("Voice activity: {:?}", activity);
+    }
+    println!("Redundancy: {} frames", opts.redundancy);
+    println!("MTU sweep: {}", opts.mtu_sweep);
+    let key_ids: Vec<u8> = opts.auth_key.iter().map(|key| key.id).collect();
+    println!("Authentication key IDs: {:?}", key_ids);
+    if let Some(len) = opts.burst {
+        println!(
+            "Packet trains: {} packets every {:?}",
+            len, opts.burst_interval
+        );
+    }
+    println!("Packet size: {} bytes", opts.packet_size);
+    if let Some(model) = &opts.size_model {
+        println!("Size model: {:?}", model);
+    }
+    println!("Format: {:?}", opts.format);
+    println!("DSCP: {}", opts.dscp);
+    println!("ECN: {:?}", opts.ecn);
+    match opts.max_clients {
+        Some(max) => println!("Max clients: {}", max),
+        None => println!("Max clients: unlimited"),
+    }
+    println!("Idle timeout: {:?}", opts.idle_timeout);
+    match opts.source_rate {
+        0 => println!("Source rate limit: none"),
+        rate => println!("Source rate limit: {} joins per second", rate),
+    }
+    if let Some(path) = &opts.admin_socket {
+        println!("Admin socket: {}", path.display());
+    }
+    println!("Payload: {:?}", opts.payload);
+    match opts.seed {
+        Some(seed) => println!("Payload seed: {}", seed),
+        None => println!("Payload seed: random"),
+    }
+    println!("Statistics interval: {:?}", opts.stats.display_interval);
+    match opts.stats.window_time {
+        Some(window) => println!("Statistics window: {:?}", window),
+        None => println!("Statistics window: {} samples", opts.stats.window),
+    }
+    println!("Quiet: {}", opts.stats.quiet);
+    let percentiles: Vec<_> = opts
+        .stats
+        .percentiles
+        .iter()
+        .map(|p| statistic::format_percent(*p))
+        .collect();
+    println!("Percentiles: {}", percentiles.join(","));
+
+    Ok(())
+}
+
+/// Answers the commands of the `--admin-socket`, `recvs` are the receiving sides of
+/// `servers`
+async fn serve_admin(
+    opts: &ServeOpts,
+    servers: &[Server],
+    recvs: &[RefCell<ServerRecv<'_>>],
+) -> Result<(), Error> {
+    let path = some_or_ret!(&opts.admin_socket, Ok(()));
+    admin::serve(path, |command| {
+        let mut args = command.split_whitespace();
+        match args.next() {
+            // Packets are handled without awaiting, none is half-counted when they are reset
+            Some("reset") => {
+                for recv in recvs {
+                    recv.borrow_mut().reset();
+                }
+                Ok("ok".to_owned())
+            }
+            Some("clients") => Ok(servers
+                .iter()
+                .flat_map(|server| &server.clients)
+                .map(|c| {
+                    format!(
+                        "{:08x} {} interval={:?} size={}",
+                        c.session, c.addr, c.params.interval, c.params.packet_size
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("; ")),
+            Some("set") => set_client_rate(servers, args),
+            _ => Err(Error::new(format!("unknown command: {}", command))),
+        }
+    })
+    .await
+}
+
+/// The `set` admin command: `<SESSION|ADDR> [interval=<DURATION>] [size=<BYTES>]`
+fn set_client_rate<'a>(
+    servers: &[Server],
+    mut args: impl Iterator<Item = &'a str>,
+) -> Result<String, Error> {
+    let id = args
+        .next()
+        .ok_or_else(|| Error::new("expected a session or an address"))?;
+    let (mut interval, mut packet_size) = (None, None);
+    for arg in args {
+        match arg.split_once('=') {
+            Some(("interval", value)) => interval = Some(parse_duration(value)?),
+            Some(("size", value)) => {
+                packet_size = Some(
+                    value
+                        .parse::<usize>()
+                        .map_err(|_| Error::new(format!("invalid size: {}", value)))?,
+                )
+            }
+            _ => return Err(Error::new(format!("unknown argument: {}", arg))),
+        }
+    }
+    if interval.is_none() && packet_size.is_none() {
+        return Err(Error::new("expected interval=<DURATION> or size=<BYTES>"));
+    }
+
+    for server in servers {
+        let client = server
+            .clients
+            .iter()
+            .find(|c| match id.parse::<SocketAddr>() {
+                Ok(addr) => c.addr == addr,
+                Err(_) => u32::from_str_radix(id, 16) == Ok(c.session),
+            });
+        let client = match client {
+            Some(client) => client,
+            None => continue,
+        };
+        if let Some(interval) = interval {
+            if !(MIN_CLIENT_INTERVAL..=MAX_CLIENT_INTERVAL).contains(&interval) {
+                return Err(Error::new("unsupported interval"));
+            }
+        }
+        if let Some(packet_size) = packet_size {
+            let min_packet_size =
+                protocol::MIN_DATA_LEN + server.format.overhead() + server.auth.trailer_len();
+            if !(min_packet_size..=protocol::MAX_PKT_LEN).contains(&packet_size) {
+                return Err(Error::new("unsupported packet size"));
+            }
+        }
+        server
+            .clients
+            .set_rate(client.session, interval, packet_size);
+        return Ok("ok".to_owned());
+    }
+    Err(Error::new(format!("no such client: {}", id)))
+}
+
+/// Relays QUIC clients to the first server socket
+#[cfg(feature = "quic")]
+async fn serve_quic(opts: &ServeOpts, servers: &[Server]) -> Result<(), Error> {
+    let bind = some_or_ret!(&opts.quic, Ok(()));
+    let bind = crate::net::resolve(bind).await?;
+    crate::quic::serve(bind, relay_target(servers)?).await
+}
+
+#[cfg(not(feature = "quic"))]
+async fn serve_quic(opts: &ServeOpts, _servers: &[Server]) -> Result<(), Error> {
+    match opts.quic {
+        Some(_) => Err(Error::new("QUIC needs a build with the `quic` feature")),
+        None => Ok(()),
+    }
+}
+
+#[cfg(feature = "dtls")]
+async fn serve_dtls(opts: &ServeOpts, servers: &[Server]) -> Result<(), Error> {
+    let bind = some_or_ret!(&opts.dtls, Ok(()));
+    let psk = opts.dtls_psk.as_deref().unwrap_or_default();
+    let bind = crate::net::resolve(bind).await?;
+    crate::dtls::serve(bind, relay_target(servers)?, psk).await
+}
+
+#[cfg(not(feature = "dtls"))]
+async fn serve_dtls(opts: &ServeOpts, _servers: &[Server]) -> Result<(), Error> {
+    if opts.dtls.is_some() || opts.dtls_psk.is_some() {
+        return Err(Error::new("DTLS needs a build with the `dtls` feature"));
+    }
+    Ok(())
+}
+
+/// Where QUIC and DTLS relays send packets of their clients: the first server socket
+#[cfg(any(feature = "quic", feature = "dtls"))]
+fn relay_target(servers: &[Server]) -> Result<SocketAddr, Error> {
+    let mut addr = servers[0].socket.local_addr()?;
+    if addr.ip().is_unspecified() {
+        addr.set_ip(crate::net::loopback_addr(addr.ip()).ip());
+    }
+    Ok(addr)
+}
+
+/// Re-reads the `--config` file on SIGHUP and applies it to the running servers,
+/// `recvs` are their receiving sides. Settings absent in the file are taken from the
+/// command line. `started` are the settings the servers started with.
+async fn reload_on_sighup(
+    cli_opts: &ServeOpts,
+    started: &StatsConfig,
+    servers: &[Server],
+    recvs: &[RefCell<ServerRecv<'_>>],
+) -> Result<(), Error> {
+    const CHECK_INTERVAL: Duration = Duration::from_millis(200);
+
+    if cli_opts.config.is_none() {
+        return Ok(());
+    }
+
+    let hup = Arc::new(AtomicBool::new(false));
+    signal_hook::flag::register(SIGHUP, hup.clone())?;
+
+    loop {
+        sleep(CHECK_INTERVAL).await;
+        if !hup.swap(false, Ordering::Relaxed) {
+            continue;
+        }
+
+        let mut opts = cli_opts.clone();
+        if let Err(e) = opts.apply_config_file() {
+            error!("Cannot reload configuration: {}", e);
+            continue;
+        }
+        // P² estimators of the run are made at the start, for the percentiles of then
+        if started.run_quantiles == RunQuantiles::P2
+            && opts.stats.percentiles != started.percentiles
+        {
+            warn!("Percentiles are not reloaded, they can't change with --run-quantiles p2");
+            opts.stats.percentiles = started.percentiles.clone();
+        }
+
+        for server in servers {
+            if let Err(e) = set_tos(&server.socket, opts.dscp, opts.ecn) {
+                error!("Cannot set the DSCP: {}", e);
+            }
+        }
+        // Packets are handled without awaiting, the settings change between two of them
+        for recv in recvs {
+            recv.borrow_mut().set_config(opts.stats.clone());
+        }
+        info!("Configuration reloaded");
+    }
+}
@@ -1,62 +1,1514 @@
-use std::collections::VecDeque;
+use crate::alert::Alerter;
+use crate::baseline;
+use crate::config::StatsConfig;
+use crate::error::Error;
+use crate::export::{self, IntervalRow, RowLoss, CSV_HEADER, ROW_PERCENTILES};
+use crate::heatmap::Heatmap;
+use crate::histogram::{Histogram, PercentileMethod};
+use crate::hlog;
+use crate::influx;
+use crate::metrics;
+use crate::mos::Quality;
+use crate::net::Ecn;
+use crate::otlp;
+use crate::p2::{RunQuantiles, P2};
+use crate::report;
+use crate::statsd;
+use crate::store;
+use crate::threshold::Metric;
+use crate::websocket;
+use async_std::task;
+use log::{info, warn};
+use std::cmp;
+use std::collections::{BTreeMap, VecDeque};
 use std::fmt::Write;
-use std::time::{Duration, Instant};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Once;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 
-const QUEUE_LEN: usize = 150;
-const DISPLAY_INTERVAL: Duration = Duration::from_secs(2);
-const PERCENTILES: [f64; 9] = [0.80, 0.90, 0.95, 0.98, 0.985, 0.99, 0.995, 0.998, 0.999];
+/// Ids of `Delays` to know who printed the last live output.
+/// Only the last writer can clear its output, otherwise it would erase somebody else's lines.
+static NEXT_ID: AtomicUsize = AtomicUsize::new(1);
+static LAST_WRITER: AtomicUsize = AtomicUsize::new(0);
+/// The header of `cfg.rows` is printed once for all `Delays`
+static ROWS_HEADER: Once = Once::new();
 
 pub struct Delays {
-    delays: VecDeque<Duration>,
+    cfg: StatsConfig,
+    id: usize,
+    label: Option<String>,
+    /// Samples of the window
+    delays: VecDeque<Sample>,
     last_display: Instant,
-    sorted_delays: Vec<Duration>,
+    /// The delays of the window, for percentiles
+    histogram: Histogram,
     last_new_lines: usize,
+    /// Window statistics are displayed on stderr, see `set_live`
+    live: bool,
+    /// No sample arrived in the latest interval nor since, although the stream started:
+    /// the window counts as lost, see `tick`
+    silent: bool,
+    totals: Totals,
+    /// RFC 3550 jitter of the samples: differences of consecutive delays are differences
+    /// of transit times
+    jitter: InterarrivalJitter,
+    /// Exponentially weighted moving averages of the delay and of differences
+    /// of consecutive delays
+    ewma: Ewma,
+    ewma_jitter: Ewma,
+    seq: SeqStats,
+    /// Jitter reported by the receiving side
+    reported_jitter_ms: Option<f64>,
+    ecn: EcnCounts,
+    /// Bytes of `set_bytes`, and when and at which values the run started or was reset
+    bytes: ByteCounts,
+    bytes_start: (Instant, ByteCounts),
+    created: Instant,
+    /// Samples and loss since the previous interval, for `cfg.rows` and `cfg.hlog`
+    row: RowStats,
+    /// Samples too late to play by the depths of `cfg.jitter_buffers`
+    late: Vec<u64>,
+    /// Call quality estimated from these statistics, see `set_quality`
+    quality: Option<Quality>,
+    /// Periods without packets, e.g. while a client reconnects, and their total duration
+    gaps: u64,
+    gap_time: Duration,
+    alerter: Alerter,
+    /// Delays of the whole run by time, see `enable_heatmap`
+    heatmap: Option<Heatmap>,
+    /// The clock of recorded samples, see `replay_event`
+    replay: Option<Replay>,
+    /// Tags of `--statsd`, `--influx` and `--otlp` metrics besides the label, see `add_tag`
+    tags: Vec<(&'static str, String)>,
+}
+
+/// Unit of delays on display, see `format_ms`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Units {
+    /// The biggest unit the value is at least 1 of
+    Auto,
+    Ms,
+    Us,
+    Ns,
+}
+
+/// Packet loss, reordering and corruption
+#[derive(Debug, Default, Clone, Copy)]
+pub struct SeqStats {
+    pub expected: u64,
+    pub received: u64,
+    /// Packets received after a packet with a bigger sequence number
+    pub reordered: u64,
+    /// The biggest distance from the highest sequence number of a reordered packet
+    pub max_reorder: u32,
+    /// Packets received again, they are not counted as received
+    pub duplicates: u64,
+    /// Packets with a wrong payload checksum
+    pub corrupted: u64,
+    /// Frames missing when a later packet carrying them in its redundancy arrived.
+    /// Receivers using the redundancy would have concealed these losses
+    pub recovered: u64,
+}
+
+/// Bytes of the packets of a stream and of the replies to them
+#[derive(Debug, Default, Clone, Copy)]
+pub struct ByteCounts {
+    pub sent: u64,
+    /// Bytes of replies, which are as big as the packets they acknowledge
+    pub acked: u64,
+}
+
+/// ECN codepoints of received packets
+#[derive(Debug, Default, Clone, Copy)]
+pub struct EcnCounts {
+    pub not_ect: u64,
+    /// ECT(0) or ECT(1): ECN-capable and not marked on the way
+    pub ect: u64,
+    /// Congestion experienced
+    pub ce: u64,
+}
+
+/// Tracks sequence numbers of one stream. Packets after the highest received one
+/// are not expected yet: they may be still in flight
+#[derive(Debug, Default)]
+pub struct SeqTracker {
+    first: Option<u32>,
+    max: u32,
+    received: u64,
+    /// Bit `i` is set if `max - i` was received
+    seen: u128,
+    /// Bit `i` is set if the frame of `max - i` was received in redundancy
+    recovered: u128,
+}
+
+/// Columns of `Delays::table_row`
+pub const TABLE_HEADER: &str = " SAMPLES   AVG_MS   P99_MS   MAX_MS JITT_MS  LOSS_% REORD_%";
+/// Number of the worst samples shown in the summary
+const WORST_LEN: usize = 5;
+
+/// How often `tick_every` ticks: how late an interval without samples can end
+const TICK_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Samples in the window before spikes of `cfg.spike_factor` are detected
+const SPIKE_MIN_SAMPLES: usize = 10;
+
+/// Quantile of the delay PDV is measured at, as in ITU-T Y.1541
+const PDV_QUANTILE: f64 = 0.999;
+/// Quantile of absolute IPDV values shown
+const IPDV_QUANTILE: f64 = 0.99;
+
+/// Delay variation of the samples in the window
+struct Pdv {
+    /// Smallest and biggest difference of consecutive delays, RFC 3393 IPDV
+    ipdv_min_ms: f64,
+    ipdv_max_ms: f64,
+    /// `IPDV_QUANTILE` of the absolute IPDV values
+    ipdv_quantile_ms: f64,
+    /// `PDV_QUANTILE` of the delay minus the smallest delay, RFC 5481 PDV
+    pdv_ms: f64,
+}
+
+struct RowStats {
+    start: Instant,
+    samples: Histogram,
+    count: u64,
+    sum: Duration,
+    max: Duration,
+    /// Sequence statistics at the start of the row
+    seq: SeqStats,
+    bytes: ByteCounts,
+}
+
+/// Time of recorded samples replayed instead of the system clock
+struct Replay {
+    /// Unix time of the first sample, it is at `Delays::created`
+    start: Duration,
+    /// The time of the last sample
+    now: Instant,
+}
+
+struct Sample {
+    time: Instant,
+    dur: Duration,
+    /// Expected and received packets when the sample was added, for the loss of the window
+    expected: u64,
+    received: u64,
+}
+
+/// Aggregates over the whole run, not limited by the window
+#[derive(Default)]
+struct Totals {
+    /// P² estimators of the percentiles, see `cfg.run_quantiles`. The histogram is
+    /// not kept then
+    estimators: Option<Vec<P2>>,
+    count: u64,
+    sum: Duration,
+    min: Option<Duration>,
+    max: Duration,
+    sum_abs_diff: Duration,
+    prev: Option<Duration>,
+    /// All samples, for percentiles of the whole run
+    histogram: Histogram,
+    /// Samples within the satisfied and the tolerating thresholds of `cfg.apdex`
+    satisfied: u64,
+    tolerating: u64,
+    /// The biggest samples and when they were taken since the start, the biggest first
+    worst: Vec<(Duration, Duration)>,
 }
 
 impl Delays {
+    /// `label` is printed along with the statistics, to tell apart several `Delays`
+    pub fn new(cfg: StatsConfig, label: Option<String>) -> Self {
+        Self {
+            id: NEXT_ID.fetch_add(1, Ordering::Relaxed),
+            label,
+            delays: VecDeque::new(),
+            last_display: Instant::now(),
+            histogram: Default::default(),
+            late: vec![0; cfg.jitter_buffers.len()],
+            totals: Totals::new(&cfg),
+            created: Instant::now(),
+            row: RowStats {
+                start: Instant::now(),
+                samples: Default::default(),
+                count: 0,
+                sum: Duration::ZERO,
+                max: Duration::ZERO,
+                seq: Default::default(),
+                bytes: Default::default(),
+            },
+            cfg,
+            last_new_lines: 0,
+            live: true,
+            silent: false,
+            jitter: Default::default(),
+            ewma: Default::default(),
+            ewma_jitter: Default::default(),
+            seq: Default::default(),
+            reported_jitter_ms: None,
+            ecn: Default::default(),
+            bytes: Default::default(),
+            bytes_start: (Instant::now(), Default::default()),
+            quality: None,
+            gaps: 0,
+            gap_time: Duration::ZERO,
+            alerter: Default::default(),
+            heatmap: None,
+            replay: None,
+            tags: Vec::new(),
+        }
+    }
+
+    /// Collects the heatmap of `cfg.heatmap`, written to `path` with the summary.
+    /// Only the main statistics of a run have one, others would overwrite it
+    pub fn enable_heatmap(&mut self, path: PathBuf) {
+        self.heatmap = Some(Heatmap::new(path, self.cfg.heatmap_interval));
+    }
+
+    /// Changes settings keeping already collected samples
+    pub fn set_config(&mut self, cfg: StatsConfig) {
+        if cfg.jitter_buffers != self.cfg.jitter_buffers {
+            self.late = vec![0; cfg.jitter_buffers.len()];
+        }
+        self.cfg = cfg;
+        self.trim_window(self.now(), 0);
+    }
+
+    /// Turns the live display off or on, e.g. for one of many similar `Delays` shown
+    /// in a table. Records are still printed with `quiet`
+    pub fn set_live(&mut self, live: bool) {
+        self.live = live;
+    }
+
+    /// Tags `--statsd`, `--influx` and `--otlp` metrics of these statistics, e.g. with the client
+    /// they are of
+    pub fn add_tag(&mut self, key: &'static str, value: String) {
+        self.tags.push((key, value));
+    }
+
+    /// Sets the jitter reported by the receiving side, shown along with the delays
+    pub fn set_reported_jitter(&mut self, jitter_ms: f64) {
+        self.reported_jitter_ms = Some(jitter_ms);
+    }
+
+    /// Forgets all collected samples and counters
+    pub fn reset(&mut self) {
+        self.delays.clear();
+        self.histogram.clear();
+        self.totals = Totals::new(&self.cfg);
+        self.jitter = Default::default();
+        self.ewma = Default::default();
+        self.ewma_jitter = Default::default();
+        self.seq = Default::default();
+        self.reported_jitter_ms = None;
+        self.ecn = Default::default();
+        self.late.iter_mut().for_each(|late| *late = 0);
+        self.row.samples.clear();
+        self.row.count = 0;
+        self.row.sum = Duration::ZERO;
+        self.row.max = Duration::ZERO;
+        self.row.seq = Default::default();
+        self.row.bytes = self.bytes;
+        self.bytes_start = (self.now(), self.bytes);
+        self.quality = None;
+        self.gaps = 0;
+        self.gap_time = Duration::ZERO;
+        self.silent = false;
+    }
+
+    /// Sets the ECN codepoints of received packets shown along with the delays
+    pub fn set_ecn(&mut self, ecn: EcnCounts) {
+        self.ecn = ecn;
+    }
+
+    /// Sets the estimated call quality shown in the summary
+    pub fn set_quality(&mut self, quality: Quality) {
+        self.quality = Some(quality);
+    }
+
+    pub fn label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+
+    /// Average of all samples, `None` without samples
+    pub fn avg_ms(&self) -> Option<f64> {
+        match self.totals.count {
+            0 => None,
+            count => Some(as_millis_f64(self.totals.sum) / count as f64),
+        }
+    }
+
+    /// Apdex score of `cfg.apdex`, lost packets frustrate. `None` without it or without
+    /// packets
+    pub fn apdex(&self) -> Option<f64> {
+        self.cfg.apdex?;
+        let t = &self.totals;
+        match t.count + self.seq.lost() {
+            0 => None,
+            packets => Some((t.satisfied as f64 + t.tolerating as f64 / 2.) / packets as f64),
+        }
+    }
+
+    /// RFC 3550 jitter of the samples
+    pub fn jitter_ms(&self) -> f64 {
+        self.jitter.jitter_ms()
+    }
+
+    pub fn seq_stats(&self) -> SeqStats {
+        self.seq
+    }
+
+    /// Value of the metric over the whole run, `None` if it is unknown
+    pub fn metric(&self, metric: Metric) -> Option<f64> {
+        let t = &self.totals;
+        let known = match metric {
+            Metric::Loss => self.seq.expected > 0,
+            Metric::Reordered => self.seq.received > 0,
+            Metric::Mos => self.quality.is_some(),
+            Metric::Apdex => self.apdex().is_some(),
+            _ => t.count > 0,
+        };
+        if !known {
+            return None;
+        }
+        Some(match metric {
+            Metric::Percentile(p) => {
+                as_millis_f64(cmp::min(t.quantile(p, self.cfg.percentile_method)?, t.max))
+            }
+            Metric::Avg => as_millis_f64(t.sum) / t.count as f64,
+            Metric::Max => as_millis_f64(t.max),
+            Metric::Jitter => self.jitter.jitter_ms(),
+            Metric::Loss => self.seq.loss_percent(),
+            Metric::Reordered => self.seq.reordered_percent(),
+            Metric::Mos => self.quality?.mos,
+            Metric::Apdex => self.apdex()?,
+        })
+    }
+
+    /// Violations of the `--fail-if` thresholds and regressions beyond `--regression-if`
+    /// tolerances, labeled
+    pub fn check_thresholds(&self) -> Vec<String> {
+        let baseline = self.baseline_metrics();
+        let regressions = self.cfg.regression_if.iter().filter_map(|tolerance| {
+            let base = baseline?.get(&tolerance.metric().to_string()).copied();
+            tolerance.check(self.metric(tolerance.metric()), base)
+        });
+        self.cfg
+            .fail_if
+            .iter()
+            .filter_map(|threshold| threshold.check(|metric| self.metric(metric)))
+            .chain(regressions)
+            .map(|violation| match &self.label {
+                Some(label) => format!("{} {}", label, violation),
+                None => violation,
+            })
+            .collect()
+    }
+
+    /// Metrics of the baseline with the label of these statistics, `None` without one
+    fn baseline_metrics(&self) -> Option<&BTreeMap<String, f64>> {
+        let baseline = self.cfg.baseline.as_ref()?;
+        baseline.metrics(self.label.as_deref().unwrap_or_default())
+    }
+
+    /// Prints the deltas of the metrics in the baseline, or their record with `quiet`
+    fn print_baseline_deltas(&self) {
+        let (baseline, metrics) = match (&self.cfg.baseline, self.baseline_metrics()) {
+            (Some(baseline), Some(metrics)) => (baseline, metrics),
+            _ => return,
+        };
+        let mut rec = self.record_start("baseline_delta");
+        let mut lines = Vec::new();
+        for name in baseline::METRICS {
+            let metric: Metric = name.parse().expect("Known metric");
+            let (value, base) = match (self.metric(metric), metrics.get(name)) {
+                (Some(value), Some(base)) => (value, *base),
+                _ => continue,
+            };
+            write!(rec, " {}_delta={:.3}", name, value - base).unwrap();
+            let mut line = format!(
+                "{}: {:.2}{unit}, baseline {:.2}{unit}, {:+.2}{unit}",
+                name,
+                value,
+                base,
+                value - base,
+                unit = metric.unit()
+            );
+            if base != 0. && metric.unit() != "%" {
+                write!(line, " ({:+.1}%)", percent_change(base, value)).unwrap();
+            }
+            let regressed = self
+                .cfg
+                .regression_if
+                .iter()
+                .filter(|tolerance| tolerance.metric() == metric)
+                .any(|tolerance| tolerance.check(Some(value), Some(base)).is_some());
+            if regressed {
+                line.push_str(" REGRESSION");
+            }
+            lines.push(line);
+        }
+        if let Some(path) = &self.cfg.jsonl {
+            export::append_json(path, &rec);
+        }
+        if self.cfg.quiet {
+            println!("{}", rec);
+            return;
+        }
+        println!("Compared to the baseline {}:", baseline.path().display());
+        for line in lines {
+            println!("  {}", line);
+        }
+    }
+
+    /// Value of the metric over the window, `None` if it is unknown
+    fn window_metric(&self, metric: Metric) -> Option<f64> {
+        if self.silent {
+            return match metric {
+                Metric::Loss => Some(100.),
+                _ => None,
+            };
+        }
+        let max = self.window().max()?;
+        Some(match metric {
+            Metric::Percentile(p) => as_millis_f64(cmp::min(
+                self.histogram.quantile(p, self.cfg.percentile_method)?,
+                max,
+            )),
+            Metric::Avg => self.calculate_avg(),
+            Metric::Max => as_millis_f64(max),
+            Metric::Jitter => self.jitter.jitter_ms(),
+            Metric::Loss => self.window_loss_percent()?,
+            Metric::Reordered if self.seq.received > 0 => self.seq.reordered_percent(),
+            Metric::Mos => self.quality?.mos,
+            Metric::Reordered | Metric::Apdex => return None,
+        })
+    }
+
+    /// Raises an alert if the window breaches `--alert-if` thresholds. Unknown metrics,
+    /// e.g. the loss of a window without it, are no breach
+    fn check_alerts(&mut self) {
+        // Alerts are of the statistics of the live display, a page per breach is enough
+        if !self.live {
+            return;
+        }
+        let violations: Vec<String> = self
+            .cfg
+            .alert_if
+            .iter()
+            .filter(|threshold| self.window_metric(threshold.metric()).is_some())
+            .filter_map(|threshold| threshold.check(|metric| self.window_metric(metric)))
+            .map(|violation| match &self.label {
+                Some(label) => format!("{} {}", label, violation),
+                None => violation,
+            })
+            .collect();
+        if !violations.is_empty() {
+            self.alerter.alert(
+                &violations,
+                self.cfg.alert_cmd.as_deref(),
+                self.cfg.alert_webhook.as_ref(),
+                self.cfg.alert_interval,
+            );
+        }
+    }
+
+    /// The median of the window if `dur` is a spike: longer than `cfg.spike_factor`
+    /// times the median. Call before adding the sample
+    pub fn spike_median(&self, dur: Duration) -> Option<Duration> {
+        let factor = self.cfg.spike_factor?;
+        if self.delays.len() < SPIKE_MIN_SAMPLES {
+            return None;
+        }
+        // With a zero median, e.g. of whole ms on a LAN, any RTT would be a spike
+        let median = self
+            .histogram
+            .quantile(0.5, self.cfg.percentile_method)
+            .filter(|median| !median.is_zero())?;
+        (dur.as_secs_f64() > median.as_secs_f64() * factor).then_some(median)
+    }
+
+    /// Marks a period of `dur` without packets: the stream stalled, it is not a delay
+    pub fn add_gap(&mut self, dur: Duration) {
+        self.gaps += 1;
+        self.gap_time += dur;
+    }
+
+    /// Adds a sample recorded at `time`, since the Unix epoch. All times of these
+    /// statistics then follow recorded samples instead of the system clock
+    pub fn replay_event(&mut self, time: Duration, dur: Duration) {
+        let (start, last) = match &self.replay {
+            Some(replay) => (replay.start, replay.now),
+            None => (time, self.created),
+        };
+        // Samples are recorded as they arrive, a clock step must not go back
+        let now = cmp::max(self.created + time.saturating_sub(start), last);
+        self.replay = Some(Replay { start, now });
+        self.new_event(dur);
+    }
+
     pub fn new_event(&mut self, dur: Duration) {
-        while self.delays.len() >= QUEUE_LEN {
-            self.delays.pop_front();
+        self.silent = false;
+        let now = self.now();
+        self.trim_window(now, 1);
+        self.delays.push_back(Sample {
+            time: now,
+            dur,
+            expected: self.seq.expected,
+            received: self.seq.received,
+        });
+        self.histogram.add(dur);
+        if let Some(prev) = self.totals.prev {
+            let diff_ms = as_millis_f64(dur.abs_diff(prev));
+            self.ewma_jitter.add(diff_ms, self.cfg.ewma_alpha);
+        }
+        self.ewma.add(as_millis_f64(dur), self.cfg.ewma_alpha);
+        // The buffers play out relative to the fastest packet so far
+        let over_min = dur.saturating_sub(self.totals.min.unwrap_or(dur));
+        for (depth, late) in self.cfg.jitter_buffers.iter().zip(&mut self.late) {
+            if over_min > *depth {
+                *late += 1;
+            }
+        }
+        self.totals.add(dur);
+        if let Some(apdex) = self.cfg.apdex {
+            if dur <= apdex.satisfied {
+                self.totals.satisfied += 1;
+            } else if dur <= apdex.tolerating {
+                self.totals.tolerating += 1;
+            }
         }
-        self.delays.push_back(dur);
+        let since_start = now.duration_since(self.created);
+        self.totals.add_worst(dur, since_start);
+        if let Some(heatmap) = &mut self.heatmap {
+            heatmap.add(since_start, dur);
+        }
+        self.jitter.on_transit(as_millis_f64(dur));
+        if self.cfg.rows || self.cfg.hlog.is_some() || self.publishes_metrics() {
+            self.row.samples.add(dur);
+        }
+        self.row.count += 1;
+        self.row.sum += dur;
+        self.row.max = cmp::max(self.row.max, dur);
+
+        self.display_statistic();
+    }
 
+    /// Ends the interval if it is over although no sample arrived, e.g. in an outage:
+    /// its row is written and its alerts are raised, with all packets lost. Call every
+    /// `TICK_INTERVAL`, see `tick_every`
+    pub fn tick(&mut self) {
         self.display_statistic();
     }
 
+    /// Drops the samples which are out of the window, leaving room for `room` new ones
+    fn trim_window(&mut self, now: Instant, room: usize) {
+        while let Some(&Sample { time, dur, .. }) = self.delays.front() {
+            let outdated = match self.cfg.window_time {
+                Some(window) => now.duration_since(time) >= window,
+                None => self.delays.len() + room > self.cfg.window,
+            };
+            if !outdated {
+                break;
+            }
+            self.delays.pop_front();
+            self.histogram.remove(dur);
+        }
+    }
+
+    /// Durations of the samples in the window, oldest first
+    fn window(&self) -> impl Iterator<Item = Duration> + Clone + '_ {
+        self.delays.iter().map(|sample| sample.dur)
+    }
+
+    /// Loss between the first and the last sample of the window, in percents.
+    /// `None` if nothing is expected in that time, e.g. if the loss is unknown
+    fn window_loss_percent(&self) -> Option<f64> {
+        if self.silent {
+            return Some(100.);
+        }
+        let (first, last) = (self.delays.front()?, self.delays.back()?);
+        let expected = last.expected.checked_sub(first.expected)?;
+        let received = last.received.saturating_sub(first.received);
+        match expected {
+            0 => None,
+            _ => Some(percent(expected.saturating_sub(received), expected)),
+        }
+    }
+
+    /// Sets the bytes sent and acknowledged so far, shown as throughput along with
+    /// the delays
+    pub fn set_bytes(&mut self, bytes: ByteCounts) {
+        self.bytes = bytes;
+    }
+
+    /// Sent and acknowledged Mbit/s since the start, `None` if nothing was sent
+    pub fn throughput_mbps(&self) -> Option<(f64, f64)> {
+        let (start, bytes) = self.bytes_start;
+        self.bytes
+            .since(&bytes)
+            .mbps(self.now().duration_since(start))
+    }
+
+    /// Sent and acknowledged Mbit/s since the previous interval
+    fn interval_throughput_mbps(&self) -> Option<(f64, f64)> {
+        self.bytes
+            .since(&self.row.bytes)
+            .mbps(self.now().duration_since(self.row.start))
+    }
+
+    /// Sets the loss and reordering shown along with the delays
+    pub fn set_seq_stats(&mut self, seq: SeqStats) {
+        self.seq = seq;
+    }
+
+    /// Prints statistics of the whole run to stdout
+    pub fn print_summary(&mut self) {
+        if let Some(path) = self.cfg.jsonl.clone() {
+            export::append_json(&path, &self.summary_record());
+        }
+        if let Some(heatmap) = &self.heatmap {
+            match heatmap.write() {
+                Ok(()) => info!("The heatmap is written to {}", heatmap.path().display()),
+                Err(e) => warn!("{}", e),
+            }
+        }
+        if let Some(path) = &self.cfg.save_baseline {
+            if self.totals.count > 0 {
+                let label = self.label.as_deref().unwrap_or_default();
+                let rec = baseline::record(label, |metric| self.metric(metric));
+                export::append(path, Some("# udp-jitter-test baseline"), &rec);
+            }
+        }
+        if self.cfg.quiet {
+            self.print_summary_record();
+            self.print_baseline_deltas();
+            return;
+        }
+
+        if let Some(label) = &self.label {
+            println!("{}:", label);
+        }
+
+        let t = &self.totals;
+        if t.count == 0 {
+            println!("No samples received");
+            // Loss can still be known, e.g. from reports of clients
+            self.print_seq_summary();
+            return;
+        }
+
+        println!("Samples: {}", t.count);
+        let cfg = &self.cfg;
+        println!(
+            "Min/avg/max: {}.",
+            format_ms_list(
+                cfg,
+                &[
+                    as_millis_f64(t.min.unwrap_or_default()),
+                    as_millis_f64(t.sum) / t.count as f64,
+                    as_millis_f64(t.max),
+                ]
+            )
+        );
+        if t.count > 1 {
+            println!(
+                "Jitter (mean difference): {}.",
+                format_ms(cfg, as_millis_f64(t.sum_abs_diff) / (t.count - 1) as f64)
+            );
+            println!(
+                "Jitter (RFC 3550): {}.",
+                format_ms(cfg, self.jitter.jitter_ms())
+            );
+        }
+        println!(
+            "EWMA delay/jitter: {}.",
+            format_ms_list(cfg, &[self.ewma.value(), self.ewma_jitter.value()])
+        );
+
+        self.print_seq_summary();
+
+        let worst: Vec<String> = self
+            .totals
+            .worst
+            .iter()
+            .map(|(dur, at)| {
+                format!(
+                    "{} at {:.1}s",
+                    format_ms(&self.cfg, as_millis_f64(*dur)),
+                    at.as_secs_f64()
+                )
+            })
+            .collect();
+        println!("Worst: {}", worst.join(", "));
+        if let Some(pdv) = self.calculate_pdv() {
+            println!(
+                "IPDV (RFC 3393): {} min/max, {}% of |IPDV| within {}.",
+                format_values(&self.cfg, &[pdv.ipdv_min_ms, pdv.ipdv_max_ms], true),
+                format_percent(IPDV_QUANTILE),
+                format_ms(&self.cfg, pdv.ipdv_quantile_ms)
+            );
+            println!(
+                "PDV (RFC 5481): {} at {}%.",
+                format_ms(&self.cfg, pdv.pdv_ms),
+                format_percent(PDV_QUANTILE)
+            );
+        }
+        match self.cfg.window_time {
+            Some(window) => println!(
+                "Last {:.1}s, {} samples:",
+                window.as_secs_f64(),
+                self.delays.len()
+            ),
+            None => println!("Last {} samples:", self.delays.len()),
+        }
+        let percentiles = self.calculate_percentiles(&self.histogram);
+        println!("{}", self.percentiles_to_str(&percentiles));
+        println!("Whole run, {} samples:", self.totals.count);
+        let percentiles = self.calculate_run_percentiles();
+        println!("{}", self.percentiles_to_str(&percentiles));
+        self.print_baseline_deltas();
+    }
+
+    /// A row of `TABLE_HEADER` with the totals, the 99th percentile of the window
+    /// and the loss
+    pub fn table_row(&self) -> String {
+        let t = &self.totals;
+        if t.count == 0 {
+            return format!(
+                "{:>8} {:>8} {:>8} {:>8} {:>8} {:>7} {:>7}",
+                0, "-", "-", "-", "-", "-", "-"
+            );
+        }
+        let digits = self.cfg.precision;
+        format!(
+            "{:>8} {:>8.*} {:>8.*} {:>8.*} {:>8.*} {:>7.2} {:>7.2}",
+            t.count,
+            digits,
+            as_millis_f64(t.sum) / t.count as f64,
+            digits,
+            // Buckets are rounded up, the maximum is exact
+            as_millis_f64(cmp::min(
+                self.histogram
+                    .quantile(0.99, self.cfg.percentile_method)
+                    .unwrap_or_default(),
+                t.max
+            )),
+            digits,
+            as_millis_f64(t.max),
+            digits,
+            self.jitter.jitter_ms(),
+            self.seq.loss_percent(),
+            self.seq.reordered_percent()
+        )
+    }
+
+    fn print_seq_summary(&self) {
+        if self.seq.expected > 0 {
+            println!(
+                "Loss: {:.2}% ({} of {})",
+                self.seq.loss_percent(),
+                self.seq.lost(),
+                self.seq.expected
+            );
+            if let Some(loss) = self.window_loss_percent() {
+                println!("Loss in the window: {:.2}%", loss);
+            }
+            println!(
+                "Reordered: {:.2}% ({} of {}, max distance {})",
+                self.seq.reordered_percent(),
+                self.seq.reordered,
+                self.seq.received,
+                self.seq.max_reorder
+            );
+            println!("Duplicates: {}", self.seq.duplicates);
+            println!("Corrupted: {}", self.seq.corrupted);
+            if self.seq.recovered > 0 {
+                println!(
+                    "Recovered by redundancy: {:.2}% ({} of {} lost)",
+                    self.seq.recovered_percent(),
+                    self.seq.recovered,
+                    self.seq.lost()
+                );
+            }
+        }
+        if let Some(jitter) = self.reported_jitter_ms {
+            println!(
+                "Reported jitter (RFC 3550): {}.",
+                format_ms(&self.cfg, jitter)
+            );
+        }
+        if self.ecn.is_used() {
+            println!(
+                "ECN: CE-marked {:.2}% ({} of {}), not ECN-capable {}",
+                self.ecn.ce_percent(),
+                self.ecn.ce,
+                self.ecn.ect + self.ecn.ce,
+                self.ecn.not_ect
+            );
+        }
+        if self.totals.count > 0 && !self.late.is_empty() {
+            let mut line = "Too late for a jitter buffer of".to_owned();
+            for (i, (depth, late)) in self.cfg.jitter_buffers.iter().zip(&self.late).enumerate() {
+                let sep = if i == 0 { "" } else { "," };
+                write!(
+                    line,
+                    "{} {}ms: {:.2}% ({})",
+                    sep,
+                    as_millis_f64(*depth),
+                    percent(*late, self.totals.count),
+                    late
+                )
+                .unwrap();
+            }
+            println!("{}", line);
+        }
+        if let Some(quality) = self.quality {
+            println!("Estimated call quality (E-model): {}", quality);
+        }
+        if let (Some(apdex), Some(score)) = (self.cfg.apdex, self.apdex()) {
+            let t = &self.totals;
+            let packets = t.count + self.seq.lost();
+            println!(
+                "Apdex (T={}ms, F={}ms): {:.2}, satisfied {:.2}%, tolerating {:.2}%, frustrated {:.2}%",
+                as_millis_f64(apdex.satisfied),
+                as_millis_f64(apdex.tolerating),
+                score,
+                percent(t.satisfied, packets),
+                percent(t.tolerating, packets),
+                percent(packets - t.satisfied - t.tolerating, packets)
+            );
+        }
+        if let Some((sent, acked)) = self.throughput_mbps() {
+            let bytes = self.bytes.since(&self.bytes_start.1);
+            println!(
+                "Throughput: {:.2} Mbit/s sent, {:.2} Mbit/s acknowledged ({:.2}% of the bytes)",
+                sent,
+                acked,
+                percent(bytes.acked, bytes.sent)
+            );
+        }
+        if self.gaps > 0 {
+            println!(
+                "Gaps: {} ({:.1}s without packets)",
+                self.gaps,
+                self.gap_time.as_secs_f64()
+            );
+        }
+    }
+
     fn display_statistic(&mut self) {
-        if self.last_display.elapsed() < DISPLAY_INTERVAL || self.delays.is_empty() {
+        let now = self.now();
+        // Nothing to show before the stream starts
+        if now.duration_since(self.last_display) < self.cfg.display_interval
+            || self.totals.count == 0
+        {
             return;
         }
+        self.last_display = now;
+        self.trim_window(now, 0);
+        self.silent = self.row.count == 0;
+        self.check_alerts();
+        // Reports are of the statistics of the live display, not of every client
+        let report_to = self.cfg.report_to.as_ref().filter(|_| self.live);
+        let publish = self.publishes_metrics();
+        if self.cfg.csv.is_some()
+            || self.cfg.sqlite.is_some()
+            || self.cfg.statsd.is_some()
+            || self.cfg.influx.is_some()
+            || self.cfg.otlp.is_some()
+            || report_to.is_some()
+            || publish
+        {
+            let row = self.interval_row();
+            if let Some(path) = &self.cfg.csv {
+                export::append(path, Some(CSV_HEADER), &row.to_csv());
+            }
+            store::add_interval(&row);
+            statsd::send(&self.cfg, &self.tags, &row);
+            influx::send(&self.cfg, &self.tags, &row);
+            otlp::send(&self.cfg, &self.tags, &row);
+            if let Some(target) = report_to {
+                report::send(target, self.cfg.site.as_deref(), &row);
+            }
+            if publish {
+                metrics::update_delays(row, &self.row.samples, self.row.sum);
+            }
+        }
+        if self.cfg.jsonl.is_some() || self.cfg.websocket.is_some() {
+            let rec = self.window_record("interval");
+            if let Some(path) = &self.cfg.jsonl {
+                export::append_json(path, &rec);
+            }
+            websocket::publish(&rec);
+        }
+        self.write_hlog_line();
+        if self.live || self.cfg.quiet {
+            self.display_window();
+        }
+        self.next_row();
+    }
+
+    /// Whether the statistics go to `--metrics`, those of the live display only like reports
+    fn publishes_metrics(&self) -> bool {
+        self.cfg.metrics.is_some() && self.live
+    }
 
-        self.last_display = Instant::now();
+    /// Prints the window statistics in the format of the settings
+    fn display_window(&mut self) {
+        if self.cfg.quiet {
+            self.print_interval_record();
+            return;
+        }
+        if self.cfg.rows {
+            self.print_row();
+            return;
+        }
 
         self.clear_last_output();
         self.last_new_lines = 0;
 
-        eprintln!("Avg: {:.2}ms.", self.calculate_avg());
+        let mut line = String::new();
+        if let Some(label) = &self.label {
+            write!(line, "{} ", label).unwrap();
+        }
+        let cfg = &self.cfg;
+        let samples = self.window_len();
+        if samples == 0 {
+            line.push_str("No samples.");
+        } else {
+            write!(line, "Avg: {}.", format_ms(cfg, self.calculate_avg())).unwrap();
+            let (min, max, stddev_ms) = self.calculate_spread();
+            write!(
+                line,
+                " Min/max: {}. Stddev: {}.",
+                format_ms_list(cfg, &[as_millis_f64(min), as_millis_f64(max)]),
+                format_ms(cfg, stddev_ms)
+            )
+            .unwrap();
+            write!(
+                line,
+                " RFC 3550 jitter: {}.",
+                format_ms(cfg, self.jitter.jitter_ms())
+            )
+            .unwrap();
+            write!(
+                line,
+                " EWMA: {}.",
+                format_ms_list(cfg, &[self.ewma.value(), self.ewma_jitter.value()])
+            )
+            .unwrap();
+        }
+        if self.seq.expected > 0 {
+            write!(line, " Loss: {:.2}%", self.seq.loss_percent(),).unwrap();
+            if let Some(loss) = self.window_loss_percent() {
+                write!(line, " ({:.2}% in window)", loss).unwrap();
+            }
+            write!(line, ". Reordered: {:.2}%.", self.seq.reordered_percent()).unwrap();
+        }
+        if let Some(jitter) = self.reported_jitter_ms {
+            write!(line, " Jitter: {}.", format_ms(&self.cfg, jitter)).unwrap();
+        }
+        if self.ecn.is_used() {
+            write!(line, " CE: {:.2}%.", self.ecn.ce_percent()).unwrap();
+        }
+        if let Some((sent, acked)) = self.interval_throughput_mbps() {
+            write!(line, " Sent/acked: {:.2}/{:.2} Mbit/s.", sent, acked).unwrap();
+        }
+        eprintln!("{}", line);
         self.last_new_lines += 1;
+        if samples == 0 {
+            return;
+        }
 
-        let percentiles = self.calculate_percentiles();
+        let percentiles = self.calculate_percentiles(&self.histogram);
         eprintln!("{}", self.percentiles_to_str(&percentiles));
         self.last_new_lines += 1;
+
+        if self.cfg.sparkline {
+            eprintln!("{}", self.sparkline());
+            self.last_new_lines += 1;
+        }
+        for line in self.histogram_panel() {
+            eprintln!("{}", line);
+            self.last_new_lines += 1;
+        }
+    }
+
+    /// The window samples as blocks from the minimum to the maximum of the window.
+    /// A window wider than `SPARKLINE_WIDTH` is split into chunks drawn by their slowest
+    /// sample, so no spike is lost
+    fn sparkline(&self) -> String {
+        const SPARKLINE_WIDTH: usize = 60;
+        const BLOCKS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+        let (min, max, _) = self.calculate_spread();
+        let (min, max) = (as_millis_f64(min), as_millis_f64(max));
+        let chunk = self.delays.len().div_ceil(SPARKLINE_WIDTH).max(1);
+        let samples: Vec<Duration> = self.window().collect();
+        let line: String = samples
+            .chunks(chunk)
+            .map(|chunk| {
+                let slowest = as_millis_f64(chunk.iter().copied().max().unwrap_or_default());
+                let level = if max > min {
+                    ((slowest - min) / (max - min) * (BLOCKS.len() - 1) as f64).round()
+                } else {
+                    0.
+                };
+                BLOCKS[level as usize]
+            })
+            .collect();
+        format!("{} {:.2}-{:.2}ms", line, min, max)
+    }
+
+    /// Lines of the `cfg.histogram` panel: a bar per bucket of the window samples,
+    /// the longest bar is `HISTOGRAM_WIDTH` wide
+    fn histogram_panel(&self) -> Vec<String> {
+        const HISTOGRAM_WIDTH: u64 = 40;
+
+        if self.cfg.histogram.is_empty() {
+            return Vec::new();
+        }
+        let mut bounds = self.cfg.histogram.clone();
+        bounds.sort();
+        bounds.dedup();
+        let mut counts = vec![0u64; bounds.len() + 1];
+        for dur in self.window() {
+            counts[bounds.partition_point(|bound| *bound <= dur)] += 1;
+        }
+        let most = counts.iter().copied().max().unwrap_or_default().max(1);
+
+        let ms = |d: &Duration| format!("{}", as_millis_f64(*d));
+        counts
+            .iter()
+            .enumerate()
+            .map(|(i, count)| {
+                let range = match i {
+                    0 => format!("<{}", ms(&bounds[0])),
+                    i if i == bounds.len() => format!(">={}", ms(&bounds[i - 1])),
+                    i => format!("{}-{}", ms(&bounds[i - 1]), ms(&bounds[i])),
+                };
+                // A bucket with samples has a bar however few they are
+                let width = (count * HISTOGRAM_WIDTH).div_ceil(most);
+                format!(
+                    "{:>12}ms |{:<width$}| {}",
+                    range,
+                    "#".repeat(width as usize),
+                    count,
+                    width = HISTOGRAM_WIDTH as usize
+                )
+            })
+            .collect()
+    }
+
+    /// Prints a row with the statistics since the previous one, and the header before
+    /// the first row of all `Delays`
+    fn print_row(&mut self) {
+        ROWS_HEADER.call_once(|| {
+            println!(
+                "{:<15} {:>8} {:>8} {:>7} {:>8} {:>8} {:>8}  LABEL",
+                "INTERVAL_S", "EXPECTED", "RECEIVED", "LOSS_%", "AVG_MS", "P99_MS", "JITT_MS"
+            )
+        });
+
+        let now = self.now();
+        let from = self.row.start.duration_since(self.created).as_secs_f64();
+        let to = now.duration_since(self.created).as_secs_f64();
+        let mut seq = self.seq;
+        seq.expected = seq.expected.saturating_sub(self.row.seq.expected);
+        seq.received = seq.received.saturating_sub(self.row.seq.received);
+        let (avg, p99) = match self.row.count {
+            0 => (0., 0.),
+            n => (
+                as_millis_f64(self.row.sum) / n as f64,
+                as_millis_f64(
+                    self.row
+                        .samples
+                        .quantile(0.99, self.cfg.percentile_method)
+                        .unwrap_or_default(),
+                ),
+            ),
+        };
+        let digits = self.cfg.precision;
+        println!(
+            "{:<15} {:>8} {:>8} {:>7.2} {:>8.*} {:>8.*} {:>8.*}  {}",
+            format!("{:.1}-{:.1}", from, to),
+            seq.expected,
+            seq.received,
+            if self.silent {
+                100.
+            } else {
+                seq.loss_percent()
+            },
+            digits,
+            avg,
+            digits,
+            p99,
+            digits,
+            self.jitter.jitter_ms(),
+            self.label.as_deref().unwrap_or_default()
+        );
+    }
+
+    /// Appends the histogram of the samples since the previous interval to `cfg.hlog`
+    fn write_hlog_line(&self) {
+        let path = match &self.cfg.hlog {
+            Some(path) => path,
+            None => return,
+        };
+        let end = self.unix_now();
+        let start = end.saturating_sub(self.now().duration_since(self.row.start));
+        let line = hlog::line(
+            self.label.as_deref().unwrap_or("delays"),
+            start,
+            end,
+            self.row.max,
+            &self.row.samples,
+        );
+        export::append(path, Some(&hlog::header(start)), &line);
+    }
+
+    /// Starts collecting the samples of the next interval, see `RowStats`
+    fn next_row(&mut self) {
+        self.row.start = self.now();
+        self.row.seq = self.seq;
+        self.row.bytes = self.bytes;
+        self.row.samples.clear();
+        self.row.count = 0;
+        self.row.sum = Duration::ZERO;
+        self.row.max = Duration::ZERO;
+    }
+
+    /// The window statistics for `cfg.csv` and `cfg.sqlite`
+    fn interval_row(&self) -> IntervalRow {
+        let time = self.unix_now();
+        let samples = self.window_len();
+        let ((min, max, stddev_ms), avg_ms) = match samples {
+            0 => ((Duration::ZERO, Duration::ZERO, 0.), 0.),
+            _ => (self.calculate_spread(), self.calculate_avg()),
+        };
+        let mut percentiles_ms = [0.; ROW_PERCENTILES.len()];
+        for (p, ms) in ROW_PERCENTILES.iter().zip(&mut percentiles_ms) {
+            let d = self.histogram.quantile(*p, self.cfg.percentile_method);
+            *ms = as_millis_f64(cmp::min(d.unwrap_or_default(), max));
+        }
+        IntervalRow {
+            time: time.as_secs_f64(),
+            label: self.label.clone().unwrap_or_default(),
+            samples,
+            avg_ms,
+            min_ms: as_millis_f64(min),
+            max_ms: as_millis_f64(max),
+            stddev_ms,
+            jitter_ms: self.jitter.jitter_ms(),
+            percentiles_ms,
+            loss: if self.seq.expected > 0 {
+                Some(RowLoss {
+                    expected: self.seq.expected,
+                    received: self.seq.received,
+                    loss_pct: self.seq.loss_percent(),
+                    window_loss_pct: self.window_loss_percent(),
+                    reordered_pct: self.seq.reordered_percent(),
+                })
+            } else {
+                None
+            },
+        }
+    }
+
+    /// Prints the window statistics as a single `key=value` line
+    fn print_interval_record(&mut self) {
+        println!("{}", self.window_record("interval"));
+    }
+
+    /// The window statistics as a single `key=value` line of the given type
+    pub fn window_record(&mut self, rec_type: &str) -> String {
+        self.trim_window(self.now(), 0);
+        let mut rec = self.record_start(rec_type);
+        write!(rec, " samples={}", self.window_len()).unwrap();
+        if self.window_len() > 0 {
+            write!(rec, " avg_ms={:.3}", self.calculate_avg()).unwrap();
+            let (min, max, stddev_ms) = self.calculate_spread();
+            write!(
+                rec,
+                " min_ms={:.3} max_ms={:.3} stddev_ms={:.3}",
+                as_millis_f64(min),
+                as_millis_f64(max),
+                stddev_ms
+            )
+            .unwrap();
+            write!(rec, " rfc3550_jitter_ms={:.3}", self.jitter.jitter_ms()).unwrap();
+            self.write_ewma_record(&mut rec);
+        }
+        self.write_seq_record(&mut rec);
+        if self.window_len() > 0 {
+            self.write_pdv_record(&mut rec);
+            self.write_percentiles_record(&mut rec);
+        }
+        if let Some((sent, acked)) = self.interval_throughput_mbps() {
+            write!(rec, " sent_mbps={:.3} acked_mbps={:.3}", sent, acked).unwrap();
+        }
+        rec
+    }
+
+    fn print_summary_record(&mut self) {
+        println!("{}", self.summary_record());
+    }
+
+    fn summary_record(&mut self) -> String {
+        let mut rec = self.record_start("summary");
+        let t = &self.totals;
+        write!(rec, " count={}", t.count).unwrap();
+        if t.count > 0 {
+            write!(
+                rec,
+                " min_ms={:.3} avg_ms={:.3} max_ms={:.3}",
+                as_millis_f64(t.min.unwrap_or_default()),
+                as_millis_f64(t.sum) / t.count as f64,
+                as_millis_f64(t.max),
+            )
+            .unwrap();
+            if t.count > 1 {
+                let jitter = as_millis_f64(t.sum_abs_diff) / (t.count - 1) as f64;
+                write!(rec, " jitter_ms={:.3}", jitter).unwrap();
+                write!(rec, " rfc3550_jitter_ms={:.3}", self.jitter.jitter_ms()).unwrap();
+            }
+            self.write_ewma_record(&mut rec);
+            self.write_seq_record(&mut rec);
+            write!(rec, " samples={}", self.delays.len()).unwrap();
+            self.write_pdv_record(&mut rec);
+            self.write_percentiles_record(&mut rec);
+            self.write_run_percentiles_record(&mut rec);
+            let worst: Vec<String> = self
+                .totals
+                .worst
+                .iter()
+                .map(|(dur, at)| format!("{:.3}@{:.3}", as_millis_f64(*dur), at.as_secs_f64()))
+                .collect();
+            write!(rec, " worst_ms={}", worst.join(",")).unwrap();
+        }
+        if let Some(apdex) = self.apdex() {
+            write!(rec, " apdex={:.3}", apdex).unwrap();
+        }
+        if let Some((sent, acked)) = self.throughput_mbps() {
+            let bytes = self.bytes.since(&self.bytes_start.1);
+            write!(
+                rec,
+                " sent_bytes={} acked_bytes={} sent_mbps={:.3} acked_mbps={:.3}",
+                bytes.sent, bytes.acked, sent, acked
+            )
+            .unwrap();
+        }
+        rec
+    }
+
+    fn record_start(&self, rec_type: &str) -> String {
+        let time = self.unix_now();
+
+        let mut rec = format!("type={} time={:.3}", rec_type, time.as_secs_f64());
+        if let Some(label) = &self.label {
+            if label.contains(' ') {
+                write!(rec, " label={:?}", label).unwrap();
+            } else {
+                write!(rec, " label={}", label).unwrap();
+            }
+        }
+        rec
+    }
+
+    fn write_seq_record(&self, rec: &mut String) {
+        if self.seq.expected > 0 {
+            write!(
+                rec,
+                " expected={} lost={} loss_pct={:.3} reordered={} reordered_pct={:.3} max_reorder={} \
+                 duplicates={} corrupted={}",
+                self.seq.expected,
+                self.seq.lost(),
+                self.seq.loss_percent(),
+                self.seq.reordered,
+                self.seq.reordered_percent(),
+                self.seq.max_reorder,
+                self.seq.duplicates,
+                self.seq.corrupted
+            )
+            .unwrap();
+            if self.seq.recovered > 0 {
+                write!(rec, " recovered={}", self.seq.recovered).unwrap();
+            }
+            if let Some(loss) = self.window_loss_percent() {
+                write!(rec, " window_loss_pct={:.3}", loss).unwrap();
+            }
+        }
+        if let Some(jitter) = self.reported_jitter_ms {
+            write!(rec, " reported_jitter_ms={:.3}", jitter).unwrap();
+        }
+        if self.ecn.is_used() {
+            write!(
+                rec,
+                " ect={} ce={} not_ect={} ce_pct={:.3}",
+                self.ecn.ect,
+                self.ecn.ce,
+                self.ecn.not_ect,
+                self.ecn.ce_percent()
+            )
+            .unwrap();
+        }
+        if self.totals.count > 0 {
+            for (depth, late) in self.cfg.jitter_buffers.iter().zip(&self.late) {
+                let depth = as_millis_f64(*depth);
+                write!(
+                    rec,
+                    " late_{}ms={} late_{}ms_pct={:.3}",
+                    depth,
+                    late,
+                    depth,
+                    percent(*late, self.totals.count)
+                )
+                .unwrap();
+            }
+        }
+        if let Some(quality) = self.quality {
+            write!(
+                rec,
+                " r_factor={:.1} mos={:.2}",
+                quality.r_factor, quality.mos
+            )
+            .unwrap();
+        }
+        if self.gaps > 0 {
+            write!(
+                rec,
+                " gaps={} gap_s={:.3}",
+                self.gaps,
+                self.gap_time.as_secs_f64()
+            )
+            .unwrap();
+        }
+    }
+
+    fn write_ewma_record(&self, rec: &mut String) {
+        write!(
+            rec,
+            " ewma_ms={:.3} ewma_jitter_ms={:.3}",
+            self.ewma.value(),
+            self.ewma_jitter.value()
+        )
+        .unwrap();
+    }
+
+    fn write_pdv_record(&mut self, rec: &mut String) {
+        if let Some(pdv) = self.calculate_pdv() {
+            write!(
+                rec,
+                " ipdv_min_ms={:.3} ipdv_max_ms={:.3} ipdv_p{}_ms={:.3} pdv_p{}_ms={:.3}",
+                pdv.ipdv_min_ms,
+                pdv.ipdv_max_ms,
+                format_percent(IPDV_QUANTILE),
+                pdv.ipdv_quantile_ms,
+                format_percent(PDV_QUANTILE),
+                pdv.pdv_ms
+            )
+            .unwrap();
+        }
+    }
+
+    fn write_percentiles_record(&mut self, rec: &mut String) {
+        for (p, d) in self.calculate_percentiles(&self.histogram) {
+            write!(rec, " p{}_ms={:.3}", format_percent(p), as_millis_f64(d)).unwrap();
+        }
+    }
+
+    /// Percentiles of all samples of the run
+    fn write_run_percentiles_record(&self, rec: &mut String) {
+        for (p, d) in self.calculate_run_percentiles() {
+            write!(
+                rec,
+                " run_p{}_ms={:.3}",
+                format_percent(p),
+                as_millis_f64(d)
+            )
+            .unwrap();
+        }
+    }
+
+    /// Samples of the window, none while it is silent
+    fn window_len(&self) -> usize {
+        if self.silent {
+            0
+        } else {
+            self.delays.len()
+        }
     }
 
     fn calculate_avg(&self) -> f64 {
-        (self.delays.iter().sum::<Duration>().as_millis() as f64) / self.delays.len() as f64
+        as_millis_f64(self.window().sum::<Duration>()) / self.delays.len() as f64
+    }
+
+    /// Minimum, maximum and standard deviation in milliseconds of the window,
+    /// call only with samples
+    fn calculate_spread(&self) -> (Duration, Duration, f64) {
+        let min = self.window().min().unwrap_or_default();
+        let max = self.window().max().unwrap_or_default();
+        let n = self.delays.len() as f64;
+        let mean = self.window().map(as_millis_f64).sum::<f64>() / n;
+        let variance = self
+            .window()
+            .map(|d| (as_millis_f64(d) - mean).powi(2))
+            .sum::<f64>()
+            / n;
+        (min, max, variance.sqrt())
+    }
+
+    fn calculate_percentiles(&self, histogram: &Histogram) -> Vec<(f64, Duration)> {
+        self.cfg
+            .percentiles
+            .iter()
+            .map(|p| {
+                let d = histogram.quantile(*p, self.cfg.percentile_method);
+                (*p, d.unwrap_or_default())
+            })
+            .collect()
     }
 
-    fn calculate_percentiles(&mut self) -> Vec<(f64, Duration)> {
-        self.sorted_delays.clear();
-        self.sorted_delays.extend(self.delays.iter());
-        self.sorted_delays.sort_unstable();
+    /// Percentiles of all samples, without the ones P² estimators are missing for
+    fn calculate_run_percentiles(&self) -> Vec<(f64, Duration)> {
+        self.cfg
+            .percentiles
+            .iter()
+            .filter_map(|p| Some((*p, self.totals.quantile(*p, self.cfg.percentile_method)?)))
+            .collect()
+    }
 
-        let mut per_dur = Vec::with_capacity(PERCENTILES.len());
-        for p in &PERCENTILES {
-            let idx = (self.sorted_delays.len() as f64 * p) as usize;
-            per_dur.push((*p, self.sorted_delays[idx]));
+    /// IPDV and PDV of the window, `None` with less than 2 samples
+    fn calculate_pdv(&self) -> Option<Pdv> {
+        if self.delays.len() < 2 {
+            return None;
         }
+        let mut ipdv: Vec<f64> = self
+            .window()
+            .zip(self.window().skip(1))
+            .map(|(prev, next)| as_millis_f64(next) - as_millis_f64(prev))
+            .collect();
+        let ipdv_min_ms = ipdv.iter().copied().fold(f64::INFINITY, f64::min);
+        let ipdv_max_ms = ipdv.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        ipdv.iter_mut().for_each(|d| *d = d.abs());
+        ipdv.sort_unstable_by(f64::total_cmp);
+        let ipdv_idx = cmp::min((ipdv.len() as f64 * IPDV_QUANTILE) as usize, ipdv.len() - 1);
+
+        let min = self.histogram.min().unwrap_or_default();
+        let pdv = self
+            .histogram
+            .quantile(PDV_QUANTILE, self.cfg.percentile_method)
+            .unwrap_or_default();
 
-        per_dur
+        Some(Pdv {
+            ipdv_min_ms,
+            ipdv_max_ms,
+            ipdv_quantile_ms: ipdv[ipdv_idx],
+            pdv_ms: as_millis_f64(pdv.saturating_sub(min)),
+        })
     }
 
     fn percentiles_to_str(&mut self, percentiles: &[(f64, Duration)]) -> String {
@@ -70,15 +1522,43 @@ impl Delays {
                     per_str.push('\t');
                 }
             }
-            write!(per_str, "{:.1}%: {}ms.", *p * 100., d.as_millis() as u64).unwrap();
+            write!(
+                per_str,
+                "{}%: {}.",
+                format_percent(*p),
+                format_ms(&self.cfg, as_millis_f64(*d))
+            )
+            .unwrap();
         }
 
         per_str
     }
 
+    /// The time of the last replayed sample, or the current time
+    fn now(&self) -> Instant {
+        match &self.replay {
+            Some(replay) => replay.now,
+            None => Instant::now(),
+        }
+    }
+
+    /// `now` since the Unix epoch
+    fn unix_now(&self) -> Duration {
+        match &self.replay {
+            Some(replay) => replay.start + replay.now.duration_since(self.created),
+            None => SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default(),
+        }
+    }
+
     fn clear_last_output(&self) {
-        const MOVE_UP: &'static str = "\x1b[1A";
-        const DEL_LINE: &'static str = "\x1b[K";
+        const MOVE_UP: &str = "\x1b[1A";
+        const DEL_LINE: &str = "\x1b[K";
+
+        if LAST_WRITER.swap(self.id, Ordering::Relaxed) != self.id {
+            return;
+        }
 
         for _ in 0..self.last_new_lines {
             eprint!("{}{}", MOVE_UP, DEL_LINE);
@@ -86,13 +1566,532 @@ impl Delays {
     }
 }
 
-impl Default for Delays {
-    fn default() -> Self {
+impl Totals {
+    /// With P² estimators of the percentiles on display, of thresholds, of regression
+    /// tolerances and of a baseline to save
+    fn new(cfg: &StatsConfig) -> Self {
+        let estimators = (cfg.run_quantiles == RunQuantiles::P2).then(|| {
+            let thresholds = cfg.fail_if.iter().map(|t| t.metric());
+            let tolerances = cfg.regression_if.iter().map(|t| t.metric());
+            let saved = cfg.save_baseline.iter().flat_map(|_| {
+                baseline::METRICS.map(|name| name.parse::<Metric>().expect("Known metric"))
+            });
+            let mut quantiles: Vec<f64> = thresholds
+                .chain(tolerances)
+                .chain(saved)
+                .filter_map(|metric| match metric {
+                    Metric::Percentile(p) => Some(p),
+                    _ => None,
+                })
+                .chain(cfg.percentiles.iter().copied())
+                .chain(ROW_PERCENTILES)
+                .collect();
+            quantiles.sort_unstable_by(f64::total_cmp);
+            quantiles.dedup();
+            quantiles.into_iter().map(P2::new).collect()
+        });
         Self {
-            delays: VecDeque::with_capacity(QUEUE_LEN),
-            last_display: Instant::now(),
-            sorted_delays: Vec::with_capacity(QUEUE_LEN),
-            last_new_lines: 0,
+            estimators,
+            ..Default::default()
+        }
+    }
+
+    /// `None` without samples, or if the quantile has no estimator
+    fn quantile(&self, q: f64, method: PercentileMethod) -> Option<Duration> {
+        match &self.estimators {
+            Some(estimators) => estimators
+                .iter()
+                .find(|p2| (p2.quantile_of() - q).abs() < 1e-9)?
+                .value(),
+            None => self.histogram.quantile(q, method),
+        }
+    }
+
+    fn add_worst(&mut self, dur: Duration, at: Duration) {
+        if self.worst.len() == WORST_LEN && self.worst[WORST_LEN - 1].0 >= dur {
+            return;
+        }
+        let idx = self.worst.partition_point(|(worst, _)| *worst >= dur);
+        self.worst.insert(idx, (dur, at));
+        self.worst.truncate(WORST_LEN);
+    }
+
+    fn add(&mut self, dur: Duration) {
+        self.count += 1;
+        self.sum += dur;
+        self.min = Some(self.min.map_or(dur, |m| cmp::min(m, dur)));
+        self.max = cmp::max(self.max, dur);
+        match &mut self.estimators {
+            Some(estimators) => estimators.iter_mut().for_each(|p2| p2.add(dur)),
+            None => self.histogram.add(dur),
+        }
+        if let Some(prev) = self.prev {
+            self.sum_abs_diff += dur.abs_diff(prev);
+        }
+        self.prev = Some(dur);
+    }
+}
+
+/// Exponentially weighted moving average, starts at the first value
+#[derive(Debug, Default)]
+struct Ewma {
+    value: Option<f64>,
+}
+
+impl Ewma {
+    fn add(&mut self, x: f64, alpha: f64) {
+        self.value = Some(self.value.map_or(x, |v| v + alpha * (x - v)));
+    }
+
+    fn value(&self) -> f64 {
+        self.value.unwrap_or_default()
+    }
+}
+
+/// Interarrival jitter of RFC 3550: a smoothed mean difference of transit times
+/// of consecutive packets
+#[derive(Debug, Default)]
+pub struct InterarrivalJitter {
+    prev_transit_ms: Option<f64>,
+    jitter_ms: f64,
+}
+
+impl InterarrivalJitter {
+    pub fn on_transit(&mut self, transit_ms: f64) {
+        if let Some(prev) = self.prev_transit_ms {
+            let d = (transit_ms - prev).abs();
+            self.jitter_ms += (d - self.jitter_ms) / 16.;
+        }
+        self.prev_transit_ms = Some(transit_ms);
+    }
+
+    pub fn jitter_ms(&self) -> f64 {
+        self.jitter_ms
+    }
+
+    /// Starts over from the next packet, e.g. of a new stream with another clock,
+    /// keeping the current estimate
+    pub fn restart(&mut self) {
+        self.prev_transit_ms = None;
+    }
+}
+
+/// How late events of the test itself are: wake-ups of a send loop after the executor
+/// and the OS let it run, or packets sent after their time on the schedule. It adds to
+/// the measured jitter, it is the part of the jitter the test inflicts on itself
+pub struct Lateness {
+    /// Type of the record and what is late, e.g. `wake-ups`
+    rec_type: &'static str,
+    what: &'static str,
+    count: u64,
+    sum: Duration,
+    max: Duration,
+    histogram: Histogram,
+    jitter: InterarrivalJitter,
+}
+
+impl Lateness {
+    pub fn new(rec_type: &'static str, what: &'static str) -> Self {
+        Self {
+            rec_type,
+            what,
+            count: 0,
+            sum: Duration::ZERO,
+            max: Duration::ZERO,
+            histogram: Default::default(),
+            jitter: Default::default(),
+        }
+    }
+
+    /// Adds an event at `actual` which was due at `intended`
+    pub fn add(&mut self, intended: Instant, actual: Instant) {
+        let late = actual.saturating_duration_since(intended);
+        self.count += 1;
+        self.sum += late;
+        self.max = cmp::max(self.max, late);
+        self.histogram.add(late);
+        self.jitter.on_transit(as_millis_f64(late));
+    }
+
+    /// Publishes the lateness to `--metrics` as of the server at `server`
+    pub fn publish(&self, server: &str) {
+        if self.count > 0 {
+            let avg = Duration::from_secs_f64(self.sum.as_secs_f64() / self.count as f64);
+            metrics::update_lateness(server, self.rec_type, avg, self.max);
+        }
+    }
+
+    /// Prints the lateness along with the share of `rtt_jitter_ms` it may explain, if
+    /// given, or its record with `cfg.quiet`. Written to `cfg.jsonl` too
+    pub fn print_summary(&self, cfg: &StatsConfig, label: &str, rtt_jitter_ms: Option<f64>) {
+        if self.count == 0 {
+            return;
+        }
+        let quantile = |q| {
+            let d = self.histogram.quantile(q, cfg.percentile_method);
+            as_millis_f64(cmp::min(d.unwrap_or_default(), self.max))
+        };
+        let avg_ms = as_millis_f64(self.sum) / self.count as f64;
+        let time = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let rec = format!(
+            "type={} time={:.3} label={:?} count={} avg_ms={:.3} p50_ms={:.3} p90_ms={:.3} \
+             p99_ms={:.3} p99.9_ms={:.3} max_ms={:.3} rfc3550_jitter_ms={:.3}",
+            self.rec_type,
+            time.as_secs_f64(),
+            label,
+            self.count,
+            avg_ms,
+            quantile(0.5),
+            quantile(0.9),
+            quantile(0.99),
+            quantile(0.999),
+            as_millis_f64(self.max),
+            self.jitter.jitter_ms()
+        );
+        if let Some(path) = &cfg.jsonl {
+            export::append_json(path, &rec);
+        }
+        if cfg.quiet {
+            println!("{}", rec);
+            return;
+        }
+        let late = [
+            avg_ms,
+            quantile(0.5),
+            quantile(0.9),
+            quantile(0.99),
+            quantile(0.999),
+            as_millis_f64(self.max),
+        ];
+        println!(
+            "{}: {} {} late by {} avg/p50/p90/p99/p99.9/max, jitter (RFC 3550) {}.",
+            label,
+            self.count,
+            self.what,
+            format_ms_list(cfg, &late),
+            format_ms(cfg, self.jitter.jitter_ms())
+        );
+        if let Some(rtt_jitter_ms) = rtt_jitter_ms.filter(|jitter| *jitter > 0.) {
+            println!(
+                "Self-inflicted: up to {:.1}% of the RTT jitter (RFC 3550) of {}.",
+                (self.jitter.jitter_ms() / rtt_jitter_ms * 100.).min(100.),
+                format_ms(cfg, rtt_jitter_ms)
+            );
+        }
+    }
+}
+
+impl ByteCounts {
+    /// Counts since `start`, earlier values of the same counters
+    fn since(&self, start: &Self) -> Self {
+        Self {
+            sent: self.sent.saturating_sub(start.sent),
+            acked: self.acked.saturating_sub(start.acked),
+        }
+    }
+
+    /// Sent and acknowledged Mbit/s over `time`, `None` if nothing was sent
+    fn mbps(&self, time: Duration) -> Option<(f64, f64)> {
+        let secs = time.as_secs_f64();
+        if self.sent == 0 || secs <= 0. {
+            return None;
+        }
+        let mbps = |bytes: u64| bytes as f64 * 8. / secs / 1e6;
+        Some((mbps(self.sent), mbps(self.acked)))
+    }
+}
+
+impl EcnCounts {
+    pub fn add(&mut self, ecn: Ecn) {
+        match ecn {
+            Ecn::NotEct => self.not_ect += 1,
+            Ecn::Ect0 | Ecn::Ect1 => self.ect += 1,
+            Ecn::Ce => self.ce += 1,
+        }
+    }
+
+    /// Any ECN-capable packets were received: the sender marks them, and the codepoints
+    /// are worth showing. Not ECN-capable ones then mean the marks are cleared on the way
+    pub fn is_used(&self) -> bool {
+        self.ect + self.ce > 0
+    }
+
+    /// CE-marked packets among ECN-capable ones
+    pub fn ce_percent(&self) -> f64 {
+        match self.ect + self.ce {
+            0 => 0.,
+            total => self.ce as f64 * 100. / total as f64,
+        }
+    }
+}
+
+impl SeqStats {
+    pub fn lost(&self) -> u64 {
+        self.expected.saturating_sub(self.received)
+    }
+
+    pub fn loss_percent(&self) -> f64 {
+        percent(self.lost(), self.expected)
+    }
+
+    pub fn reordered_percent(&self) -> f64 {
+        percent(self.reordered, self.received)
+    }
+
+    pub fn add(&mut self, other: SeqStats) {
+        self.expected += other.expected;
+        self.received += other.received;
+        self.reordered += other.reordered;
+        self.max_reorder = cmp::max(self.max_reorder, other.max_reorder);
+        self.duplicates += other.duplicates;
+        self.corrupted += other.corrupted;
+        self.recovered += other.recovered;
+    }
+
+    pub fn recovered_percent(&self) -> f64 {
+        percent(self.recovered, self.lost())
+    }
+}
+
+impl SeqTracker {
+    /// Registers a received packet and returns how the statistics of the stream changed.
+    /// A duplicate packet is only counted in `duplicates`
+    pub fn on_seq(&mut self, seq: u32) -> SeqStats {
+        let mut change = SeqStats::default();
+        if self.is_duplicate(seq) {
+            change.duplicates = 1;
+            return change;
+        }
+
+        let prev_expected = self.expected();
+        match self.first {
+            None => {
+                self.first = Some(seq);
+                self.max = seq;
+                self.seen = 1;
+            }
+            Some(first) => {
+                if seq < self.max {
+                    change.reordered = 1;
+                    change.max_reorder = self.max - seq;
+                    self.seen |= 1u128.checked_shl(self.max - seq).unwrap_or(0);
+                } else {
+                    self.seen = self.seen.checked_shl(seq - self.max).unwrap_or(0) | 1;
+                    self.recovered = self.recovered.checked_shl(seq - self.max).unwrap_or(0);
+                    self.max = seq;
+                }
+                // A late packet sent before the first received one
+                self.first = Some(cmp::min(first, seq));
+            }
+        }
+        self.received += 1;
+
+        change.received = 1;
+        change.expected = self.expected() - prev_expected;
+        change
+    }
+
+    /// Registers frames of `frames` packets before `seq` carried in its redundancy,
+    /// returns how many of them were missing. Call after `on_seq` of the packet
+    pub fn on_redundancy(&mut self, seq: u32, frames: u8) -> u64 {
+        let first = match self.first {
+            Some(first) => first,
+            None => return 0,
+        };
+        let mut recovered = 0;
+        for prev in (1..=u32::from(frames)).filter_map(|i| seq.checked_sub(i)) {
+            // Packets before the first received one are not counted as lost
+            if prev < first || prev > self.max {
+                continue;
+            }
+            let bit = 1u128.checked_shl(self.max - prev).unwrap_or(0);
+            if bit != 0 && (self.seen | self.recovered) & bit == 0 {
+                self.recovered |= bit;
+                recovered += 1;
+            }
         }
+        recovered
+    }
+
+    /// Packets too far behind the highest one are not remembered and are never duplicates
+    fn is_duplicate(&self, seq: u32) -> bool {
+        if self.first.is_none() || seq > self.max {
+            return false;
+        }
+        let bit = 1u128.checked_shl(self.max - seq).unwrap_or(0);
+        self.seen & bit != 0
+    }
+
+    /// The highest sequence number received, 0 before the first packet
+    pub fn max_seq(&self) -> u32 {
+        self.max
+    }
+
+    fn expected(&self) -> u64 {
+        match self.first {
+            Some(first) => u64::from(self.max - first) + 1,
+            None => 0,
+        }
+    }
+}
+
+fn percent(part: u64, total: u64) -> f64 {
+    if total == 0 {
+        0.
+    } else {
+        part as f64 * 100. / total as f64
+    }
+}
+
+/// A delay in milliseconds in `cfg.units` with `cfg.precision` digits, e.g. `12.34ms`
+pub fn format_ms(cfg: &StatsConfig, ms: f64) -> String {
+    format_values(cfg, &[ms], false)
+}
+
+/// Delays in milliseconds separated by slashes, in the unit of the biggest one,
+/// e.g. `0.15/1.20/3.00ms`
+pub fn format_ms_list(cfg: &StatsConfig, ms: &[f64]) -> String {
+    format_values(cfg, ms, false)
+}
+
+/// See `format_ms_list`, with the sign of positive values too
+fn format_values(cfg: &StatsConfig, ms: &[f64], signed: bool) -> String {
+    let biggest = ms.iter().fold(0., |max: f64, ms| max.max(ms.abs()));
+    let (unit, scale) = cfg.units.of(biggest);
+    // Timestamps are in µs, see `protocol::DataHeader`: digits beyond would be noise
+    let digits = cmp::min(cfg.precision, (3. - scale.log10()).max(0.) as usize);
+    let mut s = String::new();
+    for (i, ms) in ms.iter().enumerate() {
+        if i > 0 {
+            s.push('/');
+        }
+        if signed {
+            write!(s, "{:+.*}", digits, ms * scale).unwrap();
+        } else {
+            write!(s, "{:.*}", digits, ms * scale).unwrap();
+        }
+    }
+    s.push_str(unit);
+    s
+}
+
+impl Units {
+    /// The name of the unit for a value of `ms` milliseconds, and units in a millisecond.
+    /// Auto goes down to µs, the resolution of timestamps
+    fn of(self, ms: f64) -> (&'static str, f64) {
+        const MS: (&str, f64) = ("ms", 1.);
+        const US: (&str, f64) = ("µs", 1e3);
+        const NS: (&str, f64) = ("ns", 1e6);
+        match self {
+            Units::Ms => MS,
+            Units::Us => US,
+            Units::Ns => NS,
+            Units::Auto if ms >= 1. || ms == 0. => MS,
+            Units::Auto => US,
+        }
+    }
+}
+
+impl FromStr for Units {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Units::Auto),
+            "ms" => Ok(Units::Ms),
+            "us" | "µs" => Ok(Units::Us),
+            "ns" => Ok(Units::Ns),
+            _ => Err(Error::new(format!(
+                "Unknown unit: {}, expected auto, ms, us or ns",
+                s
+            ))),
+        }
+    }
+}
+
+/// The `key=value` fields of a spike event of `rtt` over the window `median`
+pub fn spike_fields(seq: u32, rtt: Duration, median: Duration) -> String {
+    format!(
+        "seq={} rtt_ms={:.3} median_ms={:.3} factor={:.1}",
+        seq,
+        as_millis_f64(rtt),
+        as_millis_f64(median),
+        rtt.as_secs_f64() / median.as_secs_f64()
+    )
+}
+
+/// Calls `tick` every `TICK_INTERVAL` until the run ends, to tick `Delays`
+pub async fn tick_every(mut tick: impl FnMut()) -> Result<(), Error> {
+    loop {
+        task::sleep(TICK_INTERVAL).await;
+        tick();
+    }
+}
+
+/// Change from `from` to `to` in percents of `from`
+fn percent_change(from: f64, to: f64) -> f64 {
+    (to - from) / from.abs() * 100.
+}
+
+pub fn as_millis_f64(d: Duration) -> f64 {
+    d.as_secs_f64() * 1000.
+}
+
+/// Formats a fraction as a percent without trailing zeros: `0.999` -> `99.9`
+pub fn format_percent(p: f64) -> String {
+    let s = format!("{:.3}", p * 100.);
+    s.trim_end_matches('0').trim_end_matches('.').to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, fs, iter, process};
+    use structopt::StructOpt;
+
+    fn delays(args: &[&str], samples_ms: impl Iterator<Item = u64>) -> Delays {
+        let cfg = StatsConfig::from_iter(iter::once("udp-jitter-test").chain(args.iter().copied()));
+        let mut delays = Delays::new(cfg, None);
+        for ms in samples_ms {
+            delays.new_event(Duration::from_millis(ms));
+        }
+        delays
+    }
+
+    #[test]
+    fn violated_threshold_fails_the_run() {
+        let passed = delays(&["--fail-if", "p99>40ms"], (1..=100).map(|i| i % 30));
+        assert!(passed.check_thresholds().is_empty());
+
+        let failed = delays(&["--fail-if", "p99>40ms"], (1..=100).map(|i| i % 50));
+        let violations = failed.check_thresholds();
+        assert_eq!(violations.len(), 1);
+        assert_eq!(Error::failed(violations).exit_code(), 2);
+        assert_eq!(Error::new("no route").exit_code(), 1);
+    }
+
+    #[test]
+    fn regression_with_p2() {
+        // p97 is neither displayed nor in rows: only the tolerance asks for its estimator
+        let path = env::temp_dir().join(format!("baseline-{}.txt", process::id()));
+        fs::write(&path, "label=\"\" p97=20.000\n").unwrap();
+        let baseline = path.to_str().unwrap();
+        let args = [
+            "--run-quantiles=p2",
+            "--baseline",
+            baseline,
+            "--regression-if",
+            "p97+10%",
+        ];
+        let same = delays(&args, (1..=1000).map(|i| i % 21));
+        let worse = delays(&args, (1..=1000).map(|i| i % 50));
+        fs::remove_file(&path).unwrap();
+
+        assert!(same.check_thresholds().is_empty());
+        let regressions = worse.check_thresholds();
+        assert_eq!(regressions.len(), 1, "{:?}", regressions);
+        assert!(regressions[0].starts_with("p97+10%"), "{:?}", regressions);
     }
 }
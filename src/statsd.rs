@@ -0,0 +1,81 @@
+//! Metrics of `--statsd`: the window statistics of every interval pushed to a StatsD
+//! agent, gauges with DogStatsD tags, e.g.
+//! `udp_jitter_test.p99_ms:12.345|g|#label:RTT,site:branch-1`
+//!
+//! The names are the keys of `--quiet` records. Tags are the label of the statistics,
+//! those of `--statsd-tag` and, for a client of a server, its address and session. Datadog
+//! agents and Telegraf with `datadog_extensions` read them. Like reports, metrics are fire
+//! and forget.
+
+use crate::config::StatsConfig;
+use crate::export::{self, IntervalRow};
+use crate::report;
+use log::{debug, warn};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::net::{SocketAddr, UdpSocket};
+use std::sync::Mutex;
+
+/// Metrics are batched in datagrams which fit in an Ethernet frame
+const MAX_DATAGRAM: usize = 1432;
+
+/// Sockets by agent address, `None` if the address can't be resolved or bound for
+static SOCKETS: Mutex<BTreeMap<String, Option<(UdpSocket, SocketAddr)>>> =
+    Mutex::new(BTreeMap::new());
+
+/// Sends the statistics of `row` to the agent of `cfg.statsd`, with `tags` besides
+/// the label and `cfg.statsd_tag`
+pub fn send(cfg: &StatsConfig, tags: &[(&str, String)], row: &IntervalRow) {
+    let target = some_or_ret!(&cfg.statsd);
+    let mut all_tags = format!("label:{}", sanitize(&row.label, ",|"));
+    for tag in &cfg.statsd_tag {
+        write!(all_tags, ",{}", sanitize(tag, ",|")).unwrap();
+    }
+    for (key, value) in tags {
+        write!(all_tags, ",{}:{}", key, sanitize(value, ",|")).unwrap();
+    }
+    let mut datagrams = vec![String::new()];
+    let rec = row.to_record("statsd");
+    for (key, value) in export::parse_record(&rec) {
+        if key == "type" || key == "time" || key == "label" {
+            continue;
+        }
+        let line = format!(
+            "{}.{}:{}|g|#{}\n",
+            cfg.statsd_prefix,
+            sanitize(key, "."),
+            value,
+            all_tags
+        );
+        let datagram = datagrams.last_mut().unwrap();
+        if !datagram.is_empty() && datagram.len() + line.len() > MAX_DATAGRAM {
+            datagrams.push(line);
+        } else {
+            datagram.push_str(&line);
+        }
+    }
+
+    let mut sockets = SOCKETS.lock().unwrap_or_else(|e| e.into_inner());
+    let socket =
+        sockets
+            .entry(target.to_owned())
+            .or_insert_with(|| match report::connect(target) {
+                Ok(socket) => Some(socket),
+                Err(e) => {
+                    warn!("Cannot send metrics to {}: {}", target, e);
+                    None
+                }
+            });
+    if let Some((socket, addr)) = socket {
+        for datagram in datagrams {
+            if let Err(e) = socket.send_to(datagram.trim_end().as_bytes(), *addr) {
+                debug!("Cannot send metrics to {}: {}", addr, e);
+            }
+        }
+    }
+}
+
+/// `s` with the characters of `reserved` replaced by `_`
+fn sanitize(s: &str, reserved: &str) -> String {
+    s.replace(|c| reserved.contains(c), "_")
+}
@@ -0,0 +1,46 @@
+//! Stopping runs on a time limit or a termination signal
+
+use crate::error::Error;
+use async_std::{future, task::sleep};
+use futures::future::{select, Either};
+use signal_hook::consts::{SIGINT, SIGTERM};
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Runs `fut` until it finishes, `duration` elapses or SIGINT/SIGTERM is received.
+/// Stopping by time or by a signal is not an error.
+pub async fn run_until_stopped<F>(fut: F, duration: Option<Duration>) -> Result<(), Error>
+where
+    F: Future<Output = Result<(), Error>>,
+{
+    let stop = async {
+        match duration {
+            Some(duration) => future::timeout(duration, termination())
+                .await
+                .unwrap_or(Ok(())),
+            None => termination().await,
+        }
+    };
+
+    match select(Box::pin(fut), Box::pin(stop)).await {
+        Either::Left((res, _)) => res,
+        Either::Right((res, _)) => res,
+    }
+}
+
+/// Resolves when SIGINT or SIGTERM is received
+async fn termination() -> Result<(), Error> {
+    const CHECK_INTERVAL: Duration = Duration::from_millis(100);
+
+    let term = Arc::new(AtomicBool::new(false));
+    signal_hook::flag::register(SIGINT, term.clone())?;
+    signal_hook::flag::register(SIGTERM, term.clone())?;
+
+    while !term.load(Ordering::Relaxed) {
+        sleep(CHECK_INTERVAL).await;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,208 @@
+//! SQLite history of runs of `--sqlite`, needs the `sqlite` feature
+//!
+//! Every run adds a row to `runs`. Clients joining the server are in `clients`, and
+//! the window statistics of every interval, the columns of `--csv`, in `intervals`. So
+//! a probe host accumulates measurements across runs in one file:
+//!
+//! ```sql
+//! SELECT datetime(time, 'unixepoch'), p99_ms, loss_pct FROM intervals
+//!     WHERE label = 'RTT' ORDER BY time;
+//! ```
+//!
+//! A database which fails is logged and closed, the test goes on.
+
+use crate::error::Error;
+use crate::export::IntervalRow;
+use std::net::SocketAddr;
+use std::path::Path;
+
+#[cfg(feature = "sqlite")]
+mod db {
+    use super::*;
+    use log::warn;
+    use rusqlite::{params, Connection};
+    use std::sync::Mutex;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    const SCHEMA: &str = "
+        CREATE TABLE IF NOT EXISTS runs (
+            id INTEGER PRIMARY KEY,
+            started REAL NOT NULL,
+            ended REAL,
+            command TEXT NOT NULL
+        );
+        CREATE TABLE IF NOT EXISTS clients (
+            run_id INTEGER NOT NULL REFERENCES runs(id),
+            session TEXT NOT NULL,
+            addr TEXT NOT NULL,
+            joined REAL NOT NULL,
+            left REAL,
+            leave_reason TEXT,
+            PRIMARY KEY (run_id, session)
+        );
+        CREATE TABLE IF NOT EXISTS intervals (
+            run_id INTEGER NOT NULL REFERENCES runs(id),
+            time REAL NOT NULL,
+            label TEXT NOT NULL,
+            samples INTEGER NOT NULL,
+            avg_ms REAL,
+            min_ms REAL,
+            max_ms REAL,
+            stddev_ms REAL,
+            jitter_ms REAL,
+            p50_ms REAL,
+            p90_ms REAL,
+            p95_ms REAL,
+            p99_ms REAL,
+            p999_ms REAL,
+            expected INTEGER,
+            received INTEGER,
+            loss_pct REAL,
+            window_loss_pct REAL,
+            reordered_pct REAL
+        );
+        CREATE INDEX IF NOT EXISTS intervals_by_time ON intervals (label, time);
+    ";
+
+    struct Store {
+        conn: Connection,
+        run_id: i64,
+    }
+
+    static STORE: Mutex<Option<Store>> = Mutex::new(None);
+
+    fn now() -> f64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs_f64()
+    }
+
+    pub fn open(path: &Path, command: &str) -> Result<(), Error> {
+        let err = |e: rusqlite::Error| Error::new(format!("SQLite {}: {}", path.display(), e));
+        let conn = Connection::open(path).map_err(err)?;
+        conn.execute_batch(SCHEMA).map_err(err)?;
+        conn.execute(
+            "INSERT INTO runs (started, command) VALUES (?1, ?2)",
+            params![now(), command],
+        )
+        .map_err(err)?;
+        let run_id = conn.last_insert_rowid();
+        *STORE.lock().unwrap_or_else(|e| e.into_inner()) = Some(Store { conn, run_id });
+        Ok(())
+    }
+
+    /// Runs `f` with the open database, closes it if `f` fails
+    fn with_store(f: impl FnOnce(&Store) -> rusqlite::Result<usize>) {
+        let mut store = STORE.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(s) = &*store {
+            if let Err(e) = f(s) {
+                warn!("Cannot write to the SQLite database, closing it: {}", e);
+                *store = None;
+            }
+        }
+    }
+
+    pub fn close() {
+        with_store(|s| {
+            s.conn.execute(
+                "UPDATE runs SET ended = ?1 WHERE id = ?2",
+                params![now(), s.run_id],
+            )
+        });
+        STORE.lock().unwrap_or_else(|e| e.into_inner()).take();
+    }
+
+    pub fn add_interval(row: &IntervalRow) {
+        // NULL delays for an interval without samples
+        let ms = |ms: f64| (row.samples > 0).then_some(ms);
+        let p = row.percentiles_ms.map(ms);
+        let loss = row.loss.as_ref();
+        with_store(|s| {
+            s.conn
+                .prepare_cached(
+                    "INSERT INTO intervals VALUES
+                     (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16,
+                      ?17, ?18, ?19)",
+                )?
+                .execute(params![
+                    s.run_id,
+                    row.time,
+                    row.label,
+                    row.samples as i64,
+                    ms(row.avg_ms),
+                    ms(row.min_ms),
+                    ms(row.max_ms),
+                    ms(row.stddev_ms),
+                    ms(row.jitter_ms),
+                    p[0],
+                    p[1],
+                    p[2],
+                    p[3],
+                    p[4],
+                    loss.map(|l| l.expected as i64),
+                    loss.map(|l| l.received as i64),
+                    loss.map(|l| l.loss_pct),
+                    loss.and_then(|l| l.window_loss_pct),
+                    loss.map(|l| l.reordered_pct),
+                ])
+        });
+    }
+
+    pub fn client_joined(addr: &SocketAddr, session: u32) {
+        with_store(|s| {
+            s.conn.execute(
+                "INSERT OR REPLACE INTO clients (run_id, session, addr, joined)
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    s.run_id,
+                    format!("{:08x}", session),
+                    addr.to_string(),
+                    now()
+                ],
+            )
+        });
+    }
+
+    pub fn client_left(session: u32, reason: &str) {
+        with_store(|s| {
+            s.conn.execute(
+                "UPDATE clients SET left = ?1, leave_reason = ?2
+                 WHERE run_id = ?3 AND session = ?4",
+                params![now(), reason, s.run_id, format!("{:08x}", session)],
+            )
+        });
+    }
+}
+
+/// Opens the database at `path` and adds the run of `command` to it
+pub fn open(_path: &Path, _command: &str) -> Result<(), Error> {
+    #[cfg(feature = "sqlite")]
+    return db::open(_path, _command);
+    #[cfg(not(feature = "sqlite"))]
+    return Err(Error::new(
+        "SQLite storage needs a build with the `sqlite` feature",
+    ));
+}
+
+/// Marks the end of the run and closes the database
+pub fn close() {
+    #[cfg(feature = "sqlite")]
+    db::close();
+}
+
+/// Adds the statistics of an interval. Does nothing without a database
+pub fn add_interval(_row: &IntervalRow) {
+    #[cfg(feature = "sqlite")]
+    db::add_interval(_row);
+}
+
+pub fn client_joined(_addr: &SocketAddr, _session: u32) {
+    #[cfg(feature = "sqlite")]
+    db::client_joined(_addr, _session);
+}
+
+pub fn client_left(_session: u32, _reason: &str) {
+    #[cfg(feature = "sqlite")]
+    db::client_left(_session, _reason);
+}
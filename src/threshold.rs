@@ -0,0 +1,187 @@
+//! Pass/fail thresholds of `--fail-if`, checked against the statistics of the whole run
+//!
+//! A threshold is `METRIC OP VALUE`, e.g. `p99>40ms`, `loss>1%` or `mos<4`. The run fails
+//! if any threshold holds, or if its metric is unknown, e.g. without any samples.
+
+use crate::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Metric {
+    /// Fraction of the percentile, e.g. 0.99
+    Percentile(f64),
+    Avg,
+    Max,
+    /// RFC 3550 jitter
+    Jitter,
+    /// Percents
+    Loss,
+    Reordered,
+    Mos,
+    /// The score of `--apdex`, from 0 to 1
+    Apdex,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Op {
+    Greater,
+    GreaterOrEqual,
+    Less,
+    LessOrEqual,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Threshold {
+    metric: Metric,
+    op: Op,
+    /// Milliseconds, percents or the MOS
+    value: f64,
+    /// As given, for messages
+    text: String,
+}
+
+impl Threshold {
+    pub fn metric(&self) -> Metric {
+        self.metric
+    }
+
+    /// Checks the value of the metric, `lookup` returns `None` if it is unknown.
+    /// Returns the violation if the threshold holds
+    pub fn check(&self, lookup: impl Fn(Metric) -> Option<f64>) -> Option<String> {
+        let value = match lookup(self.metric) {
+            Some(value) => value,
+            None => return Some(format!("{}: {} is unknown", self, self.metric)),
+        };
+        let holds = match self.op {
+            Op::Greater => value > self.value,
+            Op::GreaterOrEqual => value >= self.value,
+            Op::Less => value < self.value,
+            Op::LessOrEqual => value <= self.value,
+        };
+        if holds {
+            Some(format!(
+                "{}: {} is {:.2}{}",
+                self,
+                self.metric,
+                value,
+                self.metric.unit()
+            ))
+        } else {
+            None
+        }
+    }
+}
+
+impl Metric {
+    pub fn unit(&self) -> &'static str {
+        match self {
+            Metric::Percentile(_) | Metric::Avg | Metric::Max | Metric::Jitter => "ms",
+            Metric::Loss | Metric::Reordered => "%",
+            Metric::Mos | Metric::Apdex => "",
+        }
+    }
+}
+
+impl FromStr for Metric {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(match s {
+            "avg" => Metric::Avg,
+            "max" => Metric::Max,
+            "jitter" => Metric::Jitter,
+            "loss" => Metric::Loss,
+            "reordered" => Metric::Reordered,
+            "mos" => Metric::Mos,
+            "apdex" => Metric::Apdex,
+            _ => {
+                let p = s
+                    .strip_prefix('p')
+                    .and_then(|p| p.parse::<f64>().ok())
+                    .filter(|p| *p > 0. && *p <= 100.)
+                    .ok_or_else(|| {
+                        Error::new(format!(
+                            "Unknown metric: {}, expected pNN, avg, max, jitter, loss, reordered, \
+                             mos or apdex",
+                            s
+                        ))
+                    })?;
+                Metric::Percentile(p / 100.)
+            }
+        })
+    }
+}
+
+impl fmt::Display for Metric {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Metric::Percentile(p) => write!(f, "p{}", crate::statistic::format_percent(*p)),
+            Metric::Avg => write!(f, "avg"),
+            Metric::Max => write!(f, "max"),
+            Metric::Jitter => write!(f, "jitter"),
+            Metric::Loss => write!(f, "loss"),
+            Metric::Reordered => write!(f, "reordered"),
+            Metric::Mos => write!(f, "mos"),
+            Metric::Apdex => write!(f, "apdex"),
+        }
+    }
+}
+
+impl FromStr for Threshold {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let pos = s.find(['<', '>']).ok_or_else(|| {
+            Error::new(format!("Expected METRIC<VALUE or METRIC>VALUE, got: {}", s))
+        })?;
+        let metric: Metric = s[..pos].trim().parse()?;
+        let rest = &s[pos..];
+        let (op, value) = if let Some(v) = rest.strip_prefix(">=") {
+            (Op::GreaterOrEqual, v)
+        } else if let Some(v) = rest.strip_prefix("<=") {
+            (Op::LessOrEqual, v)
+        } else if let Some(v) = rest.strip_prefix('>') {
+            (Op::Greater, v)
+        } else {
+            (Op::Less, &rest[1..])
+        };
+
+        let value = value.trim();
+        let (num, mult) = match metric.unit() {
+            "ms" => {
+                if let Some(n) = value.strip_suffix("ms") {
+                    (n, 1.)
+                } else if let Some(n) = value.strip_suffix("us") {
+                    (n, 0.001)
+                } else if let Some(n) = value.strip_suffix('s') {
+                    (n, 1000.)
+                } else {
+                    (value, 1.)
+                }
+            }
+            "%" => (value.strip_suffix('%').unwrap_or(value), 1.),
+            _ => (value, 1.),
+        };
+        let value = num
+            .trim()
+            .parse::<f64>()
+            .ok()
+            .filter(|v| v.is_finite())
+            .ok_or_else(|| Error::new(format!("Invalid threshold value: {}", s)))?;
+
+        Ok(Self {
+            metric,
+            op,
+            value: value * mult,
+            text: s.to_owned(),
+        })
+    }
+}
+
+impl fmt::Display for Threshold {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.text)
+    }
+}
@@ -0,0 +1,148 @@
+//! TWAMP-Light test packets: the unauthenticated mode of RFC 5357.
+//! Multi-byte numbers are big-endian.
+
+use crate::error::Error;
+use std::convert::TryInto;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Sequence number, timestamp and error estimate
+pub const SENDER_LEN: usize = 14;
+/// Reflector fields followed by the sender ones
+pub const REFLECTOR_LEN: usize = 41;
+/// Clocks are not known to be synchronized, the error is about a millisecond:
+/// S = 0, Z = 0, scale = 22, multiplier = 1
+pub const ERROR_ESTIMATE: u16 = 0x1601;
+/// Senders send packets with TTL 255, so reflectors can tell the number of hops
+pub const SENDER_TTL: u8 = 255;
+
+/// Seconds between 1900, the NTP epoch, and 1970
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+
+/// NTP timestamp: seconds since 1900 and a fraction of a second in 1/2^32 units
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct NtpTime {
+    pub secs: u32,
+    pub frac: u32,
+}
+
+/// Test packet of a session sender
+#[derive(Debug, Clone, Copy)]
+pub struct SenderPacket {
+    pub seq: u32,
+    pub time: NtpTime,
+    pub error_estimate: u16,
+}
+
+/// Test packet of a session reflector
+#[derive(Debug, Clone, Copy)]
+pub struct ReflectorPacket {
+    pub seq: u32,
+    /// When the reflector sent the packet
+    pub time: NtpTime,
+    pub error_estimate: u16,
+    /// When the reflector received the sender packet
+    pub receive_time: NtpTime,
+    pub sender_seq: u32,
+    pub sender_time: NtpTime,
+    pub sender_error_estimate: u16,
+    pub sender_ttl: u8,
+}
+
+impl NtpTime {
+    pub fn now() -> Self {
+        let since_unix = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let frac = (u64::from(since_unix.subsec_nanos()) << 32) / 1_000_000_000;
+        Self {
+            secs: (since_unix.as_secs() + NTP_UNIX_OFFSET) as u32,
+            frac: frac as u32,
+        }
+    }
+
+    /// Time since `earlier`, zero if `earlier` is later
+    pub fn duration_since(&self, earlier: NtpTime) -> Duration {
+        let to_units = |t: &NtpTime| (u64::from(t.secs) << 32) | u64::from(t.frac);
+        let units = to_units(self).saturating_sub(to_units(&earlier));
+        Duration::from_nanos(((u128::from(units) * 1_000_000_000) >> 32) as u64)
+    }
+
+    /// The middle 32 bits: seconds and the fraction in 1/65536 units, as in RTCP reports
+    pub fn compact(&self) -> u32 {
+        (self.secs << 16) | (self.frac >> 16)
+    }
+
+    pub fn write(&self, buf: &mut Vec<u8>) {
+        buf.extend_from_slice(&self.secs.to_be_bytes());
+        buf.extend_from_slice(&self.frac.to_be_bytes());
+    }
+
+    pub fn parse(b: &[u8]) -> Self {
+        Self {
+            secs: u32::from_be_bytes(b[0..4].try_into().unwrap()),
+            frac: u32::from_be_bytes(b[4..8].try_into().unwrap()),
+        }
+    }
+}
+
+impl SenderPacket {
+    /// Writes the packet padded with zeros to `len`
+    pub fn write(&self, buf: &mut Vec<u8>, len: usize) {
+        buf.extend_from_slice(&self.seq.to_be_bytes());
+        self.time.write(buf);
+        buf.extend_from_slice(&self.error_estimate.to_be_bytes());
+        buf.resize(len.max(SENDER_LEN), 0);
+    }
+
+    pub fn parse(pkt: &[u8]) -> Result<Self, Error> {
+        if pkt.len() < SENDER_LEN {
+            return Err(Error::new(format!(
+                "Too short TWAMP test packet, len: {}",
+                pkt.len()
+            )));
+        }
+
+        Ok(Self {
+            seq: u32::from_be_bytes(pkt[0..4].try_into().unwrap()),
+            time: NtpTime::parse(&pkt[4..12]),
+            error_estimate: u16::from_be_bytes(pkt[12..14].try_into().unwrap()),
+        })
+    }
+}
+
+impl ReflectorPacket {
+    /// Writes the packet padded with zeros to `len`
+    pub fn write(&self, buf: &mut Vec<u8>, len: usize) {
+        buf.extend_from_slice(&self.seq.to_be_bytes());
+        self.time.write(buf);
+        buf.extend_from_slice(&self.error_estimate.to_be_bytes());
+        buf.extend_from_slice(&[0; 2]);
+        self.receive_time.write(buf);
+        buf.extend_from_slice(&self.sender_seq.to_be_bytes());
+        self.sender_time.write(buf);
+        buf.extend_from_slice(&self.sender_error_estimate.to_be_bytes());
+        buf.extend_from_slice(&[0; 2]);
+        buf.push(self.sender_ttl);
+        buf.resize(len.max(REFLECTOR_LEN), 0);
+    }
+
+    pub fn parse(pkt: &[u8]) -> Result<Self, Error> {
+        if pkt.len() < REFLECTOR_LEN {
+            return Err(Error::new(format!(
+                "Too short TWAMP reflected packet, len: {}",
+                pkt.len()
+            )));
+        }
+
+        Ok(Self {
+            seq: u32::from_be_bytes(pkt[0..4].try_into().unwrap()),
+            time: NtpTime::parse(&pkt[4..12]),
+            error_estimate: u16::from_be_bytes(pkt[12..14].try_into().unwrap()),
+            receive_time: NtpTime::parse(&pkt[16..24]),
+            sender_seq: u32::from_be_bytes(pkt[24..28].try_into().unwrap()),
+            sender_time: NtpTime::parse(&pkt[28..36]),
+            sender_error_estimate: u16::from_be_bytes(pkt[36..38].try_into().unwrap()),
+            sender_ttl: pkt[40],
+        })
+    }
+}
@@ -0,0 +1,259 @@
+//! Live statistics of `--websocket`: a WebSocket endpoint streaming the records of
+//! `--jsonl` as they are made, a JSON text message per record
+//!
+//! Subscribers get the window statistics of every interval, `"type":"interval"`, and
+//! events of clients such as spikes, `"type":"event"`. Nothing is sent before they
+//! subscribe, and a subscriber which falls `QUEUE` messages behind loses the next ones.
+//! Messages of subscribers are read only to answer pings and closes.
+//!
+//! With the `dashboard` feature, a plain GET of `/` is a page which subscribes and
+//! charts the delays, jitter and loss of every statistics, e.g. of every client, and
+//! lists the events: a browser is all a field technician needs.
+
+use crate::error::Error;
+use crate::export;
+use async_std::channel::{self, Receiver, Sender, TrySendError};
+use async_std::future;
+use async_std::io::{ReadExt, WriteExt};
+use async_std::net::{TcpListener, TcpStream};
+use async_std::task;
+use base64::Engine;
+use futures::future::{select, Either};
+use futures::{pin_mut, StreamExt};
+use log::{debug, info};
+use sha1::{Digest, Sha1};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Messages a subscriber can be behind
+const QUEUE: usize = 256;
+/// Time a subscriber has to send its handshake
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+/// Longest message of a subscriber, which closes the connection
+const MAX_MESSAGE: u64 = 64 * 1024;
+/// Of RFC 6455, appended to the key of a handshake
+const ACCEPT_GUID: &str = "258EAFA5-E914-47DA-95CA-C5AB0DC85B11";
+
+const OP_TEXT: u8 = 0x1;
+const OP_CLOSE: u8 = 0x8;
+const OP_PING: u8 = 0x9;
+const OP_PONG: u8 = 0xA;
+
+/// Page of the `dashboard` feature, self-contained
+#[cfg(feature = "dashboard")]
+const DASHBOARD: &str = include_str!("dashboard.html");
+
+static SUBSCRIBERS: Mutex<Vec<Sender<Vec<u8>>>> = Mutex::new(Vec::new());
+
+/// Accepts subscribers on `bind` until the run ends, returns right away without it
+pub async fn serve(bind: Option<&str>) -> Result<(), Error> {
+    let bind = some_or_ret!(bind, Ok(()));
+    let listener = TcpListener::bind(bind)
+        .await
+        .map_err(|e| Error::new(format!("Cannot bind to {}: {}", bind, e)))?;
+    info!("Live statistics: ws://{}/", listener.local_addr()?);
+
+    let mut incoming = listener.incoming();
+    while let Some(stream) = incoming.next().await {
+        let stream = stream?;
+        task::spawn(async move {
+            if let Err(e) = subscribe(stream).await {
+                debug!("WebSocket subscriber error: {}", e);
+            }
+        });
+    }
+    Ok(())
+}
+
+/// Sends the record `rec` as JSON to every subscriber
+pub fn publish(rec: &str) {
+    let mut subscribers = SUBSCRIBERS.lock().unwrap_or_else(|e| e.into_inner());
+    if subscribers.is_empty() {
+        return;
+    }
+    let message = frame(OP_TEXT, export::record_to_json(rec).as_bytes());
+    subscribers.retain(|subscriber| match subscriber.try_send(message.clone()) {
+        Ok(()) => true,
+        Err(TrySendError::Full(_)) => {
+            debug!("A WebSocket subscriber is behind, a message is dropped");
+            true
+        }
+        Err(TrySendError::Closed(_)) => false,
+    });
+}
+
+async fn subscribe(stream: TcpStream) -> std::io::Result<()> {
+    let request = match future::timeout(HANDSHAKE_TIMEOUT, read_request(&stream)).await {
+        Ok(request) => request?,
+        Err(_) => return Err(invalid("no handshake in time")),
+    };
+    let (path, key) = match request {
+        Some(request) => (Some(request.path), request.key),
+        None => (None, None),
+    };
+    let key = some_or_ret!(key, respond(&stream, path.as_deref()).await);
+    let accept = base64::engine::general_purpose::STANDARD
+        .encode(Sha1::digest(format!("{}{}", key, ACCEPT_GUID)));
+    let response = format!(
+        "HTTP/1.1 101 Switching Protocols\r\nUpgrade: websocket\r\nConnection: Upgrade\r\n\
+         Sec-WebSocket-Accept: {}\r\n\r\n",
+        accept
+    );
+    (&stream).write_all(response.as_bytes()).await?;
+
+    let (sender, receiver) = channel::bounded(QUEUE);
+    SUBSCRIBERS
+        .lock()
+        .unwrap_or_else(|e| e.into_inner())
+        .push(sender.clone());
+    let write = write_messages(&stream, receiver);
+    let read = read_messages(&stream, sender);
+    pin_mut!(write, read);
+    match select(write, read).await {
+        Either::Left((res, _)) | Either::Right((res, _)) => res,
+    }
+}
+
+/// A GET request, `key` is the `Sec-WebSocket-Key` of a handshake
+struct Request {
+    path: String,
+    key: Option<String>,
+}
+
+/// `None` if the request isn't a GET
+async fn read_request(mut stream: &TcpStream) -> std::io::Result<Option<Request>> {
+    let mut request = Vec::new();
+    let mut byte = [0];
+    // Byte by byte, the first message may follow the request
+    while !request.ends_with(b"\r\n\r\n") {
+        if request.len() >= 16 * 1024 || stream.read(&mut byte).await? == 0 {
+            return Ok(None);
+        }
+        request.push(byte[0]);
+    }
+    let request = String::from_utf8_lossy(&request);
+    let path = match request.strip_prefix("GET ") {
+        Some(rest) => rest.split(' ').next().unwrap_or_default(),
+        None => return Ok(None),
+    };
+    let path = path.split('?').next().unwrap_or_default().to_owned();
+    let mut upgrade = false;
+    let mut key = None;
+    for line in request.lines().skip(1) {
+        let (name, value) = some_or_cont!(line.split_once(':'));
+        let value = value.trim();
+        match name.trim().to_ascii_lowercase().as_str() {
+            "upgrade" => upgrade = value.eq_ignore_ascii_case("websocket"),
+            "sec-websocket-key" => key = Some(value.to_owned()),
+            _ => {}
+        }
+    }
+    Ok(Some(Request {
+        path,
+        key: key.filter(|_| upgrade),
+    }))
+}
+
+/// Answers a request which isn't a handshake: the page of the `dashboard` feature
+/// at `/`, an error otherwise
+async fn respond(mut stream: &TcpStream, path: Option<&str>) -> std::io::Result<()> {
+    let (status, content_type, body) = match path {
+        #[cfg(feature = "dashboard")]
+        Some("/") => ("200 OK", "text/html; charset=utf-8", DASHBOARD),
+        _ => (
+            "400 Bad Request",
+            "text/plain",
+            "Expected a WebSocket handshake\n",
+        ),
+    };
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    stream.write_all(response.as_bytes()).await
+}
+
+async fn write_messages(
+    mut stream: &TcpStream,
+    messages: Receiver<Vec<u8>>,
+) -> std::io::Result<()> {
+    while let Ok(message) = messages.recv().await {
+        stream.write_all(&message).await?;
+        if message.first() == Some(&(0x80 | OP_CLOSE)) {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Answers pings and closes of the subscriber through its queue of `messages`
+async fn read_messages(mut stream: &TcpStream, messages: Sender<Vec<u8>>) -> std::io::Result<()> {
+    loop {
+        let mut header = [0; 2];
+        stream.read_exact(&mut header).await?;
+        let opcode = header[0] & 0x0f;
+        let len = match header[1] & 0x7f {
+            126 => {
+                let mut len = [0; 2];
+                stream.read_exact(&mut len).await?;
+                u16::from_be_bytes(len).into()
+            }
+            127 => {
+                let mut len = [0; 8];
+                stream.read_exact(&mut len).await?;
+                u64::from_be_bytes(len)
+            }
+            len => len.into(),
+        };
+        if len > MAX_MESSAGE {
+            return Err(invalid("message too long"));
+        }
+        let mut mask = [0; 4];
+        if header[1] & 0x80 != 0 {
+            stream.read_exact(&mut mask).await?;
+        }
+        let mut payload = vec![0; len as usize];
+        stream.read_exact(&mut payload).await?;
+        for (i, byte) in payload.iter_mut().enumerate() {
+            *byte ^= mask[i % 4];
+        }
+        let reply = match opcode {
+            OP_PING => frame(OP_PONG, &payload),
+            OP_CLOSE => frame(OP_CLOSE, &payload[..payload.len().min(2)]),
+            _ => continue,
+        };
+        // Replies go through the queue not to interleave with a message being written,
+        // the connection ends once the reply to a close is
+        if messages.send(reply).await.is_err() {
+            return Ok(());
+        }
+        if opcode == OP_CLOSE {
+            future::pending::<()>().await;
+        }
+    }
+}
+
+/// A final unmasked frame of `opcode` with `payload`
+fn frame(opcode: u8, payload: &[u8]) -> Vec<u8> {
+    let mut frame = vec![0x80 | opcode];
+    match payload.len() {
+        len if len < 126 => frame.push(len as u8),
+        len if len <= u16::MAX as usize => {
+            frame.push(126);
+            frame.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+        len => {
+            frame.push(127);
+            frame.extend_from_slice(&(len as u64).to_be_bytes());
+        }
+    }
+    frame.extend_from_slice(payload);
+    frame
+}
+
+fn invalid(msg: &str) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, msg)
+}